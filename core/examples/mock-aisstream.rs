@@ -0,0 +1,33 @@
+// Runs the mock aisstream.io server, for pointing a local seawatch at:
+//
+//   cargo run -p seamon-core --features mock --example mock-aisstream -- 127.0.0.1:9000 frames.jsonl
+//   AIS_STREAM_URL=ws://127.0.0.1:9000/v0/stream AIS_STREAM_API_KEY=test-key cargo run
+//
+// Each line of the frames file is sent as one message, a second apart, to
+// every client that authenticates with `test-key`; the connection is then
+// closed, so the client's reconnect logic gets exercised too. Without a
+// file, a handful of position reports off Rotterdam are sent.
+use seamon_core::mock::{position_report, End, MockAisStream, Script};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:9000".to_string());
+    let frames = match args.next() {
+        Some(path) => std::fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty()).map(|line| line.as_bytes().to_vec()).collect(),
+        None => (0..10).map(|i| position_report(244660000 + i, "MOCK", 51.9 + i as f64 * 0.01, 4.1)).collect(),
+    };
+
+    let mock = MockAisStream::bind(&addr, Script {
+        frames,
+        interval: Duration::from_secs(1),
+        then: End::Close,
+        ..Default::default()
+    })
+    .await?;
+    println!("Mock aisstream.io listening on {} (API key test-key)", mock.url());
+
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
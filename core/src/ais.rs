@@ -0,0 +1,389 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use tokio_tungstenite::{connect_async, tungstenite::Message, WebSocketStream, MaybeTlsStream};
+use tokio::net::TcpStream;
+use url::Url;
+use std::sync::Arc;
+
+use crate::intern;
+use futures_util::{SinkExt, StreamExt};
+
+pub struct AisStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AisMessage {
+    #[serde(rename = "MessageType")]
+    pub message_type: String,
+    #[serde(rename = "MetaData")]
+    pub metadata: Metadata,
+    #[serde(rename = "Message")]
+    pub message: MessageData,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Metadata {
+    #[serde(rename = "MMSI")]
+    pub mmsi: u32,
+    #[serde(rename = "ShipName", deserialize_with = "intern::deserialize")]
+    pub ship_name: Arc<str>,
+    #[serde(rename = "latitude")]
+    pub latitude: f64,
+    #[serde(rename = "longitude")]
+    pub longitude: f64,
+    #[serde(rename = "time_utc")]
+    pub time_utc: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageData {
+    #[serde(rename = "PositionReport")]
+    pub position_report: Option<PositionReport>,
+    #[serde(rename = "ShipStaticData")]
+    pub ship_static_data: Option<ShipStaticData>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PositionReport {
+    #[serde(rename = "Cog")]
+    pub cog: f64,
+    #[serde(rename = "NavigationalStatus")]
+    pub navigational_status: u32,
+    #[serde(rename = "Sog")]
+    pub sog: f64,
+    #[serde(rename = "TrueHeading")]
+    pub true_heading: u32,
+    // True for a DGPS-grade fix, under 10 m; most transponders report false
+    #[serde(rename = "PositionAccuracy", default)]
+    pub position_accuracy: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShipStaticData {
+    #[serde(rename = "Type")]
+    pub ship_type: u32,
+    #[serde(rename = "Destination", deserialize_with = "intern::deserialize")]
+    pub destination: Arc<str>,
+    #[serde(rename = "ImoNumber")]
+    pub imo_number: u32,
+    #[serde(rename = "CallSign", default, deserialize_with = "intern::deserialize")]
+    pub call_sign: Arc<str>,
+    #[serde(rename = "Eta", default)]
+    pub eta: Option<Eta>,
+    #[serde(rename = "Dimension", default)]
+    pub dimension: Option<Dimension>,
+    // Metres; 0 is "not available"
+    #[serde(rename = "MaximumStaticDraught", default)]
+    pub draught: Option<f64>,
+}
+
+// The kind of transponder a vessel has. Class A is mandatory for large and
+// passenger ships; class B is the cheaper, lower-powered kind on small craft,
+// reporting less often and with less.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub enum AisClass {
+    A,
+    B,
+}
+
+impl AisClass {
+    // Which class sends this message type; None for ones either may send, or
+    // that don't come from vessels at all (base stations, aids to navigation)
+    pub fn of(message_type: &str) -> Option<Self> {
+        match message_type {
+            "PositionReport" | "ShipStaticData" => Some(Self::A),
+            "StandardClassBPositionReport" | "ExtendedClassBPositionReport" | "StaticDataReport" => Some(Self::B),
+            _ => None,
+        }
+    }
+}
+
+// Metres from the GPS antenna to the bow (A), stern (B), port (C) and
+// starboard (D) sides, as broadcast. 0 is "not available"; 511 (A, B) and 63
+// (C, D) mean that much or more.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dimension {
+    #[serde(rename = "A")]
+    pub a: u32,
+    #[serde(rename = "B")]
+    pub b: u32,
+    #[serde(rename = "C")]
+    pub c: u32,
+    #[serde(rename = "D")]
+    pub d: u32,
+}
+
+// Estimated time of arrival as broadcast: UTC, with no year. Month 0, day 0,
+// hour 24 or minute 60 mean "not available".
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Eta {
+    #[serde(rename = "Month")]
+    pub month: u32,
+    #[serde(rename = "Day")]
+    pub day: u32,
+    #[serde(rename = "Hour")]
+    pub hour: u32,
+    #[serde(rename = "Minute")]
+    pub minute: u32,
+}
+
+impl Eta {
+    // As a Unix timestamp, in whichever year puts it nearest to `now`
+    pub fn resolve(&self, now: u64) -> Option<u64> {
+        use chrono::{Datelike, TimeZone, Utc};
+
+        if self.month == 0 || self.day == 0 || self.hour >= 24 || self.minute >= 60 {
+            return None;
+        }
+        let year = Utc.timestamp_opt(now as i64, 0).single()?.year();
+        (year - 1..=year + 1)
+            .filter_map(|year| {
+                Utc.with_ymd_and_hms(year, self.month, self.day, self.hour, self.minute, 0).single()
+            })
+            .map(|time| time.timestamp().max(0) as u64)
+            .min_by_key(|&time| time.abs_diff(now))
+    }
+}
+
+/// What we ask aisstream.io to send us. Bounding boxes are `[[lat, lng], [lat, lng]]`
+/// corner pairs, as in the aisstream subscription message.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Subscription {
+    pub bounding_boxes: Vec<[[f64; 2]; 2]>,
+    pub message_types: Vec<String>,
+    pub mmsi: Vec<u32>,
+}
+
+/// Partial update of a `Subscription`; omitted fields are left unchanged.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct SubscriptionUpdate {
+    pub bounding_boxes: Option<Vec<[[f64; 2]; 2]>>,
+    pub message_types: Option<Vec<String>>,
+    pub mmsi: Option<Vec<u32>>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            bounding_boxes: vec![[[-90.0, -180.0], [90.0, 180.0]]], // Global coverage
+            message_types: vec!["PositionReport".to_string(), "ShipStaticData".to_string()],
+            mmsi: Vec::new(),
+        }
+    }
+}
+
+impl Subscription {
+    pub fn apply(&mut self, update: SubscriptionUpdate) {
+        if let Some(bounding_boxes) = update.bounding_boxes {
+            self.bounding_boxes = bounding_boxes;
+        }
+        if let Some(message_types) = update.message_types {
+            self.message_types = message_types;
+        }
+        if let Some(mmsi) = update.mmsi {
+            self.mmsi = mmsi;
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.bounding_boxes.is_empty() {
+            return Err(anyhow::anyhow!("At least one bounding box is required"));
+        }
+        for corner in self.bounding_boxes.iter().flatten() {
+            let [lat, lng] = *corner;
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+                return Err(anyhow::anyhow!("Bounding box corner out of range: [{}, {}]", lat, lng));
+            }
+        }
+        if self.message_types.iter().any(|t| t.is_empty()) {
+            return Err(anyhow::anyhow!("Message type filters must not be empty strings"));
+        }
+        if self.mmsi.len() > 50 {
+            // aisstream.io rejects subscriptions with more than 50 MMSI filters
+            return Err(anyhow::anyhow!("At most 50 MMSI filters are supported"));
+        }
+        Ok(())
+    }
+
+    fn to_auth_message(&self, api_key: &str) -> serde_json::Value {
+        let mut auth_message = serde_json::json!({
+            "APIKey": api_key,
+            "BoundingBoxes": self.bounding_boxes,
+        });
+        if !self.message_types.is_empty() {
+            auth_message["FilterMessageTypes"] = serde_json::json!(self.message_types);
+        }
+        if !self.mmsi.is_empty() {
+            let mmsi: Vec<String> = self.mmsi.iter().map(|m| m.to_string()).collect();
+            auth_message["FiltersShipMMSI"] = serde_json::json!(mmsi);
+        }
+        auth_message
+    }
+}
+
+// Working memory kept by each parser between frames
+#[derive(Default)]
+pub struct ParseScratch {
+    #[cfg(feature = "simd-json")]
+    buffers: simd_json::Buffers,
+}
+
+// Parse a raw frame from the stream, logging (and skipping) malformed ones.
+// simd-json parses in place, hence the mutable buffer.
+pub fn parse_message(data: &mut [u8], scratch: &mut ParseScratch) -> Option<AisMessage> {
+    #[cfg(feature = "simd-json")]
+    let parsed = simd_json::serde::from_slice_with_buffers::<AisMessage>(data, &mut scratch.buffers);
+    #[cfg(not(feature = "simd-json"))]
+    let parsed = {
+        let _ = scratch;
+        serde_json::from_slice::<AisMessage>(data)
+    };
+
+    match parsed {
+        Ok(message) => Some(message),
+        Err(e) => {
+            tracing::warn!("Failed to parse AIS message: {}", e);
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum AuthMessage {
+    AuthError(AuthError),
+    Message(AisMessage),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthError {
+    pub error: String,
+}
+
+impl AisStream {
+    pub async fn connect(url: Url, api_key: String, subscription: &Subscription) -> Result<Self> {
+        let (mut socket, _) = connect_async(url).await?;
+
+        // Send authentication
+        let auth_message = subscription.to_auth_message(&api_key);
+
+        socket
+            .send(Message::Text(auth_message.to_string()))
+            .await?;
+
+        // Wait for authentication response
+        if let Some(msg) = socket.next().await {
+            match msg? {
+                Message::Binary(data) => {
+                    match serde_json::from_slice::<AuthMessage>(&data)? {
+                        AuthMessage::AuthError(error) => {
+                            return Err(anyhow::anyhow!("Authentication error: {}", error.error));
+                        }
+                        AuthMessage::Message(_) => {
+                            // Authentication successful
+                        }
+                    }
+                }
+                _ => {
+                    return Err(anyhow::anyhow!("Unexpected authentication response"));
+                }
+            }
+        }
+
+        Ok(Self { socket })
+    }
+
+    // The next raw message frame, left unparsed so parsing can happen off the read loop
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        while let Some(msg) = self.socket.next().await {
+            match msg? {
+                Message::Binary(data) => return Ok(Some(data)),
+                Message::Close(_) => {
+                    return Err(anyhow::anyhow!("WebSocket connection closed"));
+                }
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn close(mut self) {
+        if let Err(e) = self.socket.close(None).await {
+            tracing::debug!("Error closing AIS stream: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        let mut frame = br#"{
+            "MessageType": "PositionReport",
+            "MetaData": {"MMSI": 244660000, "ShipName": "ALIDA ", "latitude": 51.9, "longitude": 4.1, "time_utc": "2025-06-01 12:00:00"},
+            "Message": {"PositionReport": {"Cog": 92.5, "NavigationalStatus": 0, "Sog": 11.2, "TrueHeading": 91}}
+        }"#
+        .to_vec();
+        let mut scratch = ParseScratch::default();
+        let message = parse_message(&mut frame, &mut scratch).unwrap();
+        assert_eq!(message.metadata.mmsi, 244660000);
+        assert!(Arc::ptr_eq(&message.metadata.ship_name, &crate::intern::intern("ALIDA ")));
+        assert_eq!(message.message.position_report.unwrap().true_heading, 91);
+
+        assert!(parse_message(&mut b"{\"MessageType\": 3}".to_vec(), &mut scratch).is_none());
+    }
+
+    #[test]
+    fn test_eta_resolve() {
+        // 2025-12-30 12:00 UTC
+        let now = 1_767_096_000;
+        let eta = |month, day, hour, minute| Eta { month, day, hour, minute }.resolve(now);
+        assert_eq!(eta(12, 31, 6, 30), Some(1_767_162_600));
+        // Early January is next year's
+        assert_eq!(eta(1, 2, 0, 0), Some(1_767_312_000));
+        assert_eq!(eta(0, 0, 24, 60), None);
+        assert_eq!(eta(2, 30, 0, 0), None);
+    }
+
+    #[test]
+    fn test_subscription_update_keeps_omitted_fields() {
+        let mut subscription = Subscription::default();
+        subscription.apply(SubscriptionUpdate {
+            mmsi: Some(vec![244660000]),
+            ..Default::default()
+        });
+
+        assert_eq!(subscription.mmsi, vec![244660000]);
+        assert_eq!(subscription.bounding_boxes, Subscription::default().bounding_boxes);
+        assert_eq!(subscription.message_types, Subscription::default().message_types);
+    }
+
+    #[test]
+    fn test_subscription_validation() {
+        let mut subscription = Subscription::default();
+        assert!(subscription.validate().is_ok());
+
+        subscription.bounding_boxes = vec![[[-91.0, 0.0], [10.0, 10.0]]];
+        assert!(subscription.validate().is_err());
+
+        subscription.bounding_boxes.clear();
+        assert!(subscription.validate().is_err());
+    }
+
+    #[test]
+    fn test_auth_message_stringifies_mmsi_filters() {
+        let subscription = Subscription {
+            mmsi: vec![368207620],
+            ..Default::default()
+        };
+        let auth_message = subscription.to_auth_message("key");
+
+        assert_eq!(auth_message["FiltersShipMMSI"], serde_json::json!(["368207620"]));
+        assert_eq!(auth_message["BoundingBoxes"], serde_json::json!([[[-90.0, -180.0], [90.0, 180.0]]]));
+    }
+}
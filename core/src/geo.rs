@@ -0,0 +1,72 @@
+// Mean Earth radius, in metres
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+// Great-circle distance between two positions, in metres
+pub fn haversine_m(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lng2 - lng1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+// Initial great-circle bearing from the first position to the second, in
+// degrees clockwise from north
+pub fn bearing_deg(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lambda = (lng2 - lng1).to_radians();
+    let y = d_lambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * d_lambda.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+// Whether a point lies inside a polygon of [lat, lng] vertices (ray casting;
+// the ring may be given open or closed). Fine for zones that don't straddle
+// the antimeridian.
+pub fn point_in_polygon(lat: f64, lng: f64, polygon: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for i in 0..polygon.len() {
+        let [lat_i, lng_i] = polygon[i];
+        let [lat_j, lng_j] = polygon[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lng < (lng_j - lng_i) * (lat - lat_i) / (lat_j - lat_i) + lng_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine() {
+        // Dover to Calais is about 41 km
+        let d = haversine_m(51.1279, 1.3134, 50.9513, 1.8587);
+        assert!((d - 42_000.0).abs() < 1_500.0, "{}", d);
+        assert_eq!(haversine_m(10.0, 10.0, 10.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn test_bearing() {
+        assert!((bearing_deg(50.0, -4.0, 51.0, -4.0) - 0.0).abs() < 1e-9);
+        assert!((bearing_deg(0.0, 0.0, 0.0, 1.0) - 90.0).abs() < 1e-9);
+        assert!((bearing_deg(50.0, -4.0, 49.0, -4.0) - 180.0).abs() < 1e-9);
+        // Calais from Dover is roughly south-east
+        let b = bearing_deg(51.1279, 1.3134, 50.9513, 1.8587);
+        assert!((115.0..125.0).contains(&b), "{}", b);
+    }
+
+    #[test]
+    fn test_point_in_polygon() {
+        let square = [[0.0, 0.0], [0.0, 10.0], [10.0, 10.0], [10.0, 0.0]];
+        assert!(point_in_polygon(5.0, 5.0, &square));
+        assert!(!point_in_polygon(15.0, 5.0, &square));
+        assert!(!point_in_polygon(5.0, -0.1, &square));
+        assert!(!point_in_polygon(5.0, 5.0, &[]));
+    }
+}
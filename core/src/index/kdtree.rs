@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use super::{is_valid_position, SpatialIndex};
+use crate::memory::hash_map_bytes;
+
+// Rebuild once incremental changes outnumber the indexed ships (and at least this many)...
+const MIN_CHANGES_BEFORE_REBUILD: usize = 5_000;
+// ...or the deepest node is this many levels below a balanced tree's depth
+const MAX_EXTRA_DEPTH: usize = 32;
+// Subtrees smaller than this are built on the current thread; splitting
+// further costs more in task overhead than it saves
+const PARALLEL_BUILD_THRESHOLD: usize = 4_096;
+
+// KD-Tree node for spatial indexing
+#[derive(Debug, Clone)]
+struct KdNode {
+    mmsi: u32,
+    lat: f64,
+    lng: f64,
+    // Bounding box of this node's subtree as (sw_lat, sw_lng, ne_lat, ne_lng),
+    // so whole subtrees outside the query can be skipped with one check
+    bounds: (f64, f64, f64, f64),
+    // Removed nodes stay in place as tombstones until the next full rebuild
+    removed: bool,
+    left: Option<Box<KdNode>>,
+    right: Option<Box<KdNode>>,
+    depth: usize,
+}
+
+impl KdNode {
+    fn new(mmsi: u32, lat: f64, lng: f64, depth: usize) -> Self {
+        Self {
+            mmsi,
+            lat,
+            lng,
+            bounds: (lat, lng, lat, lng),
+            removed: false,
+            left: None,
+            right: None,
+            depth,
+        }
+    }
+
+    fn dimension(&self) -> usize {
+        self.depth % 2 // 0 for latitude, 1 for longitude
+    }
+
+    fn coordinate(&self, dim: usize) -> f64 {
+        match dim {
+            0 => self.lat,
+            1 => self.lng,
+            _ => unreachable!(),
+        }
+    }
+
+    fn contains(&self, lat: f64, lng: f64) -> bool {
+        let (min_lat, min_lng, max_lat, max_lng) = self.bounds;
+        lat >= min_lat && lat <= max_lat && lng >= min_lng && lng <= max_lng
+    }
+}
+
+// KD-Tree for fast spatial queries. Built balanced from a snapshot, then kept
+// current with single-point inserts and tombstoned removals; it slowly loses
+// balance and gets rebuilt from scratch in the background.
+#[derive(Debug, Default)]
+pub struct KdTree {
+    root: Option<Box<KdNode>>,
+    // Where each MMSI currently sits in the tree, so it can be found again
+    positions: HashMap<u32, (f64, f64)>,
+    max_depth: usize,
+    nodes: usize, // Including tombstones
+}
+
+impl KdTree {
+    pub fn build(mut points: Vec<(u32, f64, f64)>) -> Self {
+        let positions = points.iter().map(|&(mmsi, lat, lng)| (mmsi, (lat, lng))).collect();
+        let nodes = points.len();
+        let (root, max_depth) = Self::build_recursive(&mut points, 0);
+        Self { root, positions, max_depth, nodes }
+    }
+
+    fn build_recursive(points: &mut [(u32, f64, f64)], depth: usize) -> (Option<Box<KdNode>>, usize) {
+        if points.is_empty() {
+            return (None, 0);
+        }
+
+        let dim = depth % 2; // 0 for lat, 1 for lng
+
+        // Partition around the median of the current dimension; no need for a full sort
+        let median = points.len() / 2;
+        points.select_nth_unstable_by(median, |a, b| {
+            let coord_a = if dim == 0 { a.1 } else { a.2 };
+            let coord_b = if dim == 0 { b.1 } else { b.2 };
+            coord_a.total_cmp(&coord_b)
+        });
+        let (mmsi, lat, lng) = points[median];
+
+        let mut node = Box::new(KdNode::new(mmsi, lat, lng, depth));
+        node.bounds = points.iter().fold(node.bounds, |b, &(_, lat, lng)| {
+            (b.0.min(lat), b.1.min(lng), b.2.max(lat), b.3.max(lng))
+        });
+
+        // Recursively build left and right subtrees, in parallel while they are big
+        let (left, right) = points.split_at_mut(median);
+        let right = &mut right[1..];
+        let ((left, left_depth), (right, right_depth)) = if left.len() >= PARALLEL_BUILD_THRESHOLD {
+            rayon::join(
+                || Self::build_recursive(left, depth + 1),
+                || Self::build_recursive(right, depth + 1),
+            )
+        } else {
+            (Self::build_recursive(left, depth + 1), Self::build_recursive(right, depth + 1))
+        };
+        node.left = left;
+        node.right = right;
+
+        (Some(node), depth.max(left_depth).max(right_depth))
+    }
+
+    fn insert_recursive(node: &mut KdNode, mmsi: u32, lat: f64, lng: f64) -> usize {
+        let b = node.bounds;
+        node.bounds = (b.0.min(lat), b.1.min(lng), b.2.max(lat), b.3.max(lng));
+
+        let dim = node.dimension();
+        let coordinate = if dim == 0 { lat } else { lng };
+        let split_value = node.coordinate(dim);
+        // Ties may go either way; spread them so repeated positions don't chain
+        let tie_goes_left = || {
+            let hash = (mmsi as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            (hash >> (63 - node.depth % 64)) & 1 == 1
+        };
+        let child = if coordinate < split_value || (coordinate == split_value && tie_goes_left()) {
+            &mut node.left
+        } else {
+            &mut node.right
+        };
+
+        match child {
+            Some(child) => Self::insert_recursive(child, mmsi, lat, lng),
+            None => {
+                *child = Some(Box::new(KdNode::new(mmsi, lat, lng, node.depth + 1)));
+                node.depth + 1
+            }
+        }
+    }
+
+    fn remove_recursive(node: &mut KdNode, mmsi: u32, lat: f64, lng: f64) -> bool {
+        if !node.contains(lat, lng) {
+            return false;
+        }
+        if node.mmsi == mmsi && !node.removed && node.lat == lat && node.lng == lng {
+            node.removed = true;
+            return true;
+        }
+
+        // Points equal to the split value can be on either side
+        let dim = node.dimension();
+        let coordinate = if dim == 0 { lat } else { lng };
+        let split_value = node.coordinate(dim);
+
+        if let Some(ref mut left) = node.left
+            && coordinate <= split_value
+            && Self::remove_recursive(left, mmsi, lat, lng)
+        {
+            return true;
+        }
+        if let Some(ref mut right) = node.right
+            && coordinate >= split_value
+        {
+            return Self::remove_recursive(right, mmsi, lat, lng);
+        }
+        false
+    }
+
+    fn range_query_recursive(
+        node: &KdNode,
+        sw_lat: f64,
+        sw_lng: f64,
+        ne_lat: f64,
+        ne_lng: f64,
+        result: &mut Vec<u32>,
+    ) {
+        // Skip the whole subtree if it can't intersect the bounding box
+        let (min_lat, min_lng, max_lat, max_lng) = node.bounds;
+        if max_lat < sw_lat || min_lat > ne_lat || max_lng < sw_lng || min_lng > ne_lng {
+            return;
+        }
+
+        // Check if current node is within the bounding box
+        if !node.removed
+            && node.lat >= sw_lat
+            && node.lat <= ne_lat
+            && node.lng >= sw_lng
+            && node.lng <= ne_lng
+        {
+            result.push(node.mmsi);
+        }
+
+        let dim = node.dimension();
+        let split_value = node.coordinate(dim);
+        let (range_min, range_max) = if dim == 0 {
+            (sw_lat, ne_lat)
+        } else {
+            (sw_lng, ne_lng)
+        };
+
+        // Recursively search left subtree if needed
+        if let Some(ref left) = node.left
+            && range_min <= split_value
+        {
+            Self::range_query_recursive(left, sw_lat, sw_lng, ne_lat, ne_lng, result);
+        }
+
+        // Recursively search right subtree if needed
+        if let Some(ref right) = node.right
+            && range_max >= split_value
+        {
+            Self::range_query_recursive(right, sw_lat, sw_lng, ne_lat, ne_lng, result);
+        }
+    }
+}
+
+impl SpatialIndex for KdTree {
+    fn upsert(&mut self, mmsi: u32, lat: f64, lng: f64) -> bool {
+        if self.positions.get(&mmsi) == Some(&(lat, lng)) {
+            return false;
+        }
+        self.remove(mmsi);
+        if !is_valid_position(lat, lng) {
+            return true;
+        }
+
+        self.positions.insert(mmsi, (lat, lng));
+        self.nodes += 1;
+        match self.root {
+            Some(ref mut root) => {
+                let depth = Self::insert_recursive(root, mmsi, lat, lng);
+                self.max_depth = self.max_depth.max(depth);
+            }
+            None => self.root = Some(Box::new(KdNode::new(mmsi, lat, lng, 0))),
+        }
+        true
+    }
+
+    fn remove(&mut self, mmsi: u32) -> bool {
+        let Some((lat, lng)) = self.positions.remove(&mmsi) else {
+            return false;
+        };
+        if let Some(ref mut root) = self.root {
+            Self::remove_recursive(root, mmsi, lat, lng);
+        }
+        true
+    }
+
+    fn range_query(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Vec<u32> {
+        let mut result = Vec::new();
+        if let Some(ref root) = self.root {
+            Self::range_query_recursive(root, sw_lat, sw_lng, ne_lat, ne_lng, &mut result);
+        }
+        result
+    }
+
+    fn points(&self) -> Vec<(u32, f64, f64)> {
+        self.positions.iter().map(|(&mmsi, &(lat, lng))| (mmsi, lat, lng)).collect()
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.nodes * std::mem::size_of::<KdNode>() + hash_map_bytes(&self.positions)
+    }
+
+    fn needs_rebuild(&self, changes: usize) -> bool {
+        let balanced_depth = (usize::BITS - self.positions.len().leading_zeros()) as usize;
+        changes >= MIN_CHANGES_BEFORE_REBUILD.max(self.positions.len())
+            || self.max_depth > balanced_depth + MAX_EXTRA_DEPTH
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_build_matches_brute_force() {
+        // Big enough to take the parallel path, with plenty of duplicate coordinates
+        let mut seed = 42u64;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64
+        };
+        let points: Vec<(u32, f64, f64)> = (1..=50_000)
+            .map(|mmsi| {
+                let lat = (next() * 200.0).round() / 10.0 + 40.0;
+                let lng = (next() * 200.0).round() / 10.0 - 10.0;
+                (mmsi, lat, lng)
+            })
+            .collect();
+        let tree = KdTree::build(points.clone());
+
+        for (sw_lat, sw_lng, ne_lat, ne_lng) in [(45.0, -5.0, 46.0, -4.0), (40.0, -10.0, 60.0, 10.0), (50.0, 0.0, 50.0, 0.0)] {
+            let mut found = tree.range_query(sw_lat, sw_lng, ne_lat, ne_lng);
+            found.sort_unstable();
+            let expected: Vec<u32> = points
+                .iter()
+                .filter(|&&(_, lat, lng)| lat >= sw_lat && lat <= ne_lat && lng >= sw_lng && lng <= ne_lng)
+                .map(|&(mmsi, _, _)| mmsi)
+                .collect();
+            assert_eq!(found, expected);
+        }
+        assert!(tree.max_depth <= 16);
+    }
+}
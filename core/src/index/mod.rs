@@ -0,0 +1,80 @@
+use anyhow::Result;
+use std::str::FromStr;
+
+mod kdtree;
+mod rtree;
+
+pub use kdtree::KdTree;
+pub use rtree::RTreeIndex;
+
+// A point index over ship positions, keyed by MMSI. Implementations are kept
+// current with per-ship updates and rebuilt from a snapshot when they report
+// that a rebuild would pay off.
+pub trait SpatialIndex: Send + Sync {
+    // Move a point to its new position (or add it), returning whether the index changed
+    fn upsert(&mut self, mmsi: u32, lat: f64, lng: f64) -> bool;
+
+    // Remove a point, returning whether it was indexed
+    fn remove(&mut self, mmsi: u32) -> bool;
+
+    // MMSIs of all points inside the bounding box, edges included
+    fn range_query(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Vec<u32>;
+
+    // Every indexed point, to rebuild from
+    fn points(&self) -> Vec<(u32, f64, f64)>;
+
+    // Approximate heap used by the index
+    fn memory_bytes(&self) -> usize;
+
+    // Whether rebuilding from scratch is worthwhile after `changes` incremental updates
+    fn needs_rebuild(&self, changes: usize) -> bool;
+}
+
+// Ships report 0,0 before they have a fix; those are never indexed
+pub fn is_valid_position(lat: f64, lng: f64) -> bool {
+    lat != 0.0 && lng != 0.0
+}
+
+// Longest geohash there is; 12 characters resolve to a few centimetres
+pub const MAX_GEOHASH_PRECISION: usize = 12;
+
+// Geohash of a position at `precision` characters
+pub fn geohash(lat: f64, lng: f64, precision: usize) -> Option<String> {
+    geohash::encode(geohash::Coord { x: lng, y: lat }, precision).ok()
+}
+
+// Height and width in degrees of a geohash cell at `precision` characters
+pub fn geohash_cell_size(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let lng_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (180.0 / 2f64.powi(lat_bits), 360.0 / 2f64.powi(lng_bits))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexKind {
+    #[default]
+    KdTree,
+    RTree,
+}
+
+impl IndexKind {
+    pub fn build(self, points: Vec<(u32, f64, f64)>) -> Box<dyn SpatialIndex> {
+        match self {
+            IndexKind::KdTree => Box::new(KdTree::build(points)),
+            IndexKind::RTree => Box::new(RTreeIndex::build(points)),
+        }
+    }
+}
+
+impl FromStr for IndexKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "kdtree" | "kd-tree" => Ok(IndexKind::KdTree),
+            "rtree" | "r-tree" => Ok(IndexKind::RTree),
+            other => Err(anyhow::anyhow!("Unknown spatial index '{}', expected kdtree or rtree", other)),
+        }
+    }
+}
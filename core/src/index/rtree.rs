@@ -0,0 +1,73 @@
+use rstar::{primitives::GeomWithData, RTree, AABB};
+use std::collections::HashMap;
+
+use super::{is_valid_position, SpatialIndex};
+use crate::memory::hash_map_bytes;
+
+// Points are stored as [lng, lat] so envelopes read like x/y
+type IndexedPoint = GeomWithData<[f64; 2], u32>;
+
+// R*-tree backed index. Inserts and removals keep the tree balanced, so it
+// never asks for a rebuild.
+pub struct RTreeIndex {
+    tree: RTree<IndexedPoint>,
+    positions: HashMap<u32, (f64, f64)>,
+}
+
+impl RTreeIndex {
+    pub fn build(points: Vec<(u32, f64, f64)>) -> Self {
+        let positions = points.iter().map(|&(mmsi, lat, lng)| (mmsi, (lat, lng))).collect();
+        let tree = RTree::bulk_load(
+            points
+                .into_iter()
+                .map(|(mmsi, lat, lng)| IndexedPoint::new([lng, lat], mmsi))
+                .collect(),
+        );
+        Self { tree, positions }
+    }
+}
+
+impl SpatialIndex for RTreeIndex {
+    fn upsert(&mut self, mmsi: u32, lat: f64, lng: f64) -> bool {
+        if self.positions.get(&mmsi) == Some(&(lat, lng)) {
+            return false;
+        }
+        self.remove(mmsi);
+        if !is_valid_position(lat, lng) {
+            return true;
+        }
+
+        self.positions.insert(mmsi, (lat, lng));
+        self.tree.insert(IndexedPoint::new([lng, lat], mmsi));
+        true
+    }
+
+    fn remove(&mut self, mmsi: u32) -> bool {
+        let Some((lat, lng)) = self.positions.remove(&mmsi) else {
+            return false;
+        };
+        self.tree.remove(&IndexedPoint::new([lng, lat], mmsi));
+        true
+    }
+
+    fn range_query(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Vec<u32> {
+        let envelope = AABB::from_corners([sw_lng, sw_lat], [ne_lng, ne_lat]);
+        self.tree
+            .locate_in_envelope(&envelope)
+            .map(|point| point.data)
+            .collect()
+    }
+
+    fn points(&self) -> Vec<(u32, f64, f64)> {
+        self.positions.iter().map(|(&mmsi, &(lat, lng))| (mmsi, lat, lng)).collect()
+    }
+
+    fn memory_bytes(&self) -> usize {
+        // Leaves plus roughly one inner node entry per leaf at rstar's default fan-out
+        self.tree.size() * 2 * std::mem::size_of::<IndexedPoint>() + hash_map_bytes(&self.positions)
+    }
+
+    fn needs_rebuild(&self, _changes: usize) -> bool {
+        false
+    }
+}
@@ -0,0 +1,76 @@
+use dashmap::DashSet;
+use serde::de::{self, Deserializer, Visitor};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::{Arc, LazyLock};
+
+use crate::memory::table_bytes;
+
+// Ship names and destinations repeat heavily across a global fleet ("", "ROTTERDAM",
+// "FOR ORDERS", ...), so every copy held by a `Ship` shares one allocation.
+static STRINGS: LazyLock<DashSet<Arc<str>>> = LazyLock::new(DashSet::new);
+
+pub fn intern(s: &str) -> Arc<str> {
+    if let Some(existing) = STRINGS.get(s) {
+        return existing.clone();
+    }
+    let interned: Arc<str> = Arc::from(s);
+    // Another thread may have interned the same string in the meantime; keep theirs
+    if !STRINGS.insert(interned.clone()) {
+        return STRINGS.get(s).map(|existing| existing.clone()).unwrap_or(interned);
+    }
+    interned
+}
+
+// For `#[serde(deserialize_with)]`: intern straight from the input buffer, so
+// a name we've seen before costs a lookup rather than a fresh String
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+    struct InternVisitor;
+
+    impl Visitor<'_> for InternVisitor {
+        type Value = Arc<str>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a string")
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<Arc<str>, E> {
+            Ok(intern(s))
+        }
+    }
+
+    deserializer.deserialize_str(InternVisitor)
+}
+
+// Drops strings no ship refers to any more, returning how many were freed
+pub fn purge_unused() -> usize {
+    let before = STRINGS.len();
+    STRINGS.retain(|s| Arc::strong_count(s) > 1);
+    before - STRINGS.len()
+}
+
+// Approximate heap held by interned strings, including the Arc headers
+pub fn memory_bytes() -> usize {
+    let strings: usize = STRINGS.iter().map(|s| s.len() + 2 * size_of::<usize>()).sum();
+    strings + table_bytes::<Arc<str>, ()>(STRINGS.capacity())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_shares_allocations() {
+        let a = intern("INTERN TEST ROTTERDAM");
+        let b = intern(&String::from("INTERN TEST ROTTERDAM"));
+        assert!(Arc::ptr_eq(&a, &b));
+
+        drop(b);
+        purge_unused();
+        assert!(STRINGS.contains("INTERN TEST ROTTERDAM"));
+
+        drop(a);
+        purge_unused();
+        assert!(!STRINGS.contains("INTERN TEST ROTTERDAM"));
+    }
+}
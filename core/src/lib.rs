@@ -0,0 +1,12 @@
+// The ship cache, spatial index, AIS models and stream client, without the
+// web server, for embedding ingestion in other programs. The seawatch binary
+// is built on top of this.
+pub mod ais;
+pub mod geo;
+pub mod index;
+pub mod intern;
+pub mod memory;
+pub mod ship;
+pub mod tiles;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
@@ -0,0 +1,51 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+// Approximate heap used by each part of the cache, in bytes. These are
+// estimates from entry counts and sizes, not allocator statistics, but they
+// track the real footprint closely enough to spot growth before an OOM.
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, Default)]
+pub struct MemoryUsage {
+    pub ships: usize,
+    pub serialized_states: usize,
+    pub index: usize,
+    pub query_cache: usize,
+    pub tile_cache: usize,
+    pub strings: usize,
+    pub total: usize,
+}
+
+impl MemoryUsage {
+    pub fn with_total(mut self) -> Self {
+        self.total = self.ships
+            + self.serialized_states
+            + self.index
+            + self.query_cache
+            + self.tile_cache
+            + self.strings;
+        self
+    }
+
+    // Per-component byte counts, for metrics and logs
+    pub fn components(&self) -> [(&'static str, usize); 6] {
+        [
+            ("ships", self.ships),
+            ("serialized_states", self.serialized_states),
+            ("index", self.index),
+            ("query_cache", self.query_cache),
+            ("tile_cache", self.tile_cache),
+            ("strings", self.strings),
+        ]
+    }
+}
+
+// Swiss tables store one control byte per slot next to the entry itself
+pub fn table_bytes<K, V>(capacity: usize) -> usize {
+    capacity * (size_of::<(K, V)>() + 1)
+}
+
+pub fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    table_bytes::<K, V>(map.capacity())
+}
@@ -0,0 +1,205 @@
+use futures_util::{SinkExt, StreamExt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+// A stand-in for aisstream.io's WebSocket API, for tests of `AisStream`
+// and the reconnect loop that need no network or API key. Like the real
+// thing, it wants the subscription as the first (text) message, answers a
+// bad key or subscription with a binary `{"error": ...}` frame and a close,
+// and sends messages as binary JSON frames.
+pub struct MockAisStream {
+    addr: std::net::SocketAddr,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+// What each connection is sent once authenticated
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub api_key: String,
+    pub frames: Vec<Vec<u8>>,
+    pub interval: Duration,
+    pub then: End,
+    // The real server gives up on clients that don't subscribe in 3 seconds
+    pub auth_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum End {
+    // Keep the connection open, sending nothing more
+    Hold,
+    // Send a close frame
+    Close,
+    // Drop the TCP connection without a close frame
+    Drop,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            api_key: "test-key".to_string(),
+            frames: Vec::new(),
+            interval: Duration::ZERO,
+            then: End::Hold,
+            auth_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    connections: AtomicUsize,
+    subscriptions: Mutex<Vec<serde_json::Value>>,
+}
+
+impl MockAisStream {
+    // Listens on an ephemeral port on localhost
+    pub async fn start(script: Script) -> std::io::Result<Self> {
+        Self::bind("127.0.0.1:0", script).await
+    }
+
+    pub async fn bind(addr: &str, script: Script) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        let script = Arc::new(script);
+        let task = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    shared.connections.fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(serve(stream, script.clone(), shared.clone()));
+                }
+            }
+        });
+        Ok(Self { addr, shared, task })
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("ws://{}/v0/stream", self.addr)).unwrap()
+    }
+
+    // Connections accepted so far, including ones that failed to authenticate
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::Relaxed)
+    }
+
+    // The subscription messages received, in order, whether accepted or not
+    pub fn subscriptions(&self) -> Vec<serde_json::Value> {
+        self.shared.subscriptions.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockAisStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(stream: TcpStream, script: Arc<Script>, shared: Arc<Shared>) {
+    let Ok(mut socket) = accept_async(stream).await else {
+        return;
+    };
+
+    let subscription = match tokio::time::timeout(script.auth_timeout, socket.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<serde_json::Value>(&text).ok(),
+        Ok(Some(Ok(_))) => None,
+        // Timed out, or the client went away
+        _ => {
+            let _ = socket.close(None).await;
+            return;
+        }
+    };
+    if let Some(subscription) = &subscription {
+        shared.subscriptions.lock().unwrap().push(subscription.clone());
+    }
+
+    if let Err(error) = check(subscription.as_ref(), &script.api_key) {
+        let error = serde_json::json!({ "error": error }).to_string();
+        let _ = socket.send(Message::Binary(error.into_bytes())).await;
+        let _ = socket.close(None).await;
+        return;
+    }
+
+    for frame in &script.frames {
+        if !script.interval.is_zero() {
+            tokio::time::sleep(script.interval).await;
+        }
+        if socket.send(Message::Binary(frame.clone())).await.is_err() {
+            return;
+        }
+    }
+
+    match script.then {
+        End::Hold => while let Some(Ok(_)) = socket.next().await {},
+        End::Close => {
+            let _ = socket.close(None).await;
+            // Wait for the client's close in reply
+            while let Some(Ok(_)) = socket.next().await {}
+        }
+        End::Drop => {}
+    }
+}
+
+// The errors are aisstream.io's own wording
+fn check(subscription: Option<&serde_json::Value>, api_key: &str) -> Result<(), &'static str> {
+    let subscription = subscription.ok_or("Error parsing subscription message")?;
+    if subscription["APIKey"].as_str() != Some(api_key) {
+        return Err("Api Key Is Not Valid");
+    }
+    let boxes = subscription["BoundingBoxes"].as_array().ok_or("Missing BoundingBoxes")?;
+    if boxes.is_empty() {
+        return Err("Missing BoundingBoxes");
+    }
+    Ok(())
+}
+
+// A position report frame as aisstream.io sends them
+pub fn position_report(mmsi: u32, name: &str, lat: f64, lng: f64) -> Vec<u8> {
+    serde_json::json!({
+        "MessageType": "PositionReport",
+        "MetaData": {"MMSI": mmsi, "ShipName": name, "latitude": lat, "longitude": lng, "time_utc": "2025-06-01 12:00:00.000000000 +0000 UTC"},
+        "Message": {"PositionReport": {"Cog": 92.5, "NavigationalStatus": 0, "Sog": 11.2, "TrueHeading": 91}}
+    })
+    .to_string()
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ais::{AisStream, Subscription};
+
+    #[tokio::test]
+    async fn test_mock_stream() {
+        let frames: Vec<_> = (0..3).map(|i| position_report(244660000 + i, "ALIDA", 51.9, 4.1)).collect();
+        let mock = MockAisStream::start(Script {
+            frames: frames.clone(),
+            then: End::Close,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let subscription = Subscription { mmsi: vec![244660000], ..Default::default() };
+
+        let error = AisStream::connect(mock.url(), "wrong".to_string(), &subscription).await.err().unwrap();
+        assert_eq!(error.to_string(), "Authentication error: Api Key Is Not Valid");
+
+        // The first frame is taken as the go-ahead
+        let mut stream = AisStream::connect(mock.url(), "test-key".to_string(), &subscription).await.unwrap();
+        assert_eq!(stream.next_frame().await.unwrap(), Some(frames[1].clone()));
+        assert_eq!(stream.next_frame().await.unwrap(), Some(frames[2].clone()));
+        assert!(stream.next_frame().await.is_err());
+
+        assert_eq!(mock.connections(), 2);
+        let subscriptions = mock.subscriptions();
+        assert_eq!(subscriptions[1]["APIKey"], "test-key");
+        assert_eq!(subscriptions[1]["FiltersShipMMSI"], serde_json::json!(["244660000"]));
+    }
+}
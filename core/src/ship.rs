@@ -0,0 +1,1157 @@
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+};
+
+use crate::ais::{AisClass, AisMessage, Dimension};
+use crate::index::{
+    geohash, geohash_cell_size, is_valid_position, IndexKind, SpatialIndex, MAX_GEOHASH_PRECISION,
+};
+use crate::intern::{self, intern};
+use crate::memory::{hash_map_bytes, table_bytes, MemoryUsage};
+use crate::tiles::TileCache;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct Ship {
+    pub mmsi: u32,
+    pub name: Arc<str>, // Interned, see `crate::intern`
+    pub lat: f64,
+    pub lng: f64,
+    pub heading: u32,
+    pub speed: f64,
+    pub cog: f64, // Course over ground, degrees; 360 when unknown
+    pub nav_status: u32,
+    pub ship_type: u32,
+    pub destination: Arc<str>, // Interned
+    // The UN/LOCODE `destination` refers to, if it could be resolved; see `crate::locode`
+    #[serde(default)]
+    pub destination_locode: Option<Arc<str>>,
+    #[serde(default)]
+    pub eta: Option<u64>, // Broadcast ETA at `destination`, Unix time
+    pub imo_number: u32,
+    #[serde(default)]
+    pub call_sign: Arc<str>, // Interned; empty while unknown
+    #[serde(default)]
+    pub dimensions: Option<Dimensions>,
+    #[serde(default)]
+    pub draught: Option<f64>, // Metres, as the crew last entered it
+    // From the message types it sends; see `AisClass::of`
+    #[serde(default)]
+    pub class: Option<AisClass>,
+    // The last position report's accuracy flag; see `PositionReport`
+    #[serde(default)]
+    pub position_accuracy: bool,
+    pub last_update: u64,
+}
+
+// Hull size and where the GPS antenna sits on it, in metres, for drawing
+// ships to scale: the reported position is `to_bow` aft of the bow and
+// `to_port` in from the port side
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct Dimensions {
+    pub length: u32,
+    pub beam: u32,
+    pub to_bow: u32,
+    pub to_stern: u32,
+    pub to_port: u32,
+    pub to_starboard: u32,
+}
+
+impl Dimensions {
+    // None unless both the length and the beam were given
+    pub fn from_ais(dimension: Dimension) -> Option<Self> {
+        let Dimension { a, b, c, d } = dimension;
+        if a + b == 0 || c + d == 0 {
+            return None;
+        }
+        Some(Self { length: a + b, beam: c + d, to_bow: a, to_stern: b, to_port: c, to_starboard: d })
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ShipState {
+    pub mmsi: u32,
+    pub name: Arc<str>,
+    pub lat: f64,
+    pub lng: f64,
+    pub heading: u32,
+    pub speed: f64,
+    pub ship_type: u32,
+    // Left out while unknown, to keep the bbox and tile responses small
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub dimensions: Option<Dimensions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub class: Option<AisClass>,
+    pub last_update: u64,
+}
+
+// A ship's `ShipState` pre-serialized as JSON, refreshed on every update so hot
+// endpoints can concatenate buffers instead of serializing thousands of structs
+struct SerializedState {
+    lat: f64,
+    lng: f64,
+    json: Box<[u8]>,
+}
+
+impl SerializedState {
+    fn of(ship: &Ship) -> Self {
+        Self {
+            lat: ship.lat,
+            lng: ship.lng,
+            json: serde_json::to_vec(&ship.to_state()).unwrap_or_default().into(),
+        }
+    }
+}
+
+// The index is partitioned into CELL_SIZE degree cells, each with its own
+// lock, so queries and writes in different oceans never contend and a
+// rebuild only touches the cell that drifted.
+const CELL_SIZE: f64 = 30.0;
+const CELL_COLUMNS: usize = (360.0 / CELL_SIZE) as usize;
+const CELL_ROWS: usize = (180.0 / CELL_SIZE) as usize;
+pub const CELL_COUNT: usize = CELL_ROWS * CELL_COLUMNS;
+
+pub fn cell_of(lat: f64, lng: f64) -> usize {
+    let row = (((lat + 90.0) / CELL_SIZE).floor() as i64).clamp(0, CELL_ROWS as i64 - 1) as usize;
+    let col = (((lng + 180.0) / CELL_SIZE).floor() as i64).clamp(0, CELL_COLUMNS as i64 - 1) as usize;
+    row * CELL_COLUMNS + col
+}
+
+fn cells_in_bbox(sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> impl Iterator<Item = usize> {
+    let (sw, ne) = (cell_of(sw_lat, sw_lng), cell_of(ne_lat, ne_lng));
+    let (min_row, min_col) = (sw / CELL_COLUMNS, sw % CELL_COLUMNS);
+    let (max_row, max_col) = (ne / CELL_COLUMNS, ne % CELL_COLUMNS);
+    (min_row..=max_row).flat_map(move |row| (min_col..=max_col).map(move |col| row * CELL_COLUMNS + col))
+}
+
+// Geohash characters two positions must share for a move to be skipped; the
+// index is off by at most one cell of this size, which queries make up for
+pub const DEFAULT_GEOHASH_PRECISION: usize = 8;
+
+// Where a ship is indexed, and the geohash of the position it was indexed at
+struct Placement {
+    cell: usize,
+    geohash: Option<String>,
+}
+
+// An index write: a new position for a ship, or None to drop it
+type IndexOp = (u32, Option<(f64, f64)>);
+
+// A cell's index plus, while a rebuild is running, the changes applied since
+// it started so they can be replayed onto the new index before it is swapped in.
+struct IndexState {
+    index: Box<dyn SpatialIndex>,
+    rebuild_log: Option<Vec<IndexOp>>,
+}
+
+struct IndexCell {
+    state: RwLock<IndexState>,
+    pending_changes: AtomicUsize, // Index changes since the cell was last rebuilt
+    generation: AtomicU64,        // Bumped after every write to a ship in the cell
+}
+
+// Drop every cached query if clients have asked for this many distinct bboxes
+const MAX_CACHED_QUERIES: usize = 10_000;
+
+// A bbox result, valid as long as none of the cells it covers has been written to
+struct CachedQuery {
+    generations: Vec<u64>,
+    body: Bytes,
+}
+
+// Ships live in a sharded map so ingestion and HTTP readers only contend
+// when they touch the same shard. Index writers first take `placements`,
+// then cell locks, then (never while holding a cell) ship shards.
+pub struct ShipCache {
+    pub ships: DashMap<u32, Ship>,
+    states: DashMap<u32, SerializedState>,
+    index_kind: IndexKind,
+    cells: Vec<IndexCell>,
+    // Cell each indexed ship is in. Also serializes index writers, which are
+    // few (the ingestion batcher and cleanup), so queries only ever wait on
+    // writes to the cells they read.
+    placements: Mutex<HashMap<u32, Placement>>,
+    // 0 to re-index on every move
+    geohash_precision: usize,
+    query_margin: (f64, f64), // Degrees of lat/lng the index may lag behind by
+    queries: DashMap<[u64; 4], CachedQuery>, // Keyed by the bbox's f64 bits
+    pub tiles: TileCache,
+    changed: DashSet<u32>, // Ships changed since the live feed last drained them
+}
+
+impl Ship {
+    pub fn new(mmsi: u32, name: &str) -> Self {
+        Self {
+            mmsi,
+            name: intern(name),
+            lat: 0.0,
+            lng: 0.0,
+            heading: 0,
+            speed: 0.0,
+            cog: 360.0,
+            nav_status: 0,
+            ship_type: 0,
+            destination: intern(""),
+            destination_locode: None,
+            eta: None,
+            imo_number: 0,
+            call_sign: intern(""),
+            dimensions: None,
+            draught: None,
+            class: None,
+            position_accuracy: false,
+            last_update: 0,
+        }
+    }
+
+    // Applies a message received at `timestamp`, as the ingest pipeline does
+    pub fn apply_message(&mut self, message: AisMessage, timestamp: u64) {
+        // Update basic info
+        // Already interned while parsing; usually the very same string the ship has
+        self.name = message.metadata.ship_name;
+        self.lat = message.metadata.latitude;
+        self.lng = message.metadata.longitude;
+        self.last_update = timestamp;
+        if let Some(class) = AisClass::of(&message.message_type) {
+            self.class = Some(class);
+        }
+
+        // Update type-specific data
+        match message.message_type.as_str() {
+            "PositionReport" | "StandardClassBPositionReport" | "ExtendedClassBPositionReport" => {
+                if let Some(pos_report) = message.message.position_report {
+                    self.heading = pos_report.true_heading;
+                    self.speed = pos_report.sog;
+                    self.cog = pos_report.cog;
+                    self.nav_status = pos_report.navigational_status;
+                    self.position_accuracy = pos_report.position_accuracy;
+                }
+            }
+            "ShipStaticData" => {
+                if let Some(static_data) = message.message.ship_static_data {
+                    self.ship_type = static_data.ship_type;
+                    self.destination = static_data.destination;
+                    self.imo_number = static_data.imo_number;
+                    self.call_sign = static_data.call_sign;
+                    self.eta = static_data.eta.and_then(|eta| eta.resolve(timestamp));
+                    // Kept from earlier messages if this one lacks them
+                    if let Some(dimensions) = static_data.dimension.and_then(Dimensions::from_ais) {
+                        self.dimensions = Some(dimensions);
+                    }
+                    if let Some(draught) = static_data.draught.filter(|draught| *draught > 0.0) {
+                        self.draught = Some(draught);
+                    }
+                }
+            }
+            // Class B static data, in two parts: the name, taken from the
+            // metadata above, then the type, call sign and dimensions
+            "StaticDataReport" => {
+                if let Some(static_data) = message.message.ship_static_data {
+                    self.ship_type = static_data.ship_type;
+                    self.call_sign = static_data.call_sign;
+                    if let Some(dimensions) = static_data.dimension.and_then(Dimensions::from_ais) {
+                        self.dimensions = Some(dimensions);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn to_state(&self) -> ShipState {
+        ShipState {
+            mmsi: self.mmsi,
+            name: self.name.clone(),
+            lat: self.lat,
+            lng: self.lng,
+            heading: self.heading,
+            speed: self.speed,
+            ship_type: self.ship_type,
+            dimensions: self.dimensions,
+            class: self.class,
+            last_update: self.last_update,
+        }
+    }
+}
+
+impl Default for ShipCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShipCache {
+    pub fn new() -> Self {
+        Self::with_index(IndexKind::default())
+    }
+
+    pub fn with_index(index_kind: IndexKind) -> Self {
+        Self {
+            ships: DashMap::new(),
+            states: DashMap::new(),
+            index_kind,
+            cells: (0..CELL_COUNT)
+                .map(|_| IndexCell {
+                    state: RwLock::new(IndexState {
+                        index: index_kind.build(Vec::new()),
+                        rebuild_log: None,
+                    }),
+                    pending_changes: AtomicUsize::new(0),
+                    generation: AtomicU64::new(0),
+                })
+                .collect(),
+            placements: Mutex::new(HashMap::new()),
+            geohash_precision: 0,
+            query_margin: (0.0, 0.0),
+            queries: DashMap::new(),
+            tiles: TileCache::new(),
+            changed: DashSet::new(),
+        }
+    }
+
+    // Only move a ship in the index once it leaves the geohash cell it was
+    // indexed in, so anchored vessels swinging on their chain don't churn it
+    pub fn with_geohash_precision(mut self, precision: usize) -> Self {
+        self.geohash_precision = precision.min(MAX_GEOHASH_PRECISION);
+        self.query_margin = match self.geohash_precision {
+            0 => (0.0, 0.0),
+            precision => geohash_cell_size(precision),
+        };
+        self
+    }
+
+    pub fn pending_changes(&self) -> usize {
+        self.cells
+            .iter()
+            .map(|cell| cell.pending_changes.load(Ordering::Acquire))
+            .sum()
+    }
+
+    // Cells whose index has drifted far enough from a fresh build to rebuild
+    pub fn cells_needing_rebuild(&self) -> Vec<usize> {
+        (0..CELL_COUNT)
+            .filter(|&cell| {
+                let cell = &self.cells[cell];
+                let changes = cell.pending_changes.load(Ordering::Acquire);
+                changes > 0 && cell.state.read().unwrap().index.needs_rebuild(changes)
+            })
+            .collect()
+    }
+
+    pub fn index_needs_rebuild(&self) -> bool {
+        !self.cells_needing_rebuild().is_empty()
+    }
+
+    // Bring the index entries for `mmsis` in line with the ships' current
+    // positions, taking each touched cell's lock once for the whole batch
+    fn reindex(&self, mmsis: &[u32]) {
+        let mut placements = self.placements.lock().unwrap();
+
+        let mut ops: HashMap<usize, Vec<IndexOp>> = HashMap::new();
+        for &mmsi in mmsis {
+            let position = self
+                .ships
+                .get(&mmsi)
+                .map(|ship| (ship.lat, ship.lng))
+                .filter(|&(lat, lng)| is_valid_position(lat, lng));
+
+            let Some((lat, lng)) = position else {
+                if let Some(previous) = placements.remove(&mmsi) {
+                    ops.entry(previous.cell).or_default().push((mmsi, None));
+                }
+                continue;
+            };
+            let cell = cell_of(lat, lng);
+            let geohash = (self.geohash_precision > 0)
+                .then(|| geohash(lat, lng, self.geohash_precision))
+                .flatten();
+
+            let previous = placements.get(&mmsi);
+            if let Some(previous) = previous
+                && previous.cell == cell
+                && geohash.is_some()
+                && previous.geohash == geohash
+            {
+                // Still in the cell it was indexed in; only cached results go stale
+                ops.entry(cell).or_default();
+                continue;
+            }
+            if let Some(previous) = previous.filter(|previous| previous.cell != cell) {
+                ops.entry(previous.cell).or_default().push((mmsi, None));
+            }
+            ops.entry(cell).or_default().push((mmsi, position));
+            placements.insert(mmsi, Placement { cell, geohash });
+        }
+
+        for (cell, ops) in ops {
+            let cell = &self.cells[cell];
+            let mut state = cell.state.write().unwrap();
+            let mut changes = 0;
+            for (mmsi, position) in ops {
+                let changed = match position {
+                    Some((lat, lng)) => state.index.upsert(mmsi, lat, lng),
+                    None => state.index.remove(mmsi),
+                };
+                if changed {
+                    changes += 1;
+                    if let Some(ref mut log) = state.rebuild_log {
+                        log.push((mmsi, position));
+                    }
+                }
+            }
+            cell.pending_changes.fetch_add(changes, Ordering::Release);
+            // Even without a move the ship's state changed, so cached results are stale
+            cell.generation.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    // Queue ships for the next live feed diff
+    fn mark_changed(&self, mmsis: &[u32]) {
+        for &mmsi in mmsis {
+            self.changed.insert(mmsi);
+        }
+    }
+
+    // Take the ships changed since the last call
+    pub fn drain_changed(&self) -> Vec<u32> {
+        let mut drained = Vec::new();
+        self.changed.retain(|&mmsi| {
+            drained.push(mmsi);
+            false
+        });
+        drained
+    }
+
+    // Drop cached tiles showing a ship that changed. Called once the change is
+    // visible in both the map and the index.
+    fn invalidate_tiles(&self, before: Option<(f64, f64)>, after: Option<(f64, f64)>) {
+        if let Some((lat, lng)) = before {
+            self.tiles.invalidate(lat, lng);
+        }
+        if let Some((lat, lng)) = after.filter(|&after| Some(after) != before) {
+            self.tiles.invalidate(lat, lng);
+        }
+    }
+
+    pub fn insert_ship(&self, mmsi: u32, ship: Ship) {
+        let after = (ship.lat, ship.lng);
+        self.states.insert(mmsi, SerializedState::of(&ship));
+        let before = self.ships.insert(mmsi, ship).map(|old| (old.lat, old.lng));
+        self.reindex(&[mmsi]);
+        self.mark_changed(&[mmsi]);
+        self.invalidate_tiles(before, Some(after));
+    }
+
+    // Apply an in-place update to a ship, creating it if we haven't seen it yet
+    pub fn update_ship<F: FnOnce(&mut Ship)>(&self, mmsi: u32, update: F) {
+        let (before, after) = {
+            let mut ship = self
+                .ships
+                .entry(mmsi)
+                .or_insert_with(|| Ship::new(mmsi, ""));
+            let before = (ship.lat, ship.lng);
+            update(&mut ship);
+            // Refreshed while the ship's shard is still locked, so racing updates can't reorder
+            self.states.insert(mmsi, SerializedState::of(&ship));
+            (before, (ship.lat, ship.lng))
+        };
+        self.reindex(&[mmsi]);
+        self.mark_changed(&[mmsi]);
+        self.invalidate_tiles(Some(before), Some(after));
+    }
+
+    // Apply a batch of in-place updates, taking the index lock once for the
+    // whole batch instead of once per update
+    pub fn update_ships<I, F>(&self, updates: I)
+    where
+        I: IntoIterator<Item = (u32, F)>,
+        F: FnOnce(&mut Ship),
+    {
+        let mut mmsis = Vec::new();
+        let mut moves = Vec::new();
+        for (mmsi, update) in updates {
+            let mut ship = self
+                .ships
+                .entry(mmsi)
+                .or_insert_with(|| Ship::new(mmsi, ""));
+            let before = (ship.lat, ship.lng);
+            update(&mut ship);
+            self.states.insert(mmsi, SerializedState::of(&ship));
+            mmsis.push(mmsi);
+            moves.push((before, (ship.lat, ship.lng)));
+        }
+        mmsis.sort_unstable();
+        mmsis.dedup();
+
+        self.reindex(&mmsis);
+        self.mark_changed(&mmsis);
+        for (before, after) in moves {
+            self.invalidate_tiles(Some(before), Some(after));
+        }
+    }
+
+    pub fn remove_ship(&self, mmsi: u32) -> Option<Ship> {
+        let result = self.ships.remove(&mmsi).map(|(_, ship)| ship);
+        self.states.remove(&mmsi);
+        if let Some(ref ship) = result {
+            self.reindex(&[mmsi]);
+            self.mark_changed(&[mmsi]);
+            self.invalidate_tiles(Some((ship.lat, ship.lng)), None);
+        }
+        result
+    }
+
+    // Remove ships last updated before `cutoff`, returning how many were dropped
+    pub fn remove_stale(&self, cutoff: u64) -> usize {
+        let mut removed = Vec::new();
+        self.ships.retain(|&mmsi, ship| {
+            let keep = ship.last_update >= cutoff;
+            if !keep {
+                removed.push((mmsi, ship.lat, ship.lng));
+            }
+            keep
+        });
+        let mmsis: Vec<u32> = removed.iter().map(|&(mmsi, _, _)| mmsi).collect();
+        for mmsi in &mmsis {
+            self.states.remove(mmsi);
+        }
+        self.reindex(&mmsis);
+        self.mark_changed(&mmsis);
+        for (_, lat, lng) in &removed {
+            self.invalidate_tiles(Some((*lat, *lng)), None);
+        }
+        removed.len()
+    }
+
+    // Build a fresh, balanced index for one cell and swap it in. Queries and
+    // updates keep using the current index meanwhile; this is expensive and
+    // meant to run off the request path (see `index_rebuild_task`).
+    pub fn rebuild_cell(&self, cell: usize) {
+        let cell = &self.cells[cell];
+        let points = {
+            let mut state = cell.state.write().unwrap();
+            state.rebuild_log = Some(Vec::new());
+            state.index.points()
+        };
+        let mut rebuilt = self.index_kind.build(points);
+
+        let mut state = cell.state.write().unwrap();
+        for (mmsi, position) in state.rebuild_log.take().unwrap_or_default() {
+            match position {
+                Some((lat, lng)) => rebuilt.upsert(mmsi, lat, lng),
+                None => rebuilt.remove(mmsi),
+            };
+        }
+        state.index = rebuilt;
+        cell.pending_changes.store(0, Ordering::Release);
+    }
+
+    pub fn rebuild_index(&self) {
+        for cell in 0..CELL_COUNT {
+            self.rebuild_cell(cell);
+        }
+    }
+
+    // Candidates for a bbox query, widened by however far indexed positions
+    // may lag; callers re-check current positions
+    fn query_index(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Vec<u32> {
+        let (lat_margin, lng_margin) = self.query_margin;
+        let (sw_lat, sw_lng) = (sw_lat - lat_margin, sw_lng - lng_margin);
+        let (ne_lat, ne_lng) = (ne_lat + lat_margin, ne_lng + lng_margin);
+
+        let mut mmsis = Vec::new();
+        for cell in cells_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng) {
+            let state = self.cells[cell].state.read().unwrap();
+            mmsis.extend(state.index.range_query(sw_lat, sw_lng, ne_lat, ne_lng));
+        }
+        mmsis
+    }
+
+    pub fn get_ships_in_bbox(
+        &self,
+        sw_lat: f64,
+        sw_lng: f64,
+        ne_lat: f64,
+        ne_lng: f64,
+    ) -> Vec<ShipState> {
+        // Use the spatial index for fast query, then convert MMSIs to ShipStates,
+        // re-checking positions in case a ship moved after the query but before
+        // its index entry was updated
+        self.query_index(sw_lat, sw_lng, ne_lat, ne_lng)
+            .into_iter()
+            .filter_map(|mmsi| self.ships.get(&mmsi))
+            .filter(|ship| {
+                ship.lat >= sw_lat && ship.lat <= ne_lat && ship.lng >= sw_lng && ship.lng <= ne_lng
+            })
+            .map(|ship| ship.to_state())
+            .collect()
+    }
+
+    // Full records of the ships in a bbox, for analyses that need more than `ShipState`
+    pub fn get_full_ships_in_bbox(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Vec<Ship> {
+        self.query_index(sw_lat, sw_lng, ne_lat, ne_lng)
+            .into_iter()
+            .filter_map(|mmsi| self.ships.get(&mmsi))
+            .filter(|ship| {
+                ship.lat >= sw_lat && ship.lat <= ne_lat && ship.lng >= sw_lng && ship.lng <= ne_lng
+            })
+            .map(|ship| ship.clone())
+            .collect()
+    }
+
+    // Same as `get_ships_in_bbox`, but as a JSON array built from the
+    // pre-serialized states
+    pub fn get_ships_in_bbox_json(
+        &self,
+        sw_lat: f64,
+        sw_lng: f64,
+        ne_lat: f64,
+        ne_lng: f64,
+    ) -> Vec<u8> {
+        let mmsis = self.query_index(sw_lat, sw_lng, ne_lat, ne_lng);
+
+        let mut body = Vec::with_capacity(2 + mmsis.len() * 192);
+        body.push(b'[');
+        for mmsi in mmsis {
+            let Some(state) = self.states.get(&mmsi) else {
+                continue;
+            };
+            if state.lat >= sw_lat && state.lat <= ne_lat && state.lng >= sw_lng && state.lng <= ne_lng {
+                if body.len() > 1 {
+                    body.push(b',');
+                }
+                body.extend_from_slice(&state.json);
+            }
+        }
+        body.push(b']');
+        body
+    }
+
+    // `get_ships_in_bbox_json`, served from cache while none of the cells the
+    // bbox covers has changed. Map clients tend to poll the same bbox over and over.
+    pub fn get_ships_in_bbox_cached(
+        &self,
+        sw_lat: f64,
+        sw_lng: f64,
+        ne_lat: f64,
+        ne_lng: f64,
+    ) -> Bytes {
+        let key = [sw_lat, sw_lng, ne_lat, ne_lng].map(f64::to_bits);
+        // Read before querying, so a write racing the query leaves the entry stale
+        let generations: Vec<u64> = cells_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng)
+            .map(|cell| self.cells[cell].generation.load(Ordering::Acquire))
+            .collect();
+        if let Some(cached) = self.queries.get(&key)
+            && cached.generations == generations
+        {
+            return cached.body.clone();
+        }
+
+        let body = Bytes::from(self.get_ships_in_bbox_json(sw_lat, sw_lng, ne_lat, ne_lng));
+        if self.queries.len() >= MAX_CACHED_QUERIES {
+            self.queries.clear();
+        }
+        self.queries.insert(key, CachedQuery { generations, body: body.clone() });
+        body
+    }
+
+    fn placements_memory_bytes(&self) -> usize {
+        let placements = self.placements.lock().unwrap();
+        let geohashes: usize = placements
+            .values()
+            .filter_map(|placement| placement.geohash.as_ref().map(String::capacity))
+            .sum();
+        geohashes + hash_map_bytes(&placements)
+    }
+
+    // Approximate memory held by the cache. Walks every serialized state and
+    // cached result, so it is meant for periodic stats rather than hot paths.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let states: usize = self.states.iter().map(|state| state.json.len()).sum();
+        let queries: usize = self
+            .queries
+            .iter()
+            .map(|query| query.body.len() + query.generations.len() * 8)
+            .sum();
+        let index: usize = self
+            .cells
+            .iter()
+            .map(|cell| cell.state.read().unwrap().index.memory_bytes())
+            .sum();
+
+        MemoryUsage {
+            ships: table_bytes::<u32, Ship>(self.ships.capacity()),
+            serialized_states: states + table_bytes::<u32, SerializedState>(self.states.capacity()),
+            index: index + self.placements_memory_bytes(),
+            query_cache: queries + table_bytes::<[u64; 4], CachedQuery>(self.queries.capacity()),
+            tile_cache: self.tiles.memory_bytes(),
+            strings: intern::memory_bytes(),
+            total: 0,
+        }
+        .with_total()
+    }
+
+    // Linear scan over all ships, bypassing the index
+    pub fn get_ships_in_bbox_linear(
+        &self,
+        sw_lat: f64,
+        sw_lng: f64,
+        ne_lat: f64,
+        ne_lng: f64,
+    ) -> Vec<ShipState> {
+        let mut result = Vec::new();
+        for ship in self.ships.iter() {
+            if ship.lat >= sw_lat
+                && ship.lat <= ne_lat
+                && ship.lng >= sw_lng
+                && ship.lng <= ne_lng
+                && is_valid_position(ship.lat, ship.lng)
+            {
+                result.push(ship.to_state());
+            }
+        }
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.ships.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ships.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn create_test_ship(mmsi: u32, name: &str, lat: f64, lng: f64) -> Ship {
+        Ship {
+            mmsi,
+            name: intern(name),
+            lat,
+            lng,
+            heading: 0,
+            speed: 0.0,
+            cog: 360.0,
+            nav_status: 0,
+            ship_type: 0,
+            destination: intern(""),
+            destination_locode: None,
+            eta: None,
+            imo_number: 0,
+            call_sign: intern(""),
+            dimensions: None,
+            draught: None,
+            class: None,
+            position_accuracy: false,
+            last_update: 0,
+        }
+    }
+
+    fn create_test_cache() -> ShipCache {
+        let cache = ShipCache::new();
+
+        cache.insert_ship(1, create_test_ship(1, "NYC Ship", 40.7128, -74.0060));
+        cache.insert_ship(2, create_test_ship(2, "London Ship", 51.5074, -0.1278));
+        cache.insert_ship(3, create_test_ship(3, "Tokyo Ship", 35.6762, 139.6503));
+        cache.insert_ship(4, create_test_ship(4, "Sydney Ship", -33.8688, 151.2093));
+        cache.insert_ship(5, create_test_ship(5, "Invalid Ship", 0.0, 0.0));
+        cache.insert_ship(6, create_test_ship(6, "Near NYC", 40.7500, -73.9000));
+
+        cache
+    }
+
+    #[test]
+    fn test_static_data_dimensions() {
+        let static_data = |dimension| {
+            let frame = serde_json::json!({
+                "MessageType": "ShipStaticData",
+                "MetaData": {"MMSI": 244660000, "ShipName": "ALIDA", "latitude": 51.9, "longitude": 4.1, "time_utc": ""},
+                "Message": {"ShipStaticData": {"Type": 70, "Destination": "NLRTM", "ImoNumber": 9074729, "Dimension": dimension}}
+            });
+            serde_json::from_value::<AisMessage>(frame).unwrap()
+        };
+        let mut ship = Ship::new(244660000, "ALIDA");
+        ship.apply_message(static_data(serde_json::json!({"A": 150, "B": 30, "C": 12, "D": 16})), 1);
+        let dimensions = Dimensions { length: 180, beam: 28, to_bow: 150, to_stern: 30, to_port: 12, to_starboard: 16 };
+        assert_eq!(ship.dimensions, Some(dimensions));
+        assert_eq!(serde_json::to_value(ship.to_state()).unwrap()["dimensions"]["length"], 180);
+
+        // "Not available" leaves what was known
+        ship.apply_message(static_data(serde_json::json!({"A": 0, "B": 0, "C": 0, "D": 0})), 2);
+        assert_eq!(ship.dimensions, Some(dimensions));
+        assert!(serde_json::to_value(Ship::new(1, "").to_state()).unwrap().get("dimensions").is_none());
+    }
+
+    #[test]
+    fn test_class_follows_message_types() {
+        let message = |message_type: &str| {
+            let frame = serde_json::json!({
+                "MessageType": message_type,
+                "MetaData": {"MMSI": 235012345, "ShipName": "SEA BREEZE", "latitude": 50.8, "longitude": -1.3, "time_utc": ""},
+                "Message": {}
+            });
+            serde_json::from_value::<AisMessage>(frame).unwrap()
+        };
+        let mut ship = Ship::new(235012345, "SEA BREEZE");
+        assert!(serde_json::to_value(ship.to_state()).unwrap().get("class").is_none());
+        ship.apply_message(message("StandardClassBPositionReport"), 1);
+        assert_eq!(ship.class, Some(AisClass::B));
+        // Safety broadcasts come from either kind, so say nothing
+        ship.apply_message(message("SafetyBroadcastMessage"), 2);
+        assert_eq!(serde_json::to_value(ship.to_state()).unwrap()["class"], "B");
+        ship.apply_message(message("PositionReport"), 3);
+        assert_eq!(ship.class, Some(AisClass::A));
+    }
+
+    #[test]
+    fn test_kdtree_correctness() {
+        let cache = create_test_cache();
+
+        // Test NYC area
+        let result = cache.get_ships_in_bbox(40.5, -74.5, 41.0, -73.5);
+        assert_eq!(result.len(), 2);
+        let mmsis: Vec<u32> = result.iter().map(|s| s.mmsi).collect();
+        assert!(mmsis.contains(&1));
+        assert!(mmsis.contains(&6));
+    }
+
+    #[test]
+    fn test_kdtree_vs_linear_performance() {
+        let cache = ShipCache::new();
+
+        // Create 50,000 ships for meaningful comparison
+        for i in 0..50_000 {
+            let lat = ((i * 7) % 180) as f64 - 90.0;
+            let lng = ((i * 11) % 360) as f64 - 180.0;
+            if lat != 0.0 || lng != 0.0 {
+                let name = format!("Ship_{}", i);
+                cache.insert_ship(i, create_test_ship(i, &name, lat, lng));
+            }
+        }
+
+        println!("Created {} ships in cache", cache.len());
+
+        // Test KD-tree performance (with rebuild)
+        let start = Instant::now();
+        cache.rebuild_index();
+        let kdtree_result = cache.get_ships_in_bbox(40.0, -75.0, 41.0, -73.0);
+        let kdtree_duration = start.elapsed();
+
+        // Test linear search performance
+        let start = Instant::now();
+        let linear_result = cache.get_ships_in_bbox_linear(40.0, -75.0, 41.0, -73.0);
+        let linear_duration = start.elapsed();
+
+        println!("KD-tree query time: {:?}", kdtree_duration);
+        println!("Linear search time: {:?}", linear_duration);
+        println!("KD-tree found {} ships", kdtree_result.len());
+        println!("Linear search found {} ships", linear_result.len());
+
+        // Results should be the same
+        assert_eq!(kdtree_result.len(), linear_result.len());
+
+        if linear_duration > Duration::from_millis(1) {
+            println!(
+                "Speedup: {:.2}x",
+                linear_duration.as_nanos() as f64 / kdtree_duration.as_nanos() as f64
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiple_queries_performance() {
+        let cache = ShipCache::new();
+
+        // Create test dataset
+        for i in 0..100_000 {
+            let lat = ((i * 7) % 180) as f64 - 90.0;
+            let lng = ((i * 11) % 360) as f64 - 180.0;
+            if lat != 0.0 || lng != 0.0 {
+                let name = format!("Ship_{}", i);
+                cache.insert_ship(i, create_test_ship(i, &name, lat, lng));
+            }
+        }
+
+        println!("Testing with {} ships", cache.len());
+
+        // Build index once
+        cache.rebuild_index();
+
+        // Multiple queries (index already built)
+        let queries = vec![
+            (40.0, -75.0, 41.0, -73.0),   // NYC area
+            (51.0, -1.0, 52.0, 1.0),      // London area
+            (35.0, 139.0, 36.0, 140.0),   // Tokyo area
+            (-34.0, 151.0, -33.0, 152.0), // Sydney area
+        ];
+
+        let start = Instant::now();
+        let mut total_results = 0;
+        for _ in 0..100000 {
+            for (sw_lat, sw_lng, ne_lat, ne_lng) in &queries {
+                let result = cache.get_ships_in_bbox(*sw_lat, *sw_lng, *ne_lat, *ne_lng);
+                total_results += result.len();
+            }
+        }
+
+        let kdtree_duration = start.elapsed();
+
+        println!(
+            "KD-tree: {} queries in {:?} (avg: {:?})",
+            queries.len() * 10000,
+            kdtree_duration,
+            kdtree_duration / queries.len() as u32
+        );
+        println!("Total results: {}", total_results);
+
+        // KD-tree should be very fast for subsequent queries
+        assert!(kdtree_duration < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_index_rebuild_on_updates() {
+        let cache = ShipCache::new();
+
+        // Add initial ships
+        cache.insert_ship(1, create_test_ship(1, "Ship1", 40.0, -74.0));
+        cache.insert_ship(2, create_test_ship(2, "Ship2", 41.0, -73.0));
+
+        // Build index and query
+        cache.rebuild_index();
+        let result1 = cache.get_ships_in_bbox(39.0, -75.0, 42.0, -72.0);
+        assert_eq!(result1.len(), 2);
+
+        // Add more ships
+        cache.insert_ship(3, create_test_ship(3, "Ship3", 40.5, -73.5));
+        assert_eq!(cache.pending_changes(), 1);
+
+        // Index should be rebuilt and include new ship
+        cache.rebuild_index();
+        let result2 = cache.get_ships_in_bbox(39.0, -75.0, 42.0, -72.0);
+        assert_eq!(result2.len(), 3);
+    }
+
+    #[test]
+    fn test_incremental_index_updates() {
+        let cache = ShipCache::new();
+        cache.insert_ship(1, create_test_ship(1, "Ship1", 40.0, -74.0));
+        cache.insert_ship(2, create_test_ship(2, "Ship2", 41.0, -73.0));
+        cache.rebuild_index();
+
+        // Ship 1 sails out of the box and a new ship appears, without a rebuild
+        cache.update_ship(1, |ship| ship.lat = 10.0);
+        cache.insert_ship(3, create_test_ship(3, "Ship3", 40.5, -73.5));
+        cache.remove_ship(2);
+
+        let mmsis: Vec<u32> = cache
+            .get_ships_in_bbox(39.0, -75.0, 42.0, -72.0)
+            .iter()
+            .map(|s| s.mmsi)
+            .collect();
+        assert_eq!(mmsis, vec![3]);
+        assert_eq!(cache.get_ships_in_bbox(9.0, -75.0, 11.0, -73.0).len(), 1);
+        // Ship 1 changed cells, which counts against both of them
+        assert_eq!(cache.pending_changes(), 4);
+
+        // Unchanged positions don't touch the index
+        cache.update_ship(3, |ship| ship.speed = 12.0);
+        assert_eq!(cache.pending_changes(), 4);
+    }
+
+    #[test]
+    fn test_bbox_json_matches_serialized_states() {
+        let cache = create_test_cache();
+        cache.update_ship(6, |ship| ship.speed = 14.5);
+
+        let json = cache.get_ships_in_bbox_json(40.5, -74.5, 41.0, -73.5);
+        let mut parsed: Vec<serde_json::Value> = serde_json::from_slice(&json).unwrap();
+        parsed.sort_by_key(|ship| ship["mmsi"].as_u64());
+
+        let mut expected = cache.get_ships_in_bbox(40.5, -74.5, 41.0, -73.5);
+        expected.sort_by_key(|ship| ship.mmsi);
+        assert_eq!(parsed, serde_json::to_value(&expected).unwrap().as_array().unwrap().clone());
+        assert_eq!(parsed[1]["speed"], 14.5);
+
+        cache.remove_ship(1);
+        cache.remove_ship(6);
+        assert_eq!(cache.get_ships_in_bbox_json(40.5, -74.5, 41.0, -73.5), b"[]");
+    }
+
+    #[test]
+    fn test_batched_updates() {
+        let cache = ShipCache::new();
+        cache.rebuild_index();
+
+        let updates = (1..=100u32).map(|mmsi| {
+            (mmsi, move |ship: &mut Ship| {
+                ship.lat = 50.0 + mmsi as f64 * 0.01;
+                ship.lng = 1.0;
+            })
+        });
+        cache.update_ships(updates);
+        // The same ship twice in one batch: the last update wins
+        cache.update_ships([60.0, 51.5].map(|lat| (1, move |ship: &mut Ship| ship.lat = lat)));
+
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.get_ships_in_bbox(50.0, 0.0, 51.0, 2.0).len(), 99);
+        assert_eq!(cache.get_ships_in_bbox(51.4, 0.0, 51.6, 2.0).len(), 1);
+        assert_eq!(cache.drain_changed().len(), 100);
+    }
+
+    #[test]
+    fn test_cells_partition_the_index() {
+        let cache = create_test_cache();
+        cache.rebuild_index();
+        assert_eq!(cells_in_bbox(-90.0, -180.0, 90.0, 180.0).count(), CELL_COUNT);
+
+        // Rebuilding only the dirty cell leaves the others' changes pending
+        let (atlantic, pacific) = (cell_of(40.75, -73.9), cell_of(35.6762, 139.6503));
+        cache.update_ship(6, |ship| ship.lat = 40.8);
+        cache.update_ship(3, |ship| ship.lat = 35.7);
+        cache.rebuild_cell(atlantic);
+        assert_eq!(cache.cells[atlantic].pending_changes.load(Ordering::Acquire), 0);
+        assert_eq!(cache.cells[pacific].pending_changes.load(Ordering::Acquire), 1);
+
+        // A ship sailing across a cell boundary is only found where it is now
+        cache.update_ship(1, |ship| ship.lng = -59.0);
+        assert_ne!(cell_of(40.7128, -59.0), atlantic);
+        assert!(cache.get_ships_in_bbox(40.0, -75.0, 41.0, -73.0).iter().all(|s| s.mmsi != 1));
+        assert_eq!(cache.get_ships_in_bbox(40.0, -61.0, 41.0, -58.0).len(), 1);
+        assert_eq!(cache.get_ships_in_bbox(-90.0, -180.0, 90.0, 180.0).len(), 5);
+    }
+
+    #[test]
+    fn test_query_cache_follows_cell_generations() {
+        let cache = create_test_cache();
+        let nyc = cache.get_ships_in_bbox_cached(40.5, -74.5, 41.0, -73.5);
+        assert_eq!(nyc, cache.get_ships_in_bbox_json(40.5, -74.5, 41.0, -73.5));
+
+        // Nothing changed in view (or only elsewhere): the same buffer again
+        cache.update_ship(3, |ship| ship.speed = 9.0);
+        let again = cache.get_ships_in_bbox_cached(40.5, -74.5, 41.0, -73.5);
+        assert_eq!(again.as_ptr(), nyc.as_ptr());
+
+        // Any write in view, moving or not, is picked up
+        cache.update_ship(6, |ship| ship.speed = 14.5);
+        let updated = cache.get_ships_in_bbox_cached(40.5, -74.5, 41.0, -73.5);
+        assert_ne!(updated.as_ptr(), nyc.as_ptr());
+        assert!(std::str::from_utf8(&updated).unwrap().contains("14.5"));
+    }
+
+    #[test]
+    fn test_memory_usage_grows_with_ships() {
+        let cache = ShipCache::new();
+        let empty = cache.memory_usage();
+
+        for i in 1..=1000 {
+            cache.insert_ship(i, create_test_ship(i, "Ship", 50.0 + i as f64 * 0.001, 1.0));
+        }
+        let usage = cache.memory_usage();
+        assert!(usage.ships >= 1000 * std::mem::size_of::<Ship>());
+        assert!(usage.serialized_states > empty.serialized_states + 1000 * 50);
+        assert!(usage.index > empty.index);
+        assert_eq!(usage.total, usage.components().iter().map(|&(_, bytes)| bytes).sum::<usize>());
+    }
+
+    #[test]
+    fn test_geohash_precision_skips_small_moves() {
+        let cache = ShipCache::new().with_geohash_precision(7);
+        cache.insert_ship(1, create_test_ship(1, "Anchored", 51.95, 4.05));
+        let pending = cache.pending_changes();
+
+        // Swinging at anchor stays inside one ~150m geohash cell
+        for step in 1..=20 {
+            cache.update_ship(1, |ship| ship.lng = 4.05 + step as f64 * 0.00001);
+        }
+        assert_eq!(cache.pending_changes(), pending);
+
+        // Queries still see the exact current position, however stale the index entry
+        let lng = 4.05 + 20.0 * 0.00001;
+        assert_eq!(cache.get_ships_in_bbox(51.9, lng, 52.0, 4.1).len(), 1);
+        assert!(cache.get_ships_in_bbox(51.9, lng + 0.000001, 52.0, 4.1).is_empty());
+        assert!(cache.get_ships_in_bbox(51.9, 4.0, 52.0, 4.05).is_empty());
+
+        // Weighing anchor moves it in the index
+        cache.update_ship(1, |ship| ship.lng = 4.2);
+        assert_eq!(cache.pending_changes(), pending + 1);
+    }
+
+    fn assert_incremental_index_matches_linear_scan(index_kind: IndexKind) {
+        let cache = ShipCache::with_index(index_kind);
+        for i in 1..2_000 {
+            let lat = ((i * 7) % 160) as f64 - 80.0;
+            let lng = ((i * 13) % 340) as f64 - 170.0;
+            cache.insert_ship(i, create_test_ship(i, "Ship", lat, lng));
+        }
+        cache.rebuild_index();
+
+        // Move every other ship and drop every tenth
+        for i in (1..2_000).step_by(2) {
+            cache.update_ship(i, |ship| {
+                ship.lat = (ship.lat + 3.5).min(89.0);
+                ship.lng = (ship.lng - 2.25).max(-179.0);
+            });
+        }
+        for i in (1..2_000).step_by(10) {
+            cache.remove_ship(i);
+        }
+
+        let mut indexed: Vec<u32> = cache
+            .get_ships_in_bbox(-20.0, -60.0, 30.0, 45.0)
+            .iter()
+            .map(|s| s.mmsi)
+            .collect();
+        let mut linear: Vec<u32> = cache
+            .get_ships_in_bbox_linear(-20.0, -60.0, 30.0, 45.0)
+            .iter()
+            .map(|s| s.mmsi)
+            .collect();
+        indexed.sort_unstable();
+        linear.sort_unstable();
+        assert!(!linear.is_empty());
+        assert_eq!(indexed, linear);
+    }
+
+    #[test]
+    fn test_incremental_kdtree_matches_linear_scan() {
+        assert_incremental_index_matches_linear_scan(IndexKind::KdTree);
+    }
+
+    #[test]
+    fn test_incremental_rtree_matches_linear_scan() {
+        assert_incremental_index_matches_linear_scan(IndexKind::RTree);
+    }
+
+    #[test]
+    fn test_rtree_correctness() {
+        let cache = ShipCache::with_index(IndexKind::RTree);
+        cache.insert_ship(1, create_test_ship(1, "NYC Ship", 40.7128, -74.0060));
+        cache.insert_ship(2, create_test_ship(2, "Invalid Ship", 0.0, 0.0));
+        cache.insert_ship(3, create_test_ship(3, "Near NYC", 40.7500, -73.9000));
+        cache.rebuild_index();
+        cache.insert_ship(4, create_test_ship(4, "London Ship", 51.5074, -0.1278));
+
+        let mut mmsis: Vec<u32> = cache
+            .get_ships_in_bbox(40.5, -74.5, 41.0, -73.5)
+            .iter()
+            .map(|s| s.mmsi)
+            .collect();
+        mmsis.sort_unstable();
+        assert_eq!(mmsis, vec![1, 3]);
+        assert!(!cache.index_needs_rebuild());
+    }
+}
@@ -0,0 +1,167 @@
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::f64::consts::PI;
+
+use crate::memory::table_bytes;
+
+// Deepest zoom whose tiles are cached; deeper tiles are small enough to query directly
+pub const MAX_CACHED_ZOOM: u8 = 12;
+// Deepest zoom served at all
+pub const MAX_ZOOM: u8 = 18;
+// Drop everything if clients have asked for this many distinct tiles
+const MAX_CACHED_TILES: usize = 50_000;
+
+// Web mercator latitude limit, where tiles end
+const MAX_LAT: f64 = 85.051_128_779_806_59;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tile {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Tile {
+    pub fn new(z: u8, x: u32, y: u32) -> Option<Self> {
+        let n = 1u64 << z;
+        (z <= MAX_ZOOM && (x as u64) < n && (y as u64) < n).then_some(Self { z, x, y })
+    }
+
+    // Tile containing a point at zoom `z`
+    pub fn containing(lat: f64, lng: f64, z: u8) -> Self {
+        let n = (1u64 << z) as f64;
+        let lat = lat.clamp(-MAX_LAT, MAX_LAT).to_radians();
+        let x = ((lng + 180.0) / 360.0 * n).floor();
+        let y = ((1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n).floor();
+        let max = n - 1.0;
+        Self {
+            z,
+            x: x.clamp(0.0, max) as u32,
+            y: y.clamp(0.0, max) as u32,
+        }
+    }
+
+    // Bounding box as (sw_lat, sw_lng, ne_lat, ne_lng)
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let n = (1u64 << self.z) as f64;
+        let lng = |x: f64| x / n * 360.0 - 180.0;
+        let lat = |y: f64| (PI * (1.0 - 2.0 * y / n)).sinh().atan().to_degrees();
+        let (x, y) = (self.x as f64, self.y as f64);
+        (lat(y + 1.0), lng(x), lat(y), lng(x + 1.0))
+    }
+}
+
+struct CachedTile {
+    body: Option<Bytes>,
+    // Bumped on every invalidation, so results computed before it are discarded
+    generation: u64,
+}
+
+// Serialized bbox results per map tile, for the many clients that look at the
+// same busy waters. Entries are invalidated whenever a ship in them changes.
+pub struct TileCache {
+    tiles: DashMap<Tile, CachedTile>,
+}
+
+impl Default for TileCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TileCache {
+    pub fn new() -> Self {
+        Self {
+            tiles: DashMap::new(),
+        }
+    }
+
+    // Serve a cached tile, or compute, cache and serve it
+    pub fn get_or_compute<F: FnOnce() -> Bytes>(&self, tile: Tile, compute: F) -> Bytes {
+        if tile.z > MAX_CACHED_ZOOM {
+            return compute();
+        }
+
+        let generation = {
+            if self.tiles.len() >= MAX_CACHED_TILES {
+                self.tiles.clear();
+            }
+            let entry = self.tiles.entry(tile).or_insert(CachedTile {
+                body: None,
+                generation: 0,
+            });
+            if let Some(ref body) = entry.body {
+                return body.clone();
+            }
+            entry.generation
+        };
+
+        let body = compute();
+        if let Some(mut entry) = self.tiles.get_mut(&tile)
+            && entry.generation == generation
+        {
+            entry.body = Some(body.clone());
+        }
+        body
+    }
+
+    // Approximate heap held by cached tiles
+    pub fn memory_bytes(&self) -> usize {
+        let bodies: usize = self
+            .tiles
+            .iter()
+            .filter_map(|entry| entry.body.as_ref().map(Bytes::len))
+            .sum();
+        bodies + table_bytes::<Tile, CachedTile>(self.tiles.capacity())
+    }
+
+    // Forget every cached tile containing the point
+    pub fn invalidate(&self, lat: f64, lng: f64) {
+        if self.tiles.is_empty() {
+            return;
+        }
+        for z in 0..=MAX_CACHED_ZOOM {
+            if let Some(mut entry) = self.tiles.get_mut(&Tile::containing(lat, lng, z)) {
+                entry.body = None;
+                entry.generation += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_math_round_trips() {
+        // Dover Strait
+        let tile = Tile::containing(51.0, 1.5, 10);
+        let (sw_lat, sw_lng, ne_lat, ne_lng) = tile.bounds();
+        assert!(sw_lat <= 51.0 && 51.0 <= ne_lat);
+        assert!(sw_lng <= 1.5 && 1.5 <= ne_lng);
+        assert_eq!(Tile::containing(0.0, 0.0, 0), Tile { z: 0, x: 0, y: 0 });
+        assert!(Tile::new(2, 4, 0).is_none());
+    }
+
+    #[test]
+    fn test_invalidation_discards_cached_and_in_flight_results() {
+        let cache = TileCache::new();
+        let tile = Tile::containing(51.0, 1.5, 8);
+
+        cache.get_or_compute(tile, || Bytes::from_static(b"[1]"));
+        assert_eq!(cache.get_or_compute(tile, || Bytes::from_static(b"[2]")), "[1]");
+
+        cache.invalidate(51.0, 1.5);
+        assert_eq!(cache.get_or_compute(tile, || Bytes::from_static(b"[3]")), "[3]");
+
+        // A ship moving while the tile is being computed keeps the result out of the cache
+        let tile = Tile::containing(51.0, 1.5, 9);
+        let result = cache.get_or_compute(tile, || {
+            cache.invalidate(51.0, 1.5);
+            Bytes::from_static(b"[4]")
+        });
+        assert_eq!(result, "[4]");
+        assert_eq!(cache.get_or_compute(tile, || Bytes::from_static(b"[5]")), "[5]");
+    }
+}
@@ -0,0 +1,141 @@
+use axum::body::HttpBody;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// Upper bounds of the latency histogram, in seconds
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+#[derive(Default)]
+struct Endpoint {
+    statuses: BTreeMap<u16, u64>,
+    buckets: [u64; BUCKETS.len()], // Cumulative, as Prometheus has them
+    seconds: f64,
+    bytes: u64,
+}
+
+// Per-endpoint request counts, latencies and response sizes, keyed by the
+// route template (`/api/ship/:mmsi`) rather than the path, so the number of
+// series stays bounded
+#[derive(Default)]
+pub struct HttpMetrics {
+    endpoints: Mutex<HashMap<(Method, Arc<str>), Endpoint>>,
+    access_log: AtomicBool,
+}
+
+impl HttpMetrics {
+    pub fn new(access_log: bool) -> Self {
+        Self { access_log: AtomicBool::new(access_log), ..Self::default() }
+    }
+
+    // Turns the access log on or off, for a reloaded configuration
+    pub fn set_access_log(&self, access_log: bool) {
+        self.access_log.store(access_log, Ordering::Relaxed);
+    }
+
+    fn record(&self, method: Method, route: &str, status: u16, elapsed: Duration, bytes: u64) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = endpoints.entry((method, Arc::from(route))).or_default();
+        *endpoint.statuses.entry(status).or_default() += 1;
+        let seconds = elapsed.as_secs_f64();
+        for (count, _) in endpoint.buckets.iter_mut().zip(BUCKETS).filter(|(_, bound)| seconds <= *bound) {
+            *count += 1;
+        }
+        endpoint.seconds += seconds;
+        endpoint.bytes += bytes;
+    }
+
+    // Prometheus text exposition, appended to the rest of /metrics
+    pub fn render(&self, out: &mut String) {
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut keys: Vec<_> = endpoints.keys().collect();
+        keys.sort_by(|a, b| (&a.1, a.0.as_str()).cmp(&(&b.1, b.0.as_str())));
+
+        let _ = writeln!(out, "# HELP seawatch_http_requests_total HTTP requests, by route and status");
+        let _ = writeln!(out, "# TYPE seawatch_http_requests_total counter");
+        for key @ (method, route) in &keys {
+            for (status, count) in &endpoints[*key].statuses {
+                let _ = writeln!(out, "seawatch_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}", method, route, status, count);
+            }
+        }
+        let _ = writeln!(out, "# HELP seawatch_http_request_duration_seconds Time to the response headers, by route");
+        let _ = writeln!(out, "# TYPE seawatch_http_request_duration_seconds histogram");
+        for key @ (method, route) in &keys {
+            let endpoint = &endpoints[*key];
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            for (count, bound) in endpoint.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(out, "seawatch_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let total: u64 = endpoint.statuses.values().sum();
+            let _ = writeln!(out, "seawatch_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, total);
+            let _ = writeln!(out, "seawatch_http_request_duration_seconds_sum{{{}}} {}", labels, endpoint.seconds);
+            let _ = writeln!(out, "seawatch_http_request_duration_seconds_count{{{}}} {}", labels, total);
+        }
+        let _ = writeln!(out, "# HELP seawatch_http_response_bytes_total Response body bytes of known size, by route");
+        let _ = writeln!(out, "# TYPE seawatch_http_response_bytes_total counter");
+        for key @ (method, route) in &keys {
+            let _ = writeln!(out, "seawatch_http_response_bytes_total{{method=\"{}\",route=\"{}\"}} {}", method, route, endpoints[*key].bytes);
+        }
+    }
+}
+
+// Middleware for the routes; needs to be added with `route_layer` so the
+// matched route is known. Streamed bodies (SSE, files) count as 0 bytes.
+pub async fn track(State(metrics): State<Arc<HttpMetrics>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|route| route.as_str().to_string());
+    let response = next.run(request).await;
+
+    let elapsed = started.elapsed();
+    let status = response.status().as_u16();
+    let bytes = response.body().size_hint().exact().unwrap_or(0);
+    if metrics.access_log.load(Ordering::Relaxed) {
+        info!(
+            target: "seawatch::access",
+            method = %method,
+            path = %path,
+            status,
+            latency_ms = elapsed.as_secs_f64() * 1000.0,
+            bytes,
+            "{} {} {} {:.1}ms {}B",
+            method,
+            path,
+            status,
+            elapsed.as_secs_f64() * 1000.0,
+            bytes
+        );
+    }
+    // Services nested whole, like a static directory, have no route template
+    metrics.record(method, route.as_deref().unwrap_or("other"), status, elapsed, bytes);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = HttpMetrics::new(false);
+        metrics.record(Method::GET, "/api/ship/:mmsi", 200, Duration::from_millis(3), 512);
+        metrics.record(Method::GET, "/api/ship/:mmsi", 404, Duration::from_millis(2), 0);
+        metrics.record(Method::GET, "/api/ship/:mmsi", 200, Duration::from_millis(300), 512);
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        assert!(out.contains("seawatch_http_requests_total{method=\"GET\",route=\"/api/ship/:mmsi\",status=\"200\"} 2\n"));
+        assert!(out.contains("seawatch_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/ship/:mmsi\",le=\"0.005\"} 2\n"));
+        assert!(out.contains("seawatch_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/ship/:mmsi\",le=\"0.5\"} 3\n"));
+        assert!(out.contains("seawatch_http_request_duration_seconds_count{method=\"GET\",route=\"/api/ship/:mmsi\"} 3\n"));
+        assert!(out.contains("seawatch_http_response_bytes_total{method=\"GET\",route=\"/api/ship/:mmsi\"} 1024\n"));
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::warn;
+
+use crate::events::{EventKind, EventLog};
+use crate::filter::Filter;
+use crate::geofence::Geofences;
+use crate::ship::Ship;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    // AIS ship type codes, inclusive; tankers are 80-89
+    ShipType { min: u32, max: u32 },
+    SpeedAbove { knots: f64 },
+    SpeedBelow { knots: f64 },
+    // Holds on the update where the status changes, optionally from/to a given one
+    NavStatusChange {
+        #[serde(default)]
+        from: Option<u32>,
+        #[serde(default)]
+        to: Option<u32>,
+    },
+    InZone { zone: String },
+    // Not heard from for this long; checked periodically rather than on updates
+    Stale { secs: u64 },
+    // A filter expression, e.g. "type:tanker AND speed>15"; see `crate::filter`
+    Filter {
+        #[schemars(with = "String")]
+        filter: Filter,
+    },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    // Write the alert to the server log
+    Log,
+    // Mail the alert to these addresses, when SMTP is configured
+    Email { to: Vec<String> },
+    // Chat messages, see `crate::chat`. Without a template the default one is used.
+    Telegram {
+        chat_id: String,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    // Slack and Discord post to their incoming webhook, or the configured default
+    Slack {
+        #[serde(default)]
+        webhook_url: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+    },
+    Discord {
+        #[serde(default)]
+        webhook_url: Option<String>,
+        #[serde(default)]
+        template: Option<String>,
+    },
+}
+
+// Fires once when all of its conditions start holding for a ship, and again
+// only after they have stopped holding in between.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: String,
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(anyhow::anyhow!("A rule needs a name"));
+        }
+        if self.conditions.is_empty() {
+            return Err(anyhow::anyhow!("A rule needs at least one condition"));
+        }
+        for condition in &self.conditions {
+            match condition {
+                Condition::ShipType { min, max } if min > max => {
+                    return Err(anyhow::anyhow!("Ship type range {}-{} is empty", min, max));
+                }
+                Condition::SpeedAbove { knots } | Condition::SpeedBelow { knots }
+                    if !knots.is_finite() || *knots < 0.0 =>
+                {
+                    return Err(anyhow::anyhow!("Speed must be a non-negative number of knots"));
+                }
+                // Rules are evaluated on every update, without scoring the ship
+                Condition::Filter { filter } if filter.uses_quality() => {
+                    return Err(anyhow::anyhow!("Rule filters can't use quality"));
+                }
+                _ => {}
+            }
+        }
+        validate_actions(&self.actions)
+    }
+
+    fn is_periodic(&self) -> bool {
+        self.conditions.iter().any(|c| matches!(c, Condition::Stale { .. }))
+    }
+
+    fn matches(&self, update: &Update, geofences: &Geofences) -> bool {
+        let ship = update.ship;
+        self.conditions.iter().all(|condition| match condition {
+            Condition::ShipType { min, max } => (*min..=*max).contains(&ship.ship_type),
+            Condition::SpeedAbove { knots } => ship.speed > *knots,
+            Condition::SpeedBelow { knots } => ship.speed < *knots,
+            Condition::NavStatusChange { from, to } => update.before.is_some_and(|before| {
+                before.nav_status != ship.nav_status
+                    && from.is_none_or(|from| before.nav_status == from)
+                    && to.is_none_or(|to| ship.nav_status == to)
+            }),
+            Condition::InZone { zone } => geofences.is_inside(ship.mmsi, zone),
+            Condition::Stale { secs } => update.now.saturating_sub(ship.last_update) >= *secs,
+            Condition::Filter { filter } => filter.matches(ship, geofences),
+        })
+    }
+}
+
+// A JSON array of rules, as monitor.alert_rules names
+pub fn load_rules(path: &str) -> Result<Vec<Rule>> {
+    let rules: Vec<Rule> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    for rule in &rules {
+        rule.validate()?;
+    }
+    Ok(rules)
+}
+
+pub fn validate_actions(actions: &[Action]) -> Result<()> {
+    for action in actions {
+        match action {
+            Action::Email { to } => {
+                if to.is_empty() {
+                    return Err(anyhow::anyhow!("An email action needs at least one recipient"));
+                }
+                for address in to {
+                    address
+                        .parse::<lettre::message::Mailbox>()
+                        .map_err(|e| anyhow::anyhow!("Invalid email address '{}': {}", address, e))?;
+                }
+            }
+            Action::Telegram { chat_id, .. } if chat_id.is_empty() => {
+                return Err(anyhow::anyhow!("A Telegram action needs a chat_id"));
+            }
+            Action::Slack { webhook_url: Some(url), .. } | Action::Discord { webhook_url: Some(url), .. } => {
+                url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid webhook URL '{}': {}", url, e))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// A ship as just updated (or as found by the periodic sweep, with no `before`)
+pub struct Update<'a> {
+    pub before: Option<&'a Ship>,
+    pub ship: &'a Ship,
+    pub now: u64,
+}
+
+#[derive(Default)]
+pub struct AlertRules {
+    rules: RwLock<Vec<Rule>>,
+    // (rule, mmsi) pairs whose conditions held at the last evaluation
+    active: Mutex<HashSet<(String, u32)>>,
+}
+
+impl AlertRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn rule(&self, name: &str) -> Option<Rule> {
+        self.rules.read().unwrap().iter().find(|rule| rule.name == name).cloned()
+    }
+
+    pub fn has_periodic(&self) -> bool {
+        self.rules.read().unwrap().iter().any(Rule::is_periodic)
+    }
+
+    // Add a rule, or replace the one with the same name
+    pub fn upsert(&self, rule: Rule) {
+        let mut rules = self.rules.write().unwrap();
+        self.forget(&rule.name);
+        match rules.iter_mut().find(|existing| existing.name == rule.name) {
+            Some(existing) => *existing = rule,
+            None => rules.push(rule),
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        let before = rules.len();
+        rules.retain(|rule| rule.name != name);
+        self.forget(name);
+        rules.len() != before
+    }
+
+    fn forget(&self, name: &str) {
+        self.active.lock().unwrap().retain(|(rule, _)| rule != name);
+    }
+
+    // Evaluate rules against an update, firing the ones that newly match.
+    // `periodic` restricts it to rules that depend on time passing.
+    pub fn evaluate(&self, events: &EventLog, geofences: &Geofences, update: &Update, periodic: bool) {
+        let rules = self.rules.read().unwrap();
+        let mmsi = update.ship.mmsi;
+        for rule in rules.iter().filter(|rule| !periodic || rule.is_periodic()) {
+            let matches = rule.matches(update, geofences);
+            let key = (rule.name.clone(), mmsi);
+            let fired = {
+                let mut active = self.active.lock().unwrap();
+                if matches {
+                    active.insert(key)
+                } else {
+                    active.remove(&key);
+                    false
+                }
+            };
+            if fired {
+                fire(events, rule, update);
+            }
+        }
+    }
+}
+
+fn fire(events: &EventLog, rule: &Rule, update: &Update) {
+    let ship = update.ship;
+    let event = events.push(update.now, ship.mmsi, EventKind::Alert { rule: Arc::from(rule.name.as_str()) });
+    // Other actions are carried out by the notifiers that pick the event up
+    if rule.actions.contains(&Action::Log) {
+        warn!("Alert '{}' for {} ({}), event {}", rule.name, ship.name, ship.mmsi, event.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+    use crate::geofence::{Shape, Zone};
+
+    fn ship(speed: f64, nav_status: u32, last_update: u64) -> Ship {
+        let mut ship = Ship::new(1, "TANKER");
+        ship.ship_type = 84;
+        ship.lat = 51.0;
+        ship.lng = 1.5;
+        ship.speed = speed;
+        ship.nav_status = nav_status;
+        ship.last_update = last_update;
+        ship
+    }
+
+    fn rule(name: &str, conditions: Vec<Condition>) -> Rule {
+        Rule { name: name.into(), conditions, actions: vec![Action::Log] }
+    }
+
+    fn fired(events: &EventLog) -> Vec<(u64, String)> {
+        events
+            .query(&EventFilter::default())
+            .iter()
+            .filter_map(|event| match &event.kind {
+                EventKind::Alert { rule } => Some((event.timestamp, rule.to_string())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rules_fire_once_per_match() {
+        let (events, geofences, alerts) = (EventLog::new(), Geofences::new(), AlertRules::new());
+        geofences.upsert(Zone::new(
+            "Strait",
+            Shape::Polygon { points: vec![[50.8, 1.0], [51.2, 1.0], [51.2, 2.0], [50.8, 2.0]] },
+        ));
+        alerts.upsert(rule(
+            "fast tanker",
+            vec![
+                Condition::ShipType { min: 80, max: 89 },
+                Condition::SpeedAbove { knots: 15.0 },
+                Condition::InZone { zone: "Strait".into() },
+            ],
+        ));
+        alerts.upsert(rule("anchored", vec![Condition::NavStatusChange { from: None, to: Some(1) }]));
+        let filter: Condition = serde_json::from_str(r#"{"type": "filter", "filter": "type:tanker AND speed>16.5 AND zone:Strait"}"#).unwrap();
+        alerts.upsert(rule("faster tanker", vec![filter]));
+
+        let updates = [ship(12.0, 0, 100), ship(16.0, 0, 110), ship(17.0, 0, 120), ship(10.0, 1, 130), ship(16.0, 1, 140)];
+        let mut before: Option<Ship> = None;
+        for ship in &updates {
+            geofences.observe(&events, ship);
+            let update = Update { before: before.as_ref(), ship, now: ship.last_update };
+            alerts.evaluate(&events, &geofences, &update, false);
+            before = Some(ship.clone());
+        }
+
+        assert_eq!(
+            fired(&events),
+            vec![(110, "fast tanker".into()), (120, "faster tanker".into()), (130, "anchored".into()), (140, "fast tanker".into())]
+        );
+    }
+
+    #[test]
+    fn test_stale_rules_only_fire_on_sweeps() {
+        let (events, geofences, alerts) = (EventLog::new(), Geofences::new(), AlertRules::new());
+        alerts.upsert(rule("silent", vec![Condition::Stale { secs: 600 }]));
+        assert!(alerts.has_periodic());
+
+        let ship = ship(0.0, 0, 100);
+        let sweep = |now| alerts.evaluate(&events, &geofences, &Update { before: None, ship: &ship, now }, true);
+        sweep(500);
+        sweep(700);
+        sweep(800);
+        assert_eq!(fired(&events), vec![(700, "silent".into())]);
+
+        assert!(rule("", vec![Condition::Stale { secs: 1 }]).validate().is_err());
+        assert!(rule("empty", vec![]).validate().is_err());
+        assert!(rule("bad", vec![Condition::SpeedAbove { knots: f64::NAN }]).validate().is_err());
+        assert!(rule("scored", vec![Condition::Filter { filter: "quality<0.5".parse().unwrap() }]).validate().is_err());
+
+        let mut email = rule("email", vec![Condition::Stale { secs: 1 }]);
+        email.actions = vec![Action::Email { to: vec!["harbour@example.com".into()] }];
+        assert!(email.validate().is_ok());
+        email.actions = vec![Action::Email { to: vec!["not an address".into()] }];
+        assert!(email.validate().is_err());
+    }
+}
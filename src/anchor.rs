@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::alerts::Action;
+use crate::events::{EventKind, EventLog};
+use crate::geo::haversine_m;
+use crate::ship::Ship;
+
+// A vessel marked as anchored: it should stay within `radius_m` of the anchor
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AnchorWatch {
+    pub lat: f64,
+    pub lng: f64,
+    pub radius_m: f64,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    // Outside the swing circle since the last drag alert
+    #[serde(default)]
+    pub dragging: bool,
+}
+
+#[derive(Default)]
+pub struct AnchorWatches {
+    watches: Mutex<HashMap<u32, AnchorWatch>>,
+}
+
+impl AnchorWatches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watches(&self) -> HashMap<u32, AnchorWatch> {
+        self.watches.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, mmsi: u32, watch: AnchorWatch) {
+        self.watches.lock().unwrap().insert(mmsi, watch);
+    }
+
+    pub fn remove(&self, mmsi: u32) -> bool {
+        self.watches.lock().unwrap().remove(&mmsi).is_some()
+    }
+
+    pub fn actions(&self, mmsi: u32) -> Option<Vec<Action>> {
+        self.watches.lock().unwrap().get(&mmsi).map(|watch| watch.actions.clone())
+    }
+
+    // Raise a drag alert the first time a watched ship leaves its swing
+    // circle; it rearms once the ship is back inside
+    pub fn observe(&self, events: &EventLog, ship: &Ship) {
+        let mut watches = self.watches.lock().unwrap();
+        let Some(watch) = watches.get_mut(&ship.mmsi) else {
+            return;
+        };
+        let distance_m = haversine_m(watch.lat, watch.lng, ship.lat, ship.lng);
+        let outside = distance_m > watch.radius_m;
+        if outside && !watch.dragging {
+            warn!("{} ({}) is dragging its anchor: {:.0}m from it", ship.name, ship.mmsi, distance_m);
+            events.push(
+                ship.last_update,
+                ship.mmsi,
+                EventKind::AnchorDrag { lat: ship.lat, lng: ship.lng, distance_m: distance_m.round(), radius_m: watch.radius_m },
+            );
+        }
+        watch.dragging = outside;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_drag_alerts() {
+        let (anchors, events) = (AnchorWatches::new(), EventLog::new());
+        anchors.set(1, AnchorWatch { lat: 50.0, lng: -4.0, radius_m: 100.0, actions: Vec::new(), dragging: false });
+
+        // ~55m, ~222m, ~333m, ~55m, ~222m north of the anchor
+        let mut ship = Ship::new(1, "SEA BREEZE");
+        for (timestamp, lat) in [(10, 50.0005), (20, 50.002), (30, 50.003), (40, 50.0005), (50, 50.002)] {
+            (ship.lat, ship.lng, ship.last_update) = (lat, -4.0, timestamp);
+            anchors.observe(&events, &ship);
+        }
+
+        let drags: Vec<(u64, f64)> = events
+            .query(&EventFilter::default())
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::AnchorDrag { distance_m, .. } => (event.timestamp, distance_m),
+                _ => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(drags, vec![(20, 222.0), (50, 222.0)]);
+    }
+}
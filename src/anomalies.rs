@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::ports::Ports;
+use crate::predict::course_change;
+use crate::ship::Ship;
+
+// AIS reports 102.3 kn for "not available"
+const SPEED_UNAVAILABLE: f64 = 102.2;
+// A speed only counts as sustained after this long over the limit, so a
+// single GPS glitch doesn't
+const MIN_FAST_SECS: u64 = 120;
+// Course reversals: this much of a turn between reports at most
+// MAX_REVERSAL_GAP apart, at this speed or more on both
+const REVERSAL_DEGREES: f64 = 150.0;
+const MAX_REVERSAL_GAP: u64 = 120;
+const MIN_REVERSAL_SPEED: f64 = 5.0;
+// Drifting: "under way using engine" but below this speed, outside any port,
+// for at least DRIFT_SECS
+const DRIFT_SPEED: f64 = 0.5;
+const DRIFT_SECS: u64 = 30 * 60;
+// Anomalies stay listed this long after they were last seen
+const RETENTION_SECS: u64 = 6 * 3600;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    ImplausibleSpeed,
+    CourseReversal,
+    DriftingUnderway,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    pub mmsi: u32,
+    pub kind: AnomalyKind,
+    // How far past the detection threshold, which scores 1; anomalies are
+    // ranked by it
+    pub score: f64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+// Top speed a ship of this AIS type could plausibly sustain, in knots
+fn max_plausible_speed(ship_type: u32) -> f64 {
+    match ship_type {
+        30 => 25.0,                // Fishing
+        31 | 32 | 52 => 20.0,      // Towing, tugs
+        33 | 34 => 20.0,           // Dredging, diving
+        35 | 51 | 55 => 50.0,      // Military, search and rescue, law enforcement
+        36 => 25.0,                // Sailing
+        37 => 50.0,                // Pleasure craft
+        40..=49 => 60.0,           // High-speed craft
+        60..=69 => 40.0,           // Passenger
+        70..=79 => 30.0,           // Cargo
+        80..=89 => 25.0,           // Tanker
+        _ => 60.0,
+    }
+}
+
+#[derive(Default)]
+struct State {
+    last: Option<(u64, f64, f64)>, // Time, speed and course of the last report
+    fast_since: Option<u64>,
+    drifting_since: Option<u64>,
+}
+
+// Kinematically unusual behaviour per ship, for analysts to review
+#[derive(Default)]
+pub struct Anomalies {
+    states: Mutex<HashMap<u32, State>>,
+    found: Mutex<HashMap<(u32, AnomalyKind), Anomaly>>,
+}
+
+impl Anomalies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Highest scores first
+    pub fn ranked(&self, limit: usize) -> Vec<Anomaly> {
+        let mut anomalies: Vec<Anomaly> = self.found.lock().unwrap().values().cloned().collect();
+        anomalies.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.last_seen.cmp(&a.last_seen)));
+        anomalies.truncate(limit);
+        anomalies
+    }
+
+    // The kinds listed against one ship
+    pub fn of(&self, mmsi: u32) -> Vec<AnomalyKind> {
+        let mut kinds: Vec<AnomalyKind> = self.found.lock().unwrap().keys().filter(|(of, _)| *of == mmsi).map(|(_, kind)| *kind).collect();
+        kinds.sort_by_key(|kind| *kind as u8);
+        kinds
+    }
+
+    pub fn observe(&self, events: &EventLog, ports: &Ports, ship: &Ship) {
+        if ship.speed >= SPEED_UNAVAILABLE {
+            return;
+        }
+        let now = ship.last_update;
+        let mut detected = Vec::new();
+        {
+            let mut states = self.states.lock().unwrap();
+            let state = states.entry(ship.mmsi).or_default();
+
+            let limit = max_plausible_speed(ship.ship_type);
+            if ship.speed > limit {
+                let since = *state.fast_since.get_or_insert(now);
+                if now.saturating_sub(since) >= MIN_FAST_SECS {
+                    detected.push((AnomalyKind::ImplausibleSpeed, ship.speed / limit));
+                }
+            } else {
+                state.fast_since = None;
+            }
+
+            if let Some((t, speed, cog)) = state.last
+                && now > t
+                && now - t <= MAX_REVERSAL_GAP
+                && speed.min(ship.speed) >= MIN_REVERSAL_SPEED
+                && cog < 360.0
+                && ship.cog < 360.0
+            {
+                let turned = course_change(cog, ship.cog).abs();
+                if turned >= REVERSAL_DEGREES {
+                    detected.push((AnomalyKind::CourseReversal, turned / REVERSAL_DEGREES));
+                }
+            }
+
+            if ship.nav_status == 0 && ship.speed < DRIFT_SPEED && ports.containing(ship.lat, ship.lng).is_none() {
+                let since = *state.drifting_since.get_or_insert(now);
+                if now.saturating_sub(since) >= DRIFT_SECS {
+                    detected.push((AnomalyKind::DriftingUnderway, now.saturating_sub(since) as f64 / DRIFT_SECS as f64));
+                }
+            } else {
+                state.drifting_since = None;
+            }
+
+            if state.last.is_none_or(|(t, _, _)| now > t) {
+                state.last = Some((now, ship.speed, ship.cog));
+            }
+        }
+
+        let mut found = self.found.lock().unwrap();
+        for (kind, score) in detected {
+            match found.get_mut(&(ship.mmsi, kind)) {
+                Some(anomaly) => {
+                    (anomaly.last_seen, anomaly.lat, anomaly.lng) = (now, ship.lat, ship.lng);
+                    anomaly.score = anomaly.score.max(score);
+                }
+                None => {
+                    let anomaly =
+                        Anomaly { mmsi: ship.mmsi, kind, score, first_seen: now, last_seen: now, lat: ship.lat, lng: ship.lng };
+                    found.insert((ship.mmsi, kind), anomaly);
+                    events.push(now, ship.mmsi, EventKind::Anomaly { anomaly: kind, score });
+                }
+            }
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.found.lock().unwrap().retain(|_, anomaly| now.saturating_sub(anomaly.last_seen) <= RETENTION_SECS);
+        self.states
+            .lock()
+            .unwrap()
+            .retain(|_, state| state.last.is_some_and(|(t, _, _)| now.saturating_sub(t) <= RETENTION_SECS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_detects_and_ranks_anomalies() {
+        let (anomalies, events, ports) = (Anomalies::new(), EventLog::new(), Ports::builtin());
+
+        // A tanker "doing" 40 kn for several minutes
+        let mut tanker = Ship::new(1, "");
+        (tanker.ship_type, tanker.speed, tanker.cog, tanker.lat, tanker.lng) = (80, 40.0, 90.0, 45.0, -30.0);
+        for t in [0, 60, 120, 180] {
+            tanker.last_update = t;
+            anomalies.observe(&events, &ports, &tanker);
+        }
+
+        // A ferry spinning round at speed
+        let mut ferry = Ship::new(2, "");
+        (ferry.ship_type, ferry.speed, ferry.lat, ferry.lng) = (60, 15.0, 45.0, -30.0);
+        for (t, cog) in [(0, 10.0), (30, 200.0)] {
+            (ferry.cog, ferry.last_update) = (cog, t);
+            anomalies.observe(&events, &ports, &ferry);
+        }
+
+        // Engine "under way" but dead in the water for an hour
+        let mut drifter = Ship::new(3, "");
+        (drifter.nav_status, drifter.speed, drifter.lat, drifter.lng) = (0, 0.1, 45.0, -30.0);
+        for t in (0..=3600).step_by(600) {
+            drifter.last_update = t;
+            anomalies.observe(&events, &ports, &drifter);
+        }
+
+        // The same in Dover harbour is just a ship alongside
+        (drifter.mmsi, drifter.lat, drifter.lng) = (4, 51.12, 1.34);
+        for t in (0..=3600).step_by(600) {
+            drifter.last_update = t;
+            anomalies.observe(&events, &ports, &drifter);
+        }
+
+        let ranked: Vec<(u32, AnomalyKind)> = anomalies.ranked(10).iter().map(|a| (a.mmsi, a.kind)).collect();
+        assert_eq!(
+            ranked,
+            vec![(3, AnomalyKind::DriftingUnderway), (1, AnomalyKind::ImplausibleSpeed), (2, AnomalyKind::CourseReversal)]
+        );
+        let filter = EventFilter { kind: Some("anomaly".into()), ..Default::default() };
+        assert_eq!(events.query(&filter).len(), 3);
+
+        anomalies.purge(3600 + RETENTION_SECS + 1);
+        assert!(anomalies.ranked(10).is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use anyhow::Result;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::ratelimit::{FixedWindow, RateLimiter};
+
+// Served to anyone: the UI, its settings and basemap tiles, which browsers
+// load without headers, and the peer endpoints, which check their own token
+const PUBLIC_ROUTES: [&str; 6] = ["/", "/static/*path", "/api/config", "/tiles/:layer/:z/:x/:file", "/api/peer", "/api/peer/feed"];
+// Routes that change or reveal the server's setup, which need an admin key
+pub const ADMIN_PREFIX: &str = "/api/admin/";
+// The only routes a key restricted to an area may use, as they are the ones
+// that know to leave out ships elsewhere
+const AREA_ROUTES: [&str; 6] = [
+    "/api/ships/:sw_lat/:sw_lng/:ne_lat/:ne_lng",
+    "/api/history/timelapse/:sw_lat/:sw_lng/:ne_lat/:ne_lng",
+    "/api/ships/region/:name",
+    "/api/searches/:name/ships",
+    "/api/tiles/:z/:x/:y",
+    "/api/ship/:mmsi",
+];
+
+// South, west, north, east; not across the antimeridian
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct Area(pub [f64; 4]);
+
+impl Area {
+    pub fn contains(&self, lat: f64, lng: f64) -> bool {
+        let [south, west, north, east] = self.0;
+        lat >= south && lat <= north && lng >= west && lng <= east
+    }
+
+    pub fn covers(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> bool {
+        self.contains(sw_lat, sw_lng) && self.contains(ne_lat, ne_lng)
+    }
+
+    // The part of the bbox inside the area, if any
+    pub fn clip(&self, sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> Option<(f64, f64, f64, f64)> {
+        let [south, west, north, east] = self.0;
+        let clipped = (sw_lat.max(south), sw_lng.max(west), ne_lat.min(north), ne_lng.min(east));
+        (clipped.0 <= clipped.2 && clipped.1 <= clipped.3).then_some(clipped)
+    }
+}
+
+// One consumer, e.g. `{"name": "harbour-app", "key": "...", "requests_per_minute": 60,
+// "endpoints": ["/api/ships", "/api/tiles"], "bbox": [51.0, 3.0, 52.5, 5.0]}`, or
+// the operator's `{"name": "ops", "key": "...", "admin": true}`
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiKeySpec {
+    pub name: String,
+    pub key: String,
+    pub requests_per_minute: Option<usize>,
+    pub requests_per_day: Option<usize>,
+    // Route prefixes, e.g. "/api/ship/" for everything about single ships; all routes if empty
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    pub bbox: Option<Area>,
+    // May use the /api/admin routes, which no other key can
+    #[serde(default)]
+    pub admin: bool,
+}
+
+// A key's usage since startup, for /api/admin/keys
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct KeyUsage {
+    pub name: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub denied: u64, // Routes the key isn't allowed
+}
+
+// A day's requests are counted rather than timed one by one
+struct Limits {
+    per_minute: Option<RateLimiter>,
+    per_day: Option<FixedWindow>,
+}
+
+struct Tenant {
+    spec: ApiKeySpec,
+    limits: Mutex<Limits>,
+    requests: AtomicU64,
+    rate_limited: AtomicU64,
+    denied: AtomicU64,
+}
+
+#[derive(Default)]
+struct Keys {
+    tenants: Vec<Arc<Tenant>>,
+    by_key: HashMap<String, usize>,
+}
+
+// The keys accepted on the API. With none, the API is open to everyone,
+// except for the admin routes, which are off.
+#[derive(Default)]
+pub struct ApiKeys {
+    keys: RwLock<Keys>,
+}
+
+impl ApiKeys {
+    pub fn new(specs: Vec<ApiKeySpec>) -> Result<Self> {
+        let mut keys = Keys::default();
+        for spec in specs {
+            if keys.tenants.iter().any(|tenant| tenant.spec.name == spec.name) {
+                return Err(anyhow::anyhow!("Duplicate API key name '{}'", spec.name));
+            }
+            if spec.key.is_empty() || keys.by_key.contains_key(&spec.key) {
+                return Err(anyhow::anyhow!("API key '{}' needs a key of its own", spec.name));
+            }
+            if let Some(Area([south, west, north, east])) = spec.bbox
+                && !(south <= north
+                    && west <= east
+                    && [south, north].iter().all(|lat| (-90.0..=90.0).contains(lat))
+                    && [west, east].iter().all(|lng| (-180.0..=180.0).contains(lng)))
+            {
+                return Err(anyhow::anyhow!("API key '{}' has an invalid bbox; give [south, west, north, east]", spec.name));
+            }
+            let limits = Limits {
+                per_minute: spec.requests_per_minute.map(|max| RateLimiter::new(max, Duration::from_secs(60))),
+                per_day: spec.requests_per_day.map(|max| FixedWindow::new(max, Duration::from_secs(24 * 60 * 60))),
+            };
+            keys.by_key.insert(spec.key.clone(), keys.tenants.len());
+            keys.tenants.push(Arc::new(Tenant {
+                spec,
+                limits: Mutex::new(limits),
+                requests: AtomicU64::new(0),
+                rate_limited: AtomicU64::new(0),
+                denied: AtomicU64::new(0),
+            }));
+        }
+        Ok(Self { keys: RwLock::new(keys) })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        Self::new(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    // Takes the keys of `fresh` in place of these, e.g. from the file read
+    // again. A key that is the same as before keeps its rate limits' counts,
+    // and one under the same name its usage.
+    pub fn replace(&self, fresh: ApiKeys) {
+        let mut fresh = fresh.keys.into_inner().unwrap();
+        let mut keys = self.keys.write().unwrap();
+        for tenant in &mut fresh.tenants {
+            let Some(old) = keys.tenants.iter().find(|old| old.spec.name == tenant.spec.name) else {
+                continue;
+            };
+            if old.spec == tenant.spec {
+                *tenant = old.clone();
+            } else {
+                for (counter, old) in [(&tenant.requests, &old.requests), (&tenant.rate_limited, &old.rate_limited), (&tenant.denied, &old.denied)] {
+                    counter.store(old.load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+            }
+        }
+        *keys = fresh;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.read().unwrap().tenants.is_empty()
+    }
+
+    pub fn usage(&self) -> Vec<KeyUsage> {
+        self.keys
+            .read()
+            .unwrap()
+            .tenants
+            .iter()
+            .map(|tenant| KeyUsage {
+                name: tenant.spec.name.clone(),
+                requests: tenant.requests.load(Ordering::Relaxed),
+                rate_limited: tenant.rate_limited.load(Ordering::Relaxed),
+                denied: tenant.denied.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // Whether `key` may call `route` (the template, relative to the base
+    // path) now, and if so which area it is restricted to
+    fn check(&self, key: Option<&str>, route: &str, now: Instant) -> Result<Option<Area>, StatusCode> {
+        let keys = self.keys.read().unwrap();
+        let tenant = key.and_then(|key| keys.by_key.get(key)).map(|&index| &keys.tenants[index]).ok_or(StatusCode::UNAUTHORIZED)?;
+        let allowed = (tenant.spec.admin || !route.starts_with(ADMIN_PREFIX))
+            && (tenant.spec.endpoints.is_empty() || tenant.spec.endpoints.iter().any(|prefix| route.starts_with(prefix.as_str())))
+            && (tenant.spec.bbox.is_none() || AREA_ROUTES.contains(&route));
+        if !allowed {
+            tenant.denied.fetch_add(1, Ordering::Relaxed);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        // A request turned away by one limit doesn't count against the others
+        let mut limits = tenant.limits.lock().unwrap();
+        let Limits { per_minute, per_day } = &mut *limits;
+        if !(per_minute.as_mut().is_none_or(|limit| limit.check("", now)) && per_day.as_mut().is_none_or(|limit| limit.check(now))) {
+            tenant.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        per_minute.iter_mut().for_each(|limit| limit.record("", now));
+        per_day.iter_mut().for_each(|limit| limit.record(now));
+        drop(limits);
+        tenant.requests.fetch_add(1, Ordering::Relaxed);
+        Ok(tenant.spec.bbox)
+    }
+
+    // Prometheus text exposition, appended to the rest of /metrics
+    pub fn render(&self, out: &mut String) {
+        if self.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP seawatch_api_key_requests_total Requests served, by API key");
+        let _ = writeln!(out, "# TYPE seawatch_api_key_requests_total counter");
+        for usage in self.usage() {
+            let _ = writeln!(out, "seawatch_api_key_requests_total{{key=\"{}\"}} {}", usage.name, usage.requests);
+        }
+        let _ = writeln!(out, "# HELP seawatch_api_key_rejected_total Requests turned away, by API key and reason");
+        let _ = writeln!(out, "# TYPE seawatch_api_key_rejected_total counter");
+        for usage in self.usage() {
+            let _ = writeln!(out, "seawatch_api_key_rejected_total{{key=\"{}\",reason=\"rate_limited\"}} {}", usage.name, usage.rate_limited);
+            let _ = writeln!(out, "seawatch_api_key_rejected_total{{key=\"{}\",reason=\"denied\"}} {}", usage.name, usage.denied);
+        }
+    }
+}
+
+// `Authorization: Bearer <key>`, `X-Api-Key: <key>`, or `?api_key=<key>` for
+// WebSockets and EventSource, which can't set headers
+fn key_of<'a>(headers: &'a HeaderMap, query: Option<&'a str>) -> Option<&'a str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let header = headers.get("x-api-key").and_then(|value| value.to_str().ok());
+    let query = query.and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("api_key=")));
+    bearer.or(header).or(query)
+}
+
+// Middleware for the routes, added with `route_layer`. A key restricted to an
+// area is handed to the handlers as an `Extension<Area>`.
+pub async fn authorize(State((keys, base_path)): State<(Arc<ApiKeys>, Arc<str>)>, mut request: Request, next: Next) -> Response {
+    // Services nested whole, like a static directory, have no route template
+    let Some(route) = request.extensions().get::<MatchedPath>().map(|route| route.as_str().to_string()) else {
+        return next.run(request).await;
+    };
+    // The nested UI page's template is the base path itself
+    let route = match route.strip_prefix(&*base_path) {
+        Some("") => "/",
+        Some(route) => route,
+        None => &route,
+    };
+    if keys.is_empty() {
+        // Open to everyone, so nobody gets to change the server
+        if route.starts_with(ADMIN_PREFIX) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        return next.run(request).await;
+    }
+    if PUBLIC_ROUTES.contains(&route) {
+        return next.run(request).await;
+    }
+    match keys.check(key_of(request.headers(), request.uri().query()), route, Instant::now()) {
+        Ok(area) => {
+            if let Some(area) = area {
+                request.extensions_mut().insert(area);
+            }
+            next.run(request).await
+        }
+        Err(StatusCode::UNAUTHORIZED) => (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let keys = ApiKeys::new(serde_json::from_str(r#"[
+            {"name": "admin", "key": "a", "admin": true},
+            {"name": "harbour", "key": "h", "requests_per_minute": 2, "endpoints": ["/api/ship"], "bbox": [51.0, 3.0, 52.5, 5.0]}
+        ]"#).unwrap()).unwrap();
+        let now = Instant::now();
+        assert_eq!(keys.check(None, "/api/events", now), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(keys.check(Some("wrong"), "/api/events", now), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(keys.check(Some("a"), "/api/admin/stats", now), Ok(None));
+        assert_eq!(keys.check(Some("h"), "/api/admin/stats", now), Err(StatusCode::FORBIDDEN));
+
+        let area = Area([51.0, 3.0, 52.5, 5.0]);
+        assert_eq!(keys.check(Some("h"), "/api/ship/:mmsi", now), Ok(Some(area)));
+        // Within the prefixes, but not area-aware
+        assert_eq!(keys.check(Some("h"), "/api/ship/:mmsi/port-calls", now), Err(StatusCode::FORBIDDEN));
+        assert_eq!(keys.check(Some("h"), "/api/tiles/:z/:x/:y", now), Err(StatusCode::FORBIDDEN));
+        assert_eq!(keys.check(Some("h"), "/api/ships/:sw_lat/:sw_lng/:ne_lat/:ne_lng", now), Ok(Some(area)));
+        assert_eq!(keys.check(Some("h"), "/api/ship/:mmsi", now), Err(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(keys.check(Some("h"), "/api/ship/:mmsi", now + Duration::from_secs(61)), Ok(Some(area)));
+
+        let usage = &keys.usage()[1];
+        assert_eq!((usage.requests, usage.rate_limited, usage.denied), (3, 1, 3));
+        assert_eq!(area.clip(50.0, 4.0, 51.5, 6.0), Some((51.0, 4.0, 51.5, 5.0)));
+        assert_eq!(area.clip(10.0, 4.0, 11.0, 6.0), None);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(key_of(&headers, Some("x=1&api_key=h")), Some("h"));
+        headers.insert(header::AUTHORIZATION, "Bearer a".parse().unwrap());
+        assert_eq!(key_of(&headers, Some("api_key=h")), Some("a"));
+        let bbox = |bbox| ApiKeys::new(vec![ApiKeySpec { bbox: Some(Area(bbox)), ..keys.keys.read().unwrap().tenants[1].spec.clone() }]);
+        assert!(bbox([52.0, 3.0, 51.0, 5.0]).is_err());
+        assert!(bbox([51.0, -1800.0, 52.5, 5.0]).is_err());
+        assert!(bbox([51.0, 3.0, 52.5, 180.5]).is_err());
+        assert!(bbox([-90.0, -180.0, 90.0, 180.0]).is_ok());
+    }
+
+    #[test]
+    fn test_limits() {
+        let keys = ApiKeys::new(serde_json::from_str(r#"[{"name": "app", "key": "a", "requests_per_minute": 1, "requests_per_day": 1}]"#).unwrap()).unwrap();
+        let (now, day) = (Instant::now(), Duration::from_secs(24 * 60 * 60));
+        assert_eq!(keys.check(Some("a"), "/api/events", now), Ok(None));
+        // Turned away for the day, which doesn't use up the minute
+        assert_eq!(keys.check(Some("a"), "/api/events", now + day - Duration::from_secs(30)), Err(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(keys.check(Some("a"), "/api/events", now + day), Ok(None));
+        assert_eq!(keys.usage()[0].rate_limited, 1);
+    }
+
+    #[test]
+    fn test_replace() {
+        let specs = |json: &str| serde_json::from_str::<Vec<ApiKeySpec>>(json).unwrap();
+        let keys = ApiKeys::new(specs(r#"[{"name": "ops", "key": "o", "requests_per_minute": 1}, {"name": "app", "key": "a"}]"#)).unwrap();
+        let now = Instant::now();
+        assert_eq!(keys.check(Some("o"), "/api/events", now), Ok(None));
+        assert_eq!(keys.check(Some("a"), "/api/events", now), Ok(None));
+
+        // The unchanged key is still at its limit, the changed one keeps its count under a new key
+        keys.replace(ApiKeys::new(specs(r#"[{"name": "ops", "key": "o", "requests_per_minute": 1}, {"name": "app", "key": "b"}]"#)).unwrap());
+        assert_eq!(keys.check(Some("o"), "/api/events", now), Err(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(keys.check(Some("a"), "/api/events", now), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(keys.check(Some("b"), "/api/events", now), Ok(None));
+        assert_eq!(keys.usage()[1].requests, 2);
+        keys.replace(ApiKeys::default());
+        assert!(keys.is_empty());
+    }
+}
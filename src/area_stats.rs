@@ -0,0 +1,159 @@
+use anyhow::Result;
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::ship::ShipCache;
+
+// Traffic per zone is summarised in buckets this long, kept for a week
+pub const BUCKET_SECS: u64 = 15 * 60;
+pub const MAX_WINDOW_SECS: u64 = 7 * 86_400;
+
+struct Bucket {
+    start: u64,
+    ships: HashSet<u32>,
+    peak: usize,
+    speed_sum: f64,
+    samples: u32,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct AreaBucket {
+    pub start: u64,
+    pub ships: usize,      // Distinct ships seen inside during the bucket
+    pub peak_ships: usize, // Most inside at any one sample
+    pub avg_speed_kn: Option<f64>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct AreaHistory {
+    pub zone: Arc<str>,
+    pub bucket_secs: u64,
+    pub buckets: Vec<AreaBucket>,
+}
+
+// Parse a window like "90m", "24h" or "7d"; plain numbers are seconds
+pub fn parse_window(window: &str) -> Result<u64> {
+    let (number, unit) = match window.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => (&window[..i], unit),
+        _ => (window, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return Err(anyhow::anyhow!("Unknown unit in window '{}'", window)),
+    };
+    let secs = number.parse::<u64>()?.saturating_mul(scale);
+    if secs == 0 || secs > MAX_WINDOW_SECS {
+        return Err(anyhow::anyhow!("Window must be between 1s and 7d"));
+    }
+    Ok(secs)
+}
+
+// Counts and speeds of the ships inside each zone over time, sampled on
+// every monitor sweep
+#[derive(Default)]
+pub struct AreaStats {
+    zones: Mutex<HashMap<Arc<str>, VecDeque<Bucket>>>,
+}
+
+impl AreaStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `occupants` has every zone, with the ships currently inside it. Zones
+    // missing from it have been deleted and lose their history.
+    pub fn record(&self, occupants: &HashMap<Arc<str>, Vec<u32>>, ships: &ShipCache, now: u64) {
+        let start = now - now % BUCKET_SECS;
+        let mut zones = self.zones.lock().unwrap();
+        zones.retain(|zone, _| occupants.contains_key(zone));
+
+        for (zone, inside) in occupants {
+            let buckets = zones.entry(zone.clone()).or_default();
+            if buckets.back().is_none_or(|bucket| bucket.start < start) {
+                buckets.push_back(Bucket { start, ships: HashSet::new(), peak: 0, speed_sum: 0.0, samples: 0 });
+            }
+            while buckets.front().is_some_and(|bucket| bucket.start + MAX_WINDOW_SECS <= start) {
+                buckets.pop_front();
+            }
+            let bucket = buckets.back_mut().unwrap();
+            bucket.ships.extend(inside);
+            bucket.peak = bucket.peak.max(inside.len());
+            for mmsi in inside {
+                if let Some(ship) = ships.ships.get(mmsi) {
+                    bucket.speed_sum += ship.speed;
+                    bucket.samples += 1;
+                }
+            }
+        }
+    }
+
+    // Buckets overlapping the last `window_secs`, oldest first; None for an
+    // unknown zone
+    pub fn history(&self, zone: &str, window_secs: u64, now: u64) -> Option<AreaHistory> {
+        let zones = self.zones.lock().unwrap();
+        let (zone, buckets) = zones.get_key_value(zone)?;
+        let from = now.saturating_sub(window_secs);
+        let buckets = buckets
+            .iter()
+            .filter(|bucket| bucket.start + BUCKET_SECS > from)
+            .map(|bucket| AreaBucket {
+                start: bucket.start,
+                ships: bucket.ships.len(),
+                peak_ships: bucket.peak,
+                avg_speed_kn: (bucket.samples > 0).then(|| bucket.speed_sum / bucket.samples as f64),
+            })
+            .collect();
+        Some(AreaHistory { zone: zone.clone(), bucket_secs: BUCKET_SECS, buckets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::Ship;
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("24h").unwrap(), 86_400);
+        assert_eq!(parse_window("90m").unwrap(), 5400);
+        assert_eq!(parse_window("600").unwrap(), 600);
+        assert!(parse_window("8d").is_err());
+        assert!(parse_window("3w").is_err());
+        assert!(parse_window("h").is_err());
+    }
+
+    #[test]
+    fn test_buckets() {
+        let ships = ShipCache::new();
+        for (mmsi, speed) in [(1, 10.0), (2, 4.0), (3, 0.0)] {
+            let mut ship = Ship::new(mmsi, "");
+            ship.speed = speed;
+            ships.insert_ship(mmsi, ship);
+        }
+        let stats = AreaStats::new();
+        let zone: Arc<str> = Arc::from("Strait");
+        let t0 = BUCKET_SECS * 100;
+        stats.record(&HashMap::from([(zone.clone(), vec![1, 2])]), &ships, t0);
+        stats.record(&HashMap::from([(zone.clone(), vec![2, 3])]), &ships, t0 + 60);
+        stats.record(&HashMap::from([(zone.clone(), vec![])]), &ships, t0 + BUCKET_SECS);
+
+        let history = stats.history("Strait", 86_400, t0 + BUCKET_SECS).unwrap();
+        assert_eq!(
+            history.buckets,
+            vec![
+                AreaBucket { start: t0, ships: 3, peak_ships: 2, avg_speed_kn: Some(4.5) },
+                AreaBucket { start: t0 + BUCKET_SECS, ships: 0, peak_ships: 0, avg_speed_kn: None },
+            ]
+        );
+        assert_eq!(stats.history("Strait", 60, t0 + BUCKET_SECS + 120).unwrap().buckets.len(), 1);
+
+        // Deleted zones are forgotten
+        stats.record(&HashMap::new(), &ships, t0 + BUCKET_SECS);
+        assert!(stats.history("Strait", 86_400, t0 + BUCKET_SECS).is_none());
+    }
+}
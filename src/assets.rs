@@ -0,0 +1,132 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::path::Path;
+
+// static/ compiled into the binary, so it runs from anywhere. Debug builds
+// read the files from disk instead, so UI changes show without a rebuild.
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Assets;
+
+// The page itself is revalidated on every load, so a new version shows up
+// straight away; the rest are cached for a while, then revalidated by ETag
+const INDEX_CACHE: &str = "no-cache";
+const ASSET_CACHE: &str = "public, max-age=3600";
+// Where the page loads MapLibre from, unless a copy is in static/vendor/
+const MAPLIBRE_CDN: &str = "https://unpkg.com/maplibre-gl@3.6.2/dist/";
+pub const VENDORED_MAPLIBRE: [&str; 2] = ["vendor/maplibre-gl.js", "vendor/maplibre-gl.css"];
+
+fn content_type(path: &str, guessed: &str) -> String {
+    // mime_guess takes .ts for an MPEG transport stream
+    if path.ends_with(".ts") {
+        return "application/typescript".to_string();
+    }
+    if guessed.starts_with("text/") || guessed == "application/javascript" {
+        return format!("{}; charset=utf-8", guessed);
+    }
+    guessed.to_string()
+}
+
+fn etag(sha256: &[u8]) -> String {
+    let mut etag = String::from("\"");
+    for byte in &sha256[..8] {
+        let _ = write!(etag, "{:02x}", byte);
+    }
+    etag.push('"');
+    etag
+}
+
+// An embedded file, or 404
+pub fn serve(path: &str, request: &HeaderMap) -> Response {
+    let Some(file) = Assets::get(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content_type = content_type(path, file.metadata.mimetype());
+    respond(request, etag(&file.metadata.sha256_hash()), ASSET_CACHE, &content_type, file.data.into_owned())
+}
+
+// The page, embedded or from `dir`, told the base path its requests go under
+pub fn index(base: &str, dir: Option<&Path>, request: &HeaderMap) -> Response {
+    let html = match dir {
+        Some(dir) => match std::fs::read(dir.join("index.html")) {
+            Ok(html) => html,
+            Err(_) => return StatusCode::NOT_FOUND.into_response(),
+        },
+        None => match Assets::get("index.html") {
+            Some(file) => file.data.into_owned(),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+    };
+    let mut html = with_base(&String::from_utf8_lossy(&html), base);
+    if has_vendored_maplibre(dir) {
+        html = with_vendored(&html, base);
+    }
+    let html = html.into_bytes();
+    respond(request, etag(&Sha256::digest(&html)), INDEX_CACHE, "text/html; charset=utf-8", html)
+}
+
+// Sets `window.SEAWATCH_BASE` ahead of the page's own scripts
+fn with_base(html: &str, base: &str) -> String {
+    let script = format!("<head>\n    <script>window.SEAWATCH_BASE = {};</script>", serde_json::Value::from(base));
+    html.replacen("<head>", &script, 1)
+}
+
+// Whether MapLibre was copied into static/vendor/ (or `dir`'s vendor/), so the
+// page needs nothing from the internet
+pub fn has_vendored_maplibre(dir: Option<&Path>) -> bool {
+    VENDORED_MAPLIBRE.iter().all(|file| match dir {
+        Some(dir) => dir.join(file).is_file(),
+        None => Assets::get(file).is_some(),
+    })
+}
+
+fn with_vendored(html: &str, base: &str) -> String {
+    html.replace(MAPLIBRE_CDN, &format!("{}/static/vendor/", base))
+}
+
+// 304 when the client's copy is current
+fn respond(request: &HeaderMap, etag: String, cache: &'static str, content_type: &str, body: Vec<u8>) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    let matches = request.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()).is_some_and(|tags| {
+        tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")
+    });
+    if matches {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    if let Ok(content_type) = HeaderValue::from_str(content_type) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    (headers, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() {
+        let response = index("", None, &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], INDEX_CACHE);
+
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, response.headers()[header::ETAG].clone());
+        assert_eq!(index("", None, &request).status(), StatusCode::NOT_MODIFIED);
+        // A different base path is a different page
+        assert_eq!(index("/seamon", None, &request).status(), StatusCode::OK);
+        assert_eq!(with_base("<html><head></head>", "/seamon"), "<html><head>\n    <script>window.SEAWATCH_BASE = \"/seamon\";</script></head>");
+        let page = String::from_utf8_lossy(&Assets::get("index.html").unwrap().data).into_owned();
+        assert!(with_vendored(&page, "/seamon").contains("src=\"/seamon/static/vendor/maplibre-gl.js\""));
+        assert!(!with_vendored(&page, "").contains(MAPLIBRE_CDN));
+        assert_eq!(serve("seawatch.d.ts", &HeaderMap::new()).headers()[header::CONTENT_TYPE], "application/typescript");
+        assert_eq!(serve("missing.js", &HeaderMap::new()).status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::ship::Ship;
+
+// Changes kept per ship, and how long a ship's history outlives its last change
+const MAX_CHANGES_PER_SHIP: usize = 100;
+const RETENTION_SECS: u64 = 30 * 86_400;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Name,
+    Destination,
+    Draught,
+}
+
+// One static-data field changing, for /api/ship/:mmsi/changes
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Change {
+    pub timestamp: u64,
+    pub field: Field,
+    pub from: Value, // A string, or metres for the draught
+    pub to: Value,
+}
+
+// Name, destination and draught changes per ship, oldest first. A field
+// going from unknown to known is the ship being learnt, not a change, so
+// isn't recorded
+#[derive(Default)]
+pub struct Changes {
+    changes: Mutex<HashMap<u32, VecDeque<Change>>>,
+}
+
+impl Changes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn of(&self, mmsi: u32) -> Vec<Change> {
+        self.changes.lock().unwrap().get(&mmsi).map_or_else(Vec::new, |changes| changes.iter().cloned().collect())
+    }
+
+    pub fn observe(&self, before: &Ship, ship: &Ship) {
+        let mut found = Vec::new();
+        let (from, to) = (before.name.trim(), ship.name.trim());
+        if !from.is_empty() && !to.is_empty() && from != to {
+            found.push((Field::Name, Value::from(from), Value::from(to)));
+        }
+        let (from, to) = (before.destination.trim(), ship.destination.trim());
+        if !from.is_empty() && from != to {
+            found.push((Field::Destination, Value::from(from), Value::from(to)));
+        }
+        if let (Some(from), Some(to)) = (before.draught, ship.draught)
+            && from != to
+        {
+            found.push((Field::Draught, Value::from(from), Value::from(to)));
+        }
+        if found.is_empty() {
+            return;
+        }
+
+        let mut changes = self.changes.lock().unwrap();
+        let history = changes.entry(ship.mmsi).or_default();
+        for (field, from, to) in found {
+            history.push_back(Change { timestamp: ship.last_update, field, from, to });
+        }
+        while history.len() > MAX_CHANGES_PER_SHIP {
+            history.pop_front();
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.changes
+            .lock()
+            .unwrap()
+            .retain(|_, history| history.back().is_some_and(|last| now.saturating_sub(last.timestamp) <= RETENTION_SECS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_static_changes() {
+        let changes = Changes::new();
+        let mut ship = Ship::new(244660000, "");
+        let mut next = |update: &dyn Fn(&mut Ship)| {
+            let before = ship.clone();
+            update(&mut ship);
+            ship.last_update += 60;
+            changes.observe(&before, &ship);
+        };
+        // Learning the name, destination and draught isn't a change
+        next(&|ship| (ship.name, ship.destination, ship.draught) = (intern("ALIDA"), intern("NLRTM"), Some(7.5)));
+        next(&|ship| (ship.destination, ship.draught) = (intern("BEANR"), Some(9.1)));
+        next(&|ship| ship.name = intern("SEA ALIDA"));
+
+        let history = changes.of(244660000);
+        let fields: Vec<Field> = history.iter().map(|change| change.field).collect();
+        assert_eq!(fields, vec![Field::Destination, Field::Draught, Field::Name]);
+        assert_eq!((&history[0].from, &history[0].to), (&Value::from("NLRTM"), &Value::from("BEANR")));
+        assert_eq!(history[1].to, Value::from(9.1));
+        changes.purge(history[2].timestamp + RETENTION_SECS + 1);
+        assert!(changes.of(244660000).is_empty());
+    }
+}
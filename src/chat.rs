@@ -0,0 +1,168 @@
+use anyhow::Result;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Duration;
+use tracing::{debug, warn};
+
+use crate::alerts::Action;
+use crate::events::Event;
+use crate::monitor::Monitor;
+use crate::ports::NearestPort;
+use crate::ship::{Ship, ShipCache};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Placeholders: {rule} {name} {mmsi} {lat} {lng} {speed} {heading} {destination} {time}
+pub const DEFAULT_TEMPLATE: &str = "{rule}: {name} ({mmsi}) at {lat}, {lng}, {speed} kn";
+
+// Credentials for the chat platforms; rules pick which ones an alert goes to
+#[derive(Default)]
+pub struct ChatConfig {
+    pub telegram_token: Option<String>,
+    pub slack_webhook: Option<String>,
+    pub discord_webhook: Option<String>,
+}
+
+pub struct ChatNotifier {
+    config: ChatConfig,
+    client: reqwest::Client,
+}
+
+impl ChatNotifier {
+    pub fn new(config: ChatConfig) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { config, client })
+    }
+
+    // Where an action posts to and with what body, or None if it isn't a chat
+    // action or its platform isn't configured
+    fn request(&self, action: &Action, text: impl Fn(&Option<String>) -> String) -> Option<Result<(String, serde_json::Value)>> {
+        let missing = |what: &str| Some(Err(anyhow::anyhow!("{} is not configured", what)));
+        match action {
+            Action::Telegram { chat_id, template } => match &self.config.telegram_token {
+                Some(token) => Some(Ok((
+                    format!("https://api.telegram.org/bot{}/sendMessage", token),
+                    json!({ "chat_id": chat_id, "text": text(template) }),
+                ))),
+                None => missing("TELEGRAM_BOT_TOKEN"),
+            },
+            Action::Slack { webhook_url, template } => match webhook_url.as_ref().or(self.config.slack_webhook.as_ref()) {
+                Some(url) => Some(Ok((url.clone(), json!({ "text": text(template) })))),
+                None => missing("SLACK_WEBHOOK_URL"),
+            },
+            Action::Discord { webhook_url, template } => match webhook_url.as_ref().or(self.config.discord_webhook.as_ref()) {
+                Some(url) => Some(Ok((url.clone(), json!({ "content": text(template) })))),
+                None => missing("DISCORD_WEBHOOK_URL"),
+            },
+            _ => None,
+        }
+    }
+
+    async fn post(&self, url: &str, body: &serde_json::Value) -> Result<()> {
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(body)?)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("{}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+pub fn render(template: &str, event: &Event, rule: &str, ship: Option<&Ship>, port: Option<&NearestPort>) -> String {
+    let name = ship.map(|ship| ship.name.trim()).filter(|name| !name.is_empty()).unwrap_or("Unknown vessel");
+    let time = chrono::DateTime::from_timestamp(event.timestamp as i64, 0)
+        .map_or_else(|| event.timestamp.to_string(), |time| time.to_rfc3339());
+    let field = |f: fn(&Ship) -> String| ship.map_or_else(|| "?".to_string(), f);
+
+    template
+        .replace("{rule}", rule)
+        .replace("{name}", name)
+        .replace("{mmsi}", &event.mmsi.to_string())
+        .replace("{lat}", &field(|ship| format!("{:.5}", ship.lat)))
+        .replace("{lng}", &field(|ship| format!("{:.5}", ship.lng)))
+        .replace("{speed}", &field(|ship| format!("{:.1}", ship.speed)))
+        .replace("{heading}", &field(|ship| ship.heading.to_string()))
+        .replace("{destination}", &field(|ship| ship.destination.trim().to_string()))
+        .replace("{nearest_port}", &port.map_or_else(|| "?".to_string(), |port| port.to_string()))
+        .replace("{time}", &time)
+}
+
+// Post alerts to the chat channels their actions select
+pub async fn chat_task(
+    mut events: broadcast::Receiver<Arc<Event>>,
+    notifier: Arc<ChatNotifier>,
+    monitor: Arc<Monitor>,
+    ships: Arc<ShipCache>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Chat notifier fell behind, {} events skipped", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let Some((label, actions)) = monitor.actions_for(&event) else {
+            continue;
+        };
+
+        let ship = ships.ships.get(&event.mmsi).map(|ship| ship.clone());
+        let port = ship.as_ref().and_then(|ship| monitor.nearest_port(ship));
+        let text = |template: &Option<String>| {
+            render(template.as_deref().unwrap_or(DEFAULT_TEMPLATE), &event, &label, ship.as_ref(), port.as_ref())
+        };
+        for action in &actions {
+            match notifier.request(action, text) {
+                Some(Ok((url, body))) => {
+                    let (notifier, id) = (notifier.clone(), event.id);
+                    tokio::spawn(async move {
+                        match notifier.post(&url, &body).await {
+                            Ok(()) => debug!("Posted event {} to chat", id),
+                            Err(e) => warn!("Could not post event {} to chat: {}", id, e),
+                        }
+                    });
+                }
+                Some(Err(e)) => warn!("Alert '{}' has a chat action but {}", label, e),
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventKind, Priority};
+
+    #[test]
+    fn test_templates_and_requests() {
+        let event = Event { id: 1, timestamp: 0, mmsi: 235_000_001, priority: Priority::Normal, kind: EventKind::Alert { rule: Arc::from("fast") } };
+        let mut ship = Ship::new(235_000_001, "OCEAN SPIRIT");
+        (ship.lat, ship.lng, ship.speed) = (51.0, 1.5, 16.24);
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, &event, "fast", Some(&ship), None),
+            "fast: OCEAN SPIRIT (235000001) at 51.00000, 1.50000, 16.2 kn"
+        );
+        assert_eq!(render("{name} {speed} {nearest_port}", &event, "fast", None, None), "Unknown vessel ? ?");
+
+        let notifier = ChatNotifier::new(ChatConfig {
+            slack_webhook: Some("https://hooks.slack.com/services/T/B/X".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let text = |_: &Option<String>| "hello".to_string();
+        let (url, body) = notifier.request(&Action::Slack { webhook_url: None, template: None }, text).unwrap().unwrap();
+        assert_eq!(url, "https://hooks.slack.com/services/T/B/X");
+        assert_eq!(body, json!({ "text": "hello" }));
+        let telegram = Action::Telegram { chat_id: "42".into(), template: None };
+        assert!(notifier.request(&telegram, text).unwrap().is_err());
+        assert!(notifier.request(&Action::Log, text).is_none());
+    }
+}
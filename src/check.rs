@@ -0,0 +1,355 @@
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+use crate::alerts::{self, Condition};
+use crate::apikeys::ApiKeys;
+use crate::assets;
+use crate::config::{Cli, Config};
+use crate::forward::Forwarder;
+use crate::locode::Locodes;
+use crate::photos::Photos;
+use crate::plugin::Registry;
+use crate::ports::Ports;
+use crate::searches::Searches;
+
+// The result of `seawatch check-config`: everything startup would read,
+// checked without binding a socket or connecting anywhere
+#[derive(Default)]
+pub struct Report {
+    lines: Vec<(Outcome, String, String)>, // Outcome, what was checked, detail
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Report {
+    fn ok(&mut self, subject: &str, detail: String) {
+        self.lines.push((Outcome::Ok, subject.to_string(), detail));
+    }
+
+    fn warn(&mut self, subject: &str, detail: String) {
+        self.lines.push((Outcome::Warning, subject.to_string(), detail));
+    }
+
+    fn error(&mut self, subject: &str, detail: String) {
+        self.lines.push((Outcome::Error, subject.to_string(), detail));
+    }
+
+    pub fn errors(&self) -> usize {
+        self.lines.iter().filter(|(outcome, ..)| *outcome == Outcome::Error).count()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (outcome, subject, detail) in &self.lines {
+            let label = match outcome {
+                Outcome::Ok => "ok",
+                Outcome::Warning => "warning",
+                Outcome::Error => "error",
+            };
+            out.push_str(&format!("{:<8}{}: {}\n", label, subject, detail));
+        }
+        match self.errors() {
+            0 => out.push_str("Configuration OK\n"),
+            1 => out.push_str("1 problem found\n"),
+            n => out.push_str(&format!("{} problems found\n", n)),
+        }
+        out
+    }
+}
+
+// `env` looks up the environment variables main reads besides the config's;
+// `std::env::var` in practice, a map in tests
+pub fn run(cli: &Cli, env: impl Fn(&str) -> Option<String>) -> Report {
+    let mut report = Report::default();
+
+    match Config::load(cli) {
+        Ok(config) => check_config(&mut report, cli, &config, &env),
+        Err(e) => report.error("config", format!("{:#}", e)),
+    }
+
+    check_plugins(&mut report, &env);
+    check_urls(&mut report, &env);
+    report
+}
+
+fn check_config(report: &mut Report, cli: &Cli, config: &Config, env: &impl Fn(&str) -> Option<String>) {
+    let source = match &cli.config {
+        Some(path) => path.display().to_string(),
+        None if Path::new(crate::config::DEFAULT_PATH).exists() => crate::config::DEFAULT_PATH.to_string(),
+        None => "defaults".to_string(),
+    };
+    report.ok("config", format!("loaded from {}", source));
+
+    // Without an upstream key, a server that neither simulates nor follows
+    // another instance gets no traffic; offline, it needs a local source instead
+    let upstream = cli.simulate.is_none() && config.peer.follow_url.is_none() && !cli.offline && !cli.replay;
+    if upstream {
+        match Url::parse(&config.upstream.url) {
+            Ok(url) if url.scheme() == "ws" || url.scheme() == "wss" => {}
+            Ok(url) => report.error("upstream.url", format!("{} is not a ws:// or wss:// URL", url)),
+            Err(e) => report.error("upstream.url", format!("{}: {}", config.upstream.url, e)),
+        }
+        if config.upstream.api_key.is_none() {
+            report.error("upstream.api_key", "not set; set AIS_STREAM_API_KEY or upstream.api_key, or run with --simulate".to_string());
+        }
+    }
+    if cli.offline {
+        check_offline(report, cli, config, env);
+    }
+    if cli.replay && !env("SOURCES").is_some_and(|specs| specs.split(',').any(crate::plugin::replays)) {
+        report.error("REPLAY", "nothing to replay: set SOURCES to a recording, e.g. states:ships.ndjson or nmea-file:recording.nmea".to_string());
+    }
+
+    check_monitor(report, config);
+    check_peer(report, cli, config);
+
+    if let Some(dir) = &config.server.static_dir {
+        if !dir.is_dir() {
+            report.error("server.static_dir", format!("{} is not a directory", dir.display()));
+        } else if !config.server.headless && !dir.join("index.html").is_file() {
+            report.error("server.static_dir", format!("{} has no index.html", dir.display()));
+        }
+    }
+    for (key, path) in [("server.tls_cert", &config.server.tls_cert), ("server.tls_key", &config.server.tls_key)] {
+        if let Some(path) = path
+            && let Err(e) = std::fs::File::open(path)
+        {
+            report.error(key, format!("{}: {}", path.display(), e));
+        }
+    }
+    if let Some(path) = &config.server.unix_socket {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if !parent.is_dir() {
+            report.error("server.unix_socket", format!("{} doesn't exist", parent.display()));
+        }
+    }
+    if let Some(path) = &config.server.api_keys {
+        match ApiKeys::from_file(&path.to_string_lossy()) {
+            Ok(keys) if keys.is_empty() => report.warn("server.api_keys", format!("{} has no keys, so the API stays open", path.display())),
+            Ok(keys) => report.ok("server.api_keys", format!("{} keys from {}", keys.usage().len(), path.display())),
+            Err(e) => report.error("server.api_keys", format!("{}: {}", path.display(), e)),
+        }
+    }
+    if let Some(path) = &config.server.searches_file {
+        match Searches::from_file(&path.to_string_lossy()) {
+            Ok(searches) => report.ok("server.searches_file", format!("{} saved searches from {}", searches.all().len(), path.display())),
+            Err(e) => report.error("server.searches_file", e.to_string()),
+        }
+    }
+    let photos = &config.photos;
+    if let Some(template) = &photos.api_url
+        && let Err(e) = Photos::new(template, photos.api_key.clone(), &photos.pointer, Duration::ZERO)
+    {
+        report.error("photos.api_url", format!("{}: {}", template, e));
+    }
+    if let Some(dir) = &config.tile_cache.dir
+        && dir.exists()
+        && !dir.is_dir()
+    {
+        report.error("tile_cache.dir", format!("{} is not a directory", dir.display()));
+    }
+    for (name, reports) in &config.reports {
+        if !reports.email.is_empty() && env("SMTP_URL").is_none() {
+            report.error(&format!("reports.{}.email", name), "set, but SMTP_URL isn't".to_string());
+        }
+        if let Some(dir) = &reports.dir
+            && dir.exists()
+            && !dir.is_dir()
+        {
+            report.error(&format!("reports.{}.dir", name), format!("{} is not a directory", dir.display()));
+        }
+    }
+}
+
+// The files the monitor and forwarding start from
+fn check_monitor(report: &mut Report, config: &Config) {
+    let monitor = &config.monitor;
+    let ports = match &monitor.ports_file {
+        Some(path) => match Ports::from_file(&path.to_string_lossy()) {
+            Ok(ports) => {
+                report.ok("monitor.ports_file", format!("{} ports from {}", ports.all().len(), path.display()));
+                ports
+            }
+            Err(e) => {
+                report.error("monitor.ports_file", format!("{}: {}", path.display(), e));
+                Ports::builtin()
+            }
+        },
+        None => Ports::builtin(),
+    };
+    if let Some(path) = &monitor.locodes_file {
+        match Locodes::from_file(&path.to_string_lossy(), &ports) {
+            Ok(locodes) => report.ok("monitor.locodes_file", format!("{} UN/LOCODEs from {}", locodes.len(), path.display())),
+            Err(e) => report.error("monitor.locodes_file", format!("{}: {}", path.display(), e)),
+        }
+    }
+    if let Some(path) = &monitor.alert_rules {
+        check_rules(report, &path.to_string_lossy());
+    }
+    if let Some(path) = &config.forwarding.targets_file {
+        match Forwarder::from_file(&path.to_string_lossy()) {
+            Ok(forwarder) => report.ok("forwarding.targets_file", format!("{} targets from {}", forwarder.status().len(), path.display())),
+            Err(e) => report.error("forwarding.targets_file", format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+fn check_peer(report: &mut Report, cli: &Cli, config: &Config) {
+    let peer = &config.peer;
+    for (key, url) in [("peer.follow_url", &peer.follow_url), ("peer.push_url", &peer.push_url)] {
+        if let Some(url) = url
+            && let Err(e) = Url::parse(url)
+        {
+            report.error(key, format!("{}: {}", url, e));
+        }
+    }
+    if cli.simulate.is_some() && peer.follow_url.is_some() {
+        report.error("peer.follow_url", "can't be combined with --simulate".to_string());
+    }
+    if peer.push_url.is_some() && peer.token.is_none() {
+        report.error("peer.push_url", "peer.token must be set along with it".to_string());
+    }
+}
+
+// What would still reach out to the internet, or leave the server without ships
+fn check_offline(report: &mut Report, cli: &Cli, config: &Config, env: &impl Fn(&str) -> Option<String>) {
+    if cli.simulate.is_none() && env("SOURCES").is_none() && config.peer.follow_url.is_none() {
+        report.error("OFFLINE", "no ships: set SOURCES to a local receiver, e.g. nmea-udp:0.0.0.0:10110, or peer.follow_url, or run with --simulate".to_string());
+    }
+    if config.photos.api_url.is_some() {
+        report.warn("photos.api_url", "set, but photos aren't fetched offline".to_string());
+    }
+    if config.tile_cache.dir.is_none() {
+        report.warn("tile_cache.dir", "not set, so the map has no basemap offline".to_string());
+    }
+    if !config.server.headless && !assets::has_vendored_maplibre(config.server.static_dir.as_deref()) {
+        report.warn("static/vendor", format!("no {}, so the map page loads MapLibre from unpkg.com", assets::VENDORED_MAPLIBRE.join(" or ")));
+    }
+}
+
+fn check_rules(report: &mut Report, path: &str) {
+    let rules = match alerts::load_rules(path) {
+        Ok(rules) => rules,
+        Err(e) => return report.error("ALERT_RULES", format!("{}: {}", path, e)),
+    };
+    let mut names = std::collections::HashSet::new();
+    for rule in &rules {
+        if !names.insert(rule.name.as_str()) {
+            report.error("ALERT_RULES", format!("{}: rule '{}' is defined twice; the second replaces the first", path, rule.name));
+        }
+        // Zones are only ever created through the API, so these can't be
+        // resolved here; an empty name never matches anything though
+        if rule.conditions.iter().any(|condition| matches!(condition, Condition::InZone { zone } if zone.is_empty())) {
+            report.error("ALERT_RULES", format!("{}: rule '{}' has an in_zone condition without a zone", path, rule.name));
+        }
+    }
+    report.ok("ALERT_RULES", format!("{} rules from {}", rules.len(), path));
+}
+
+fn check_plugins(report: &mut Report, env: &impl Fn(&str) -> Option<String>) {
+    #[allow(unused_mut)]
+    let mut registry = Registry::with_builtins();
+    #[cfg(feature = "dynamic-plugins")]
+    if let Some(paths) = env("PLUGINS") {
+        for path in paths.split(',') {
+            // Safety: as in main, PLUGINS is trusted configuration
+            if let Err(e) = unsafe { registry.load(path.trim()) } {
+                report.error("PLUGINS", format!("{}: {}", path.trim(), e));
+            }
+        }
+    }
+    #[cfg(not(feature = "dynamic-plugins"))]
+    if env("PLUGINS").is_some() {
+        report.warn("PLUGINS", "set, but this build can't load plugins; build with --features dynamic-plugins".to_string());
+    }
+    // Factories only parse their argument; sources and sinks do their I/O once run
+    if let Some(specs) = env("SOURCES") {
+        for spec in specs.split(',') {
+            if let Err(e) = registry.source(spec) {
+                report.error("SOURCES", format!("{}: {}", spec.trim(), e));
+            }
+        }
+    }
+    if let Some(specs) = env("SINKS") {
+        for spec in specs.split(',') {
+            if let Err(e) = registry.sink(spec) {
+                report.error("SINKS", format!("{}: {}", spec.trim(), e));
+            }
+        }
+    }
+}
+
+fn check_urls(report: &mut Report, env: &impl Fn(&str) -> Option<String>) {
+    for var in ["FIREHOSE_URL", "MQTT_URL", "NATS_URL", "INFLUX_URL", "ELASTICSEARCH_URL"] {
+        if let Some(url) = env(var)
+            && let Err(e) = Url::parse(&url)
+        {
+            report.error(var, format!("{}: {}", url, e));
+        }
+    }
+    if let Some(urls) = env("WEBHOOK_URLS") {
+        for url in urls.split(',') {
+            if let Err(e) = Url::parse(url.trim()) {
+                report.error("WEBHOOK_URLS", format!("{}: {}", url.trim(), e));
+            }
+        }
+    }
+    if env("SMTP_URL").is_some() && env("SMTP_FROM").is_none() {
+        report.error("SMTP_URL", "SMTP_FROM must be set along with it".to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_check_config() {
+        let rules = std::env::temp_dir().join(format!("seawatch-check-rules-{}.json", std::process::id()));
+        std::fs::write(&rules, r#"[{"name": "tankers", "conditions": [{"type": "ship_type", "min": 80, "max": 89}]},
+            {"name": "tankers", "conditions": [{"type": "in_zone", "zone": ""}]}]"#).unwrap();
+        let env: HashMap<&str, String> = HashMap::from([("SOURCES", "states:-,bogus".to_string())]);
+        let cli = Cli::parse_from([
+            "seawatch",
+            "--set",
+            "upstream.api_key=key",
+            "--set",
+            &format!("monitor.alert_rules={}", rules.display()),
+            "--set",
+            "monitor.ports_file=/nonexistent/ports.json",
+            "--set",
+            "peer.push_url=https://peer.example",
+            "check-config",
+        ]);
+        let report = run(&cli, |var| env.get(var).cloned());
+        std::fs::remove_file(&rules).unwrap();
+
+        let out = report.render();
+        assert!(out.contains("ok      config: "));
+        assert!(out.contains("error   monitor.ports_file: /nonexistent/ports.json: "));
+        assert!(out.contains("rule 'tankers' is defined twice"));
+        assert!(out.contains("rule 'tankers' has an in_zone condition without a zone"));
+        assert!(out.contains("error   SOURCES: bogus: No source named 'bogus'\n"));
+        assert!(out.contains("error   peer.push_url: peer.token must be set along with it\n"));
+        assert!(!out.contains("upstream.api_key"));
+        assert_eq!(report.errors(), 5);
+        assert!(out.ends_with("5 problems found\n"));
+        // Settings that don't parse stop the config from loading
+        let loiter = run(&Cli::parse_from(["seawatch", "--set", "monitor.loiter_min=ten", "check-config"]), |_| None).render();
+        assert!(loiter.contains("error   config: "));
+
+        // Offline, the key isn't needed but a local source is
+        let offline = run(&Cli::parse_from(["seawatch", "--offline", "check-config"]), |_| None).render();
+        assert!(!offline.contains("upstream.api_key"));
+        assert!(offline.contains("error   OFFLINE: no ships: "));
+        assert!(offline.contains("warning tile_cache.dir: "));
+    }
+}
@@ -0,0 +1,248 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::geo::{haversine_m, EARTH_RADIUS_M};
+use crate::predict::{Motion, Tracks};
+use crate::ship::{Ship, ShipCache};
+
+pub const METRES_PER_NM: f64 = 1852.0;
+const KNOTS_TO_MS: f64 = METRES_PER_NM / 3600.0;
+// Ships slower than this are treated as stationary targets, never as the
+// vessel a scan starts from
+const MIN_SPEED: f64 = 0.5;
+// Positions older than this are too stale to project forward
+const MAX_REPORT_AGE: u64 = 300;
+// Turning ships are compared along their predicted tracks at this resolution
+const PATH_STEP_SECS: u64 = 15;
+
+pub struct CollisionConfig {
+    pub range_m: f64,   // Only pairs closer than this are compared
+    pub cpa_m: f64,     // Warn when they will pass closer than this...
+    pub tcpa_secs: f64, // ...within this many seconds
+}
+
+impl Default for CollisionConfig {
+    fn default() -> Self {
+        Self {
+            range_m: 6.0 * METRES_PER_NM,
+            cpa_m: 0.5 * METRES_PER_NM,
+            tcpa_secs: 20.0 * 60.0,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Risk {
+    pub mmsi: u32,
+    pub other: u32,
+    pub distance_m: f64,
+    pub cpa_m: f64,
+    pub tcpa_secs: f64,
+    pub since: u64, // When the pair first crossed the thresholds
+}
+
+// Closest point of approach between two ships on their current courses and
+// speeds: (distance in metres, seconds until then, negative if it has passed).
+// Uses a flat projection around `a`, fine at collision-watch ranges.
+pub fn cpa(a: &Ship, b: &Ship) -> (f64, f64) {
+    let metres_per_degree = EARTH_RADIUS_M.to_radians();
+    let x = (b.lng - a.lng) * a.lat.to_radians().cos() * metres_per_degree;
+    let y = (b.lat - a.lat) * metres_per_degree;
+
+    let velocity = |ship: &Ship| {
+        if ship.speed < MIN_SPEED || ship.cog >= 360.0 {
+            return (0.0, 0.0);
+        }
+        let (sin, cos) = ship.cog.to_radians().sin_cos();
+        (ship.speed * KNOTS_TO_MS * sin, ship.speed * KNOTS_TO_MS * cos)
+    };
+    let ((avx, avy), (bvx, bvy)) = (velocity(a), velocity(b));
+    let (vx, vy) = (bvx - avx, bvy - avy);
+
+    let closing = vx * vx + vy * vy;
+    if closing < 1e-9 {
+        // Keeping station: the distance never changes
+        return ((x * x + y * y).sqrt(), 0.0);
+    }
+    let tcpa = -(x * vx + y * vy) / closing;
+    let (cx, cy) = (x + vx * tcpa, y + vy * tcpa);
+    ((cx * cx + cy * cy).sqrt(), tcpa)
+}
+
+// The same along predicted, possibly curved, tracks from `now` up to
+// `horizon_secs` ahead, sampled every PATH_STEP_SECS
+pub fn predicted_cpa(a: &Motion, b: &Motion, now: u64, horizon_secs: f64) -> (f64, f64) {
+    (0..=horizon_secs as u64)
+        .step_by(PATH_STEP_SECS as usize)
+        .map(|secs| {
+            let ((a_lat, a_lng), (b_lat, b_lng)) = (a.position_at(now + secs), b.position_at(now + secs));
+            (haversine_m(a_lat, a_lng, b_lat, b_lng), secs as f64)
+        })
+        .min_by(|x, y| x.0.total_cmp(&y.0))
+        .unwrap_or((f64::MAX, 0.0))
+}
+
+// Pairs of ships currently on a collision course
+pub struct CollisionWatch {
+    config: CollisionConfig,
+    risks: Mutex<HashMap<(u32, u32), Risk>>,
+}
+
+impl CollisionWatch {
+    pub fn new(config: CollisionConfig) -> Self {
+        Self { config, risks: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn risks(&self) -> Vec<Risk> {
+        let mut risks: Vec<Risk> = self.risks.lock().unwrap().values().cloned().collect();
+        risks.sort_by(|a, b| a.tcpa_secs.total_cmp(&b.tcpa_secs));
+        risks
+    }
+
+    // Compare every moving ship with its neighbours, found through the
+    // spatial index, and log a warning for each pair that newly crosses the
+    // thresholds. Pairs where either ship is turning are compared along
+    // their predicted tracks rather than straight lines.
+    pub fn scan(&self, ships: &ShipCache, tracks: &Tracks, events: &EventLog, now: u64) {
+        let fresh = |ship: &Ship| now.saturating_sub(ship.last_update) <= MAX_REPORT_AGE;
+        let movers: Vec<Ship> = ships
+            .ships
+            .iter()
+            .filter(|ship| ship.speed >= MIN_SPEED && ship.cog < 360.0 && fresh(ship))
+            .map(|ship| ship.clone())
+            .collect();
+
+        let mut found = HashMap::new();
+        for a in &movers {
+            let d_lat = (self.config.range_m / EARTH_RADIUS_M).to_degrees();
+            let d_lng = d_lat / a.lat.to_radians().cos().max(1e-6);
+            for b in ships.get_full_ships_in_bbox(a.lat - d_lat, a.lng - d_lng, a.lat + d_lat, a.lng + d_lng) {
+                if b.mmsi == a.mmsi || !fresh(&b) {
+                    continue;
+                }
+                // Pairs of movers are seen from both sides; keep one
+                let b_moves = b.speed >= MIN_SPEED && b.cog < 360.0;
+                if b_moves && b.mmsi < a.mmsi {
+                    continue;
+                }
+                let distance_m = haversine_m(a.lat, a.lng, b.lat, b.lng);
+                if distance_m > self.config.range_m {
+                    continue;
+                }
+                let (a_motion, b_motion) = (tracks.motion(a), tracks.motion(&b));
+                let (cpa_m, tcpa_secs) = if a_motion.is_turning() || b_motion.is_turning() {
+                    predicted_cpa(&a_motion, &b_motion, now, self.config.tcpa_secs)
+                } else {
+                    cpa(a, &b)
+                };
+                if cpa_m <= self.config.cpa_m && (0.0..=self.config.tcpa_secs).contains(&tcpa_secs) {
+                    let key = (a.mmsi.min(b.mmsi), a.mmsi.max(b.mmsi));
+                    let risk = Risk { mmsi: key.0, other: key.1, distance_m, cpa_m, tcpa_secs, since: now };
+                    found.insert(key, risk);
+                }
+            }
+        }
+
+        let mut risks = self.risks.lock().unwrap();
+        for (key, risk) in found.iter_mut() {
+            match risks.get(key) {
+                Some(previous) => risk.since = previous.since,
+                None => {
+                    events.push(
+                        now,
+                        risk.mmsi,
+                        EventKind::CollisionRisk {
+                            other: risk.other,
+                            cpa_m: risk.cpa_m.round(),
+                            tcpa_secs: risk.tcpa_secs.round(),
+                        },
+                    );
+                }
+            }
+        }
+        *risks = found;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    fn ship(mmsi: u32, lat: f64, lng: f64, speed: f64, cog: f64) -> Ship {
+        let mut ship = Ship::new(mmsi, "");
+        (ship.lat, ship.lng, ship.speed, ship.cog, ship.last_update) = (lat, lng, speed, cog, 1000);
+        ship
+    }
+
+    #[test]
+    fn test_cpa() {
+        // Head on, 2 nm apart, closing at 20 kn: meet in 6 minutes
+        let (a, b) = (ship(1, 50.0, -4.0, 10.0, 0.0), ship(2, 50.0 + 2.0 / 60.0, -4.0, 10.0, 180.0));
+        let (cpa_m, tcpa) = cpa(&a, &b);
+        assert!(cpa_m < 1.0, "{}", cpa_m);
+        assert!((tcpa - 360.0).abs() < 2.0, "{}", tcpa);
+
+        // Same course and speed: never closer than now
+        let c = ship(3, 50.0, -3.99, 10.0, 0.0);
+        let (cpa_m, tcpa) = cpa(&a, &c);
+        assert_eq!(tcpa, 0.0);
+        assert!((cpa_m - haversine_m(50.0, -4.0, 50.0, -3.99)).abs() < 5.0);
+
+        // Sailing apart: the closest point is behind them
+        let d = ship(4, 50.0 - 1.0 / 60.0, -4.0, 10.0, 180.0);
+        assert!(cpa(&a, &d).1 < 0.0);
+    }
+
+    #[test]
+    fn test_scan_reports_new_risks_once() {
+        let ships = ShipCache::new();
+        for ship in [
+            ship(1, 50.0, -4.0, 10.0, 0.0),
+            ship(2, 50.0 + 2.0 / 60.0, -4.0, 10.0, 180.0),
+            ship(3, 50.0, -3.0, 10.0, 0.0), // Far away
+        ] {
+            ships.insert_ship(ship.mmsi, ship);
+        }
+        let (watch, events, tracks) = (CollisionWatch::new(CollisionConfig::default()), EventLog::new(), Tracks::new());
+        watch.scan(&ships, &tracks, &events, 1000);
+        watch.scan(&ships, &tracks, &events, 1010);
+
+        let risks = watch.risks();
+        assert_eq!(risks.len(), 1);
+        assert_eq!((risks[0].mmsi, risks[0].other, risks[0].since), (1, 2, 1000));
+        assert_eq!(events.query(&EventFilter::default()).len(), 1);
+
+        // Stale positions aren't projected
+        watch.scan(&ships, &tracks, &events, 2000);
+        assert!(watch.risks().is_empty());
+    }
+
+    #[test]
+    fn test_turning_ships_use_predicted_tracks() {
+        // Parallel and 1 nm apart, but `a` is swinging to starboard across
+        // `b`'s path: a straight-line CPA would miss it
+        let mut a = ship(1, 50.0, -4.0, 10.0, 0.0);
+        let b = ship(2, 50.0, -4.0 + 1.0 / 60.0 / 50f64.to_radians().cos(), 10.0, 0.0);
+        assert!(cpa(&a, &b).0 > METRES_PER_NM);
+
+        let tracks = Tracks::new();
+        for (t, cog) in [(880, 348.0), (940, 354.0), (1000, 0.0)] {
+            (a.cog, a.last_update) = (cog, t);
+            tracks.observe(&a);
+        }
+        let (cpa_m, tcpa_secs) = predicted_cpa(&tracks.motion(&a), &tracks.motion(&b), 1000, 1200.0);
+        assert!(cpa_m < 0.5 * METRES_PER_NM, "{}", cpa_m);
+        assert!(tcpa_secs > 0.0 && tcpa_secs <= 1200.0);
+
+        let ships = ShipCache::new();
+        ships.insert_ship(1, a);
+        ships.insert_ship(2, b);
+        let (watch, events) = (CollisionWatch::new(CollisionConfig::default()), EventLog::new());
+        watch.scan(&ships, &tracks, &events, 1000);
+        assert_eq!(watch.risks().len(), 1);
+    }
+}
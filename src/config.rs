@@ -0,0 +1,679 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::collision::{CollisionConfig, METRES_PER_NM};
+use crate::geofence::ZoneSpec;
+use crate::index::IndexKind;
+use crate::ingest::{self, ShedPolicy, Unchanged};
+use crate::logging::LogFormat;
+use crate::loitering;
+use crate::photos;
+use crate::reports::ReportConfig;
+use crate::ship;
+use crate::shutdown;
+use crate::simulate;
+use crate::tileproxy;
+
+// Read when no --config is given, if it exists
+pub const DEFAULT_PATH: &str = "seawatch.toml";
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about = "Live AIS ship tracking server")]
+pub struct Cli {
+    #[arg(short, long, env = "SEAWATCH_CONFIG", help = "TOML configuration file (default: ./seawatch.toml, if present)")]
+    pub config: Option<PathBuf>,
+    #[arg(short = 's', long = "set", value_name = "KEY=VALUE", help = "Override a setting, e.g. --set retention.ship_ttl_secs=3600")]
+    pub overrides: Vec<String>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        num_args = 0..=1,
+        default_missing_value = "30",
+        help = "Consume the upstream stream for SECS seconds (default 30), print message rates, parse failures and coverage, and exit without serving"
+    )]
+    pub diagnose: Option<u64>,
+    #[arg(
+        long,
+        value_name = "VESSELS",
+        num_args = 0..=1,
+        default_missing_value = "200",
+        help = "Sail VESSELS synthetic vessels (default 200) between the known ports instead of connecting upstream"
+    )]
+    pub simulate: Option<usize>,
+    #[arg(long, value_name = "SEED", default_value_t = simulate::DEFAULT_SEED, help = "Seed for --simulate; the same seed gives the same fleet and voyages")]
+    pub simulate_seed: u64,
+    #[arg(long, env = "OFFLINE", help = "Run without the internet: ships from local receivers in SOURCES (or --simulate, or FOLLOW_URL), no photos, and only cached basemap tiles")]
+    pub offline: bool,
+    #[arg(long, env = "REPLAY", help = "Play the recordings of the states: and nmea-file: SOURCES against a clock /api/replay can pause, seek and speed up, instead of connecting upstream")]
+    pub replay: bool,
+    #[arg(long, value_enum, env = "LOG_FORMAT", default_value_t = LogFormat::Text, help = "Log as human-readable text or as JSON lines")]
+    pub log_format: LogFormat,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    #[command(about = "Write the TypeScript declarations for the API types")]
+    ExportTypes { path: Option<String> },
+    #[command(about = "Check the configuration and the files it names, without connecting anywhere")]
+    CheckConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub reconnect_secs: u64, // After the stream drops or fails to connect
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self { url: "wss://stream.aisstream.io/v0/stream".to_string(), api_key: None, reconnect_secs: 5 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String, // An IP or host name; "::" for every IPv6 and IPv4 address
+    pub port: u16,
+    pub static_dir: Option<PathBuf>, // Served under /static in place of the files built in
+    pub headless: bool, // Serve the API only, without the map UI and static files
+    pub base_path: String, // e.g. "/seamon", behind a proxy that forwards that path
+    pub access_log: bool, // Log every request, to the seawatch::access target
+    pub cors_origins: Vec<String>, // Web pages that may call the API; "*" for any, none for only its own
+    pub shutdown_timeout_secs: u64,
+    pub tls_cert: Option<PathBuf>, // With tls_key, serve HTTPS instead of HTTP
+    pub tls_key: Option<PathBuf>,
+    pub unix_socket: Option<PathBuf>, // Listen here instead of on host and port
+    pub api_keys: Option<PathBuf>, // Keys the API requires; see `crate::apikeys`
+    pub searches_file: Option<PathBuf>, // Where saved searches are kept
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            static_dir: None,
+            headless: false,
+            base_path: String::new(),
+            access_log: false,
+            cors_origins: vec!["*".to_string()],
+            shutdown_timeout_secs: shutdown::SHUTDOWN_TIMEOUT.as_secs(),
+            tls_cert: None,
+            tls_key: None,
+            unix_socket: None,
+            api_keys: None,
+            searches_file: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestConfig {
+    pub spatial_index: String, // kdtree or rtree
+    pub geohash_precision: usize,
+    pub queue_size: usize,
+    pub shed_policy: String, // drop-oldest, sample:N or class-a
+    pub parse_workers: Option<usize>, // Default: one per core, up to 4
+    pub memory_budget_mb: Option<usize>,
+    // Skip position reports that moved less than this; see `ingest::Unchanged`
+    pub unchanged_distance_m: Option<f64>,
+    pub unchanged_speed_kn: f64,
+    pub unchanged_course_deg: f64,
+    pub unchanged_max_secs: u64,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self {
+            spatial_index: "kdtree".to_string(),
+            geohash_precision: ship::DEFAULT_GEOHASH_PRECISION,
+            queue_size: ingest::DEFAULT_QUEUE_CAPACITY,
+            shed_policy: "drop-oldest".to_string(),
+            parse_workers: None,
+            memory_budget_mb: None,
+            unchanged_distance_m: None,
+            unchanged_speed_kn: ingest::DEFAULT_UNCHANGED_SPEED_KN,
+            unchanged_course_deg: ingest::DEFAULT_UNCHANGED_COURSE_DEG,
+            unchanged_max_secs: ingest::DEFAULT_UNCHANGED_MAX_SECS,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub ship_ttl_secs: u64, // Ships not heard from for this long are forgotten
+    pub cleanup_interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self { ship_ttl_secs: 86_400, cleanup_interval_secs: 300 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntervalConfig {
+    pub index_check_secs: u64, // Whether index cells have drifted and need a rebuild
+    pub memory_check_secs: u64, // Memory use against the budget
+    pub sweep_secs: u64, // Time-based checks: stale alert rules, dark ships and the like
+    pub collision_scan_secs: u64, // Vessel pairs at risk of collision
+}
+
+impl Default for IntervalConfig {
+    fn default() -> Self {
+        Self { index_check_secs: 1, memory_check_secs: 60, sweep_secs: 60, collision_scan_secs: 30 }
+    }
+}
+
+// What the map UI opens with, served as /api/config so deployments don't
+// need a customised copy of it
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MapConfig {
+    pub center: [f64; 2], // Longitude, latitude
+    pub zoom: f64,
+    pub tile_url: String, // Base layer, with {z}, {x} and {y}
+    pub tile_attribution: String,
+    pub seamark_url: Option<String>, // Overlay of buoys, lights and the like; empty for none
+    pub refresh_secs: u64, // How often the UI polls for ships while the live feed is down
+    pub features: BTreeMap<String, bool>, // Flags for the UI, e.g. photos = false
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 20.0],
+            zoom: 3.0,
+            tile_url: "https://tile.openstreetmap.org/{z}/{x}/{y}.png".to_string(),
+            tile_attribution: "© OpenStreetMap contributors".to_string(),
+            seamark_url: Some("https://tiles.openseamap.org/seamark/{z}/{x}/{y}.png".to_string()),
+            refresh_secs: 10,
+            features: BTreeMap::new(),
+        }
+    }
+}
+
+// Ship photos from a photo API; see `crate::photos`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhotosConfig {
+    pub api_url: Option<String>, // With {mmsi}, {imo} and {key}; no photos without it
+    pub api_key: Option<String>,
+    pub pointer: String, // JSON pointer to the image URL, when the API answers with JSON
+    pub cache_hours: u64,
+}
+
+impl Default for PhotosConfig {
+    fn default() -> Self {
+        Self { api_url: None, api_key: None, pointer: photos::DEFAULT_POINTER.to_string(), cache_hours: photos::DEFAULT_CACHE_HOURS }
+    }
+}
+
+// Basemap tiles fetched by the server and kept on disk; see `crate::tileproxy`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TileCacheConfig {
+    pub dir: Option<PathBuf>, // Browsers fetch tiles from the public servers without it
+    pub days: u64, // Fetched again after this long
+}
+
+impl Default for TileCacheConfig {
+    fn default() -> Self {
+        Self { dir: None, days: tileproxy::DEFAULT_CACHE_DAYS }
+    }
+}
+
+// What the monitor looks out for, and the files it starts from
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonitorConfig {
+    pub cpa_range_nm: f64, // Only pairs closer than this are compared...
+    pub cpa_alert_nm: f64, // ...and at risk if they will pass closer than this...
+    pub tcpa_alert_min: f64, // ...within this many minutes
+    pub loiter_min: u64, // Holding position in open water this long is loitering
+    pub ports_file: Option<PathBuf>, // In place of the built-in ports
+    pub locodes_file: Option<PathBuf>, // UN/LOCODE code list, for resolving destinations
+    pub alert_rules: Option<PathBuf>, // JSON array of alert rules
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        let collisions = CollisionConfig::default();
+        Self {
+            cpa_range_nm: collisions.range_m / METRES_PER_NM,
+            cpa_alert_nm: collisions.cpa_m / METRES_PER_NM,
+            tcpa_alert_min: collisions.tcpa_secs / 60.0,
+            loiter_min: loitering::DEFAULT_LOITER_SECS / 60,
+            ports_file: None,
+            locodes_file: None,
+            alert_rules: None,
+        }
+    }
+}
+
+// UDP forwarding of every update as NMEA; see `crate::forward`
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForwardingConfig {
+    pub targets_file: Option<PathBuf>, // JSON array of targets; no forwarding without it
+}
+
+// Other instances: accepting their pushes, pushing to one, or following
+// one's feed instead of aisstream.io; see `crate::peer`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerConfig {
+    pub token: Option<String>, // Shared secret; /api/peer and /api/peer/feed are off without it
+    pub push_url: Option<String>, // e.g. ws://central:8080/api/peer
+    pub name: String, // What this instance calls itself when pushing
+    pub follow_url: Option<String>, // e.g. ws://primary:8080/api/peer/feed
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self { token: None, push_url: None, name: "seawatch".to_string(), follow_url: None }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub upstream: UpstreamConfig,
+    pub server: ServerConfig,
+    pub ingest: IngestConfig,
+    pub retention: RetentionConfig,
+    pub intervals: IntervalConfig,
+    pub map: MapConfig,
+    pub photos: PhotosConfig,
+    pub tile_cache: TileCacheConfig,
+    pub monitor: MonitorConfig,
+    pub forwarding: ForwardingConfig,
+    pub peer: PeerConfig,
+    // Named zones set up at startup, e.g. [regions.Bosphorus]; see `crate::geofence`
+    pub regions: BTreeMap<String, ZoneSpec>,
+    // Traffic summaries of regions, e.g. [reports.Bosphorus]; see `crate::reports`
+    pub reports: BTreeMap<String, ReportConfig>,
+}
+
+// Environment variables that override a setting. AIS_STREAM_API_REAL is what
+// the key was read from originally; AIS_STREAM_API_KEY wins if both are set.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("AIS_STREAM_URL", "upstream.url"),
+    ("AIS_STREAM_API_REAL", "upstream.api_key"),
+    ("AIS_STREAM_API_KEY", "upstream.api_key"),
+    ("HOST", "server.host"),
+    ("PORT", "server.port"),
+    ("TLS_CERT", "server.tls_cert"),
+    ("TLS_KEY", "server.tls_key"),
+    ("UNIX_SOCKET", "server.unix_socket"),
+    ("HEADLESS", "server.headless"),
+    ("STATIC_DIR", "server.static_dir"),
+    ("BASE_PATH", "server.base_path"),
+    ("ACCESS_LOG", "server.access_log"),
+    ("CORS_ORIGINS", "server.cors_origins"),
+    ("API_KEYS", "server.api_keys"),
+    ("SEARCHES_FILE", "server.searches_file"),
+    ("SPATIAL_INDEX", "ingest.spatial_index"),
+    ("GEOHASH_PRECISION", "ingest.geohash_precision"),
+    ("INGEST_QUEUE_SIZE", "ingest.queue_size"),
+    ("SHED_POLICY", "ingest.shed_policy"),
+    ("PARSE_WORKERS", "ingest.parse_workers"),
+    ("MEMORY_BUDGET_MB", "ingest.memory_budget_mb"),
+    ("UNCHANGED_DISTANCE_M", "ingest.unchanged_distance_m"),
+    ("SHIP_TTL_SECS", "retention.ship_ttl_secs"),
+    ("MAP_CENTER", "map.center"),
+    ("MAP_ZOOM", "map.zoom"),
+    ("MAP_TILE_URL", "map.tile_url"),
+    ("PHOTO_API_URL", "photos.api_url"),
+    ("PHOTO_API_KEY", "photos.api_key"),
+    ("PHOTO_API_POINTER", "photos.pointer"),
+    ("PHOTO_CACHE_HOURS", "photos.cache_hours"),
+    ("TILE_CACHE_DIR", "tile_cache.dir"),
+    ("TILE_CACHE_DAYS", "tile_cache.days"),
+    ("CPA_RANGE_NM", "monitor.cpa_range_nm"),
+    ("CPA_ALERT_NM", "monitor.cpa_alert_nm"),
+    ("TCPA_ALERT_MIN", "monitor.tcpa_alert_min"),
+    ("LOITER_MIN", "monitor.loiter_min"),
+    ("PORTS_FILE", "monitor.ports_file"),
+    ("LOCODES_FILE", "monitor.locodes_file"),
+    ("ALERT_RULES", "monitor.alert_rules"),
+    ("UDP_FORWARD", "forwarding.targets_file"),
+    ("PEER_TOKEN", "peer.token"),
+    ("PEER_PUSH_URL", "peer.push_url"),
+    ("PEER_NAME", "peer.name"),
+    ("FOLLOW_URL", "peer.follow_url"),
+];
+
+impl Config {
+    // Defaults, then the file, then environment variables, then --set flags
+    pub fn load(cli: &Cli) -> Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Self::from_file(Path::new(DEFAULT_PATH))?,
+            None => Self::default(),
+        };
+        for (var, key) in ENV_OVERRIDES {
+            if let Ok(value) = std::env::var(var) {
+                config.set(key, &value).map_err(|e| anyhow::anyhow!("{}: {}", var, e))?;
+            }
+        }
+        for assignment in &cli.overrides {
+            let (key, value) = assignment
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("--set takes KEY=VALUE, not {}", assignment))?;
+            config.set(key.trim(), value.trim())?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "upstream.url" => self.upstream.url = value.to_string(),
+            "upstream.api_key" => self.upstream.api_key = Some(value.to_string()),
+            "upstream.reconnect_secs" => self.upstream.reconnect_secs = value.parse()?,
+            "server.host" => self.server.host = value.to_string(),
+            "server.port" => self.server.port = value.parse()?,
+            "server.static_dir" => self.server.static_dir = Some(PathBuf::from(value)),
+            "server.headless" => self.server.headless = value.parse()?,
+            "server.base_path" => self.server.base_path = value.to_string(),
+            "server.access_log" => self.server.access_log = value.parse()?,
+            "server.cors_origins" => {
+                self.server.cors_origins = value.split(',').map(str::trim).filter(|origin| !origin.is_empty()).map(String::from).collect();
+            }
+            "server.shutdown_timeout_secs" => self.server.shutdown_timeout_secs = value.parse()?,
+            "server.tls_cert" => self.server.tls_cert = Some(PathBuf::from(value)),
+            "server.tls_key" => self.server.tls_key = Some(PathBuf::from(value)),
+            "server.unix_socket" => self.server.unix_socket = Some(PathBuf::from(value)),
+            "server.api_keys" => self.server.api_keys = Some(PathBuf::from(value)),
+            "server.searches_file" => self.server.searches_file = Some(PathBuf::from(value)),
+            "ingest.spatial_index" => self.ingest.spatial_index = value.to_string(),
+            "ingest.geohash_precision" => self.ingest.geohash_precision = value.parse()?,
+            "ingest.queue_size" => self.ingest.queue_size = value.parse()?,
+            "ingest.shed_policy" => self.ingest.shed_policy = value.to_string(),
+            "ingest.parse_workers" => self.ingest.parse_workers = Some(value.parse()?),
+            "ingest.memory_budget_mb" => self.ingest.memory_budget_mb = Some(value.parse()?),
+            "ingest.unchanged_distance_m" => self.ingest.unchanged_distance_m = Some(value.parse()?),
+            "ingest.unchanged_speed_kn" => self.ingest.unchanged_speed_kn = value.parse()?,
+            "ingest.unchanged_course_deg" => self.ingest.unchanged_course_deg = value.parse()?,
+            "ingest.unchanged_max_secs" => self.ingest.unchanged_max_secs = value.parse()?,
+            "retention.ship_ttl_secs" => self.retention.ship_ttl_secs = value.parse()?,
+            "retention.cleanup_interval_secs" => self.retention.cleanup_interval_secs = value.parse()?,
+            "intervals.index_check_secs" => self.intervals.index_check_secs = value.parse()?,
+            "intervals.memory_check_secs" => self.intervals.memory_check_secs = value.parse()?,
+            "intervals.sweep_secs" => self.intervals.sweep_secs = value.parse()?,
+            "intervals.collision_scan_secs" => self.intervals.collision_scan_secs = value.parse()?,
+            "map.center" => {
+                let (lng, lat) = value.split_once(',').ok_or_else(|| anyhow::anyhow!("map.center takes LNG,LAT"))?;
+                self.map.center = [lng.trim().parse()?, lat.trim().parse()?];
+            }
+            "map.zoom" => self.map.zoom = value.parse()?,
+            "map.tile_url" => self.map.tile_url = value.to_string(),
+            "map.tile_attribution" => self.map.tile_attribution = value.to_string(),
+            "map.seamark_url" => self.map.seamark_url = Some(value.to_string()).filter(|url| !url.is_empty()),
+            "map.refresh_secs" => self.map.refresh_secs = value.parse()?,
+            _ if key.starts_with("map.features.") => {
+                self.map.features.insert(key["map.features.".len()..].to_string(), value.parse()?);
+            }
+            "photos.api_url" => self.photos.api_url = Some(value.to_string()),
+            "photos.api_key" => self.photos.api_key = Some(value.to_string()),
+            "photos.pointer" => self.photos.pointer = value.to_string(),
+            "photos.cache_hours" => self.photos.cache_hours = value.parse()?,
+            "tile_cache.dir" => self.tile_cache.dir = Some(PathBuf::from(value)),
+            "tile_cache.days" => self.tile_cache.days = value.parse()?,
+            "monitor.cpa_range_nm" => self.monitor.cpa_range_nm = value.parse()?,
+            "monitor.cpa_alert_nm" => self.monitor.cpa_alert_nm = value.parse()?,
+            "monitor.tcpa_alert_min" => self.monitor.tcpa_alert_min = value.parse()?,
+            "monitor.loiter_min" => self.monitor.loiter_min = value.parse()?,
+            "monitor.ports_file" => self.monitor.ports_file = Some(PathBuf::from(value)),
+            "monitor.locodes_file" => self.monitor.locodes_file = Some(PathBuf::from(value)),
+            "monitor.alert_rules" => self.monitor.alert_rules = Some(PathBuf::from(value)),
+            "forwarding.targets_file" => self.forwarding.targets_file = Some(PathBuf::from(value)),
+            "peer.token" => self.peer.token = Some(value.to_string()),
+            "peer.push_url" => self.peer.push_url = Some(value.to_string()),
+            "peer.name" => self.peer.name = value.to_string(),
+            "peer.follow_url" => self.peer.follow_url = Some(value.to_string()),
+            _ => return Err(anyhow::anyhow!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.index_kind()?;
+        self.shed_policy()?;
+        if self.server.tls_cert.is_some() != self.server.tls_key.is_some() {
+            return Err(anyhow::anyhow!("server.tls_cert and server.tls_key must be set together"));
+        }
+        let base = &self.server.base_path;
+        let unreserved = |c: char| c.is_ascii_alphanumeric() || "/-._~".contains(c);
+        if !base.is_empty() && (!base.starts_with('/') || !base.chars().all(unreserved)) {
+            return Err(anyhow::anyhow!("server.base_path must start with / and use only letters, digits and -._~, not {}", base));
+        }
+        let origin = |origin: &String| origin == "*" || ((origin.starts_with("http://") || origin.starts_with("https://")) && !origin.ends_with('/'));
+        if let Some(origin) = self.server.cors_origins.iter().find(|o| !origin(o)) {
+            return Err(anyhow::anyhow!("server.cors_origins takes \"*\" or origins like https://example.org, not {}", origin));
+        }
+        // A proxy in front of the socket terminates TLS
+        if self.server.unix_socket.is_some() && self.server.tls_cert.is_some() {
+            return Err(anyhow::anyhow!("server.unix_socket can't be used with server.tls_cert"));
+        }
+        let intervals = [
+            ("upstream.reconnect_secs", self.upstream.reconnect_secs),
+            ("retention.cleanup_interval_secs", self.retention.cleanup_interval_secs),
+            ("intervals.index_check_secs", self.intervals.index_check_secs),
+            ("intervals.memory_check_secs", self.intervals.memory_check_secs),
+            ("intervals.sweep_secs", self.intervals.sweep_secs),
+            ("intervals.collision_scan_secs", self.intervals.collision_scan_secs),
+            ("map.refresh_secs", self.map.refresh_secs),
+        ];
+        let ingest = &self.ingest;
+        let thresholds = [
+            ("ingest.unchanged_distance_m", ingest.unchanged_distance_m.unwrap_or(1.0)),
+            ("ingest.unchanged_speed_kn", ingest.unchanged_speed_kn),
+            ("ingest.unchanged_course_deg", ingest.unchanged_course_deg),
+        ];
+        if let Some((key, value)) = thresholds.iter().find(|(_, value)| !(value.is_finite() && *value >= 0.0)) {
+            return Err(anyhow::anyhow!("{} must be a number of at least 0, not {}", key, value));
+        }
+        let monitor = &self.monitor;
+        let limits = [
+            ("monitor.cpa_range_nm", monitor.cpa_range_nm),
+            ("monitor.cpa_alert_nm", monitor.cpa_alert_nm),
+            ("monitor.tcpa_alert_min", monitor.tcpa_alert_min),
+        ];
+        if let Some((key, value)) = limits.iter().find(|(_, value)| !(value.is_finite() && *value > 0.0)) {
+            return Err(anyhow::anyhow!("{} must be a number above 0, not {}", key, value));
+        }
+        if monitor.loiter_min == 0 {
+            return Err(anyhow::anyhow!("monitor.loiter_min must be at least 1"));
+        }
+        // An empty secret would open peering to anyone
+        if self.peer.token.as_deref().is_some_and(|token| token.trim().is_empty()) {
+            return Err(anyhow::anyhow!("peer.token can't be empty; leave it out to turn peering off"));
+        }
+        let map = &self.map;
+        let [lng, lat] = map.center;
+        if !((-180.0..=180.0).contains(&lng) && (-90.0..=90.0).contains(&lat)) {
+            return Err(anyhow::anyhow!("map.center must be a longitude and a latitude, not {:?}", map.center));
+        }
+        if !(0.0..=22.0).contains(&map.zoom) {
+            return Err(anyhow::anyhow!("map.zoom must be from 0 to 22, not {}", map.zoom));
+        }
+        let urls = std::iter::once(("map.tile_url", &map.tile_url)).chain(map.seamark_url.iter().map(|url| ("map.seamark_url", url)));
+        for (key, url) in urls {
+            if !["{z}", "{x}", "{y}"].iter().all(|part| url.contains(part)) {
+                return Err(anyhow::anyhow!("{} needs {{z}}, {{x}} and {{y}}, not {}", key, url));
+            }
+        }
+        for (name, spec) in &self.regions {
+            if name.trim().is_empty() {
+                return Err(anyhow::anyhow!("A region needs a name"));
+            }
+            spec.validate().map_err(|e| anyhow::anyhow!("regions.{}: {}", name, e))?;
+        }
+        for (name, report) in &self.reports {
+            if !self.regions.contains_key(name) {
+                return Err(anyhow::anyhow!("reports.{}: there is no region {}", name, name));
+            }
+            report.validate().map_err(|e| anyhow::anyhow!("reports.{}: {}", name, e))?;
+        }
+        // tokio's interval panics on a zero period
+        if let Some((key, _)) = intervals.iter().find(|(_, secs)| *secs == 0) {
+            return Err(anyhow::anyhow!("{} must be at least 1", key));
+        }
+        Ok(())
+    }
+
+    // The base path without a trailing slash; empty for the root
+    pub fn base_path(&self) -> &str {
+        self.server.base_path.trim_end_matches('/')
+    }
+
+    pub fn index_kind(&self) -> Result<IndexKind> {
+        self.ingest.spatial_index.parse()
+    }
+
+    pub fn shed_policy(&self) -> Result<ShedPolicy> {
+        self.ingest.shed_policy.parse()
+    }
+
+    // None unless ingest.unchanged_distance_m is set
+    pub fn unchanged(&self) -> Option<Unchanged> {
+        let ingest = &self.ingest;
+        ingest.unchanged_distance_m.map(|distance_m| Unchanged {
+            distance_m,
+            speed_kn: ingest.unchanged_speed_kn,
+            course_deg: ingest.unchanged_course_deg,
+            max_secs: ingest.unchanged_max_secs,
+        })
+    }
+
+    pub fn collisions(&self) -> CollisionConfig {
+        let monitor = &self.monitor;
+        CollisionConfig {
+            range_m: monitor.cpa_range_nm * METRES_PER_NM,
+            cpa_m: monitor.cpa_alert_nm * METRES_PER_NM,
+            tcpa_secs: monitor.tcpa_alert_min * 60.0,
+        }
+    }
+
+    pub fn parse_workers(&self) -> usize {
+        self.ingest.parse_workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get().min(4)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layering() {
+        let mut config: Config = toml::from_str(
+            "[retention]\nship_ttl_secs = 3600\n\n[ingest]\nspatial_index = \"rtree\"\n",
+        )
+        .unwrap();
+        assert_eq!((config.retention.ship_ttl_secs, config.retention.cleanup_interval_secs), (3600, 300));
+        assert_eq!(config.index_kind().unwrap(), IndexKind::RTree);
+
+        config.set("retention.ship_ttl_secs", "60").unwrap();
+        assert_eq!(config.retention.ship_ttl_secs, 60);
+        assert!(config.set("retention.ship_ttl", "60").is_err());
+        assert!(config.set("ingest.queue_size", "lots").is_err());
+        config.set("intervals.sweep_secs", "0").unwrap();
+        assert!(config.validate().is_err());
+        config.set("intervals.sweep_secs", "60").unwrap();
+        config.set("server.tls_cert", "cert.pem").unwrap();
+        assert!(config.validate().is_err());
+        config.server.tls_cert = None;
+
+        assert!(toml::from_str::<Config>("[retention]\nship_tll_secs = 1\n").is_err());
+        let example = Config::from_file(Path::new("seawatch.example.toml")).unwrap();
+        assert_eq!(example.retention.ship_ttl_secs, Config::default().retention.ship_ttl_secs);
+        let cli = Cli::parse_from(["seawatch", "--set", "upstream.reconnect_secs=1", "export-types"]);
+        assert_eq!(cli.overrides, vec!["upstream.reconnect_secs=1"]);
+        assert!(matches!(cli.command, Some(Command::ExportTypes { path: None })));
+        assert_eq!(Cli::parse_from(["seawatch", "--diagnose"]).diagnose, Some(30));
+        assert_eq!(Cli::parse_from(["seawatch", "--diagnose", "5"]).diagnose, Some(5));
+        assert_eq!(Cli::parse_from(["seawatch", "--simulate"]).simulate, Some(simulate::DEFAULT_VESSELS));
+        assert_eq!(Cli::parse_from(["seawatch", "--log-format", "json"]).log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_map() {
+        let mut config = Config::default();
+        config.set("map.center", "28.98, 41.01").unwrap();
+        config.set("map.features.photos", "false").unwrap();
+        config.set("map.seamark_url", "").unwrap();
+        config.validate().unwrap();
+        assert_eq!((config.map.center, config.map.features["photos"], config.map.seamark_url.as_deref()), ([28.98, 41.01], false, None));
+        config.set("map.tile_url", "https://tiles.example.org/{z}/{x}.png").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mut config = Config::default();
+        let defaults = CollisionConfig::default();
+        assert_eq!((config.collisions().range_m, config.collisions().cpa_m), (defaults.range_m, defaults.cpa_m));
+        config.set("monitor.cpa_alert_nm", "1").unwrap();
+        config.set("monitor.tcpa_alert_min", "10").unwrap();
+        config.set("monitor.ports_file", "ports.json").unwrap();
+        config.validate().unwrap();
+        assert_eq!((config.collisions().cpa_m, config.collisions().tcpa_secs), (METRES_PER_NM, 600.0));
+        assert_eq!(config.monitor.ports_file.as_deref(), Some(Path::new("ports.json")));
+        config.set("monitor.cpa_range_nm", "0").unwrap();
+        assert!(config.validate().is_err());
+        assert!(config.set("monitor.loiter_min", "ten").is_err());
+    }
+
+    #[test]
+    fn test_peer() {
+        let config: Config = toml::from_str("[peer]\ntoken = \"secret\"\nfollow_url = \"ws://primary:8080/api/peer/feed\"\n").unwrap();
+        config.validate().unwrap();
+        assert_eq!((config.peer.token.as_deref(), config.peer.name.as_str()), (Some("secret"), "seawatch"));
+        assert!(toml::from_str::<Config>("[peer]\nurl = \"ws://primary:8080\"\n").is_err());
+        let mut empty = Config::default();
+        empty.set("peer.token", "").unwrap();
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn test_regions() {
+        let region = |radius_m| format!("[regions.\"Port of LA\"]\ntype = \"circle\"\nlat = 33.73\nlng = -118.26\nradius_m = {}\n", radius_m);
+        let config: Config = toml::from_str(&region(2000)).unwrap();
+        config.validate().unwrap();
+        assert!(config.regions.contains_key("Port of LA"));
+        assert!(toml::from_str::<Config>(&region(0)).unwrap().validate().is_err());
+        let unnamed: Config = toml::from_str("[regions.\" \"]\ntype = \"circle\"\nlat = 0\nlng = 0\nradius_m = 10\n").unwrap();
+        assert!(unnamed.validate().is_err());
+    }
+
+    #[test]
+    fn test_base_path() {
+        let mut config = Config::default();
+        config.set("server.base_path", "/seamon/").unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.base_path(), "/seamon");
+        config.set("server.base_path", "seamon").unwrap();
+        assert!(config.validate().is_err());
+        config.set("server.base_path", "/sea mon").unwrap();
+        assert!(config.validate().is_err());
+    }
+}
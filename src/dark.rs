@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::ship::Ship;
+
+// A ship counts as transmitting regularly once it has sent this many
+// reports, on average no further apart than MAX_REGULAR_INTERVAL
+const MIN_REPORTS: u32 = 5;
+const MAX_REGULAR_INTERVAL: f64 = 300.0;
+// ...and as mid-passage while under way at this speed or more. Anchored or
+// moored ships (nav status 1 and 5) going quiet is normal.
+const MIN_PASSAGE_SPEED: f64 = 3.0;
+// It has gone dark after this many times its usual interval, and at least
+// MIN_DARK_SECS, without a report
+const DARK_INTERVALS: f64 = 10.0;
+const MIN_DARK_SECS: u64 = 1800;
+// Ships silent for this long are forgotten, as the ship cache does
+const FORGET_SECS: u64 = 86_400;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum DarkStatus {
+    WentDark,
+    Reappeared,
+}
+
+struct Track {
+    last_seen: u64,
+    lat: f64,
+    lng: f64,
+    reports: u32,
+    interval: f64, // Moving average of the time between reports, in seconds
+    under_way: bool,
+    dark: bool,
+}
+
+impl Track {
+    fn regular(&self) -> bool {
+        self.reports >= MIN_REPORTS && self.interval <= MAX_REGULAR_INTERVAL
+    }
+
+    fn dark_after(&self) -> u64 {
+        ((self.interval * DARK_INTERVALS) as u64).max(MIN_DARK_SECS)
+    }
+}
+
+// Reporting cadence per ship, for spotting ones that stop transmitting
+#[derive(Default)]
+pub struct DarkShips {
+    tracks: Mutex<HashMap<u32, Track>>,
+}
+
+impl DarkShips {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, events: &EventLog, ship: &Ship) {
+        let mut tracks = self.tracks.lock().unwrap();
+        let under_way = ship.speed >= MIN_PASSAGE_SPEED && ship.nav_status != 1 && ship.nav_status != 5;
+        let track = tracks.entry(ship.mmsi).or_insert_with(|| Track {
+            last_seen: ship.last_update,
+            lat: ship.lat,
+            lng: ship.lng,
+            reports: 0,
+            interval: 0.0,
+            under_way,
+            dark: false,
+        });
+
+        let gap = ship.last_update.saturating_sub(track.last_seen);
+        if track.dark {
+            events.push(
+                ship.last_update,
+                ship.mmsi,
+                EventKind::Dark { status: DarkStatus::Reappeared, lat: ship.lat, lng: ship.lng, silent_secs: gap },
+            );
+            // Its cadence before the gap says nothing about after it
+            track.reports = 0;
+            track.dark = false;
+        } else if gap > 0 {
+            track.interval = match track.reports {
+                0 | 1 => gap as f64,
+                _ => track.interval * 0.7 + gap as f64 * 0.3,
+            };
+        }
+        // Several messages within the same second count as one report
+        if gap > 0 || track.reports == 0 {
+            track.reports += 1;
+        }
+        (track.last_seen, track.lat, track.lng, track.under_way) = (ship.last_update, ship.lat, ship.lng, under_way);
+    }
+
+    // Flag regular, under-way ships that have been silent for too long
+    pub fn sweep(&self, events: &EventLog, now: u64) {
+        let mut tracks = self.tracks.lock().unwrap();
+        tracks.retain(|_, track| now.saturating_sub(track.last_seen) < FORGET_SECS);
+        for (&mmsi, track) in tracks.iter_mut() {
+            let silent_secs = now.saturating_sub(track.last_seen);
+            if !track.dark && track.under_way && track.regular() && silent_secs >= track.dark_after() {
+                events.push(
+                    now,
+                    mmsi,
+                    EventKind::Dark { status: DarkStatus::WentDark, lat: track.lat, lng: track.lng, silent_secs },
+                );
+                track.dark = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_went_dark_and_reappeared() {
+        let (dark, events) = (DarkShips::new(), EventLog::new());
+        let mut ship = Ship::new(1, "NIGHT OWL");
+        ship.speed = 12.0;
+        let mut anchored = Ship::new(2, "AT REST");
+        anchored.nav_status = 1;
+        for t in 0..10u64 {
+            (ship.lat, ship.lng, ship.last_update) = (50.0 + t as f64 * 0.001, -4.0, 1000 + t * 10);
+            dark.observe(&events, &ship);
+            anchored.last_update = 1000 + t * 10;
+            dark.observe(&events, &anchored);
+        }
+
+        dark.sweep(&events, 1090 + MIN_DARK_SECS - 1);
+        assert!(events.query(&EventFilter::default()).is_empty());
+        dark.sweep(&events, 1090 + MIN_DARK_SECS);
+        dark.sweep(&events, 1090 + MIN_DARK_SECS + 60);
+
+        (ship.lat, ship.last_update) = (50.5, 5000);
+        dark.observe(&events, &ship);
+
+        let filter = EventFilter { kind: Some("dark".into()), ..Default::default() };
+        let logged: Vec<(u32, EventKind)> = events.query(&filter).iter().map(|e| (e.mmsi, e.kind.clone())).collect();
+        assert_eq!(
+            logged,
+            vec![
+                (1, EventKind::Dark { status: DarkStatus::WentDark, lat: 50.0 + 9.0 * 0.001, lng: -4.0, silent_secs: MIN_DARK_SECS }),
+                (1, EventKind::Dark { status: DarkStatus::Reappeared, lat: 50.5, lng: -4.0, silent_secs: 3910 }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::time::Instant;
+use tokio::time::{timeout, timeout_at, Duration};
+use url::Url;
+
+use crate::ais::{parse_message, AisStream, ParseScratch, Subscription};
+use crate::config::UpstreamConfig;
+use crate::index::is_valid_position;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// What came down the stream while listening
+#[derive(Default)]
+pub struct Report {
+    pub connect_time: Duration,
+    pub listened: Duration,
+    pub frames: u64,
+    pub bytes: u64,
+    pub parse_failures: u64,
+    pub by_type: BTreeMap<String, u64>,
+    pub vessels: HashSet<u32>,
+    pub invalid_positions: u64,
+    // Whole-degree cells with at least one position, for a rough idea of coverage
+    pub cells: HashSet<(i16, i16)>,
+    pub bounds: Option<[f64; 4]>, // South, west, north, east
+}
+
+impl Report {
+    fn record(&mut self, mut frame: Vec<u8>, scratch: &mut ParseScratch) {
+        self.frames += 1;
+        self.bytes += frame.len() as u64;
+        let Some(message) = parse_message(&mut frame, scratch) else {
+            self.parse_failures += 1;
+            return;
+        };
+        *self.by_type.entry(message.message_type).or_default() += 1;
+        self.vessels.insert(message.metadata.mmsi);
+        let (lat, lng) = (message.metadata.latitude, message.metadata.longitude);
+        if !is_valid_position(lat, lng) || !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+            self.invalid_positions += 1;
+            return;
+        }
+        self.cells.insert((lat.floor() as i16, lng.floor() as i16));
+        let [south, west, north, east] = self.bounds.get_or_insert([lat, lng, lat, lng]);
+        (*south, *west, *north, *east) = (south.min(lat), west.min(lng), north.max(lat), east.max(lng));
+    }
+
+    pub fn render(&self) -> String {
+        let secs = self.listened.as_secs_f64().max(0.001);
+        let percent = |count: u64| 100.0 * count as f64 / self.frames.max(1) as f64;
+        let mut out = String::new();
+        let _ = writeln!(out, "Connected and authenticated in {:.1}s", self.connect_time.as_secs_f64());
+        let _ = writeln!(out, "Listened for {:.0}s", secs);
+        let _ = writeln!(out, "Messages: {} ({:.1}/s, {:.1} KB/s)", self.frames, self.frames as f64 / secs, self.bytes as f64 / 1024.0 / secs);
+        let _ = writeln!(out, "Parse failures: {} ({:.2}%)", self.parse_failures, percent(self.parse_failures));
+        for (message_type, count) in &self.by_type {
+            let _ = writeln!(out, "  {:<28} {:>8} ({:.1}/s)", message_type, count, *count as f64 / secs);
+        }
+        let _ = writeln!(out, "Vessels: {}", self.vessels.len());
+        let _ = writeln!(out, "Missing or invalid positions: {} ({:.2}%)", self.invalid_positions, percent(self.invalid_positions));
+        match self.bounds {
+            Some([south, west, north, east]) => {
+                let _ = writeln!(
+                    out,
+                    "Coverage: {} 1° cells, between {:.1},{:.1} and {:.1},{:.1} (lat,lng)",
+                    self.cells.len(),
+                    south,
+                    west,
+                    north,
+                    east
+                );
+            }
+            None => {
+                let _ = writeln!(out, "Coverage: no positions");
+            }
+        }
+        out
+    }
+}
+
+// Connects with the configured URL and key and the default (global)
+// subscription, and tallies everything received for `duration`
+pub async fn run(config: &UpstreamConfig, duration: Duration) -> Result<Report> {
+    let api_key = config
+        .api_key
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No aisstream.io API key; set AIS_STREAM_API_KEY or upstream.api_key"))?;
+    let url = Url::parse(&config.url)?;
+    let started = Instant::now();
+    let mut stream = timeout(CONNECT_TIMEOUT, AisStream::connect(url, api_key, &Subscription::default()))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out connecting to {} after {:?}", config.url, CONNECT_TIMEOUT))??;
+    let mut report = Report { connect_time: started.elapsed(), ..Report::default() };
+
+    let listening = tokio::time::Instant::now();
+    let deadline = listening + duration;
+    let mut scratch = ParseScratch::default();
+    while let Ok(frame) = timeout_at(deadline, stream.next_frame()).await {
+        match frame? {
+            Some(frame) => report.record(frame, &mut scratch),
+            None => break,
+        }
+    }
+    report.listened = listening.elapsed();
+    stream.close().await;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = Report { listened: Duration::from_secs(10), ..Report::default() };
+        let mut scratch = ParseScratch::default();
+        let frame = |mmsi, lat, lng| {
+            format!(
+                r#"{{"MessageType": "PositionReport", "MetaData": {{"MMSI": {}, "ShipName": "", "latitude": {}, "longitude": {}, "time_utc": ""}}, "Message": {{}}}}"#,
+                mmsi, lat, lng
+            )
+            .into_bytes()
+        };
+        report.record(frame(244660000, 51.9, 4.1), &mut scratch);
+        report.record(frame(244660000, 52.1, 4.3), &mut scratch);
+        report.record(frame(235012345, 0.0, 0.0), &mut scratch);
+        report.record(b"not json".to_vec(), &mut scratch);
+
+        assert_eq!((report.frames, report.parse_failures, report.invalid_positions), (4, 1, 1));
+        assert_eq!((report.vessels.len(), report.cells.len()), (2, 2));
+        assert_eq!(report.bounds, Some([51.9, 4.1, 52.1, 4.3]));
+        let text = report.render();
+        assert!(text.contains("Messages: 4 (0.4/s"), "{}", text);
+        assert!(text.contains("PositionReport"), "{}", text);
+    }
+}
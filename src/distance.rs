@@ -0,0 +1,154 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::geo::haversine_m;
+use crate::history::TrackPoint;
+
+pub const DAY_SECS: u64 = 86_400;
+// Daily totals are kept for a month
+pub const DAYS_KEPT: u64 = 30;
+// A step between history points faster than this is a position glitch, not travel
+const MAX_SPEED_KN: f64 = 80.0;
+const KN_TO_MPS: f64 = 1852.0 / 3600.0;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct DailyDistance {
+    pub day: u64, // Start of the UTC day
+    pub distance_m: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ShipDistance {
+    pub mmsi: u32,
+    pub total_m: f64,
+    // Oldest first, only days it moved
+    pub days: Vec<DailyDistance>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct FleetDay {
+    pub day: u64,
+    pub ships: usize, // That moved at all
+    pub distance_m: f64,
+}
+
+// What /api/stats/distance returns: every ship's distance summed per day,
+// and the ships that went furthest
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct DistanceReport {
+    pub since: u64,
+    pub days: Vec<FleetDay>,
+    pub ships: Vec<ShipDistance>,
+}
+
+// Great-circle distance between consecutive history points, summed per ship
+// per UTC day. A step is counted on the day of its later point
+#[derive(Default)]
+pub struct Distances {
+    days: Mutex<HashMap<u32, BTreeMap<u64, f64>>>,
+}
+
+impl Distances {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, mmsi: u32, from: &TrackPoint, to: &TrackPoint) {
+        let metres = haversine_m(from.lat, from.lng, to.lat, to.lng);
+        let secs = to.timestamp.saturating_sub(from.timestamp).max(1);
+        if metres / secs as f64 > MAX_SPEED_KN * KN_TO_MPS {
+            return;
+        }
+        let day = to.timestamp - to.timestamp % DAY_SECS;
+        *self.days.lock().unwrap().entry(mmsi).or_default().entry(day).or_default() += metres;
+    }
+
+    pub fn purge(&self, now: u64) {
+        let oldest = first_day(DAYS_KEPT, now);
+        self.days.lock().unwrap().retain(|_, days| {
+            days.retain(|&day, _| day >= oldest);
+            !days.is_empty()
+        });
+    }
+
+    // The last `days` days, today included
+    pub fn ship(&self, mmsi: u32, days: u64, now: u64) -> Option<ShipDistance> {
+        let all = self.days.lock().unwrap();
+        Some(summarise(mmsi, all.get(&mmsi)?, first_day(days, now)))
+    }
+
+    // `limit` ships with the furthest distance over the last `days` days
+    pub fn report(&self, days: u64, limit: usize, now: u64) -> DistanceReport {
+        let since = first_day(days, now);
+        let all = self.days.lock().unwrap();
+        let mut fleet: BTreeMap<u64, FleetDay> = BTreeMap::new();
+        let mut ships = Vec::new();
+        for (&mmsi, days) in all.iter() {
+            let ship = summarise(mmsi, days, since);
+            if ship.days.is_empty() {
+                continue;
+            }
+            for daily in &ship.days {
+                let day = fleet.entry(daily.day).or_insert(FleetDay { day: daily.day, ships: 0, distance_m: 0.0 });
+                day.ships += 1;
+                day.distance_m += daily.distance_m;
+            }
+            ships.push(ship);
+        }
+        ships.sort_by(|a, b| b.total_m.total_cmp(&a.total_m).then(a.mmsi.cmp(&b.mmsi)));
+        ships.truncate(limit);
+        let days = fleet.into_values().map(|day| FleetDay { distance_m: day.distance_m.round(), ..day }).collect();
+        DistanceReport { since, days, ships }
+    }
+}
+
+// Start of the UTC day `days - 1` days before today
+pub(crate) fn first_day(days: u64, now: u64) -> u64 {
+    (now - now % DAY_SECS).saturating_sub(days.saturating_sub(1) * DAY_SECS)
+}
+
+fn summarise(mmsi: u32, days: &BTreeMap<u64, f64>, since: u64) -> ShipDistance {
+    let days: Vec<DailyDistance> = days.range(since..).map(|(&day, &metres)| DailyDistance { day, distance_m: metres.round() }).collect();
+    let total_m = days.iter().map(|day| day.distance_m).sum();
+    ShipDistance { mmsi, total_m, days }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_distance() {
+        let distances = Distances::new();
+        let midnight = 1_699_920_000; // 2023-11-14 00:00 UTC
+        let point = |timestamp: u64, lat: f64| TrackPoint { timestamp, lat, lng: 4.0, speed: 12.0, cog: 0.0 };
+        // A minute of latitude a minute, about 60 knots, across midnight
+        let track: Vec<TrackPoint> = (0..=4).map(|i| point(midnight - 120 + i * 60, 52.0 + i as f64 / 60.0)).collect();
+        for pair in track.windows(2) {
+            distances.record(244660000, &pair[0], &pair[1]);
+        }
+        // A jump of a degree in a minute is ignored
+        distances.record(244660000, &track[4], &point(midnight + 180, 53.0));
+        distances.record(211000000, &track[0], &track[1]);
+
+        let ship = distances.ship(244660000, 2, midnight + 600).unwrap();
+        let days: Vec<u64> = ship.days.iter().map(|day| day.day).collect();
+        assert_eq!(days, vec![midnight - DAY_SECS, midnight]);
+        assert!((ship.days[0].distance_m - 1853.0).abs() < 10.0);
+        assert!((ship.total_m - 4.0 * 1853.0).abs() < 20.0);
+        assert_eq!(distances.ship(244660000, 1, midnight + 600).unwrap().days.len(), 1);
+
+        let report = distances.report(2, 1, midnight + 600);
+        assert_eq!(report.since, midnight - DAY_SECS);
+        assert_eq!((report.days[0].ships, report.days[1].ships), (2, 1));
+        assert_eq!(report.ships.len(), 1);
+        assert_eq!(report.ships[0].mmsi, 244660000);
+
+        let later = midnight + (DAYS_KEPT - 1) * DAY_SECS;
+        distances.purge(later);
+        assert!(distances.ship(211000000, DAYS_KEPT, later).is_none());
+        assert_eq!(distances.ship(244660000, DAYS_KEPT, later).unwrap().days.len(), 1);
+    }
+}
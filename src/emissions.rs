@@ -0,0 +1,268 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::distance::{first_day, DAYS_KEPT, DAY_SECS};
+use crate::geo::haversine_m;
+use crate::history::TrackPoint;
+use crate::ship::Ship;
+
+// Activity-based estimate: installed main engine power from ship type and
+// length, load by the cube of speed over design speed, fuel from specific
+// consumption and CO2 from the IMO carbon factor for heavy fuel oil
+const SFOC_MAIN_G_PER_KWH: f64 = 200.0;
+const SFOC_AUX_G_PER_KWH: f64 = 220.0;
+const CO2_PER_FUEL: f64 = 3.114;
+// Main engines don't run below this load, or when stopped
+const MIN_LOAD: f64 = 0.02;
+const STOPPED_KN: f64 = 1.0;
+// A longer gap between history points only counts this much of it
+const MAX_STEP_SECS: u64 = 600;
+const SPEED_UNAVAILABLE: f64 = 102.2;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Cargo,
+    Tanker,
+    Passenger,
+    Fishing,
+    Tug,
+    Pleasure,
+    Other,
+}
+
+// kW per square metre of length, design speed in knots, auxiliary power as a
+// fraction of main
+struct Profile {
+    kw_per_m2: f64,
+    design_kn: f64,
+    aux: f64,
+}
+
+impl Category {
+    pub fn of(ship_type: u32) -> Self {
+        match ship_type {
+            70..=79 => Self::Cargo,
+            80..=89 => Self::Tanker,
+            60..=69 => Self::Passenger,
+            30 => Self::Fishing,
+            31 | 32 | 52 => Self::Tug,
+            36 | 37 => Self::Pleasure,
+            _ => Self::Other,
+        }
+    }
+
+    fn profile(self) -> Profile {
+        let (kw_per_m2, design_kn, aux) = match self {
+            Self::Cargo => (0.35, 20.0, 0.2),
+            Self::Tanker => (0.3, 15.0, 0.25),
+            Self::Passenger => (0.6, 22.0, 0.3),
+            Self::Fishing => (0.6, 12.0, 0.15),
+            Self::Tug => (3.3, 12.0, 0.1),
+            Self::Pleasure => (2.2, 25.0, 0.1),
+            Self::Other => (0.3, 14.0, 0.2),
+        };
+        Profile { kw_per_m2, design_kn, aux }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DailyEmissions {
+    pub day: u64, // Start of the UTC day
+    pub hours_under_way: f64,
+    pub hours_stopped: f64,
+    pub fuel_kg: f64,
+    pub co2_kg: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ShipEmissions {
+    pub mmsi: u32,
+    pub category: Category,
+    pub main_engine_kw: f64, // Estimated from the length
+    pub fuel_kg: f64,
+    pub co2_kg: f64,
+    // Oldest first, only days it reported
+    pub days: Vec<DailyEmissions>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct FleetEmissions {
+    pub day: u64,
+    pub ships: usize,
+    pub fuel_kg: f64,
+    pub co2_kg: f64,
+}
+
+// What /api/stats/emissions returns: the estimate summed over all ships per
+// day, and the biggest emitters
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct EmissionsReport {
+    pub since: u64,
+    pub days: Vec<FleetEmissions>,
+    pub ships: Vec<ShipEmissions>,
+    // Seen without a length, so left out
+    pub unsized_ships: usize,
+}
+
+struct Estimates {
+    category: Category,
+    main_engine_kw: f64,
+    days: BTreeMap<u64, DailyEmissions>,
+}
+
+// Estimated fuel and CO2 per ship per UTC day, from each step between
+// history points. Only ships that have broadcast their length are counted
+#[derive(Default)]
+pub struct Emissions {
+    ships: Mutex<HashMap<u32, Estimates>>,
+    without_length: Mutex<HashMap<u32, u64>>,
+}
+
+impl Emissions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, ship: &Ship, from: &TrackPoint, to: &TrackPoint) {
+        let Some(length) = ship.dimensions.map(|dimensions| dimensions.length).filter(|&length| length > 0) else {
+            self.without_length.lock().unwrap().insert(ship.mmsi, to.timestamp);
+            return;
+        };
+        self.without_length.lock().unwrap().remove(&ship.mmsi);
+        let category = Category::of(ship.ship_type);
+        let profile = category.profile();
+        let main_engine_kw = profile.kw_per_m2 * (length as f64).powi(2);
+        let secs = to.timestamp.saturating_sub(from.timestamp).min(MAX_STEP_SECS);
+        let hours = secs as f64 / 3600.0;
+        let speed = if from.speed < SPEED_UNAVAILABLE && to.speed < SPEED_UNAVAILABLE {
+            (from.speed + to.speed) / 2.0
+        } else {
+            haversine_m(from.lat, from.lng, to.lat, to.lng) / 1852.0 / hours.max(1.0 / 3600.0)
+        };
+        let under_way = speed >= STOPPED_KN;
+        let load = if under_way { (speed / profile.design_kn).powi(3).clamp(MIN_LOAD, 1.0) } else { 0.0 };
+        let fuel_kg = (main_engine_kw * load * SFOC_MAIN_G_PER_KWH + main_engine_kw * profile.aux * SFOC_AUX_G_PER_KWH) * hours / 1000.0;
+
+        let day = to.timestamp - to.timestamp % DAY_SECS;
+        let mut ships = self.ships.lock().unwrap();
+        let estimates = ships.entry(ship.mmsi).or_insert_with(|| Estimates { category, main_engine_kw, days: BTreeMap::new() });
+        (estimates.category, estimates.main_engine_kw) = (category, main_engine_kw);
+        let daily = estimates.days.entry(day).or_insert(DailyEmissions { day, ..Default::default() });
+        if under_way {
+            daily.hours_under_way += hours;
+        } else {
+            daily.hours_stopped += hours;
+        }
+        daily.fuel_kg += fuel_kg;
+        daily.co2_kg += fuel_kg * CO2_PER_FUEL;
+    }
+
+    pub fn purge(&self, now: u64) {
+        let oldest = first_day(DAYS_KEPT, now);
+        self.ships.lock().unwrap().retain(|_, estimates| {
+            estimates.days.retain(|&day, _| day >= oldest);
+            !estimates.days.is_empty()
+        });
+        self.without_length.lock().unwrap().retain(|_, &mut seen| seen >= oldest);
+    }
+
+    // The last `days` days, today included
+    pub fn ship(&self, mmsi: u32, days: u64, now: u64) -> Option<ShipEmissions> {
+        let ships = self.ships.lock().unwrap();
+        Some(summarise(mmsi, ships.get(&mmsi)?, first_day(days, now)))
+    }
+
+    // `limit` ships with the most CO2 over the last `days` days
+    pub fn report(&self, days: u64, limit: usize, now: u64) -> EmissionsReport {
+        let since = first_day(days, now);
+        let mut fleet: BTreeMap<u64, FleetEmissions> = BTreeMap::new();
+        let mut ships = Vec::new();
+        for (&mmsi, estimates) in self.ships.lock().unwrap().iter() {
+            let ship = summarise(mmsi, estimates, since);
+            if ship.days.is_empty() {
+                continue;
+            }
+            for daily in &ship.days {
+                let day = fleet.entry(daily.day).or_insert(FleetEmissions { day: daily.day, ships: 0, fuel_kg: 0.0, co2_kg: 0.0 });
+                day.ships += 1;
+                day.fuel_kg += daily.fuel_kg;
+                day.co2_kg += daily.co2_kg;
+            }
+            ships.push(ship);
+        }
+        ships.sort_by(|a, b| b.co2_kg.total_cmp(&a.co2_kg).then(a.mmsi.cmp(&b.mmsi)));
+        ships.truncate(limit);
+        let days = fleet.into_values().map(|day| FleetEmissions { fuel_kg: round(day.fuel_kg), co2_kg: round(day.co2_kg), ..day }).collect();
+        let unsized_ships = self.without_length.lock().unwrap().values().filter(|&&seen| seen >= since).count();
+        EmissionsReport { since, days, ships, unsized_ships }
+    }
+}
+
+fn summarise(mmsi: u32, estimates: &Estimates, since: u64) -> ShipEmissions {
+    let days: Vec<DailyEmissions> = estimates
+        .days
+        .range(since..)
+        .map(|(_, daily)| DailyEmissions {
+            hours_under_way: round(daily.hours_under_way),
+            hours_stopped: round(daily.hours_stopped),
+            fuel_kg: round(daily.fuel_kg),
+            co2_kg: round(daily.co2_kg),
+            ..*daily
+        })
+        .collect();
+    ShipEmissions {
+        mmsi,
+        category: estimates.category,
+        main_engine_kw: estimates.main_engine_kw.round(),
+        fuel_kg: round(days.iter().map(|day| day.fuel_kg).sum()),
+        co2_kg: round(days.iter().map(|day| day.co2_kg).sum()),
+        days,
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::Dimensions;
+
+    #[test]
+    fn test_emissions() {
+        let emissions = Emissions::new();
+        let midnight = 1_699_920_000;
+        let mut ship = Ship::new(244660000, "ALIDA");
+        ship.ship_type = 70;
+        ship.dimensions = Some(Dimensions { length: 200, beam: 30, to_bow: 150, to_stern: 50, to_port: 15, to_starboard: 15 });
+        let point = |timestamp: u64, speed: f64| TrackPoint { timestamp, lat: 52.0, lng: 4.0, speed, cog: 0.0 };
+        // An hour at design speed, then an hour alongside
+        for minute in 0..60 {
+            emissions.record(&ship, &point(midnight + minute * 60, 20.0), &point(midnight + minute * 60 + 60, 20.0));
+        }
+        for minute in 60..120 {
+            emissions.record(&ship, &point(midnight + minute * 60, 0.0), &point(midnight + minute * 60 + 60, 0.0));
+        }
+        let mut small = Ship::new(211000000, "KLEIN");
+        small.ship_type = 70;
+        emissions.record(&small, &point(midnight, 10.0), &point(midnight + 60, 10.0));
+
+        let estimate = emissions.ship(244660000, 1, midnight + 7200).unwrap();
+        assert_eq!((estimate.category, estimate.main_engine_kw), (Category::Cargo, 14_000.0));
+        let day = estimate.days[0];
+        assert_eq!((day.hours_under_way, day.hours_stopped), (1.0, 1.0));
+        // 14 MW for an hour at 200 g/kWh, and 2.8 MW of auxiliaries for two at 220
+        assert!((day.fuel_kg - (2800.0 + 1232.0)).abs() < 1.0);
+        assert!((day.co2_kg - day.fuel_kg * CO2_PER_FUEL).abs() < 1.0);
+
+        let report = emissions.report(7, 10, midnight + 7200);
+        assert_eq!((report.ships.len(), report.unsized_ships), (1, 1));
+        assert_eq!(report.days[0].ships, 1);
+        emissions.purge(midnight + DAYS_KEPT * DAY_SECS);
+        assert!(emissions.ship(244660000, DAYS_KEPT, midnight + DAYS_KEPT * DAY_SECS).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::port_calls::PortCall;
+use crate::ship::Ship;
+
+// An ETA further out than this was left over from an earlier voyage
+const MAX_ERROR_SECS: u64 = 7 * 86_400;
+const SAMPLES_PER_SHIP: usize = 20;
+// Ships with no arrival in this long are forgotten
+const RETENTION_SECS: u64 = 30 * 86_400;
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct EtaSample {
+    pub locode: Arc<str>,
+    pub eta: u64,
+    pub arrived: u64,
+    pub error_secs: i64, // Positive when the ship arrived late
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+pub struct EtaSummary {
+    pub arrivals: u64,
+    pub mean_abs_error_secs: f64,
+    pub mean_error_secs: f64, // Average lateness; negative if ships tend to be early
+    pub within_1h: f64,       // Fraction of arrivals
+    pub within_6h: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ShipEtaStats {
+    pub mmsi: u32,
+    #[serde(flatten)]
+    pub summary: EtaSummary,
+    pub recent: Vec<EtaSample>, // Oldest first
+}
+
+#[derive(Default)]
+struct Totals {
+    arrivals: u64,
+    abs_sum: f64,
+    signed_sum: f64,
+    within_1h: u64,
+    within_6h: u64,
+}
+
+impl Totals {
+    fn add(&mut self, error_secs: i64) {
+        self.arrivals += 1;
+        self.abs_sum += error_secs.unsigned_abs() as f64;
+        self.signed_sum += error_secs as f64;
+        self.within_1h += u64::from(error_secs.unsigned_abs() <= 3600);
+        self.within_6h += u64::from(error_secs.unsigned_abs() <= 6 * 3600);
+    }
+
+    fn summary(&self) -> EtaSummary {
+        if self.arrivals == 0 {
+            return EtaSummary::default();
+        }
+        let n = self.arrivals as f64;
+        EtaSummary {
+            arrivals: self.arrivals,
+            mean_abs_error_secs: self.abs_sum / n,
+            mean_error_secs: self.signed_sum / n,
+            within_1h: self.within_1h as f64 / n,
+            within_6h: self.within_6h as f64 / n,
+        }
+    }
+}
+
+// How close broadcast ETAs come to the arrivals port-call detection sees
+#[derive(Default)]
+pub struct EtaAccuracy {
+    totals: Mutex<Totals>,
+    ships: Mutex<HashMap<u32, (Totals, VecDeque<EtaSample>)>>,
+}
+
+impl EtaAccuracy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Only arrivals at the port the ship said it was heading for count
+    pub fn record(&self, ship: &Ship, call: &PortCall) {
+        let Some(eta) = ship.eta else {
+            return;
+        };
+        if ship.destination_locode.as_deref() != Some(&*call.locode) || eta.abs_diff(call.arrived) > MAX_ERROR_SECS {
+            return;
+        }
+        let error_secs = call.arrived as i64 - eta as i64;
+        self.totals.lock().unwrap().add(error_secs);
+
+        let mut ships = self.ships.lock().unwrap();
+        let (totals, recent) = ships.entry(ship.mmsi).or_default();
+        totals.add(error_secs);
+        if recent.len() >= SAMPLES_PER_SHIP {
+            recent.pop_front();
+        }
+        recent.push_back(EtaSample { locode: call.locode.clone(), eta, arrived: call.arrived, error_secs });
+    }
+
+    pub fn summary(&self) -> EtaSummary {
+        self.totals.lock().unwrap().summary()
+    }
+
+    pub fn ship(&self, mmsi: u32) -> Option<ShipEtaStats> {
+        let ships = self.ships.lock().unwrap();
+        let (totals, recent) = ships.get(&mmsi)?;
+        Some(ShipEtaStats { mmsi, summary: totals.summary(), recent: recent.iter().cloned().collect() })
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.ships.lock().unwrap().retain(|_, (_, recent)| {
+            recent.back().is_some_and(|sample| now.saturating_sub(sample.arrived) <= RETENTION_SECS)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accuracy() {
+        let accuracy = EtaAccuracy::new();
+        let mut ship = Ship::new(1, "");
+        ship.destination_locode = Some(Arc::from("NLRTM"));
+        let call = |locode: &str, arrived| PortCall { port: Arc::from(""), locode: Arc::from(locode), arrived, departed: None };
+
+        ship.eta = Some(100_000);
+        accuracy.record(&ship, &call("NLRTM", 100_000 + 1800)); // Half an hour late
+        ship.eta = Some(200_000);
+        accuracy.record(&ship, &call("NLRTM", 200_000 - 4 * 3600)); // Four hours early
+        accuracy.record(&ship, &call("DEHAM", 200_000)); // Not where it said
+        ship.eta = Some(300_000 + MAX_ERROR_SECS + 1);
+        accuracy.record(&ship, &call("NLRTM", 300_000)); // Stale ETA
+
+        let summary = accuracy.summary();
+        assert_eq!(
+            summary,
+            EtaSummary {
+                arrivals: 2,
+                mean_abs_error_secs: (1800.0 + 14_400.0) / 2.0,
+                mean_error_secs: (1800.0 - 14_400.0) / 2.0,
+                within_1h: 0.5,
+                within_6h: 1.0,
+            }
+        );
+        let stats = accuracy.ship(1).unwrap();
+        assert_eq!(stats.recent.iter().map(|s| s.error_secs).collect::<Vec<_>>(), vec![1800, -14_400]);
+        assert!(accuracy.ship(2).is_none());
+
+        accuracy.purge(200_000 + RETENTION_SECS + 1);
+        assert!(accuracy.ship(1).is_none());
+        assert_eq!(accuracy.summary().arrivals, 2);
+    }
+}
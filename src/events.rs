@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use futures_util::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::anomalies::AnomalyKind;
+use crate::dark::DarkStatus;
+use crate::geofence::SpeedingStatus;
+use crate::identity::ConflictKind;
+use crate::loitering::LoiterStatus;
+use crate::rendezvous::MeetingStatus;
+
+// Events kept for /api/events; older ones are dropped
+const EVENT_LOG_CAPACITY: usize = 10_000;
+// Events buffered for notifiers that fall behind before they start missing some
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    ZoneEnter { zone: Arc<str> },
+    ZoneExit { zone: Arc<str> },
+    // A ship's predicted track enters the zone in about `eta_secs`
+    ZoneApproach { zone: Arc<str>, eta_secs: u64 },
+    Alert { rule: Arc<str> },
+    // An anchored vessel has left its swing circle
+    AnchorDrag { lat: f64, lng: f64, distance_m: f64, radius_m: f64 },
+    // A regularly reporting ship stopped transmitting mid-passage (at its last
+    // known position), or was heard from again (at its new one)
+    Dark { status: DarkStatus, lat: f64, lng: f64, silent_secs: u64 },
+    // Two ships will pass within the CPA threshold, `tcpa_secs` from now
+    CollisionRisk { other: u32, cpa_m: f64, tcpa_secs: f64 },
+    PortArrival { port: Arc<str>, locode: Arc<str> },
+    PortDeparture { port: Arc<str>, locode: Arc<str>, duration_secs: u64 },
+    // A ship went over a zone's speed limit, or is back under it (or out of
+    // the zone) with the peak speed and how long it lasted
+    SpeedLimit { zone: Arc<str>, status: SpeedingStatus, limit_kn: f64, peak_kn: f64, duration_secs: u64 },
+    // First sighting of unusual behaviour, see /api/anomalies
+    Anomaly { anomaly: AnomalyKind, score: f64 },
+    // Two slow ships have been side by side away from port for a while, or
+    // have parted; at the midpoint between them
+    Rendezvous { other: u32, status: MeetingStatus, lat: f64, lng: f64, duration_secs: u64 },
+    // A ship has held position or circled in open water for a while, or has
+    // moved on; at where it started
+    Loitering { status: LoiterStatus, lat: f64, lng: f64, duration_secs: u64 },
+    // A ship's IMO number or call sign disagrees with what it or another
+    // MMSI sent earlier, see /api/identity-conflicts
+    IdentityConflict { conflict: ConflictKind, values: Vec<String>, others: Vec<u32> },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Normal,
+    High,
+}
+
+impl EventKind {
+    // The `type` tag, for filtering
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::ZoneEnter { .. } => "zone_enter",
+            EventKind::ZoneExit { .. } => "zone_exit",
+            EventKind::ZoneApproach { .. } => "zone_approach",
+            EventKind::Alert { .. } => "alert",
+            EventKind::AnchorDrag { .. } => "anchor_drag",
+            EventKind::Dark { .. } => "dark",
+            EventKind::CollisionRisk { .. } => "collision_risk",
+            EventKind::PortArrival { .. } => "port_arrival",
+            EventKind::PortDeparture { .. } => "port_departure",
+            EventKind::SpeedLimit { .. } => "speed_limit",
+            EventKind::Anomaly { .. } => "anomaly",
+            EventKind::Rendezvous { .. } => "rendezvous",
+            EventKind::Loitering { .. } => "loitering",
+            EventKind::IdentityConflict { .. } => "identity_conflict",
+        }
+    }
+
+    pub fn priority(&self) -> Priority {
+        match self {
+            EventKind::AnchorDrag { .. } | EventKind::CollisionRisk { .. } => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct Event {
+    pub id: u64,
+    pub timestamp: u64,
+    pub mmsi: u32,
+    pub priority: Priority,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct EventFilter {
+    // Only events newer than this id, for polling
+    pub since: Option<u64>,
+    // One type, or several separated by commas
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub mmsi: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        self.since.is_none_or(|since| event.id > since)
+            && self.kind.as_deref().is_none_or(|kinds| kinds.split(',').any(|kind| kind.trim() == event.kind.name()))
+            && self.mmsi.is_none_or(|mmsi| event.mmsi == mmsi)
+    }
+}
+
+#[derive(Default)]
+struct Log {
+    events: VecDeque<Arc<Event>>,
+    next_id: u64,
+}
+
+// Recent events, newest last, shared by everything that detects them. New
+// events are also broadcast to the notifiers.
+pub struct EventLog {
+    log: Mutex<Log>,
+    notify: broadcast::Sender<Arc<Event>>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self {
+            log: Mutex::new(Log::default()),
+            notify: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.notify.subscribe()
+    }
+
+    pub fn push(&self, timestamp: u64, mmsi: u32, kind: EventKind) -> Arc<Event> {
+        let mut log = self.log.lock().unwrap();
+        log.next_id += 1;
+        let event = Arc::new(Event {
+            id: log.next_id,
+            timestamp,
+            mmsi,
+            priority: kind.priority(),
+            kind,
+        });
+        if log.events.len() >= EVENT_LOG_CAPACITY {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+        // Sent under the lock so subscribers see events in id order
+        let _ = self.notify.send(event.clone());
+        event
+    }
+
+    // Matching events, oldest first; `limit` keeps the newest
+    pub fn query(&self, filter: &EventFilter) -> Vec<Arc<Event>> {
+        let log = self.log.lock().unwrap();
+        let mut events: Vec<Arc<Event>> = log
+            .events
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(filter.limit.unwrap_or(EVENT_LOG_CAPACITY))
+            .cloned()
+            .collect();
+        events.reverse();
+        events
+    }
+
+    // Matching events as they are logged, after any already logged since
+    // `filter.since`. A subscriber that falls behind catches up from the log.
+    pub fn stream(self: Arc<Self>, filter: EventFilter) -> impl Stream<Item = Arc<Event>> {
+        let live = self.subscribe();
+        let backlog: VecDeque<Arc<Event>> =
+            if filter.since.is_some() { self.query(&filter).into() } else { VecDeque::new() };
+        let last = backlog.back().map(|event| event.id).or(filter.since).unwrap_or(0);
+
+        futures_util::stream::unfold(
+            (self, filter, live, backlog, last),
+            |(log, filter, mut live, mut backlog, mut last)| async move {
+                loop {
+                    if let Some(event) = backlog.pop_front() {
+                        last = event.id;
+                        return Some((event, (log, filter, live, backlog, last)));
+                    }
+                    match live.recv().await {
+                        Ok(event) if event.id > last && filter.matches(&event) => backlog.push_back(event),
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(_)) => {
+                            let missed = EventFilter { since: Some(last), limit: None, ..filter.clone() };
+                            backlog = log.query(&missed).into();
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_filters() {
+        let log = EventLog::new();
+        let zone: Arc<str> = Arc::from("Harbour");
+        log.push(10, 1, EventKind::ZoneEnter { zone: zone.clone() });
+        log.push(11, 2, EventKind::ZoneEnter { zone: zone.clone() });
+        log.push(12, 1, EventKind::ZoneExit { zone });
+
+        let ids = |filter: EventFilter| log.query(&filter).iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(EventFilter::default()), vec![1, 2, 3]);
+        assert_eq!(ids(EventFilter { since: Some(1), ..Default::default() }), vec![2, 3]);
+        assert_eq!(ids(EventFilter { mmsi: Some(1), ..Default::default() }), vec![1, 3]);
+        assert_eq!(ids(EventFilter { kind: Some("zone_exit".into()), ..Default::default() }), vec![3]);
+        assert_eq!(ids(EventFilter { limit: Some(2), ..Default::default() }), vec![2, 3]);
+        assert_eq!(ids(EventFilter { kind: Some("zone_exit, zone_enter".into()), mmsi: Some(2), ..Default::default() }), vec![2]);
+
+        let json = serde_json::to_value(&*log.query(&EventFilter::default())[2]).unwrap();
+        assert_eq!(json["type"], "zone_exit");
+        assert_eq!(json["zone"], "Harbour");
+    }
+
+    #[tokio::test]
+    async fn test_stream_replays_then_follows() {
+        use futures_util::StreamExt;
+
+        let log = Arc::new(EventLog::new());
+        let zone: Arc<str> = Arc::from("Harbour");
+        for mmsi in [1, 2, 1] {
+            log.push(10, mmsi, EventKind::ZoneEnter { zone: zone.clone() });
+        }
+        let filter = EventFilter { since: Some(1), mmsi: Some(1), ..Default::default() };
+        let mut stream = Box::pin(log.clone().stream(filter));
+        log.push(11, 2, EventKind::ZoneExit { zone: zone.clone() });
+        log.push(11, 1, EventKind::ZoneExit { zone });
+
+        assert_eq!(stream.next().await.unwrap().id, 3);
+        assert_eq!(stream.next().await.unwrap().id, 5);
+    }
+}
@@ -0,0 +1,84 @@
+use axum::body::{Body, Bytes};
+use futures_util::stream;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use crate::history::TrackPoint;
+use crate::monitor::Monitor;
+use crate::ship::ShipCache;
+
+// Ships serialized per chunk, so only one chunk's worth is ever buffered
+const SHIPS_PER_CHUNK: usize = 500;
+
+#[derive(Serialize)]
+struct PointLine<'a> {
+    mmsi: u32,
+    #[serde(flatten)]
+    point: &'a TrackPoint,
+}
+
+// Every ship as one line of JSON. Only the MMSIs are collected up front;
+// ships gone by the time their chunk is written are left out
+pub fn ships(ships: Arc<ShipCache>) -> Body {
+    let mmsis: Vec<u32> = ships.ships.iter().map(|ship| ship.mmsi).collect();
+    let chunks: Vec<Vec<u32>> = mmsis.chunks(SHIPS_PER_CHUNK).map(<[u32]>::to_vec).collect();
+    Body::from_stream(stream::iter(chunks.into_iter().map(move |chunk| {
+        let mut out = Vec::new();
+        for mmsi in chunk {
+            if let Some(ship) = ships.ships.get(&mmsi)
+                && serde_json::to_writer(&mut out, &*ship).is_ok()
+            {
+                out.push(b'\n');
+            }
+        }
+        Ok::<_, Infallible>(Bytes::from(out))
+    })))
+}
+
+// Each ship's track points from `since` to `until`, one line per point
+pub fn history(monitor: Arc<Monitor>, mmsi: Option<u32>, since: u64, until: u64) -> Body {
+    let mmsis = match mmsi {
+        Some(mmsi) => vec![mmsi],
+        None => monitor.history.mmsis(),
+    };
+    Body::from_stream(stream::iter(mmsis.into_iter().map(move |mmsi| {
+        let mut out = Vec::new();
+        for point in monitor.history.track(mmsi, since, until) {
+            if serde_json::to_writer(&mut out, &PointLine { mmsi, point: &point }).is_ok() {
+                out.push(b'\n');
+            }
+        }
+        Ok::<_, Infallible>(Bytes::from(out))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::Ship;
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn test_ndjson_export() {
+        let ships = Arc::new(ShipCache::new());
+        for mmsi in 0..(SHIPS_PER_CHUNK as u32 + 2) {
+            ships.insert_ship(mmsi, Ship::new(mmsi, ""));
+        }
+        let bytes = to_bytes(self::ships(ships), usize::MAX).await.unwrap();
+        let lines: Vec<&[u8]> = bytes.split(|&b| b == b'\n').filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines.len(), SHIPS_PER_CHUNK + 2);
+        assert!(serde_json::from_slice::<Ship>(lines[0]).is_ok());
+
+        let monitor = Arc::new(Monitor::new());
+        let mut ship = Ship::new(244660000, "ALIDA");
+        for t in [1_700_000_000, 1_700_000_060, 1_700_000_120] {
+            ship.last_update = t;
+            monitor.history.observe(&ship);
+        }
+        let bytes = to_bytes(history(monitor, None, 1_700_000_060, u64::MAX), usize::MAX).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with(r#"{"mmsi":244660000,"timestamp":1700000060,"#));
+    }
+}
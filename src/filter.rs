@@ -0,0 +1,515 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ais::AisClass;
+use crate::emissions::Category;
+use crate::geofence::Geofences;
+use crate::monitor::Monitor;
+use crate::ship::Ship;
+
+// Longer expressions, or deeper parentheses, are rejected rather than parsed
+const MAX_LENGTH: usize = 1000;
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Has, // `:`, part of the text, or equal to a number
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Type,
+    Status,
+    Speed,
+    Heading,
+    Course,
+    Length,
+    Draught,
+    Mmsi,
+    Imo,
+    Quality,
+}
+
+impl Number {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Type => "type",
+            Self::Status => "status",
+            Self::Speed => "speed",
+            Self::Heading => "heading",
+            Self::Course => "course",
+            Self::Length => "length",
+            Self::Draught => "draught",
+            Self::Mmsi => "mmsi",
+            Self::Imo => "imo",
+            Self::Quality => "quality",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Text {
+    Name,
+    Destination,
+    CallSign,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Term {
+    Number(Number, Op, f64),
+    Text(Text, Op, String), // Lowercased
+    // The bool is whether it should match, for `!=`
+    Category(bool, Category),
+    Class(bool, AisClass),
+    Zone(bool, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+// What a filter asks about a ship besides its own fields
+pub trait Context {
+    fn in_zone(&self, mmsi: u32, zone: &str) -> bool;
+    // None where scores aren't at hand; `quality` terms then never match
+    fn quality(&self, ship: &Ship) -> Option<f64>;
+}
+
+impl Context for Geofences {
+    fn in_zone(&self, mmsi: u32, zone: &str) -> bool {
+        self.is_inside(mmsi, zone)
+    }
+
+    fn quality(&self, _: &Ship) -> Option<f64> {
+        None
+    }
+}
+
+// Zones and data quality scores as the monitor has them at `now`
+pub struct Scored<'a> {
+    pub monitor: &'a Monitor,
+    pub now: u64,
+}
+
+impl Context for Scored<'_> {
+    fn in_zone(&self, mmsi: u32, zone: &str) -> bool {
+        self.monitor.geofences.is_inside(mmsi, zone)
+    }
+
+    fn quality(&self, ship: &Ship) -> Option<f64> {
+        Some(self.monitor.quality(ship, self.now).score)
+    }
+}
+
+// A filter expression such as `type:cargo AND speed>12 AND NOT status:moored`:
+// terms of a field, an operator (`:`, `=`, `!=`, `<`, `<=`, `>`, `>=`) and a
+// value, quoted if it has spaces, combined with AND, OR, NOT and parentheses.
+// Terms side by side are ANDed. Kept as written, which is how it serializes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self> {
+        if source.len() > MAX_LENGTH {
+            return Err(anyhow::anyhow!("Filters are limited to {} characters", MAX_LENGTH));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, next: 0, depth: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(anyhow::anyhow!("Unexpected {} in filter", token));
+        }
+        Ok(Self { source: source.trim().to_string(), expr })
+    }
+
+    pub fn matches(&self, ship: &Ship, context: &impl Context) -> bool {
+        evaluate(&self.expr, ship, context)
+    }
+
+    // Whether it needs quality scores, which not every context has
+    pub fn uses_quality(&self) -> bool {
+        fn uses(expr: &Expr) -> bool {
+            match expr {
+                Expr::And(a, b) | Expr::Or(a, b) => uses(a) || uses(b),
+                Expr::Not(expr) => uses(expr),
+                Expr::Term(term) => matches!(term, Term::Number(Number::Quality, _, _)),
+            }
+        }
+        uses(&self.expr)
+    }
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        Self::parse(source)
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = anyhow::Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.source
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn evaluate(expr: &Expr, ship: &Ship, context: &impl Context) -> bool {
+    match expr {
+        Expr::And(a, b) => evaluate(a, ship, context) && evaluate(b, ship, context),
+        Expr::Or(a, b) => evaluate(a, ship, context) || evaluate(b, ship, context),
+        Expr::Not(expr) => !evaluate(expr, ship, context),
+        Expr::Term(term) => match term {
+            Term::Number(field, op, value) => {
+                let actual = match field {
+                    Number::Type => Some(ship.ship_type as f64),
+                    Number::Status => Some(ship.nav_status as f64),
+                    Number::Speed => Some(ship.speed),
+                    Number::Heading => Some(ship.heading as f64).filter(|&heading| heading < 360.0),
+                    Number::Course => Some(ship.cog).filter(|&cog| cog < 360.0),
+                    Number::Length => ship.dimensions.map(|dimensions| dimensions.length as f64).filter(|&length| length > 0.0),
+                    Number::Draught => ship.draught,
+                    Number::Mmsi => Some(ship.mmsi as f64),
+                    Number::Imo => Some(ship.imo_number as f64).filter(|&imo| imo > 0.0),
+                    Number::Quality => context.quality(ship),
+                };
+                // Unknown values match nothing, so `NOT length>100` keeps them
+                actual.is_some_and(|actual| compare(actual, *op, *value))
+            }
+            Term::Text(field, op, value) => {
+                let text = match field {
+                    Text::Name => &ship.name,
+                    Text::Destination => &ship.destination,
+                    Text::CallSign => &ship.call_sign,
+                };
+                let text = text.trim().to_lowercase();
+                match op {
+                    Op::Has => text.contains(value.as_str()),
+                    Op::Eq => text == *value,
+                    _ => text != *value,
+                }
+            }
+            Term::Category(equal, category) => (Category::of(ship.ship_type) == *category) == *equal,
+            Term::Class(equal, class) => (ship.class == Some(*class)) == *equal,
+            Term::Zone(equal, zone) => context.in_zone(ship.mmsi, zone) == *equal,
+        },
+    }
+}
+
+fn compare(actual: f64, op: Op, value: f64) -> bool {
+    match op {
+        Op::Has | Op::Eq => actual == value,
+        Op::Ne => actual != value,
+        Op::Lt => actual < value,
+        Op::Le => actual <= value,
+        Op::Gt => actual > value,
+        Op::Ge => actual >= value,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+            Token::Op(op) => {
+                let symbol = match op {
+                    Op::Has => ":",
+                    Op::Eq => "=",
+                    Op::Ne => "!=",
+                    Op::Lt => "<",
+                    Op::Le => "<=",
+                    Op::Gt => ">",
+                    Op::Ge => ">=",
+                };
+                write!(f, "'{}'", symbol)
+            }
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Quoted(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ':' => Token::Op(Op::Has),
+            '=' => Token::Op(Op::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ne),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => return Err(anyhow::anyhow!("Unterminated quote in filter")),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"():=!<>\"".contains(*c)) {
+                    word.push(c);
+                }
+                if word == "!" {
+                    return Err(anyhow::anyhow!("Expected != in filter"));
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    next: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    // AND is implied between terms side by side
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.keyword("AND") || self.peek() == Some(&Token::Open) || (matches!(self.peek(), Some(Token::Word(_))) && !self.at_keyword("OR")) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(anyhow::anyhow!("Filter is nested too deeply"));
+        }
+        let expr = if self.keyword("NOT") {
+            Expr::Not(Box::new(self.not()?))
+        } else if self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let expr = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return Err(anyhow::anyhow!("Missing ')' in filter"));
+            }
+            self.next += 1;
+            expr
+        } else {
+            Expr::Term(self.term()?)
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Term> {
+        let field = match self.peek() {
+            Some(Token::Word(word)) => word.to_lowercase(),
+            Some(token) => return Err(anyhow::anyhow!("Expected a field, not {}", token)),
+            None => return Err(anyhow::anyhow!("Filter ends where a field was expected")),
+        };
+        let op = match self.tokens.get(self.next + 1) {
+            Some(Token::Op(op)) => *op,
+            _ => return Err(anyhow::anyhow!("Expected an operator after {}", field)),
+        };
+        let value = match self.tokens.get(self.next + 2) {
+            Some(Token::Word(value) | Token::Quoted(value)) => value.clone(),
+            _ => return Err(anyhow::anyhow!("Expected a value after {}", field)),
+        };
+        self.next += 3;
+        parse_term(&field, op, &value)
+    }
+}
+
+fn parse_term(field: &str, op: Op, value: &str) -> Result<Term> {
+    let number = |field| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map(|number| Term::Number(field, op, number))
+            .ok_or_else(|| anyhow::anyhow!("{} needs a number, not {}", field.name(), value))
+    };
+    let equality = || match op {
+        Op::Has | Op::Eq => Ok(true),
+        Op::Ne => Ok(false),
+        _ => Err(anyhow::anyhow!("{} can only be compared with :, = or !=", field)),
+    };
+    let lower = value.to_lowercase();
+    match field {
+        // A category, or AIS type codes
+        "type" => match category(&lower) {
+            Some(category) => Ok(Term::Category(equality()?, category)),
+            None => number(Number::Type),
+        },
+        "status" => match nav_status(&lower) {
+            Some(status) => Ok(Term::Number(Number::Status, if equality()? { Op::Eq } else { Op::Ne }, status as f64)),
+            None => number(Number::Status),
+        },
+        "speed" => number(Number::Speed),
+        "heading" => number(Number::Heading),
+        "course" | "cog" => number(Number::Course),
+        "length" => number(Number::Length),
+        "draught" => number(Number::Draught),
+        "mmsi" => number(Number::Mmsi),
+        "imo" => number(Number::Imo),
+        "quality" => number(Number::Quality),
+        "name" | "destination" | "callsign" => {
+            if !matches!(op, Op::Has | Op::Eq | Op::Ne) {
+                return Err(anyhow::anyhow!("{} can only be compared with :, = or !=", field));
+            }
+            let text = match field {
+                "name" => Text::Name,
+                "destination" => Text::Destination,
+                _ => Text::CallSign,
+            };
+            Ok(Term::Text(text, op, lower))
+        }
+        "class" => match lower.as_str() {
+            "a" => Ok(Term::Class(equality()?, AisClass::A)),
+            "b" => Ok(Term::Class(equality()?, AisClass::B)),
+            _ => Err(anyhow::anyhow!("class is a or b, not {}", value)),
+        },
+        "zone" => Ok(Term::Zone(equality()?, value.to_string())),
+        _ => Err(anyhow::anyhow!("Unknown filter field {}", field)),
+    }
+}
+
+fn category(name: &str) -> Option<Category> {
+    match name {
+        "cargo" => Some(Category::Cargo),
+        "tanker" => Some(Category::Tanker),
+        "passenger" => Some(Category::Passenger),
+        "fishing" => Some(Category::Fishing),
+        "tug" => Some(Category::Tug),
+        "pleasure" => Some(Category::Pleasure),
+        "other" => Some(Category::Other),
+        _ => None,
+    }
+}
+
+// AIS navigational status codes by name
+fn nav_status(name: &str) -> Option<u32> {
+    match name {
+        "underway" | "under_way" => Some(0),
+        "anchored" | "at_anchor" => Some(1),
+        "not_under_command" => Some(2),
+        "restricted" => Some(3),
+        "constrained" => Some(4),
+        "moored" => Some(5),
+        "aground" => Some(6),
+        "fishing" => Some(7),
+        "sailing" => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_filter_expressions() {
+        let geofences = Geofences::new();
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.ship_type, ship.speed, ship.nav_status, ship.destination) = (70, 14.0, 0, intern("NLRTM"));
+        let matches = |source: &str, ship: &Ship| Filter::parse(source).unwrap().matches(ship, &geofences);
+
+        assert!(matches("type:cargo AND speed>12 AND NOT status:moored", &ship));
+        assert!(matches("type:cargo speed>12", &ship));
+        assert!(!matches("type:tanker OR speed<=12", &ship));
+        assert!(matches("(type:tanker OR type>=70) and destination:rtm", &ship));
+        assert!(matches("name=\"alida\" AND mmsi:244660000 AND zone!=Bosphorus", &ship));
+        // Unknown values match nothing either way
+        assert!(!matches("length>100", &ship) && !matches("length<=100", &ship));
+        assert!(matches("NOT length>100", &ship));
+        ship.nav_status = 5;
+        assert!(!matches("type:cargo AND NOT status:moored", &ship));
+
+        for bad in ["speed>fast", "type<cargo", "colour:red", "speed>", "(type:cargo", "type:cargo OR", "name:\"open", "class:c"] {
+            assert!(Filter::parse(bad).is_err(), "{}", bad);
+        }
+        assert!(Filter::parse(&"(".repeat(100)).is_err());
+        let filter: Filter = serde_json::from_str("\" quality>=0.5 \"").unwrap();
+        assert!(filter.uses_quality() && !filter.matches(&ship, &geofences));
+        assert_eq!(serde_json::to_string(&filter).unwrap(), "\"quality>=0.5\"");
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::ship::Ship;
+use crate::webhooks::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+pub const DEFAULT_BATCH: usize = 1000;
+// A batch is sent when full or when its oldest record is this old
+const MAX_BATCH_AGE: Duration = Duration::from_secs(2);
+// Records waiting while a batch is being sent; past this they are dropped
+pub const BACKLOG: usize = 100_000;
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Raw,        // Upstream frames exactly as aisstream.io sent them
+    Normalized, // Ship states after each update, as the API returns them
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "raw" => Ok(Format::Raw),
+            "normalized" => Ok(Format::Normalized),
+            _ => Err(anyhow::anyhow!("Firehose format must be raw or normalized, not {}", format)),
+        }
+    }
+}
+
+// Where raw frames are copied for the firehose; frames are dropped rather
+// than holding up the read loop
+#[derive(Clone)]
+pub struct Tap {
+    records: mpsc::Sender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Tap {
+    pub fn send(&self, frame: &[u8]) {
+        if self.records.try_send(frame.to_vec()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Every record, newline-delimited JSON, POSTed in batches. Bodies are gzipped
+// unless turned off, and signed like webhooks when there is a secret.
+pub struct Firehose {
+    url: Url,
+    secret: Option<String>,
+    gzip: bool,
+    batch: usize,
+    client: reqwest::Client,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Firehose {
+    pub fn new(url: Url, secret: Option<String>, gzip: bool, batch: usize) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { url, secret, gzip, batch: batch.max(1), client, dropped: Arc::new(AtomicU64::new(0)) })
+    }
+
+    // The sending half for the records, and where they are received
+    pub fn channel(&self) -> (Tap, mpsc::Receiver<Vec<u8>>) {
+        let (records, receiver) = mpsc::channel(BACKLOG);
+        (Tap { records, dropped: self.dropped.clone() }, receiver)
+    }
+
+    async fn send(&self, body: Vec<u8>, records: usize) {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let mut request = self
+                .client
+                .post(self.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+                .header(TIMESTAMP_HEADER, timestamp)
+                .body(body.clone());
+            if self.gzip {
+                request = request.header(reqwest::header::CONTENT_ENCODING, "gzip");
+            }
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, sign(secret, timestamp, &body));
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_client_error()
+                        && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                        && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        warn!("Firehose {} rejected a batch of {} records: {}", self.url, records, status);
+                        return;
+                    }
+                    debug!("Firehose {} returned {} (attempt {})", self.url, status, attempt);
+                }
+                Err(e) => debug!("Firehose {} failed: {} (attempt {})", self.url, e, attempt),
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        warn!("Giving up on a firehose batch of {} records after {} attempts", records, MAX_ATTEMPTS);
+    }
+}
+
+// One record per line. Raw frames may be pretty-printed; newlines only ever
+// appear between JSON tokens, so they can become spaces.
+fn encode_batch(records: &[Vec<u8>], gzip: bool) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(records.iter().map(|record| record.len() + 1).sum());
+    for record in records {
+        body.extend(record.iter().map(|&byte| if byte == b'\n' || byte == b'\r' { b' ' } else { byte }));
+        body.push(b'\n');
+    }
+    if !gzip {
+        return Ok(body);
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&body)?;
+    Ok(encoder.finish()?)
+}
+
+// Copy each ship update into the firehose as its JSON state
+pub async fn normalized_task(tap: Tap, mut updates: broadcast::Receiver<Arc<Ship>>) {
+    loop {
+        match updates.recv().await {
+            Ok(ship) => match serde_json::to_vec(&*ship) {
+                Ok(record) => tap.send(&record),
+                Err(e) => warn!("Could not serialize ship {} for the firehose: {}", ship.mmsi, e),
+            },
+            Err(RecvError::Lagged(missed)) => {
+                tap.dropped.fetch_add(missed, Ordering::Relaxed);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+// Gather records into batches and send them one at a time; records arriving
+// meanwhile wait in the channel
+pub async fn firehose_task(firehose: Firehose, mut records: mpsc::Receiver<Vec<u8>>) {
+    info!("Sending a firehose of updates to {}", firehose.url);
+    let mut reported_dropped = 0;
+    while let Some(first) = records.recv().await {
+        let deadline = Instant::now() + MAX_BATCH_AGE;
+        let mut batch = vec![first];
+        while batch.len() < firehose.batch {
+            match timeout_at(deadline, records.recv()).await {
+                Ok(Some(record)) => batch.push(record),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        match encode_batch(&batch, firehose.gzip) {
+            Ok(body) => firehose.send(body, batch.len()).await,
+            Err(e) => warn!("Could not encode a firehose batch: {}", e),
+        }
+
+        let dropped = firehose.dropped.load(Ordering::Relaxed);
+        if dropped > reported_dropped {
+            warn!("Firehose fell behind, dropped {} records ({} total)", dropped - reported_dropped, dropped);
+            reported_dropped = dropped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_encode_batch() {
+        let records = vec![b"{\"a\":1}".to_vec(), b"{\n  \"b\": 2\n}".to_vec()];
+        let mut body = String::new();
+        GzDecoder::new(&encode_batch(&records, true).unwrap()[..]).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "{\"a\":1}\n{   \"b\": 2 }\n");
+        assert_eq!(encode_batch(&records, false).unwrap(), body.as_bytes());
+        assert!("csv".parse::<Format>().is_err());
+    }
+}
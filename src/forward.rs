@@ -0,0 +1,177 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::nmea::Encoder;
+use crate::ship::Ship;
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+// One aggregator to forward to, e.g. `{"name": "aishub", "addr": "data.aishub.net:2345"}`
+#[derive(Deserialize, Clone, Debug)]
+pub struct TargetSpec {
+    pub name: String,
+    pub addr: String, // host:port
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct TargetStatus {
+    pub name: String,
+    pub addr: String,
+    pub enabled: bool,
+    pub forwarded_packets: u64,
+    pub failed_packets: u64,
+}
+
+struct Target {
+    name: String,
+    addr: String,
+    resolved: Mutex<Option<SocketAddr>>, // Looked up again after a failed send
+    enabled: AtomicBool,
+    forwarded: AtomicU64,
+    failed: AtomicU64,
+}
+
+// UDP targets that get every update as AIVDM sentences, one per datagram
+#[derive(Default)]
+pub struct Forwarder {
+    targets: Vec<Target>,
+}
+
+impl Forwarder {
+    pub fn new(specs: Vec<TargetSpec>) -> Result<Self> {
+        let mut targets: Vec<Target> = Vec::new();
+        for spec in specs {
+            if targets.iter().any(|target| target.name == spec.name) {
+                return Err(anyhow::anyhow!("Duplicate forwarding target '{}'", spec.name));
+            }
+            if spec.addr.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err()) {
+                return Err(anyhow::anyhow!("Forwarding target '{}' needs a host:port address", spec.name));
+            }
+            targets.push(Target {
+                name: spec.name,
+                addr: spec.addr,
+                resolved: Mutex::new(None),
+                enabled: AtomicBool::new(spec.enabled),
+                forwarded: AtomicU64::new(0),
+                failed: AtomicU64::new(0),
+            });
+        }
+        Ok(Self { targets })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        Self::new(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    pub fn status(&self) -> Vec<TargetStatus> {
+        self.targets
+            .iter()
+            .map(|target| TargetStatus {
+                name: target.name.clone(),
+                addr: target.addr.clone(),
+                enabled: target.enabled.load(Ordering::Relaxed),
+                forwarded_packets: target.forwarded.load(Ordering::Relaxed),
+                failed_packets: target.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // False if there is no such target
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        match self.targets.iter().find(|target| target.name == name) {
+            Some(target) => {
+                target.enabled.store(enabled, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn send(&self, socket: &UdpSocket, sentence: &[u8]) {
+        for target in self.targets.iter().filter(|target| target.enabled.load(Ordering::Relaxed)) {
+            let resolved = *target.resolved.lock().unwrap();
+            let addr = match resolved {
+                Some(addr) => Some(addr),
+                None => match lookup_host(&target.addr).await {
+                    Ok(mut addrs) => addrs.next(),
+                    Err(e) => {
+                        debug!("Could not resolve forwarding target {}: {}", target.addr, e);
+                        None
+                    }
+                },
+            };
+            let sent = match addr {
+                Some(addr) => socket.send_to(sentence, addr).await.is_ok(),
+                None => false,
+            };
+            *target.resolved.lock().unwrap() = if sent { addr } else { None };
+            let counter = if sent { &target.forwarded } else { &target.failed };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub async fn forward_task(forwarder: Arc<Forwarder>, mut updates: broadcast::Receiver<Arc<Ship>>) {
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Could not open a UDP socket for forwarding: {}", e);
+            return;
+        }
+    };
+    info!("Forwarding AIVDM sentences to {} UDP targets", forwarder.targets.len());
+    let mut encoder = Encoder::new();
+    loop {
+        match updates.recv().await {
+            Ok(ship) => {
+                for sentence in encoder.encode(&ship, false) {
+                    forwarder.send(&socket, sentence.as_bytes()).await;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => warn!("UDP forwarding fell behind, {} updates not forwarded", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_forwarding_counts_packets() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let specs = vec![
+            TargetSpec { name: "local".into(), addr: receiver.local_addr().unwrap().to_string(), enabled: true },
+            TargetSpec { name: "off".into(), addr: "127.0.0.1:9".into(), enabled: false },
+        ];
+        let forwarder = Forwarder::new(specs).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        forwarder.send(&socket, b"!AIVDM,1,1,,A,0,0*00\r\n").await;
+
+        let mut buffer = [0u8; 64];
+        let (len, _) = receiver.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], b"!AIVDM,1,1,,A,0,0*00\r\n");
+        let status = forwarder.status();
+        assert_eq!((status[0].forwarded_packets, status[1].forwarded_packets), (1, 0));
+
+        assert!(forwarder.set_enabled("off", true));
+        assert!(!forwarder.set_enabled("missing", true));
+        assert!(Forwarder::new(vec![TargetSpec { name: "x".into(), addr: "nowhere".into(), enabled: true }]).is_err());
+    }
+}
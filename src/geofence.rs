@@ -0,0 +1,461 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::events::{EventKind, EventLog};
+use crate::geo::{haversine_m, point_in_polygon, EARTH_RADIUS_M};
+use crate::predict::Motion;
+use crate::ship::Ship;
+
+// How far ahead predicted tracks are checked for zone entries, sampled once a minute
+const LOOKAHEAD_SECS: u64 = 30 * 60;
+const LOOKAHEAD_STEP_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    Circle { lat: f64, lng: f64, radius_m: f64 },
+    // [lat, lng] vertices
+    Polygon { points: Vec<[f64; 2]> },
+}
+
+impl Shape {
+    pub fn validate(&self) -> Result<()> {
+        let in_range = |lat: f64, lng: f64| (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng);
+        match self {
+            Shape::Circle { lat, lng, radius_m } => {
+                if !in_range(*lat, *lng) {
+                    return Err(anyhow::anyhow!("Circle centre out of range: [{}, {}]", lat, lng));
+                }
+                if radius_m.is_nan() || *radius_m <= 0.0 {
+                    return Err(anyhow::anyhow!("Circle radius must be positive"));
+                }
+            }
+            Shape::Polygon { points } => {
+                if points.len() < 3 {
+                    return Err(anyhow::anyhow!("A polygon needs at least 3 points"));
+                }
+                if let Some([lat, lng]) = points.iter().find(|&&[lat, lng]| !in_range(lat, lng)) {
+                    return Err(anyhow::anyhow!("Polygon point out of range: [{}, {}]", lat, lng));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // (sw_lat, sw_lng, ne_lat, ne_lng) enclosing the shape, for a cheap first check
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Shape::Circle { lat, lng, radius_m } => {
+                let d_lat = (radius_m / EARTH_RADIUS_M).to_degrees();
+                let d_lng = d_lat / lat.to_radians().cos().max(1e-6);
+                (lat - d_lat, lng - d_lng, lat + d_lat, lng + d_lng)
+            }
+            Shape::Polygon { points } => points.iter().fold(
+                (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                |b, &[lat, lng]| (b.0.min(lat), b.1.min(lng), b.2.max(lat), b.3.max(lng)),
+            ),
+        }
+    }
+
+    fn contains(&self, lat: f64, lng: f64) -> bool {
+        match self {
+            Shape::Circle { lat: c_lat, lng: c_lng, radius_m } => {
+                haversine_m(*c_lat, *c_lng, lat, lng) <= *radius_m
+            }
+            Shape::Polygon { points } => point_in_polygon(lat, lng, points),
+        }
+    }
+}
+
+// A zone as PUT to the API or set under [regions]: its shape plus optional attributes
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ZoneSpec {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default)]
+    pub max_speed_kn: Option<f64>,
+}
+
+impl ZoneSpec {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(limit) = self.max_speed_kn
+            && (!limit.is_finite() || limit <= 0.0)
+        {
+            return Err(anyhow::anyhow!("max_speed_kn must be positive"));
+        }
+        self.shape.validate()
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Zone {
+    pub name: Arc<str>,
+    pub shape: Shape,
+    // Speed limit inside the zone, e.g. a harbour or whale protection area
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_speed_kn: Option<f64>,
+    #[serde(skip)]
+    bounds: (f64, f64, f64, f64),
+}
+
+impl Zone {
+    pub fn new(name: &str, shape: Shape) -> Self {
+        let bounds = shape.bounds();
+        Self {
+            name: Arc::from(name),
+            shape,
+            max_speed_kn: None,
+            bounds,
+        }
+    }
+
+    pub fn with_max_speed(mut self, max_speed_kn: Option<f64>) -> Self {
+        self.max_speed_kn = max_speed_kn;
+        self
+    }
+
+    // (sw_lat, sw_lng, ne_lat, ne_lng) enclosing the zone
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        self.bounds
+    }
+
+    pub fn contains(&self, lat: f64, lng: f64) -> bool {
+        let (sw_lat, sw_lng, ne_lat, ne_lng) = self.bounds;
+        lat >= sw_lat && lat <= ne_lat && lng >= sw_lng && lng <= ne_lng && self.shape.contains(lat, lng)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedingStatus {
+    Started,
+    Ended,
+}
+
+// A ship over a zone's speed limit, since `started`
+struct Violation {
+    started: u64,
+    peak_kn: f64,
+}
+
+// Named zones, which zones each ship was last seen inside and since when, who is
+// currently breaking a zone's speed limit, and who is predicted to enter one
+#[derive(Default)]
+pub struct Geofences {
+    zones: RwLock<Vec<Zone>>,
+    inside: Mutex<HashMap<u32, HashMap<Arc<str>, u64>>>,
+    speeding: Mutex<HashMap<(u32, Arc<str>), Violation>>,
+    approaching: Mutex<HashSet<(u32, Arc<str>)>>,
+}
+
+impl Geofences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn zones(&self) -> Vec<Zone> {
+        self.zones.read().unwrap().clone()
+    }
+
+    pub fn zone(&self, name: &str) -> Option<Zone> {
+        self.zones.read().unwrap().iter().find(|zone| &*zone.name == name).cloned()
+    }
+
+    // Add a zone, or replace the one with the same name
+    pub fn upsert(&self, zone: Zone) {
+        let mut zones = self.zones.write().unwrap();
+        match zones.iter_mut().find(|existing| existing.name == zone.name) {
+            Some(existing) => *existing = zone,
+            None => zones.push(zone),
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let mut zones = self.zones.write().unwrap();
+        let before = zones.len();
+        zones.retain(|zone| &*zone.name != name);
+        // Ships inside a deleted zone don't get exit events
+        for inside in self.inside.lock().unwrap().values_mut() {
+            inside.remove(name);
+        }
+        self.speeding.lock().unwrap().retain(|(_, zone), _| &**zone != name);
+        self.approaching.lock().unwrap().retain(|(_, zone)| &**zone != name);
+        zones.len() != before
+    }
+
+    // Whether the ship was inside the named zone at its last position update
+    pub fn is_inside(&self, mmsi: u32, zone: &str) -> bool {
+        self.inside.lock().unwrap().get(&mmsi).is_some_and(|inside| inside.contains_key(zone))
+    }
+
+    // Every zone with the ships last seen inside it
+    pub fn occupants(&self) -> HashMap<Arc<str>, Vec<u32>> {
+        let mut occupants: HashMap<Arc<str>, Vec<u32>> =
+            self.zones.read().unwrap().iter().map(|zone| (zone.name.clone(), Vec::new())).collect();
+        for (&mmsi, inside) in self.inside.lock().unwrap().iter() {
+            for zone in inside.keys() {
+                if let Some(ships) = occupants.get_mut(zone) {
+                    ships.push(mmsi);
+                }
+            }
+        }
+        occupants
+    }
+
+    // The ships inside a zone with when each entered it, longest there
+    // first; None for an unknown zone
+    pub fn occupancy(&self, name: &str) -> Option<Vec<(u32, u64)>> {
+        let zones = self.zones.read().unwrap();
+        let zone = zones.iter().find(|zone| &*zone.name == name)?;
+        let mut occupants: Vec<(u32, u64)> = self
+            .inside
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&mmsi, inside)| inside.get(&zone.name).map(|&entered| (mmsi, entered)))
+            .collect();
+        occupants.sort_by_key(|&(mmsi, entered)| (entered, mmsi));
+        Some(occupants)
+    }
+
+    // Check a position update against every zone, logging enter/exit events
+    // and the start and end of speeding inside zones with a limit
+    pub fn observe(&self, events: &EventLog, ship: &Ship) {
+        let (mmsi, timestamp, lat, lng) = (ship.mmsi, ship.last_update, ship.lat, ship.lng);
+        let zones = self.zones.read().unwrap();
+        let mut inside = self.inside.lock().unwrap();
+        let mut speeding = self.speeding.lock().unwrap();
+        let was_inside = inside.remove(&mmsi).unwrap_or_default();
+
+        let mut now_inside = HashMap::new();
+        // In zone order, so events come out in a stable order
+        for zone in zones.iter() {
+            let is_inside = zone.contains(lat, lng);
+            let entered = was_inside.get(&zone.name).copied();
+            match (entered, is_inside) {
+                (None, true) => {
+                    events.push(timestamp, mmsi, EventKind::ZoneEnter { zone: zone.name.clone() });
+                }
+                (Some(_), false) => {
+                    events.push(timestamp, mmsi, EventKind::ZoneExit { zone: zone.name.clone() });
+                }
+                _ => {}
+            }
+            if is_inside {
+                now_inside.insert(zone.name.clone(), entered.unwrap_or(timestamp));
+            }
+
+            let Some(limit_kn) = zone.max_speed_kn else {
+                continue;
+            };
+            let key = (mmsi, zone.name.clone());
+            if is_inside && ship.speed > limit_kn {
+                match speeding.get_mut(&key) {
+                    Some(violation) => violation.peak_kn = violation.peak_kn.max(ship.speed),
+                    None => {
+                        speeding.insert(key, Violation { started: timestamp, peak_kn: ship.speed });
+                        events.push(
+                            timestamp,
+                            mmsi,
+                            EventKind::SpeedLimit {
+                                zone: zone.name.clone(),
+                                status: SpeedingStatus::Started,
+                                limit_kn,
+                                peak_kn: ship.speed,
+                                duration_secs: 0,
+                            },
+                        );
+                    }
+                }
+            } else if let Some(violation) = speeding.remove(&key) {
+                events.push(
+                    timestamp,
+                    mmsi,
+                    EventKind::SpeedLimit {
+                        zone: zone.name.clone(),
+                        status: SpeedingStatus::Ended,
+                        limit_kn,
+                        peak_kn: violation.peak_kn,
+                        duration_secs: timestamp.saturating_sub(violation.started),
+                    },
+                );
+            }
+        }
+
+        if !now_inside.is_empty() {
+            inside.insert(mmsi, now_inside);
+        }
+    }
+
+    // Follow a ship's predicted track from `now` and log a zone_approach
+    // event for each zone it is about to enter, once until it gets there or
+    // changes course
+    pub fn look_ahead(&self, events: &EventLog, mmsi: u32, motion: &Motion, now: u64) {
+        let zones = self.zones.read().unwrap();
+        let inside = self.inside.lock().unwrap();
+        let mut approaching = self.approaching.lock().unwrap();
+        let already_inside = inside.get(&mmsi);
+
+        for zone in zones.iter() {
+            let key = (mmsi, zone.name.clone());
+            if already_inside.is_some_and(|inside| inside.contains_key(&zone.name)) || motion.speed_ms == 0.0 {
+                approaching.remove(&key);
+                continue;
+            }
+            let eta_secs = (LOOKAHEAD_STEP_SECS..=LOOKAHEAD_SECS).step_by(LOOKAHEAD_STEP_SECS as usize).find(|&secs| {
+                let (lat, lng) = motion.position_at(now + secs);
+                zone.contains(lat, lng)
+            });
+            match eta_secs {
+                Some(eta_secs) => {
+                    if approaching.insert(key) {
+                        events.push(now, mmsi, EventKind::ZoneApproach { zone: zone.name.clone(), eta_secs });
+                    }
+                }
+                None => {
+                    approaching.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    fn ship(mmsi: u32, timestamp: u64, lat: f64, lng: f64, speed: f64) -> Ship {
+        let mut ship = Ship::new(mmsi, "");
+        (ship.last_update, ship.lat, ship.lng, ship.speed) = (timestamp, lat, lng, speed);
+        ship
+    }
+
+    #[test]
+    fn test_enter_and_exit_events() {
+        let geofences = Geofences::new();
+        let events = EventLog::new();
+        geofences.upsert(Zone::new(
+            "Dover",
+            Shape::Circle { lat: 51.12, lng: 1.33, radius_m: 2_000.0 },
+        ));
+        geofences.upsert(Zone::new(
+            "Strait",
+            Shape::Polygon { points: vec![[50.8, 1.0], [51.2, 1.0], [51.2, 2.0], [50.8, 2.0]] },
+        ));
+
+        geofences.observe(&events, &ship(1, 100, 51.0, 1.5, 0.0)); // Strait only
+        geofences.observe(&events, &ship(1, 110, 51.12, 1.34, 0.0)); // Both
+        geofences.observe(&events, &ship(1, 120, 51.12, 1.34, 0.0)); // No change
+        geofences.observe(&events, &ship(1, 130, 52.0, 1.5, 0.0)); // Neither
+
+        let logged: Vec<(u64, EventKind)> = events
+            .query(&EventFilter::default())
+            .iter()
+            .map(|event| (event.timestamp, event.kind.clone()))
+            .collect();
+        let (dover, strait): (Arc<str>, Arc<str>) = (Arc::from("Dover"), Arc::from("Strait"));
+        assert_eq!(
+            logged,
+            vec![
+                (100, EventKind::ZoneEnter { zone: strait.clone() }),
+                (110, EventKind::ZoneEnter { zone: dover.clone() }),
+                (130, EventKind::ZoneExit { zone: dover }),
+                (130, EventKind::ZoneExit { zone: strait }),
+            ]
+        );
+
+        // Deleting a zone forgets who was in it
+        geofences.observe(&events, &ship(2, 140, 51.0, 1.5, 0.0));
+        assert!(geofences.remove("Strait"));
+        geofences.observe(&events, &ship(2, 150, 52.0, 1.5, 0.0));
+        assert_eq!(events.query(&EventFilter::default()).len(), 5);
+    }
+
+    #[test]
+    fn test_look_ahead() {
+        let (geofences, events) = (Geofences::new(), EventLog::new());
+        geofences.upsert(Zone::new("Harbour", Shape::Circle { lat: 50.1, lng: -4.0, radius_m: 1_000.0 }));
+
+        // Northbound at 12 kn, 6 nm (30 minutes) south of the harbour
+        let mut ship = ship(1, 0, 50.0, -4.0, 12.0);
+        ship.cog = 0.0;
+        let motion = Motion::of(&ship);
+        geofences.look_ahead(&events, 1, &motion, 0);
+        geofences.look_ahead(&events, 1, &motion, 60);
+        let logged: Vec<EventKind> = events.query(&EventFilter::default()).iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(logged.len(), 1);
+        let EventKind::ZoneApproach { zone, eta_secs } = &logged[0] else { unreachable!() };
+        assert_eq!(&**zone, "Harbour");
+        assert!((1500..=1800).contains(eta_secs), "{}", eta_secs);
+
+        // Turning away rearms it
+        ship.cog = 180.0;
+        geofences.look_ahead(&events, 1, &Motion::of(&ship), 120);
+        ship.cog = 0.0;
+        geofences.look_ahead(&events, 1, &Motion::of(&ship), 180);
+        assert_eq!(events.query(&EventFilter::default()).len(), 2);
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let (geofences, events) = (Geofences::new(), EventLog::new());
+        geofences.upsert(Zone::new("Anchorage", Shape::Circle { lat: 51.12, lng: 1.33, radius_m: 2_000.0 }));
+        assert_eq!(geofences.occupancy("Anchorage"), Some(vec![]));
+
+        geofences.observe(&events, &ship(3, 140, 51.12, 1.34, 0.0));
+        geofences.observe(&events, &ship(1, 150, 51.12, 1.34, 0.0));
+        geofences.observe(&events, &ship(2, 150, 52.0, 1.34, 0.0)); // Outside
+        // Moving about inside keeps the time it entered
+        geofences.observe(&events, &ship(3, 160, 51.121, 1.34, 0.0));
+        assert_eq!(geofences.occupancy("Anchorage"), Some(vec![(3, 140), (1, 150)]));
+        assert_eq!(geofences.occupancy("Nowhere"), None);
+    }
+
+    #[test]
+    fn test_speed_limits() {
+        let (geofences, events) = (Geofences::new(), EventLog::new());
+        geofences.upsert(
+            Zone::new("Harbour", Shape::Circle { lat: 50.0, lng: -4.0, radius_m: 5_000.0 }).with_max_speed(Some(6.0)),
+        );
+
+        for (timestamp, lat, speed) in [(0, 50.2, 14.0), (10, 50.0, 8.0), (20, 50.0, 9.5), (30, 50.0, 7.0), (40, 50.0, 5.0), (50, 50.0, 7.0), (60, 50.2, 12.0)] {
+            geofences.observe(&events, &ship(1, timestamp, lat, -4.0, speed));
+        }
+
+        let speeding: Vec<(u64, SpeedingStatus, f64, u64)> = events
+            .query(&EventFilter { kind: Some("speed_limit".into()), ..Default::default() })
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::SpeedLimit { status, peak_kn, duration_secs, .. } => (event.timestamp, status, peak_kn, duration_secs),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            speeding,
+            vec![
+                (10, SpeedingStatus::Started, 8.0, 0),
+                (40, SpeedingStatus::Ended, 9.5, 30),
+                (50, SpeedingStatus::Started, 7.0, 0),
+                // Leaving the zone ends it too
+                (60, SpeedingStatus::Ended, 7.0, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shape_validation() {
+        assert!(Shape::Circle { lat: 0.0, lng: 0.0, radius_m: 0.0 }.validate().is_err());
+        assert!(Shape::Polygon { points: vec![[0.0, 0.0], [1.0, 1.0]] }.validate().is_err());
+        assert!(Shape::Polygon { points: vec![[0.0, 0.0], [1.0, 1.0], [91.0, 0.0]] }.validate().is_err());
+
+        let spec: ZoneSpec =
+            serde_json::from_str(r#"{"type": "circle", "lat": 50.0, "lng": -4.0, "radius_m": 500, "max_speed_kn": 6}"#).unwrap();
+        assert_eq!((spec.shape.clone(), spec.max_speed_kn), (Shape::Circle { lat: 50.0, lng: -4.0, radius_m: 500.0 }, Some(6.0)));
+        assert!(ZoneSpec { max_speed_kn: Some(-1.0), ..spec }.validate().is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::ship::Ship;
+
+// Positions are kept at most one a minute per ship, for a day
+pub const POINT_INTERVAL_SECS: u64 = 60;
+pub const RETENTION_SECS: u64 = 24 * 3600;
+const MAX_POINTS: usize = (RETENTION_SECS / POINT_INTERVAL_SECS) as usize;
+// A ship is only placed in a time-lapse frame between points this close, or
+// this soon after its last one
+const MAX_GAP_SECS: u64 = 600;
+pub const MAX_FRAMES: u64 = 1440;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct TrackPoint {
+    pub timestamp: u64,
+    pub lat: f64,
+    pub lng: f64,
+    pub speed: f64,
+    pub cog: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct FramePosition {
+    pub mmsi: u32,
+    pub lat: f64,
+    pub lng: f64,
+    pub speed: f64,
+    pub cog: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub timestamp: u64,
+    pub ships: Vec<FramePosition>,
+}
+
+// What /api/history/timelapse returns: where the ships in a bbox were every
+// `step_secs` from `from` to `to`, for playing back past traffic
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Timelapse {
+    pub from: u64,
+    pub to: u64,
+    pub step_secs: u64,
+    pub frames: Vec<Frame>,
+}
+
+// Where each ship has been over the last day, oldest point first
+#[derive(Default)]
+pub struct History {
+    tracks: Mutex<HashMap<u32, VecDeque<TrackPoint>>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The step from the previous point, when a point is added after one
+    pub fn observe(&self, ship: &Ship) -> Option<(TrackPoint, TrackPoint)> {
+        let mut tracks = self.tracks.lock().unwrap();
+        let points = tracks.entry(ship.mmsi).or_default();
+        if points.back().is_some_and(|last| ship.last_update < last.timestamp + POINT_INTERVAL_SECS) {
+            return None;
+        }
+        let point = TrackPoint { timestamp: ship.last_update, lat: ship.lat, lng: ship.lng, speed: ship.speed, cog: ship.cog };
+        let previous = points.back().copied();
+        points.push_back(point);
+        while points.len() > MAX_POINTS || points.front().is_some_and(|first| ship.last_update - first.timestamp > RETENTION_SECS) {
+            points.pop_front();
+        }
+        previous.map(|previous| (previous, point))
+    }
+
+    pub fn purge(&self, now: u64) {
+        let mut tracks = self.tracks.lock().unwrap();
+        tracks.retain(|_, points| {
+            while points.front().is_some_and(|first| now.saturating_sub(first.timestamp) > RETENTION_SECS) {
+                points.pop_front();
+            }
+            !points.is_empty()
+        });
+    }
+
+    // Ships with any history, in no particular order
+    pub fn mmsis(&self) -> Vec<u32> {
+        self.tracks.lock().unwrap().keys().copied().collect()
+    }
+
+    // A ship's points from `since` to `until`, inclusive
+    pub fn track(&self, mmsi: u32, since: u64, until: u64) -> Vec<TrackPoint> {
+        let tracks = self.tracks.lock().unwrap();
+        let Some(points) = tracks.get(&mmsi) else {
+            return Vec::new();
+        };
+        points.iter().filter(|point| point.timestamp >= since && point.timestamp <= until).copied().collect()
+    }
+
+    // The points of each track a time-lapse from `from` to `to` can use, of
+    // the ships passing through [sw_lat, sw_lng, ne_lat, ne_lng] then, by
+    // MMSI; copied out so the frames are built without holding the lock
+    pub fn window(&self, bbox: [f64; 4], from: u64, to: u64) -> Vec<(u32, Vec<TrackPoint>)> {
+        let [sw_lat, sw_lng, ne_lat, ne_lng] = bbox;
+        let tracks = self.tracks.lock().unwrap();
+        let mut window: Vec<(u32, Vec<TrackPoint>)> = tracks
+            .iter()
+            .filter_map(|(&mmsi, points)| {
+                // Earlier points are too far before `from` to place the ship,
+                // and one after `to` is enough to interpolate towards
+                let first = points.partition_point(|point| point.timestamp + MAX_GAP_SECS < from);
+                let last = (points.partition_point(|point| point.timestamp <= to) + 1).min(points.len());
+                let points = points.range(first..last.max(first));
+                let (mut south, mut west, mut north, mut east) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+                for point in points.clone() {
+                    (south, west, north, east) = (south.min(point.lat), west.min(point.lng), north.max(point.lat), east.max(point.lng));
+                }
+                let touches = south <= ne_lat && north >= sw_lat && west <= ne_lng && east >= sw_lng;
+                touches.then(|| (mmsi, points.copied().collect()))
+            })
+            .collect();
+        window.sort_unstable_by_key(|&(mmsi, _)| mmsi);
+        window
+    }
+}
+
+// Positions at `from`, `from + step` and so on up to `to`, interpolated
+// between the points of `tracks` (see `History::window`), of the ships then
+// inside [sw_lat, sw_lng, ne_lat, ne_lng]
+pub fn timelapse(tracks: &[(u32, Vec<TrackPoint>)], bbox: [f64; 4], from: u64, to: u64, step: u64) -> Timelapse {
+    let [sw_lat, sw_lng, ne_lat, ne_lng] = bbox;
+    let step = step.max(1);
+    let mut frames: Vec<Frame> = (from..=to).step_by(step as usize).take(MAX_FRAMES as usize).map(|timestamp| Frame { timestamp, ships: Vec::new() }).collect();
+    for (mmsi, points) in tracks {
+        for frame in &mut frames {
+            let Some(position) = position_at(points, frame.timestamp) else {
+                continue;
+            };
+            if (sw_lat..=ne_lat).contains(&position.lat) && (sw_lng..=ne_lng).contains(&position.lng) {
+                frame.ships.push(FramePosition { mmsi: *mmsi, ..position });
+            }
+        }
+    }
+    Timelapse { from, to, step_secs: step, frames }
+}
+
+fn position_at(points: &[TrackPoint], timestamp: u64) -> Option<FramePosition> {
+    let after = points.partition_point(|point| point.timestamp <= timestamp);
+    let before = points[..after].last()?;
+    let position = |point: &TrackPoint| FramePosition { mmsi: 0, lat: point.lat, lng: point.lng, speed: point.speed, cog: point.cog };
+    match points.get(after) {
+        Some(next) if next.timestamp - before.timestamp <= MAX_GAP_SECS => {
+            let t = (timestamp - before.timestamp) as f64 / (next.timestamp - before.timestamp) as f64;
+            // The short way round, across the antimeridian if need be
+            let mut lng_step = next.lng - before.lng;
+            if lng_step.abs() > 180.0 {
+                lng_step -= 360.0 * lng_step.signum();
+            }
+            let lng = before.lng + lng_step * t;
+            Some(FramePosition {
+                lat: before.lat + (next.lat - before.lat) * t,
+                lng: if lng > 180.0 { lng - 360.0 } else if lng < -180.0 { lng + 360.0 } else { lng },
+                speed: before.speed + (next.speed - before.speed) * t,
+                ..position(before)
+            })
+        }
+        _ if timestamp - before.timestamp <= MAX_GAP_SECS => Some(position(before)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let history = History::new();
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.speed) = (51.9, 4.1, 12.0);
+        // Reports every 10 seconds for 3 minutes keep one point a minute
+        for t in (1_700_000_000..=1_700_000_180).step_by(10) {
+            ship.last_update = t;
+            history.observe(&ship);
+        }
+        let track = history.track(244660000, 0, u64::MAX);
+        let times: Vec<u64> = track.iter().map(|point| point.timestamp).collect();
+        assert_eq!(times, vec![1_700_000_000, 1_700_000_060, 1_700_000_120, 1_700_000_180]);
+        assert_eq!(history.track(244660000, 1_700_000_050, 1_700_000_120).len(), 2);
+
+        history.purge(1_700_000_100 + RETENTION_SECS);
+        assert_eq!(history.track(244660000, 0, u64::MAX).len(), 2);
+        history.purge(1_700_000_181 + RETENTION_SECS);
+        assert!(history.mmsis().is_empty());
+    }
+
+    #[test]
+    fn test_timelapse() {
+        let history = History::new();
+        let mut ship = Ship::new(244660000, "ALIDA");
+        for (t, lat, lng) in [(1_700_000_000, 51.9, 4.1), (1_700_000_180, 51.9, 4.1), (1_700_000_240, 52.1, 4.3)] {
+            (ship.last_update, ship.lat, ship.lng) = (t, lat, lng);
+            history.observe(&ship);
+        }
+        // A ship elsewhere isn't copied out for the bbox
+        let mut other = Ship::new(235012345, "BRIGHT");
+        (other.last_update, other.lat, other.lng) = (1_700_000_000, 10.0, 10.0);
+        history.observe(&other);
+        let bbox = [51.0, 4.0, 53.0, 5.0];
+        let tracks = history.window(bbox, 1_700_000_030, 1_700_001_000);
+        assert_eq!(tracks.iter().map(|(mmsi, points)| (*mmsi, points.len())).collect::<Vec<_>>(), vec![(244660000, 3)]);
+        assert!(history.window(bbox, 1_700_001_000, 1_700_002_000).is_empty());
+
+        // One frame every step, held a while after the last point
+        let frames = timelapse(&tracks, bbox, 1_700_000_030, 1_700_001_000, 300).frames;
+        let times: Vec<u64> = frames.iter().map(|frame| frame.timestamp).collect();
+        assert_eq!(times, vec![1_700_000_030, 1_700_000_330, 1_700_000_630, 1_700_000_930]);
+        assert_eq!(frames.iter().map(|frame| frame.ships.len()).collect::<Vec<_>>(), vec![1, 1, 1, 0]);
+
+        // Halfway between two points, and clipped to the bbox
+        let frame = &timelapse(&tracks, bbox, 1_700_000_210, 1_700_000_210, 60).frames[0];
+        assert!((frame.ships[0].lng - 4.2).abs() < 1e-9 && (frame.ships[0].lat - 52.0).abs() < 1e-9);
+        let frame = &timelapse(&tracks, [51.0, 4.0, 52.0, 5.0], 1_700_000_240, 1_700_000_240, 60).frames[0];
+        assert!(frame.ships.is_empty());
+    }
+}
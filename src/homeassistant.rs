@@ -0,0 +1,139 @@
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::index::is_valid_position;
+use crate::ship::Ship;
+
+pub const DEFAULT_PREFIX: &str = "homeassistant";
+
+// Home Assistant MQTT discovery for a few chosen vessels: each becomes a
+// device with a GPS device tracker and speed and heading sensors
+pub struct HomeAssistant {
+    prefix: String,
+    vessels: HashSet<u32>,
+    announced: Mutex<HashMap<u32, Arc<str>>>, // The name each was announced with
+    rediscover: AtomicBool,
+}
+
+impl HomeAssistant {
+    pub fn new(prefix: &str, vessels: HashSet<u32>) -> Self {
+        Self { prefix: prefix.to_string(), vessels, announced: Mutex::new(HashMap::new()), rediscover: AtomicBool::new(false) }
+    }
+
+    // Home Assistant announces itself here when it (re)starts
+    pub fn status_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    // Announce every vessel again on its next update
+    pub fn rediscover(&self) {
+        self.rediscover.store(true, Ordering::Relaxed);
+    }
+
+    fn attributes_topic(&self, mmsi: u32) -> String {
+        format!("{}/device_tracker/seawatch_{}/attributes", self.prefix, mmsi)
+    }
+
+    fn discovery(&self, ship: &Ship) -> Vec<(String, serde_json::Value)> {
+        let mmsi = ship.mmsi;
+        let name = if ship.name.trim().is_empty() { format!("MMSI {}", mmsi) } else { ship.name.trim().to_string() };
+        let device = json!({
+            "identifiers": [format!("seawatch_{}", mmsi)],
+            "name": name,
+            "manufacturer": "seawatch",
+            "model": "AIS vessel",
+        });
+        let attributes = self.attributes_topic(mmsi);
+        let sensor = |key: &str, label: &str, unit: &str| {
+            (
+                format!("{}/sensor/seawatch_{}_{}/config", self.prefix, mmsi, key),
+                json!({
+                    "name": label,
+                    "unique_id": format!("seawatch_{}_{}", mmsi, key),
+                    "state_topic": attributes,
+                    "value_template": format!("{{{{ value_json.{} }}}}", key),
+                    "unit_of_measurement": unit,
+                    "device": device,
+                }),
+            )
+        };
+        vec![
+            (
+                format!("{}/device_tracker/seawatch_{}/config", self.prefix, mmsi),
+                json!({
+                    "name": null,
+                    "unique_id": format!("seawatch_{}_tracker", mmsi),
+                    "json_attributes_topic": attributes,
+                    "source_type": "gps",
+                    "device": device,
+                }),
+            ),
+            sensor("speed", "Speed", "kn"),
+            sensor("heading", "Heading", "°"),
+        ]
+    }
+
+    // What to publish for an update: discovery configs (retained) the first
+    // time a vessel is seen or when its name changes, then its attributes
+    pub fn messages(&self, ship: &Ship) -> Vec<(String, bool, Vec<u8>)> {
+        if !self.vessels.contains(&ship.mmsi) || !is_valid_position(ship.lat, ship.lng) {
+            return Vec::new();
+        }
+        let mut messages = Vec::new();
+        let mut announced = self.announced.lock().unwrap();
+        if self.rediscover.swap(false, Ordering::Relaxed) {
+            announced.clear();
+        }
+        if announced.get(&ship.mmsi).is_none_or(|name| !Arc::ptr_eq(name, &ship.name)) {
+            for (topic, config) in self.discovery(ship) {
+                messages.push((topic, true, config.to_string().into_bytes()));
+            }
+            announced.insert(ship.mmsi, ship.name.clone());
+        }
+        let attributes = json!({
+            "latitude": ship.lat,
+            "longitude": ship.lng,
+            "gps_accuracy": 10,
+            "speed": ship.speed,
+            "heading": if ship.heading < 360 { json!(ship.heading) } else { json!(null) },
+            "course": if ship.cog < 360.0 { json!(ship.cog) } else { json!(null) },
+            "destination": ship.destination.trim(),
+        });
+        messages.push((self.attributes_topic(ship.mmsi), true, attributes.to_string().into_bytes()));
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovery_then_attributes() {
+        let ha = HomeAssistant::new(DEFAULT_PREFIX, HashSet::from([244660000]));
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.heading) = (51.9, 4.1, 511);
+
+        let messages = ha.messages(&ship);
+        let topics: Vec<&str> = messages.iter().map(|(topic, _, _)| topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "homeassistant/device_tracker/seawatch_244660000/config",
+                "homeassistant/sensor/seawatch_244660000_speed/config",
+                "homeassistant/sensor/seawatch_244660000_heading/config",
+                "homeassistant/device_tracker/seawatch_244660000/attributes",
+            ]
+        );
+        let attributes: serde_json::Value = serde_json::from_slice(&messages[3].2).unwrap();
+        assert_eq!((attributes["latitude"].as_f64(), attributes["heading"].is_null()), (Some(51.9), true));
+
+        // Announced once, until Home Assistant restarts
+        assert_eq!(ha.messages(&ship).len(), 1);
+        ha.rediscover();
+        assert_eq!(ha.messages(&ship).len(), 4);
+        assert!(ha.messages(&Ship::new(1, "")).is_empty());
+    }
+}
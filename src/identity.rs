@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::ship::Ship;
+
+// Identities reported within this long of each other are compared; a ship
+// renamed or reflagged months apart is not a conflict
+const WINDOW_SECS: u64 = 24 * 3600;
+// Conflicts stay listed this long after they were last seen
+const RETENTION_SECS: u64 = 7 * 86_400;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    // One MMSI sending more than one IMO number
+    ImoNumber,
+    // One MMSI sending more than one call sign
+    CallSign,
+    // One IMO number sent from more than one MMSI
+    SharedImo,
+}
+
+// Ships whose reported identities disagree, often a sign of one being
+// spoofed; for /api/identity-conflicts
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub mmsis: Vec<u32>,
+    // The IMO numbers or call signs in conflict; for `shared_imo`, the IMO
+    pub values: Vec<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+// IMO numbers and call signs each MMSI has sent recently, and when
+#[derive(Default)]
+struct Reported {
+    imos: Vec<(u32, u64)>,
+    call_signs: Vec<(String, u64)>,
+}
+
+#[derive(Default)]
+struct State {
+    reported: HashMap<u32, Reported>,
+    // MMSIs seen sending each IMO number, and when
+    by_imo: HashMap<u32, Vec<(u32, u64)>>,
+    // By kind and the MMSI, or the IMO number for `shared_imo`
+    conflicts: HashMap<(ConflictKind, u32), Conflict>,
+}
+
+#[derive(Default)]
+pub struct Identities {
+    state: Mutex<State>,
+}
+
+impl Identities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Most recently seen first
+    pub fn all(&self) -> Vec<Conflict> {
+        let mut conflicts: Vec<Conflict> = self.state.lock().unwrap().conflicts.values().cloned().collect();
+        conflicts.sort_by(|a, b| b.last_seen.cmp(&a.last_seen).then(a.mmsis.cmp(&b.mmsis)));
+        conflicts
+    }
+
+    pub fn of(&self, mmsi: u32) -> Vec<Conflict> {
+        self.all().into_iter().filter(|conflict| conflict.mmsis.contains(&mmsi)).collect()
+    }
+
+    // Called when a ship's IMO number or call sign is first seen or changes
+    pub fn observe(&self, events: &EventLog, ship: &Ship) {
+        let now = ship.last_update;
+        let call_sign = ship.call_sign.trim_end_matches('@').trim();
+        let mut state = self.state.lock().unwrap();
+        let mut found = Vec::new();
+
+        let reported = state.reported.entry(ship.mmsi).or_default();
+        if ship.imo_number != 0 {
+            record(&mut reported.imos, ship.imo_number, now);
+            if reported.imos.len() > 1 {
+                let values = reported.imos.iter().map(|(imo, _)| imo.to_string()).collect();
+                found.push((ConflictKind::ImoNumber, ship.mmsi, vec![ship.mmsi], values));
+            }
+        }
+        if !call_sign.is_empty() {
+            record(&mut reported.call_signs, call_sign.to_string(), now);
+            if reported.call_signs.len() > 1 {
+                let values = reported.call_signs.iter().map(|(call_sign, _)| call_sign.clone()).collect();
+                found.push((ConflictKind::CallSign, ship.mmsi, vec![ship.mmsi], values));
+            }
+        }
+        if ship.imo_number != 0 {
+            let mmsis = state.by_imo.entry(ship.imo_number).or_default();
+            record(mmsis, ship.mmsi, now);
+            if mmsis.len() > 1 {
+                let mmsis = mmsis.iter().map(|(mmsi, _)| *mmsi).collect();
+                found.push((ConflictKind::SharedImo, ship.imo_number, mmsis, vec![ship.imo_number.to_string()]));
+            }
+        }
+
+        for (kind, key, mut mmsis, mut values) in found {
+            mmsis.sort_unstable();
+            values.sort();
+            let grew = match state.conflicts.get_mut(&(kind, key)) {
+                Some(conflict) => {
+                    conflict.last_seen = now;
+                    let grew = mmsis.iter().any(|mmsi| !conflict.mmsis.contains(mmsi)) || values.iter().any(|value| !conflict.values.contains(value));
+                    merge(&mut conflict.mmsis, mmsis.clone());
+                    merge(&mut conflict.values, values.clone());
+                    grew
+                }
+                None => {
+                    let conflict = Conflict { kind, mmsis: mmsis.clone(), values: values.clone(), first_seen: now, last_seen: now };
+                    state.conflicts.insert((kind, key), conflict);
+                    true
+                }
+            };
+            // Logged against the ship that just reported, naming the rest
+            if grew {
+                let others = mmsis.into_iter().filter(|&mmsi| mmsi != ship.mmsi).collect();
+                events.push(now, ship.mmsi, EventKind::IdentityConflict { conflict: kind, values, others });
+            }
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        let fresh = |seen: u64| now.saturating_sub(seen) <= WINDOW_SECS;
+        let mut state = self.state.lock().unwrap();
+        state.reported.retain(|_, reported| {
+            reported.imos.retain(|&(_, seen)| fresh(seen));
+            reported.call_signs.retain(|(_, seen)| fresh(*seen));
+            !reported.imos.is_empty() || !reported.call_signs.is_empty()
+        });
+        state.by_imo.retain(|_, mmsis| {
+            mmsis.retain(|&(_, seen)| fresh(seen));
+            !mmsis.is_empty()
+        });
+        state.conflicts.retain(|_, conflict| now.saturating_sub(conflict.last_seen) <= RETENTION_SECS);
+    }
+}
+
+// Notes `value` as seen at `now`, dropping anything outside the window
+fn record<T: PartialEq>(seen: &mut Vec<(T, u64)>, value: T, now: u64) {
+    seen.retain(|(_, at)| now.saturating_sub(*at) <= WINDOW_SECS);
+    match seen.iter_mut().find(|(known, _)| *known == value) {
+        Some((_, at)) => *at = (*at).max(now),
+        None => seen.push((value, now)),
+    }
+}
+
+fn merge<T: Ord>(into: &mut Vec<T>, from: Vec<T>) {
+    into.extend(from);
+    into.sort();
+    into.dedup();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_identity_conflicts() {
+        let (identities, events) = (Identities::new(), EventLog::new());
+        let report = |mmsi: u32, imo: u32, call_sign: &str, t: u64| {
+            let mut ship = Ship::new(mmsi, "");
+            (ship.imo_number, ship.call_sign, ship.last_update) = (imo, intern(call_sign), t);
+            identities.observe(&events, &ship);
+        };
+        report(244660000, 9301562, "PDAB", 1_000);
+        report(244660000, 9301562, "PDAB", 1_400);
+        assert!(identities.all().is_empty());
+
+        // A second transmitter claiming the same MMSI, then the IMO turning
+        // up under another MMSI
+        report(244660000, 9432175, "PDAB@@@", 1_800);
+        report(211000001, 9301562, "DABC", 2_200);
+        let conflicts = identities.all();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!((conflicts[0].kind, &conflicts[0].mmsis), (ConflictKind::SharedImo, &vec![211000001, 244660000]));
+        assert_eq!((conflicts[1].kind, &conflicts[1].values), (ConflictKind::ImoNumber, &vec!["9301562".to_string(), "9432175".to_string()]));
+        assert_eq!(identities.of(211000001).len(), 1);
+        assert_eq!(events.query(&EventFilter::default()).len(), 2);
+
+        // Identities a day apart aren't compared
+        report(244660000, 9301562, "PDAC", 1_800 + 2 * WINDOW_SECS);
+        assert!(identities.of(244660000).iter().all(|conflict| conflict.kind != ConflictKind::CallSign));
+        identities.purge(2_200 + RETENTION_SECS + 1);
+        assert!(identities.all().is_empty());
+    }
+}
@@ -0,0 +1,602 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc::{sync_channel, SyncSender, TrySendError},
+    Arc, Mutex, RwLock,
+};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::ais::{parse_message, AisClass, AisMessage, ParseScratch};
+use crate::firehose::Tap;
+use crate::geo::haversine_m;
+use crate::ingest_stats::IngestStats;
+use crate::intern::intern;
+use crate::monitor::Monitor;
+use crate::predict::course_change;
+use crate::ship::{Ship, ShipCache};
+
+// How long ingested messages are buffered before being applied as one batch
+pub const BATCH_INTERVAL: Duration = Duration::from_millis(250);
+// Messages buffered between batches before the shed policy kicks in
+pub const DEFAULT_QUEUE_CAPACITY: usize = 50_000;
+// How often the message rate is logged
+const RATE_LOG_INTERVAL: Duration = Duration::from_secs(60);
+// How far ahead of our clock another instance's states may be; one further
+// ahead would hold its ship, as every state after it looks older
+const MAX_STATE_SKEW_SECS: u64 = 300;
+
+// What to do with messages arriving while the queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShedPolicy {
+    // Make room by dropping the oldest buffered message
+    #[default]
+    DropOldest,
+    // Keep one in every N arrivals (dropping the oldest for it), shed the rest
+    Sample(u32),
+    // Shed Class B messages first, keeping Class A traffic flowing
+    PrioritizeClassA,
+}
+
+impl FromStr for ShedPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "drop-oldest" => Ok(ShedPolicy::DropOldest),
+            "class-a" => Ok(ShedPolicy::PrioritizeClassA),
+            other => match other.strip_prefix("sample:").map(str::parse::<u32>) {
+                Some(Ok(n)) if n > 0 => Ok(ShedPolicy::Sample(n)),
+                _ => Err(anyhow::anyhow!(
+                    "Unknown shed policy '{}', expected drop-oldest, sample:N or class-a",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+// Class B transponders (small craft) report through their own message types
+pub fn is_class_b(message_type: &str) -> bool {
+    AisClass::of(message_type) == Some(AisClass::B)
+}
+
+struct Queued {
+    seq: u64, // Arrival order, which parsing in parallel doesn't preserve
+    timestamp: u64,
+    message: AisMessage,
+}
+
+#[derive(Default)]
+struct Buffered {
+    class_a: VecDeque<Queued>,
+    class_b: VecDeque<Queued>,
+    sampled: u32, // Arrivals since the queue filled up, for `Sample`
+}
+
+impl Buffered {
+    fn len(&self) -> usize {
+        self.class_a.len() + self.class_b.len()
+    }
+
+    fn pop_oldest(&mut self) -> Option<Queued> {
+        let a = self.class_a.front().map(|queued| queued.seq);
+        let b = self.class_b.front().map(|queued| queued.seq);
+        match (a, b) {
+            (Some(a), Some(b)) if b < a => self.class_b.pop_front(),
+            (Some(_), _) => self.class_a.pop_front(),
+            (None, _) => self.class_b.pop_front(),
+        }
+    }
+}
+
+// Thresholds under which a position report is taken to say nothing new,
+// as moored and anchored ships' mostly do, and skipped before it takes the
+// cache lock or reaches the monitor, sinks and clients
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Unchanged {
+    pub distance_m: f64,
+    pub speed_kn: f64,
+    pub course_deg: f64, // Course over ground and heading
+    // One report is applied at least this often anyway, so the ship isn't
+    // taken for stale or gone dark
+    pub max_secs: u64,
+}
+
+pub const DEFAULT_UNCHANGED_SPEED_KN: f64 = 0.2;
+pub const DEFAULT_UNCHANGED_COURSE_DEG: f64 = 5.0;
+pub const DEFAULT_UNCHANGED_MAX_SECS: u64 = 300;
+
+impl Unchanged {
+    // Only position reports are ever skipped; static data always goes through
+    pub fn matches(&self, ship: &Ship, timestamp: u64, message: &AisMessage) -> bool {
+        let metadata = &message.metadata;
+        if ship.last_update == 0
+            || timestamp.saturating_sub(ship.last_update) >= self.max_secs
+            || metadata.ship_name != ship.name
+            || haversine_m(ship.lat, ship.lng, metadata.latitude, metadata.longitude) >= self.distance_m
+        {
+            return false;
+        }
+        match message.message_type.as_str() {
+            "PositionReport" => message.message.position_report.as_ref().is_some_and(|report| {
+                report.navigational_status == ship.nav_status
+                    && (report.sog - ship.speed).abs() < self.speed_kn
+                    && course_change(ship.cog, report.cog).abs() < self.course_deg
+                    && course_change(ship.heading as f64, report.true_heading as f64).abs() < self.course_deg
+            }),
+            // Position is all that's kept from these
+            "StandardClassBPositionReport" | "ExtendedClassBPositionReport" => true,
+            _ => false,
+        }
+    }
+}
+
+// Bounded buffer between the upstream socket and the batch writer. The reader
+// never waits on it: when it is full, the shed policy decides what goes.
+pub struct IngestQueue {
+    buffered: Mutex<Buffered>,
+    capacity: usize,
+    policy: RwLock<ShedPolicy>,
+    shed: AtomicU64,
+    unchanged: RwLock<Option<Unchanged>>,
+    suppressed: AtomicU64,
+}
+
+impl IngestQueue {
+    pub fn new(capacity: usize, policy: ShedPolicy) -> Self {
+        Self {
+            buffered: Mutex::new(Buffered::default()),
+            capacity,
+            policy: RwLock::new(policy),
+            shed: AtomicU64::new(0),
+            unchanged: RwLock::new(None),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    // Skip reports that match the stored state within these thresholds
+    pub fn with_unchanged(self, unchanged: Unchanged) -> Self {
+        self.reconfigure(self.policy(), Some(unchanged));
+        self
+    }
+
+    pub fn policy(&self) -> ShedPolicy {
+        *self.policy.read().unwrap()
+    }
+
+    // For a reloaded configuration; the capacity stays as it was allocated
+    pub fn reconfigure(&self, policy: ShedPolicy, unchanged: Option<Unchanged>) {
+        *self.policy.write().unwrap() = policy;
+        *self.unchanged.write().unwrap() = unchanged;
+    }
+
+    // Unchanged reports skipped since startup
+    pub fn suppressed_count(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+
+    // Drops the reports in a drained batch that add nothing to what the
+    // cache has. Each is compared with the stored state rather than the last
+    // skipped report, so a slow drift is applied once it adds up
+    pub fn drop_unchanged(&self, ships: &ShipCache, mut batch: Vec<(u64, AisMessage)>) -> Vec<(u64, AisMessage)> {
+        let Some(unchanged) = *self.unchanged.read().unwrap() else {
+            return batch;
+        };
+        let before = batch.len();
+        batch.retain(|(timestamp, message)| {
+            !ships.ships.get(&message.metadata.mmsi).is_some_and(|ship| unchanged.matches(&ship, *timestamp, message))
+        });
+        self.suppressed.fetch_add((before - batch.len()) as u64, Ordering::Relaxed);
+        batch
+    }
+
+    // Messages shed since startup
+    pub fn shed_count(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    pub fn record_shed(&self) {
+        self.shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Buffer a message; `seq` is its position in the upstream stream
+    pub fn push(&self, seq: u64, timestamp: u64, message: AisMessage) {
+        let class_b = is_class_b(&message.message_type);
+        let mut buffered = self.buffered.lock().unwrap();
+
+        if buffered.len() >= self.capacity {
+            let admitted = match self.policy() {
+                ShedPolicy::DropOldest => buffered.pop_oldest().is_some(),
+                ShedPolicy::Sample(n) => {
+                    buffered.sampled = buffered.sampled.wrapping_add(1);
+                    buffered.sampled.is_multiple_of(n) && buffered.pop_oldest().is_some()
+                }
+                ShedPolicy::PrioritizeClassA if class_b => false,
+                ShedPolicy::PrioritizeClassA => {
+                    buffered.class_b.pop_front().is_some() || buffered.class_a.pop_front().is_some()
+                }
+            };
+            // Either the arrival or the message it displaced is lost
+            self.record_shed();
+            if !admitted {
+                return;
+            }
+        } else {
+            buffered.sampled = 0;
+        }
+
+        let queued = Queued { seq, timestamp, message };
+        if class_b {
+            buffered.class_b.push_back(queued);
+        } else {
+            buffered.class_a.push_back(queued);
+        }
+    }
+
+    // Everything buffered so far as (timestamp, message), in stream order
+    pub fn drain(&self) -> Vec<(u64, AisMessage)> {
+        let (class_a, class_b) = {
+            let mut buffered = self.buffered.lock().unwrap();
+            (
+                std::mem::take(&mut buffered.class_a),
+                std::mem::take(&mut buffered.class_b),
+            )
+        };
+        let mut messages: Vec<Queued> = class_a.into_iter().chain(class_b).collect();
+        messages.sort_unstable_by_key(|queued| queued.seq);
+        messages
+            .into_iter()
+            .map(|queued| (queued.timestamp, queued.message))
+            .collect()
+    }
+}
+
+// Raw frames waiting for each parse worker before the reader starts shedding
+const PARSE_BACKLOG: usize = 4_096;
+
+// Parses raw upstream frames on a few dedicated threads and feeds the results
+// into the ingestion queue, keeping JSON decoding off the socket read loop
+pub struct ParsePool {
+    workers: Vec<SyncSender<(u64, u64, Vec<u8>)>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    queue: Arc<IngestQueue>,
+    stats: Arc<IngestStats>,
+    next_seq: AtomicU64,
+    tap: Option<Tap>,
+}
+
+impl ParsePool {
+    // Counted in `stats` under `source`, parse failures included
+    pub fn new(workers: usize, queue: Arc<IngestQueue>, stats: Arc<IngestStats>, source: &'static str) -> Result<Self> {
+        let (workers, threads) = (0..workers.max(1))
+            .map(|worker| {
+                let (tx, rx) = sync_channel::<(u64, u64, Vec<u8>)>(PARSE_BACKLOG);
+                let (queue, stats) = (queue.clone(), stats.clone());
+                let thread = thread::Builder::new()
+                    .name(format!("ais-parse-{}", worker))
+                    .spawn(move || {
+                        let mut scratch = ParseScratch::default();
+                        for (seq, timestamp, mut frame) in rx {
+                            match parse_message(&mut frame, &mut scratch) {
+                                Some(message) => {
+                                    stats.record_message(source, &message.message_type);
+                                    queue.push(seq, timestamp, message);
+                                }
+                                None => stats.record_failure(),
+                            }
+                        }
+                    })?;
+                Ok((tx, thread))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(Self {
+            workers,
+            threads,
+            queue,
+            stats,
+            next_seq: AtomicU64::new(0),
+            tap: None,
+        })
+    }
+
+    // Copy every frame, as received, to the raw firehose
+    pub fn tap(&mut self, tap: Tap) {
+        self.tap = Some(tap);
+    }
+
+    // Where the frames' source also logs its connection's ups and downs
+    pub fn stats(&self) -> &IngestStats {
+        &self.stats
+    }
+
+    // Stop accepting frames and wait for the workers to parse what they already have
+    pub fn finish(self) {
+        drop(self.workers);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+
+    // Hand a frame to its ship's worker, shedding it if that worker is backed
+    // up. A ship's frames all go to the same one, so they are queued, and
+    // applied, in the order they came, however the batches fall.
+    pub fn submit(&self, timestamp: u64, frame: Vec<u8>) {
+        if let Some(tap) = &self.tap {
+            tap.send(&frame);
+        }
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let index = mmsi_of(&frame).map_or(seq as usize, |mmsi| mmsi as usize);
+        let worker = &self.workers[index % self.workers.len()];
+        if let Err(TrySendError::Full(_)) = worker.try_send((seq, timestamp, frame)) {
+            self.queue.record_shed();
+        }
+    }
+}
+
+// The MMSI in a frame's metadata, found without parsing it
+fn mmsi_of(frame: &[u8]) -> Option<u32> {
+    const KEY: &[u8] = b"\"MMSI\":";
+    let at = frame.windows(KEY.len()).position(|window| window == KEY)? + KEY.len();
+    let mut digits = frame[at..].iter().skip_while(|byte| byte.is_ascii_whitespace()).take_while(|byte| byte.is_ascii_digit());
+    digits.try_fold(0u32, |mmsi, digit| mmsi.checked_mul(10)?.checked_add((digit - b'0') as u32))
+}
+
+// Apply buffered messages in one batch per interval, so the index lock is
+// taken a few times a second rather than once per message
+pub async fn batch_writer_task(ships: Arc<ShipCache>, queue: Arc<IngestQueue>, monitor: Arc<Monitor>) {
+    let mut flush = interval(BATCH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut reported_shed = 0;
+    let (mut applied, mut since) = (0, Instant::now());
+
+    loop {
+        flush.tick().await;
+
+        let batch = queue.drop_unchanged(&ships, queue.drain());
+        applied += batch.len();
+        apply_batch(&ships, batch, &monitor);
+
+        // As fields too, for dashboards built on JSON logs
+        if since.elapsed() >= RATE_LOG_INTERVAL {
+            let rate = applied as f64 / since.elapsed().as_secs_f64();
+            info!(messages = applied, rate, ships = ships.len(), shed = queue.shed_count(), "Ingested {} messages ({:.1}/s)", applied, rate);
+            (applied, since) = (0, Instant::now());
+        }
+
+        let shed = queue.shed_count();
+        if shed > reported_shed {
+            warn!("Ingestion queue full, shed {} messages ({} total)", shed - reported_shed, shed);
+            reported_shed = shed;
+        }
+    }
+}
+
+// Apply a batch to the cache, then show each update to the monitor
+pub fn apply_batch(ships: &ShipCache, batch: Vec<(u64, AisMessage)>, monitor: &Monitor) {
+    if batch.is_empty() {
+        return;
+    }
+
+    // (before, after) for every update; the ship cache applies them one at a time
+    let updates = RefCell::new(Vec::with_capacity(batch.len()));
+    ships.update_ships(batch.into_iter().map(|(timestamp, message)| {
+        let mmsi = message.metadata.mmsi;
+        let updates = &updates;
+        (mmsi, move |ship: &mut Ship| {
+            // A ship that has never been updated was only just created
+            let before = (ship.last_update != 0).then(|| ship.clone());
+            let destination = ship.destination.clone();
+            ship.apply_message(message, timestamp);
+            if !Arc::ptr_eq(&destination, &ship.destination) {
+                ship.destination_locode = monitor.locodes.resolve(&ship.destination);
+            }
+            updates.borrow_mut().push((before, ship.clone()));
+        })
+    }));
+
+    for (before, ship) in updates.into_inner() {
+        monitor.observe(before.as_ref(), &ship);
+    }
+}
+
+// Apply whole ship states, as received from another instance, skipping any
+// no newer than what we already have, or from too far in the future
+pub fn apply_states(ships: &ShipCache, mut states: Vec<Ship>, monitor: &Monitor) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    states.retain(|state| state.last_update <= now.saturating_add(MAX_STATE_SKEW_SECS));
+    if states.is_empty() {
+        return;
+    }
+
+    let updates = RefCell::new(Vec::with_capacity(states.len()));
+    ships.update_ships(states.into_iter().map(|mut state| {
+        let updates = &updates;
+        (state.mmsi, move |ship: &mut Ship| {
+            if state.last_update <= ship.last_update {
+                return;
+            }
+            let before = (ship.last_update != 0).then(|| ship.clone());
+            state.name = intern(&state.name);
+            state.destination = intern(&state.destination);
+            state.call_sign = intern(&state.call_sign);
+            state.destination_locode = monitor.locodes.resolve(&state.destination);
+            *ship = state;
+            updates.borrow_mut().push((before, ship.clone()));
+        })
+    }));
+
+    for (before, ship) in updates.into_inner() {
+        monitor.observe(before.as_ref(), &ship);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ais::{MessageData, Metadata, PositionReport};
+    use crate::intern::intern;
+
+    fn message(mmsi: u32, message_type: &str) -> AisMessage {
+        AisMessage {
+            message_type: message_type.to_string(),
+            metadata: Metadata {
+                mmsi,
+                ship_name: intern(""),
+                latitude: 51.0,
+                longitude: 1.5,
+                time_utc: String::new(),
+            },
+            message: MessageData {
+                position_report: None,
+                ship_static_data: None,
+            },
+        }
+    }
+
+    fn mmsis(queue: &IngestQueue) -> Vec<u32> {
+        queue.drain().iter().map(|(_, message)| message.metadata.mmsi).collect()
+    }
+
+    #[test]
+    fn test_shed_policies() {
+        let queue = IngestQueue::new(3, ShedPolicy::DropOldest);
+        for mmsi in 1..=5 {
+            queue.push(mmsi as u64, 0, message(mmsi, "PositionReport"));
+        }
+        assert_eq!(mmsis(&queue), vec![3, 4, 5]);
+        assert_eq!(queue.shed_count(), 2);
+
+        let queue = IngestQueue::new(2, ShedPolicy::Sample(3));
+        for mmsi in 1..=8 {
+            queue.push(mmsi as u64, 0, message(mmsi, "PositionReport"));
+        }
+        // 3 and 6 of the 6 overflowing arrivals are kept
+        assert_eq!(mmsis(&queue), vec![5, 8]);
+        assert_eq!(queue.shed_count(), 6);
+
+        let queue = IngestQueue::new(2, ShedPolicy::PrioritizeClassA);
+        queue.push(1, 0, message(1, "StandardClassBPositionReport"));
+        queue.push(2, 0, message(2, "PositionReport"));
+        queue.push(3, 0, message(3, "ExtendedClassBPositionReport"));
+        queue.push(4, 0, message(4, "PositionReport"));
+        assert_eq!(mmsis(&queue), vec![2, 4]);
+        assert_eq!(queue.shed_count(), 2);
+    }
+
+    #[test]
+    fn test_parse_pool_keeps_stream_order() {
+        let queue = Arc::new(IngestQueue::new(1_000, ShedPolicy::DropOldest));
+        let stats = Arc::new(IngestStats::new());
+        let pool = ParsePool::new(3, queue.clone(), stats.clone(), "upstream").unwrap();
+        for mmsi in 1..=100 {
+            let frame = serde_json::to_vec(&message(mmsi, "PositionReport")).unwrap();
+            pool.submit(0, frame);
+        }
+        pool.submit(0, b"not json".to_vec());
+        pool.finish();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let mut parsed = Vec::new();
+        while parsed.len() < 100 && std::time::Instant::now() < deadline {
+            // Each drained batch is in stream order, whichever worker parsed what
+            let batch = mmsis(&queue);
+            assert!(batch.windows(2).all(|pair| pair[0] < pair[1]));
+            parsed.extend(batch);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        parsed.sort_unstable();
+        assert_eq!(parsed, (1..=100).collect::<Vec<_>>());
+        let minute = &stats.report().windows[0];
+        assert_eq!((minute.messages, minute.parse_failures, minute.by_source["upstream"]), (100, 1, 100));
+    }
+
+    #[test]
+    fn test_parse_pool_keeps_ship_order() {
+        let queue = Arc::new(IngestQueue::new(10_000, ShedPolicy::DropOldest));
+        let pool = ParsePool::new(4, queue.clone(), Arc::new(IngestStats::new()), "upstream").unwrap();
+        let drained = thread::scope(|scope| {
+            // Batches drained while frames are still being parsed
+            let drainer = scope.spawn(|| {
+                let mut drained = Vec::new();
+                while drained.len() < 600 {
+                    drained.extend(queue.drain().into_iter().map(|(_, message)| (message.metadata.mmsi, message.metadata.latitude)));
+                    thread::yield_now();
+                }
+                drained
+            });
+            for i in 0..200 {
+                for mmsi in [244660001, 244660002, 244660003] {
+                    let mut frame = message(mmsi, "PositionReport");
+                    frame.metadata.latitude = i as f64;
+                    pool.submit(0, serde_json::to_vec(&frame).unwrap());
+                }
+            }
+            drainer.join().unwrap()
+        });
+        pool.finish();
+        for mmsi in [244660001, 244660002, 244660003] {
+            let lats: Vec<f64> = drained.iter().filter(|(of, _)| *of == mmsi).map(|(_, lat)| *lat).collect();
+            assert_eq!(lats, (0..200).map(f64::from).collect::<Vec<_>>());
+        }
+        assert_eq!(mmsi_of(br#"{"MetaData": {"MMSI_String": "x", "MMSI": 244660000}}"#), Some(244660000));
+        assert_eq!(mmsi_of(b"not json"), None);
+    }
+
+    #[test]
+    fn test_apply_states_skips_older() {
+        let (ships, monitor) = (ShipCache::new(), Monitor::new());
+        let state = |lat, last_update| {
+            let mut ship = Ship::new(1, "ALIDA");
+            (ship.lat, ship.lng, ship.last_update, ship.destination) = (lat, 4.0, last_update, Arc::from("RTM"));
+            ship
+        };
+        apply_states(&ships, vec![state(51.0, 100), state(52.0, 200), state(50.0, 150)], &monitor);
+        let ship = ships.ships.get(&1).unwrap().clone();
+        assert_eq!((ship.lat, ship.last_update), (52.0, 200));
+        assert_eq!(ship.destination_locode.as_deref(), Some("NLRTM"));
+        assert!(Arc::ptr_eq(&ship.name, &intern("ALIDA")));
+
+        // A state from the far future is dropped, rather than pinning the ship
+        apply_states(&ships, vec![state(10.0, u64::MAX)], &monitor);
+        apply_states(&ships, vec![state(53.0, 300)], &monitor);
+        assert_eq!(ships.ships.get(&1).unwrap().lat, 53.0);
+    }
+
+    #[test]
+    fn test_drop_unchanged() {
+        let unchanged = Unchanged { distance_m: 10.0, speed_kn: 0.2, course_deg: 5.0, max_secs: 300 };
+        let queue = IngestQueue::new(100, ShedPolicy::default()).with_unchanged(unchanged);
+        let (ships, monitor) = (ShipCache::new(), Monitor::new());
+        let report = |latitude, sog, timestamp| {
+            let mut message = message(1, "PositionReport");
+            message.metadata.latitude = latitude;
+            message.message.position_report =
+                Some(PositionReport { cog: 90.0, navigational_status: 5, sog, true_heading: 90, position_accuracy: false });
+            (timestamp, message)
+        };
+        apply_batch(&ships, queue.drop_unchanged(&ships, vec![report(51.0, 0.0, 1000)]), &monitor);
+
+        // A few metres off, barely moving, or just stale enough to go through
+        let batch = vec![report(51.00002, 0.1, 1010), report(51.001, 0.1, 1020), report(51.0, 0.5, 1030), report(51.0, 0.0, 1300)];
+        let kept: Vec<u64> = queue.drop_unchanged(&ships, batch).into_iter().map(|(timestamp, _)| timestamp).collect();
+        assert_eq!(kept, vec![1020, 1030, 1300]);
+        assert_eq!(queue.suppressed_count(), 1);
+        // Static data always goes through
+        assert_eq!(queue.drop_unchanged(&ships, vec![(1010, message(1, "ShipStaticData"))]).len(), 1);
+    }
+
+    #[test]
+    fn test_parse_shed_policy() {
+        assert_eq!("sample:10".parse::<ShedPolicy>().unwrap(), ShedPolicy::Sample(10));
+        assert_eq!("Class-A".parse::<ShedPolicy>().unwrap(), ShedPolicy::PrioritizeClassA);
+        assert!("sample:0".parse::<ShedPolicy>().is_err());
+    }
+}
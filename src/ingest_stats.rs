@@ -0,0 +1,223 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Counts are kept in buckets this many seconds wide, for an hour
+const BUCKET_SECS: u64 = 10;
+const BUCKETS: usize = 360;
+// The windows reported, in seconds
+const WINDOWS: [u64; 4] = [60, 5 * 60, 15 * 60, 60 * 60];
+// Connects and disconnects kept for the history
+const MAX_CONNECTION_EVENTS: usize = 50;
+
+#[derive(Default)]
+struct Bucket {
+    start: u64, // Unix time, a multiple of BUCKET_SECS
+    by_type: HashMap<Arc<str>, u64>,
+    by_source: HashMap<Arc<str>, u64>,
+    parse_failures: u64,
+    disconnects: u64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionChange {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct ConnectionEvent {
+    pub timestamp: u64,
+    pub source: String, // "upstream", or "follow" for follower mode
+    pub change: ConnectionChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct WindowStats {
+    pub window_secs: u64, // Shorter than asked for while the server is younger
+    pub messages: u64,
+    pub messages_per_sec: f64,
+    pub by_type: BTreeMap<String, f64>, // Messages a second
+    pub parse_failures: u64,
+    pub parse_failure_rate: f64, // Of the frames received
+    // Messages from upstream or the simulator, ship states from sources and peers
+    pub by_source: BTreeMap<String, u64>,
+    pub disconnects: u64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct IngestReport {
+    pub windows: Vec<WindowStats>,
+    pub connections: Vec<ConnectionEvent>, // Oldest first
+}
+
+// What has come in, for /api/stats/ingest: rates by message type and
+// source, parse failures and the upstream connection's ups and downs
+pub struct IngestStats {
+    started: u64,
+    buckets: Mutex<VecDeque<Bucket>>,
+    connections: Mutex<VecDeque<ConnectionEvent>>,
+}
+
+impl Default for IngestStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IngestStats {
+    pub fn new() -> Self {
+        Self::started_at(now())
+    }
+
+    fn started_at(now: u64) -> Self {
+        Self { started: now, buckets: Mutex::new(VecDeque::new()), connections: Mutex::new(VecDeque::new()) }
+    }
+
+    pub fn record_message(&self, source: &str, message_type: &str) {
+        self.record(now(), |bucket| {
+            count(&mut bucket.by_type, message_type, 1);
+            count(&mut bucket.by_source, source, 1);
+        });
+    }
+
+    pub fn record_failure(&self) {
+        self.record(now(), |bucket| bucket.parse_failures += 1);
+    }
+
+    pub fn record_states(&self, source: &str, states: usize) {
+        if states > 0 {
+            self.record(now(), |bucket| count(&mut bucket.by_source, source, states as u64));
+        }
+    }
+
+    pub fn connected(&self, source: &str) {
+        self.connection(now(), source, ConnectionChange::Connected, None);
+    }
+
+    pub fn disconnected(&self, source: &str, error: String) {
+        let now = now();
+        self.record(now, |bucket| bucket.disconnects += 1);
+        self.connection(now, source, ConnectionChange::Disconnected, Some(error));
+    }
+
+    fn connection(&self, now: u64, source: &str, change: ConnectionChange, error: Option<String>) {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() == MAX_CONNECTION_EVENTS {
+            connections.pop_front();
+        }
+        connections.push_back(ConnectionEvent { timestamp: now, source: source.to_string(), change, error });
+    }
+
+    fn record(&self, now: u64, update: impl FnOnce(&mut Bucket)) {
+        let start = now - now % BUCKET_SECS;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.back().is_none_or(|bucket| bucket.start < start) {
+            if buckets.len() == BUCKETS {
+                buckets.pop_front();
+            }
+            buckets.push_back(Bucket { start, ..Default::default() });
+        }
+        // A clock step backwards lands in the newest bucket
+        update(buckets.back_mut().unwrap());
+    }
+
+    pub fn report(&self) -> IngestReport {
+        self.report_at(now())
+    }
+
+    fn report_at(&self, now: u64) -> IngestReport {
+        let buckets = self.buckets.lock().unwrap();
+        let windows = WINDOWS
+            .iter()
+            .map(|&window| {
+                let since = now.saturating_sub(window);
+                let secs = now.saturating_sub(self.started.max(since)).max(1);
+                let mut by_type: HashMap<Arc<str>, u64> = HashMap::new();
+                let mut by_source: HashMap<Arc<str>, u64> = HashMap::new();
+                let (mut parse_failures, mut disconnects) = (0, 0);
+                // Buckets straddling the start of the window count whole
+                for bucket in buckets.iter().filter(|bucket| bucket.start + BUCKET_SECS > since) {
+                    for (message_type, n) in &bucket.by_type {
+                        *by_type.entry(message_type.clone()).or_default() += n;
+                    }
+                    for (source, n) in &bucket.by_source {
+                        *by_source.entry(source.clone()).or_default() += n;
+                    }
+                    parse_failures += bucket.parse_failures;
+                    disconnects += bucket.disconnects;
+                }
+                let messages: u64 = by_type.values().sum();
+                let frames = messages + parse_failures;
+                WindowStats {
+                    window_secs: secs.min(window),
+                    messages,
+                    messages_per_sec: messages as f64 / secs as f64,
+                    by_type: by_type.into_iter().map(|(message_type, n)| (message_type.to_string(), n as f64 / secs as f64)).collect(),
+                    parse_failures,
+                    parse_failure_rate: if frames == 0 { 0.0 } else { parse_failures as f64 / frames as f64 },
+                    by_source: by_source.into_iter().map(|(source, n)| (source.to_string(), n)).collect(),
+                    disconnects,
+                }
+            })
+            .collect();
+        IngestReport { windows, connections: self.connections.lock().unwrap().iter().cloned().collect() }
+    }
+}
+
+fn count(counts: &mut HashMap<Arc<str>, u64>, key: &str, n: u64) {
+    match counts.get_mut(key) {
+        Some(count) => *count += n,
+        None => {
+            counts.insert(Arc::from(key), n);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let start = 1_750_000_000;
+        let stats = IngestStats::started_at(start - 3600);
+        // Ten minutes ago, then the last half minute
+        for _ in 0..60 {
+            stats.record(start - 600, |bucket| count(&mut bucket.by_type, "ShipStaticData", 1));
+        }
+        for second in 0..30 {
+            stats.record(start - second, |bucket| {
+                count(&mut bucket.by_type, "PositionReport", 2);
+                count(&mut bucket.by_source, "upstream", 2);
+            });
+        }
+        stats.record(start, |bucket| bucket.parse_failures += 3);
+        stats.connection(start, "upstream", ConnectionChange::Connected, None);
+
+        let report = stats.report_at(start);
+        let minute = &report.windows[0];
+        assert_eq!((minute.window_secs, minute.messages, minute.parse_failures), (60, 60, 3));
+        assert_eq!(minute.by_type["PositionReport"], 1.0);
+        assert!(!minute.by_type.contains_key("ShipStaticData"));
+        assert_eq!(minute.by_source["upstream"], 60);
+        assert_eq!(minute.parse_failure_rate, 3.0 / 63.0);
+        let quarter = &report.windows[2];
+        assert_eq!((quarter.messages, quarter.by_type["ShipStaticData"]), (120, 60.0 / 900.0));
+        assert_eq!(report.connections[0].change, ConnectionChange::Connected);
+
+        // A server up for 20 seconds divides by 20
+        let young = IngestStats::started_at(start - 20);
+        young.record(start, |bucket| count(&mut bucket.by_type, "PositionReport", 40));
+        assert_eq!(young.report_at(start).windows[3].messages_per_sec, 2.0);
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Result;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::{Client, ClientBuilder};
+use rskafka::record::Record;
+use std::collections::BTreeMap;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+use tracing::{info, warn};
+
+use crate::sinks::{Feed, Update};
+
+// Records are sent in batches of up to this many, or whatever arrived in
+// BATCH_WINDOW, whichever comes first
+const MAX_BATCH: usize = 500;
+const BATCH_WINDOW: Duration = Duration::from_millis(200);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+pub struct KafkaConfig {
+    pub brokers: Vec<String>, // host:port
+    pub topic: String,         // Ship updates
+    pub event_topic: Option<String>,
+}
+
+// Kafka's own murmur2, so records land on the partition any other Kafka
+// client would pick for the same key
+fn murmur2(data: &[u8]) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h: u32 = 0x9747_b28c ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+fn partition_for(key: &[u8], partitions: usize) -> usize {
+    (murmur2(key) & 0x7fff_ffff) as usize % partitions
+}
+
+struct Topic {
+    partitions: Vec<PartitionClient>,
+    pending: Vec<Vec<Record>>, // Per partition
+}
+
+impl Topic {
+    async fn open(client: &Client, name: &str) -> Result<Self> {
+        let count = client
+            .list_topics()
+            .await?
+            .into_iter()
+            .find(|topic| topic.name == name)
+            .map(|topic| topic.partitions.len())
+            .ok_or_else(|| anyhow::anyhow!("Kafka topic {} does not exist", name))?;
+        let mut partitions = Vec::with_capacity(count);
+        for partition in 0..count {
+            partitions.push(client.partition_client(name, partition as i32, UnknownTopicHandling::Retry).await?);
+        }
+        Ok(Self { pending: vec![Vec::new(); count], partitions })
+    }
+
+    fn add(&mut self, update: &Update) {
+        let value = match update.to_json() {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Could not serialize update for Kafka: {}", e);
+                return;
+            }
+        };
+        let key = update.mmsi().to_string().into_bytes();
+        let partition = partition_for(&key, self.partitions.len());
+        self.pending[partition].push(Record {
+            key: Some(key),
+            value: Some(value),
+            headers: BTreeMap::from([("type".to_string(), update.kind().as_bytes().to_vec())]),
+            timestamp: chrono::DateTime::from_timestamp(update.timestamp() as i64, 0).unwrap_or_default(),
+        });
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        for (client, records) in self.partitions.iter().zip(self.pending.iter_mut()) {
+            if !records.is_empty() {
+                client.produce(std::mem::take(records), Compression::NoCompression).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn connect(config: &KafkaConfig) -> Result<(Topic, Option<Topic>)> {
+    let client = ClientBuilder::new(config.brokers.clone()).client_id("seawatch").build().await?;
+    let ships = Topic::open(&client, &config.topic).await?;
+    let events = match &config.event_topic {
+        Some(name) => Some(Topic::open(&client, name).await?),
+        None => None,
+    };
+    info!("Writing to Kafka topic {} ({} partitions)", config.topic, ships.partitions.len());
+    Ok((ships, events))
+}
+
+// Records are JSON, keyed by MMSI so each ship's updates stay in order on one
+// partition, with a `type` header ("ship" or the event type). Updates that
+// arrive while the brokers are unreachable are dropped.
+pub async fn kafka_task(mut feed: Feed, config: KafkaConfig) {
+    loop {
+        let (mut ships, mut events) = match connect(&config).await {
+            Ok(topics) => topics,
+            Err(e) => {
+                warn!("Could not connect to Kafka: {}", e);
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        loop {
+            let Some(update) = feed.next().await else {
+                return;
+            };
+            let deadline = Instant::now() + BATCH_WINDOW;
+            let mut next = Some(update);
+            let mut batched = 0;
+            while let Some(update) = next.take() {
+                match (&update, events.as_mut()) {
+                    (Update::Ship(_), _) => ships.add(&update),
+                    (Update::Event(_), Some(events)) => events.add(&update),
+                    (Update::Event(_), None) => {}
+                }
+                batched += 1;
+                if batched < MAX_BATCH {
+                    next = timeout_at(deadline, feed.next()).await.ok().flatten();
+                }
+            }
+
+            let mut flushed = ships.flush().await;
+            if let (Ok(()), Some(events)) = (&flushed, events.as_mut()) {
+                flushed = events.flush().await;
+            }
+            if let Err(e) = flushed {
+                warn!("Kafka write failed, reconnecting: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partitioning_matches_kafka() {
+        // From Kafka's own UtilsTest
+        assert_eq!(murmur2(b"21") as i32, -973932308);
+        assert_eq!(murmur2(b"foobar") as i32, -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string") as i32, -985981536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string") as i32, -1486304829);
+        assert!(partition_for(b"244660000", 12) < 12);
+    }
+}
@@ -0,0 +1,109 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+
+use crate::history::TrackPoint;
+use crate::predict::course_change;
+
+// Summarised over the last hour, with average speeds every 5 minutes for sparklines
+pub const WINDOW_SECS: u64 = 3600;
+const SPARK_STEP_SECS: u64 = 300;
+// AIS reports 102.3 kn for "not available"
+const SPEED_UNAVAILABLE: f64 = 102.2;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+// How a ship has been moving lately, from its history; see `crate::history`
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Kinematics {
+    pub since: u64,
+    pub samples: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_kn: Option<Range>,
+    // Degrees. `min` and `max` are the ends of the narrowest arc holding
+    // every course, so clockwise from `min` to `max` even across north;
+    // `avg` is the circular mean
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub course: Option<Range>,
+    // Average speed in each 5 minutes, oldest first; null where there were no reports
+    pub speeds_kn: Vec<Option<f64>>,
+}
+
+impl Kinematics {
+    // None without any points in the hour before `now`
+    pub fn of(points: &[TrackPoint], now: u64) -> Option<Self> {
+        let since = now.saturating_sub(WINDOW_SECS);
+        let points: Vec<&TrackPoint> = points.iter().filter(|point| point.timestamp >= since && point.timestamp <= now).collect();
+        if points.is_empty() {
+            return None;
+        }
+        let speeds: Vec<(u64, f64)> =
+            points.iter().filter(|point| point.speed < SPEED_UNAVAILABLE).map(|point| (point.timestamp, point.speed)).collect();
+        let courses: Vec<f64> = points.iter().filter(|point| point.cog < 360.0).map(|point| point.cog).collect();
+
+        let speed_kn = (!speeds.is_empty()).then(|| {
+            let (min, max) = speeds.iter().fold((f64::MAX, f64::MIN), |(min, max), &(_, speed)| (min.min(speed), max.max(speed)));
+            Range { min, max, avg: round(speeds.iter().map(|(_, speed)| speed).sum::<f64>() / speeds.len() as f64) }
+        });
+        let course = circular_range(&courses);
+        let sparks = (WINDOW_SECS / SPARK_STEP_SECS) as usize;
+        let mut buckets = vec![(0.0, 0usize); sparks];
+        for &(timestamp, speed) in &speeds {
+            let bucket = (((timestamp - since) / SPARK_STEP_SECS) as usize).min(sparks - 1);
+            buckets[bucket] = (buckets[bucket].0 + speed, buckets[bucket].1 + 1);
+        }
+        let speeds_kn = buckets.into_iter().map(|(sum, n)| (n > 0).then(|| round(sum / n as f64))).collect();
+        Some(Self { since, samples: points.len(), speed_kn, course, speeds_kn })
+    }
+}
+
+fn circular_range(courses: &[f64]) -> Option<Range> {
+    if courses.is_empty() {
+        return None;
+    }
+    let (sin, cos) = courses.iter().fold((0.0, 0.0), |(sin, cos), course| (sin + course.to_radians().sin(), cos + course.to_radians().cos()));
+    let avg = sin.atan2(cos).to_degrees().rem_euclid(360.0);
+    let (below, above) =
+        courses.iter().map(|&course| course_change(avg, course)).fold((0.0f64, 0.0f64), |(below, above), d| (below.min(d), above.max(d)));
+    let normalise = |course: f64| round(course.rem_euclid(360.0));
+    Some(Range { min: normalise(avg + below), max: normalise(avg + above), avg: normalise(avg) })
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinematics() {
+        let now = 1_700_003_600;
+        // Slowing from 14 to 5 knots over the hour while swinging across north
+        let points: Vec<TrackPoint> = (0..60)
+            .map(|i| TrackPoint {
+                timestamp: now - 3540 + i * 60,
+                lat: 51.9,
+                lng: 4.1,
+                speed: 14.0 - 9.0 * i as f64 / 59.0,
+                cog: (350.0 + i as f64 / 3.0) % 360.0,
+            })
+            .collect();
+        let kinematics = Kinematics::of(&points, now).unwrap();
+        assert_eq!(kinematics.samples, 60);
+        let speed = kinematics.speed_kn.unwrap();
+        assert_eq!((speed.min, speed.max, speed.avg), (5.0, 14.0, 9.5));
+        let course = kinematics.course.unwrap();
+        assert_eq!((course.min, course.max), (350.0, 9.7));
+        assert!(course.avg < 1.0 || course.avg > 359.0);
+        assert_eq!(kinematics.speeds_kn.len(), 12);
+        assert!(kinematics.speeds_kn[0].unwrap() > kinematics.speeds_kn[11].unwrap());
+
+        assert!(Kinematics::of(&points, now + 2 * WINDOW_SECS).is_none());
+    }
+}
@@ -0,0 +1,86 @@
+// Everything but the command line, so seawatch's API can be mounted inside
+// another axum application; see `Seamon::builder`
+pub mod config;
+pub use seamon_core::{ais, geo, index, intern, memory, ship, tiles};
+pub mod access;
+pub mod apikeys;
+pub mod assets;
+pub mod check;
+pub mod diagnose;
+pub mod live;
+pub mod wire;
+pub mod ingest;
+pub mod ingest_stats;
+pub mod upstream;
+pub mod units;
+pub mod metrics;
+pub mod shutdown;
+pub mod simulate;
+pub mod photos;
+pub mod listen;
+pub mod logging;
+#[cfg(unix)]
+pub mod reload;
+#[cfg(unix)]
+pub mod systemd;
+pub mod events;
+pub mod geofence;
+pub mod history;
+pub mod kinematics;
+pub mod alerts;
+pub mod monitor;
+pub mod webhooks;
+pub mod ratelimit;
+pub mod email;
+pub mod chat;
+pub mod anchor;
+pub mod anomalies;
+pub mod area_stats;
+pub mod changes;
+pub mod dark;
+pub mod distance;
+pub mod emissions;
+pub mod eta;
+pub mod export;
+pub mod identity;
+pub mod collision;
+pub mod ports;
+pub mod port_calls;
+pub mod predict;
+pub mod quality;
+pub mod rendezvous;
+pub mod reports;
+pub mod voyages;
+pub mod loitering;
+pub mod locode;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "mqtt")]
+pub mod homeassistant;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod nmea;
+pub mod forward;
+pub mod peer;
+pub mod timeseries;
+pub mod elastic;
+pub mod firehose;
+pub mod plugin;
+pub mod receiver;
+pub mod replay;
+pub mod schema;
+pub mod searches;
+pub mod filter;
+pub mod signalk;
+pub mod tileproxy;
+#[cfg(feature = "ts")]
+pub mod typescript;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[allow(dead_code)] // Shared by the sinks; any one build may not use all of it
+pub mod sinks;
+pub mod server;
+
+pub use server::{Seamon, SeamonBuilder};
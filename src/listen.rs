@@ -0,0 +1,148 @@
+use anyhow::Result;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{lookup_host, TcpListener};
+#[cfg(unix)]
+use {
+    axum::Router,
+    hyper_util::rt::{TokioExecutor, TokioIo},
+    hyper_util::server::conn::auto,
+    hyper_util::service::TowerToHyperService,
+    std::path::{Path, PathBuf},
+    tokio::net::UnixListener,
+    tokio::sync::watch,
+    tracing::{debug, warn},
+};
+
+#[cfg(unix)]
+use crate::shutdown;
+
+// Connections waiting to be accepted before the kernel refuses more
+const BACKLOG: i32 = 1024;
+
+// The address to listen on: an IP (IPv6 with or without brackets) or a
+// host name, which is resolved and the first address used
+pub async fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+    let literal = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    lookup_host((host, port)).await?.next().ok_or_else(|| anyhow::anyhow!("{} did not resolve to any address", host))
+}
+
+// `::` listens on IPv4 as well, whatever the system's default for IPv6
+// sockets; any other IPv6 address is IPv6 only
+pub fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let IpAddr::V6(ip) = addr.ip() {
+        socket.set_only_v6(!ip.is_unspecified())?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+// A socket file left behind by a run that didn't shut down cleanly is
+// replaced; anything else at the path is an error
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow::anyhow!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+// axum::serve only takes a TcpListener, so connections on a Unix socket are
+// served with hyper directly: HTTP/1 (with upgrades, for WebSockets) or
+// HTTP/2, each finishing its in-flight request once shutdown is requested.
+// The socket file, if we created it, is removed on the way out.
+#[cfg(unix)]
+pub async fn serve_unix(listener: UnixListener, path: Option<PathBuf>, app: Router, shutdown_rx: watch::Receiver<bool>) -> std::io::Result<()> {
+    let (close_tx, close_rx) = watch::channel(());
+    loop {
+        let socket = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    warn!("Unix socket listener failed to accept: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = shutdown::requested(shutdown_rx.clone()) => break,
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let (shutdown_rx, close_rx) = (shutdown_rx.clone(), close_rx.clone());
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(socket), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown::requested(shutdown_rx) => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Unix socket connection failed: {}", e);
+            }
+            drop(close_rx);
+        });
+    }
+    drop(listener);
+    if let Some(path) = path {
+        let _ = std::fs::remove_file(path);
+    }
+    drop(close_rx);
+    close_tx.closed().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_and_bind() {
+        assert_eq!(resolve("[::]", 8080).await.unwrap(), "[::]:8080".parse().unwrap());
+        assert_eq!(resolve("::1", 80).await.unwrap(), "[::1]:80".parse().unwrap());
+        assert_eq!(resolve("0.0.0.0", 80).await.unwrap(), "0.0.0.0:80".parse().unwrap());
+
+        let listener = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (accepted, connected) = tokio::join!(listener.accept(), tokio::net::TcpStream::connect(addr));
+        assert!(accepted.is_ok() && connected.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_unix() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let path = std::env::temp_dir().join(format!("seawatch-test-{}.sock", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        assert!(bind_unix(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let listener = bind_unix(&path).unwrap();
+        let app = Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let server = tokio::spawn(serve_unix(listener, Some(path.clone()), app, shutdown_rx));
+
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("ok"), "{}", response);
+
+        shutdown_tx.send(true).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+}
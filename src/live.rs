@@ -0,0 +1,386 @@
+use axum::extract::ws::{Message, WebSocket};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{interval, Duration};
+use tracing::debug;
+
+use crate::filter::{Filter, Scored};
+use crate::index::is_valid_position;
+use crate::monitor::Monitor;
+use crate::ship::{Ship, ShipCache, ShipState};
+use crate::shutdown;
+use crate::wire::Encoder;
+
+// How often diffs are computed and pushed to live clients
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+// Ticks a slow client may fall behind before it is resynced with a snapshot
+pub const TICK_BUFFER: usize = 16;
+
+// The world is split into REGION_SIZE degree cells; clients subscribe to the
+// cells their viewport touches and filter the exact bounds themselves.
+const REGION_SIZE: f64 = 5.0;
+const REGION_COLUMNS: u16 = (360.0 / REGION_SIZE) as u16;
+const REGION_ROWS: u16 = (180.0 / REGION_SIZE) as u16;
+
+pub type RegionId = u16;
+
+pub fn region_of(lat: f64, lng: f64) -> RegionId {
+    let row = (((lat + 90.0) / REGION_SIZE).floor() as i64).clamp(0, REGION_ROWS as i64 - 1) as u16;
+    let col =
+        (((lng + 180.0) / REGION_SIZE).floor() as i64).clamp(0, REGION_COLUMNS as i64 - 1) as u16;
+    row * REGION_COLUMNS + col
+}
+
+pub fn regions_in_bbox(sw_lat: f64, sw_lng: f64, ne_lat: f64, ne_lng: f64) -> HashSet<RegionId> {
+    let sw = region_of(sw_lat, sw_lng);
+    let ne = region_of(ne_lat, ne_lng);
+    let (min_row, min_col) = (sw / REGION_COLUMNS, sw % REGION_COLUMNS);
+    let (max_row, max_col) = (ne / REGION_COLUMNS, ne % REGION_COLUMNS);
+
+    let mut regions = HashSet::new();
+    for row in min_row..=max_row {
+        for col in min_col..=max_col {
+            regions.insert(row * REGION_COLUMNS + col);
+        }
+    }
+    regions
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Diff {
+        tick: u64,
+        region: RegionId,
+        updated: &'a [ShipState],
+        removed: &'a [u32],
+    },
+    Snapshot {
+        tick: u64,
+        ships: &'a [ShipState],
+    },
+    // A subscription that was turned down; the previous one stays
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    // [sw_lat, sw_lng, ne_lat, ne_lng], and optionally only the ships
+    // matching a filter expression; see `crate::filter`
+    Subscribe {
+        bbox: [f64; 4],
+        #[serde(default)]
+        filter: Option<String>,
+    },
+}
+
+// How a live client is sent ships: JSON diffs per region, or the binary
+// deltas of `crate::wire`
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Json,
+    Binary,
+}
+
+#[derive(Default)]
+pub struct RegionDiff {
+    pub updated: Vec<ShipState>,
+    pub removed: Vec<u32>,
+}
+
+// One tick's worth of changes, serialized once and shared by every client,
+// and as they were for clients that filter them
+pub struct LiveTick {
+    pub tick: u64,
+    pub regions: HashMap<RegionId, String>,
+    pub diffs: HashMap<RegionId, RegionDiff>,
+}
+
+// Turns the ships changed since the last tick into per-region diffs
+#[derive(Default)]
+pub struct Publisher {
+    tick: u64,
+    // Region each ship was last published in, to tell clients when it leaves
+    published: HashMap<u32, RegionId>,
+}
+
+impl Publisher {
+    pub fn next_tick(&mut self, ships: &ShipCache) -> LiveTick {
+        self.tick += 1;
+
+        let mut diffs: HashMap<RegionId, RegionDiff> = HashMap::new();
+        for mmsi in ships.drain_changed() {
+            let current = ships
+                .ships
+                .get(&mmsi)
+                .filter(|ship| is_valid_position(ship.lat, ship.lng))
+                .map(|ship| ship.to_state());
+
+            let previous = match current {
+                Some(state) => {
+                    let region = region_of(state.lat, state.lng);
+                    diffs.entry(region).or_default().updated.push(state);
+                    self.published
+                        .insert(mmsi, region)
+                        .filter(|&previous| previous != region)
+                }
+                None => self.published.remove(&mmsi),
+            };
+            if let Some(previous) = previous {
+                diffs.entry(previous).or_default().removed.push(mmsi);
+            }
+        }
+
+        let regions = diffs
+            .iter()
+            .map(|(&region, diff)| (region, diff_message(self.tick, region, &diff.updated, &diff.removed)))
+            .collect();
+
+        LiveTick {
+            tick: self.tick,
+            regions,
+            diffs,
+        }
+    }
+}
+
+fn diff_message(tick: u64, region: RegionId, updated: &[ShipState], removed: &[u32]) -> String {
+    serde_json::to_string(&ServerMessage::Diff { tick, region, updated, removed }).unwrap_or_default()
+}
+
+// A region's diff with only the ships matching `filter`; ships that stopped
+// matching are sent as removed
+fn filtered_diff(tick: &LiveTick, region: RegionId, ships: &ShipCache, filter: &Filter, context: &Scored) -> Option<String> {
+    let diff = tick.diffs.get(&region)?;
+    let mut removed = diff.removed.clone();
+    let mut updated = Vec::new();
+    for state in &diff.updated {
+        match ships.ships.get(&state.mmsi) {
+            Some(ship) if filter.matches(&ship, context) => updated.push(state.clone()),
+            _ => removed.push(state.mmsi),
+        }
+    }
+    Some(diff_message(tick.tick, region, &updated, &removed))
+}
+
+// The tick's changes across the client's regions as one binary delta
+fn binary_delta(encoder: &mut Encoder, tick: &LiveTick, regions: &HashSet<RegionId>, ships: &ShipCache, filter: Option<&Filter>, context: &Scored) -> Option<Vec<u8>> {
+    let (mut updated, mut removed) = (Vec::new(), Vec::new());
+    for diff in regions.iter().filter_map(|region| tick.diffs.get(region)) {
+        removed.extend_from_slice(&diff.removed);
+        for state in &diff.updated {
+            match filter {
+                Some(filter) if !ships.ships.get(&state.mmsi).is_some_and(|ship| filter.matches(&ship, context)) => removed.push(state.mmsi),
+                _ => updated.push(state),
+            }
+        }
+    }
+    encoder.delta(tick.tick, updated, removed)
+}
+
+// Computes per-region diffs once per tick and fans them out to all live clients
+pub async fn publisher_task(ships: Arc<ShipCache>, tx: broadcast::Sender<Arc<LiveTick>>) {
+    let mut interval = interval(TICK_INTERVAL);
+    let mut publisher = Publisher::default();
+
+    loop {
+        interval.tick().await;
+
+        let tick = publisher.next_tick(&ships);
+        if tick.regions.is_empty() {
+            continue;
+        }
+        // Nobody listening is fine; the diff is simply dropped
+        let _ = tx.send(Arc::new(tick));
+    }
+}
+
+// A connected live client: a snapshot on (re)subscribe, then the diffs for the
+// regions its viewport touches
+pub async fn client_session(
+    mut socket: WebSocket,
+    ships: Arc<ShipCache>,
+    monitor: Arc<Monitor>,
+    format: Format,
+    mut ticks: broadcast::Receiver<Arc<LiveTick>>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut encoder = (format == Format::Binary).then(Encoder::new);
+    let mut bbox: Option<[f64; 4]> = None;
+    let mut filter: Option<Filter> = None;
+    let mut regions = HashSet::new();
+    let mut last_tick = 0;
+
+    loop {
+        tokio::select! {
+            _ = shutdown::requested(shutdown.clone()) => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe { bbox: new_bbox, filter: new_filter }) => {
+                        let new_filter = match new_filter.as_deref().map(Filter::parse).transpose() {
+                            Ok(new_filter) => new_filter,
+                            Err(e) => {
+                                let message = ServerMessage::Error { message: e.to_string() };
+                                if socket.send(Message::Text(serde_json::to_string(&message).unwrap_or_default())).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+                        let [sw_lat, sw_lng, ne_lat, ne_lng] = new_bbox;
+                        regions = regions_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng);
+                        (bbox, filter) = (Some(new_bbox), new_filter);
+                        if send_snapshot(&mut socket, &ships, &monitor, new_bbox, filter.as_ref(), encoder.as_mut(), last_tick).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => debug!("Ignoring live client message: {}", e),
+                }
+            }
+            tick = ticks.recv() => match tick {
+                Ok(tick) => {
+                    last_tick = tick.tick;
+                    let context = Scored { monitor: &monitor, now: now() };
+                    if let Some(encoder) = &mut encoder {
+                        if let Some(delta) = binary_delta(encoder, &tick, &regions, &ships, filter.as_ref(), &context)
+                            && socket.send(Message::Binary(delta)).await.is_err()
+                        {
+                            return;
+                        }
+                        continue;
+                    }
+                    for region in &regions {
+                        let diff = match &filter {
+                            Some(filter) => filtered_diff(&tick, *region, &ships, filter, &context),
+                            None => tick.regions.get(region).cloned(),
+                        };
+                        if let Some(diff) = diff
+                            && socket.send(Message::Text(diff)).await.is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Live client lagged {} ticks, resyncing", skipped);
+                    if let Some(bbox) = bbox
+                        && send_snapshot(&mut socket, &ships, &monitor, bbox, filter.as_ref(), encoder.as_mut(), last_tick).await.is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+async fn send_snapshot(
+    socket: &mut WebSocket,
+    ships: &ShipCache,
+    monitor: &Monitor,
+    bbox: [f64; 4],
+    filter: Option<&Filter>,
+    encoder: Option<&mut Encoder>,
+    tick: u64,
+) -> Result<(), axum::Error> {
+    let [sw_lat, sw_lng, ne_lat, ne_lng] = bbox;
+    if filter.is_none() && encoder.is_none() {
+        // Built around the pre-serialized states rather than through serde
+        let ships = ships.get_ships_in_bbox_json(sw_lat, sw_lng, ne_lat, ne_lng);
+        let text = format!(
+            r#"{{"type":"snapshot","tick":{},"ships":{}}}"#,
+            tick,
+            String::from_utf8_lossy(&ships)
+        );
+        return socket.send(Message::Text(text)).await;
+    }
+    let context = Scored { monitor, now: now() };
+    let states: Vec<ShipState> = ships
+        .get_full_ships_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng)
+        .iter()
+        .filter(|ship| filter.is_none_or(|filter| filter.matches(ship, &context)))
+        .map(Ship::to_state)
+        .collect();
+    match encoder {
+        Some(encoder) => socket.send(Message::Binary(encoder.snapshot(tick, &states))).await,
+        None => socket.send(Message::Text(serde_json::to_string(&ServerMessage::Snapshot { tick, ships: &states }).unwrap_or_default())).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions_cover_bbox() {
+        let regions = regions_in_bbox(49.0, -6.0, 52.0, 2.0);
+        assert_eq!(regions.len(), 6);
+        assert!(regions.contains(&region_of(51.0, 1.5)));
+        assert!(!regions.contains(&region_of(40.0, 1.5)));
+        assert_eq!(region_of(90.0, 180.0), REGION_ROWS * REGION_COLUMNS - 1);
+    }
+
+    #[test]
+    fn test_publisher_reports_region_changes() {
+        let ships = ShipCache::new();
+        let mut publisher = Publisher::default();
+        let dover = region_of(51.0, 1.5);
+        let biscay = region_of(45.5, -4.0);
+
+        ships.update_ship(1, |ship| {
+            ship.lat = 51.0;
+            ship.lng = 1.5;
+        });
+        let tick = publisher.next_tick(&ships);
+        assert_eq!(tick.regions.keys().collect::<Vec<_>>(), vec![&dover]);
+        assert!(tick.regions[&dover].contains(r#""updated":[{"mmsi":1"#));
+
+        // Nothing changed, nothing to send
+        assert!(publisher.next_tick(&ships).regions.is_empty());
+
+        // Moving regions removes the ship from the old one
+        ships.update_ship(1, |ship| {
+            ship.lat = 45.5;
+            ship.lng = -4.0;
+        });
+        let tick = publisher.next_tick(&ships);
+        assert!(tick.regions[&dover].contains(r#""removed":[1]"#));
+        assert!(tick.regions[&biscay].contains(r#""updated":[{"mmsi":1"#));
+
+        // A client filtering for fast ships is told the ship left when it slows
+        let monitor = Monitor::new();
+        let context = Scored { monitor: &monitor, now: 0 };
+        let filter = Filter::parse("speed>10").unwrap();
+        ships.update_ship(1, |ship| ship.speed = 12.0);
+        let tick = publisher.next_tick(&ships);
+        assert!(filtered_diff(&tick, biscay, &ships, &filter, &context).unwrap().contains(r#""updated":[{"mmsi":1"#));
+        ships.update_ship(1, |ship| ship.speed = 4.0);
+        let tick = publisher.next_tick(&ships);
+        assert!(filtered_diff(&tick, biscay, &ships, &filter, &context).unwrap().contains(r#""updated":[],"removed":[1]"#));
+
+        ships.remove_ship(1);
+        let tick = publisher.next_tick(&ships);
+        assert!(tick.regions[&biscay].contains(r#""removed":[1]"#));
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::intern::intern;
+use crate::ports::Ports;
+
+// Resolutions remembered per distinct raw destination; destinations repeat
+// heavily, so this rarely fills
+const MAX_CACHED: usize = 100_000;
+// Destinations that name no port
+const NO_PORT: &[&str] = &["FOR ORDERS", "FOR ORDER", "ORDERS", "UNKNOWN", "NONE", "FISHING", "AT SEA", "SEA TRIAL", "SEA TRIALS"];
+// Words wrapped around a port name that don't change which port it is
+const NOISE: &[&str] = &["PORT OF", "PORT", "PILOT STATION", "PILOT", "ANCHORAGE", "ANCH", "OFF", "ROADS", "OPL"];
+
+struct Entry {
+    locode: Arc<str>,
+    name: String, // Normalized, see `normalize`
+}
+
+// UN/LOCODEs and port names to resolve free-text AIS destinations against
+pub struct Locodes {
+    entries: Vec<Entry>,
+    by_code: HashMap<String, usize>,
+    by_location: HashMap<String, Vec<usize>>, // The 3-letter part, e.g. "RTM"
+    by_name: HashMap<String, Vec<usize>>,
+    resolved: Mutex<HashMap<Arc<str>, Option<Arc<str>>>>,
+}
+
+// Upper case letters and digits, words separated by single spaces
+fn normalize(s: &str) -> String {
+    s.to_uppercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut diagonal = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let substitution = diagonal + usize::from(a[i - 1] != b[j - 1]);
+            diagonal = row[j];
+            row[j] = substitution.min(row[j] + 1).min(row[j - 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Split one line of the UNECE code list CSV, which quotes every field
+fn csv_fields(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (Vec::new(), String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+impl Locodes {
+    pub fn new(codes: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut table = Self {
+            entries: Vec::new(),
+            by_code: HashMap::new(),
+            by_location: HashMap::new(),
+            by_name: HashMap::new(),
+            resolved: Mutex::new(HashMap::new()),
+        };
+        for (locode, name) in codes {
+            let locode = locode.to_uppercase().replace(' ', "");
+            if locode.len() != 5 || table.by_code.contains_key(&locode) {
+                continue;
+            }
+            let i = table.entries.len();
+            table.by_code.insert(locode.clone(), i);
+            table.by_location.entry(locode[2..].to_string()).or_default().push(i);
+            let name = normalize(&name);
+            table.by_name.entry(name.clone()).or_default().push(i);
+            table.entries.push(Entry { locode: intern(&locode), name });
+        }
+        table
+    }
+
+    pub fn from_ports(ports: &Ports) -> Self {
+        Self::new(ports.all().iter().map(|port| (port.locode.to_string(), port.name.to_string())))
+    }
+
+    // The UNECE UN/LOCODE code list in CSV form (Country, Location, Name,
+    // NameWoDiacritics, ..., Function), keeping only locations that are
+    // ports, after the ports in `ports`
+    pub fn from_file(path: &str, ports: &Ports) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let mut codes: Vec<(String, String)> =
+            ports.all().iter().map(|port| (port.locode.to_string(), port.name.to_string())).collect();
+        for line in text.lines() {
+            let fields = csv_fields(line);
+            if fields.len() < 8 || fields[2].is_empty() || !fields[7].starts_with('1') {
+                continue;
+            }
+            codes.push((format!("{}{}", fields[1], fields[2]), fields[4].clone()));
+        }
+        if codes.len() == ports.all().len() {
+            return Err(anyhow::anyhow!("No port locations in {}", path));
+        }
+        Ok(Self::new(codes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The UN/LOCODE a destination refers to, e.g. "NLRTM" for "RTM",
+    // "NL RTM", "ROTTERDAM" or "HAMBURG>ROTTERDAM"
+    pub fn resolve(&self, destination: &Arc<str>) -> Option<Arc<str>> {
+        if let Some(resolved) = self.resolved.lock().unwrap().get(destination) {
+            return resolved.clone();
+        }
+        let resolved = self.lookup(destination);
+        let mut cache = self.resolved.lock().unwrap();
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert(destination.clone(), resolved.clone());
+        resolved
+    }
+
+    fn unique(&self, candidates: Option<&Vec<usize>>) -> Option<Arc<str>> {
+        match candidates.map(Vec::as_slice) {
+            Some([i]) => Some(self.entries[*i].locode.clone()),
+            _ => None,
+        }
+    }
+
+    fn lookup(&self, destination: &str) -> Option<Arc<str>> {
+        // Routes are written FROM>TO; the last leg is where it is going
+        let destination = destination.rsplit('>').next().unwrap_or(destination);
+        let mut name = normalize(destination);
+        if name.is_empty() || NO_PORT.contains(&name.as_str()) {
+            return None;
+        }
+
+        let compact = name.replace(' ', "");
+        if compact.len() == 5
+            && let Some(&i) = self.by_code.get(&compact)
+        {
+            return Some(self.entries[i].locode.clone());
+        }
+        if compact.len() == 3
+            && let Some(locode) = self.unique(self.by_location.get(&compact))
+        {
+            return Some(locode);
+        }
+        if let Some(locode) = self.unique(self.by_name.get(&name)) {
+            return Some(locode);
+        }
+
+        for noise in NOISE {
+            let stripped =
+                name.strip_prefix(&format!("{} ", noise)).or_else(|| name.strip_suffix(&format!(" {}", noise)));
+            if let Some(stripped) = stripped {
+                name = stripped.to_string();
+            }
+        }
+        if let Some(locode) = self.unique(self.by_name.get(&name)) {
+            return Some(locode);
+        }
+
+        // Misspellings: the closest name, if it is close enough and the only one that close
+        let allowed = name.len() / 5;
+        if allowed == 0 {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        let mut tied = false;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.name.len().abs_diff(name.len()) > allowed {
+                continue;
+            }
+            let distance = levenshtein(&name, &entry.name);
+            match best {
+                Some((_, d)) if distance > d => {}
+                Some((_, d)) if distance == d => tied = true,
+                _ => {
+                    best = Some((i, distance));
+                    tied = false;
+                }
+            }
+        }
+        match best {
+            Some((i, distance)) if distance <= allowed && !tied => Some(self.entries[i].locode.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(table: &Locodes, destination: &str) -> Option<String> {
+        table.resolve(&intern(destination)).map(|locode| locode.to_string())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let table = Locodes::from_ports(&Ports::builtin());
+        assert_eq!(resolve(&table, "NLRTM").as_deref(), Some("NLRTM"));
+        assert_eq!(resolve(&table, "NL RTM").as_deref(), Some("NLRTM"));
+        assert_eq!(resolve(&table, "RTM").as_deref(), Some("NLRTM"));
+        assert_eq!(resolve(&table, "rotterdam").as_deref(), Some("NLRTM"));
+        assert_eq!(resolve(&table, "ROTTERDAM>HAMBURG").as_deref(), Some("DEHAM"));
+        assert_eq!(resolve(&table, "ROTERDAM").as_deref(), Some("NLRTM"));
+        assert_eq!(resolve(&table, "PORT OF FELIXSTOWE").as_deref(), Some("GBFXT"));
+        assert_eq!(resolve(&table, "FOR ORDERS"), None);
+        assert_eq!(resolve(&table, ""), None);
+        assert_eq!(resolve(&table, "XYZZY"), None);
+    }
+
+    #[test]
+    fn test_unece_csv() {
+        assert_eq!(
+            csv_fields(r#","NL","RTM","Rotterdam","Rotterdam","ZH","AI","12345---","0401",,"5155N 00430E","""#),
+            vec!["", "NL", "RTM", "Rotterdam", "Rotterdam", "ZH", "AI", "12345---", "0401", "", "5155N 00430E", ""]
+        );
+        assert_eq!(levenshtein("KITTEN", "SITTING"), 3);
+    }
+}
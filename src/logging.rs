@@ -0,0 +1,90 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    // For people: one coloured line per event
+    Text,
+    // For Loki, Elasticsearch and the like: one JSON object per line, with
+    // the event's fields (rates, URLs) alongside `message`
+    Json,
+}
+
+// The filter directives in effect, as /api/admin/log has them
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct LogLevel {
+    // e.g. "seamon_core::ais=trace,seawatch=debug,info"
+    pub filter: String,
+}
+
+// A handle on the running filter, for changing it without a restart
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    initial: String, // From RUST_LOG at startup, restored on reset
+}
+
+impl LogFilter {
+    fn new(filter: EnvFilter) -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let initial = filter.to_string();
+        let (layer, handle) = reload::Layer::new(filter);
+        (layer, Self { handle, initial })
+    }
+
+    pub fn current(&self) -> LogLevel {
+        let filter = self.handle.with_current(|filter| filter.to_string()).unwrap_or_default();
+        LogLevel { filter }
+    }
+
+    // Replaces the whole filter; RUST_LOG syntax
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| anyhow::anyhow!("Invalid filter '{}': {}", directives, e))?;
+        self.handle.reload(filter)?;
+        Ok(())
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        self.set(&self.initial)
+    }
+}
+
+pub fn init(format: LogFormat) -> LogFilter {
+    let crate_name = env!("CARGO_PKG_NAME").replace('-', "_");
+
+    let env_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info")) // Default to 'info' if RUST_LOG is unset/invalid
+        .add_directive(format!("{}={}", crate_name, "debug").parse().unwrap());
+
+    let (env_filter, handle) = LogFilter::new(env_filter);
+    let registry = tracing_subscriber::registry().with(env_filter);
+    match format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        LogFormat::Json => registry.with(fmt::layer().json().flatten_event(true).with_current_span(false).with_span_list(false)).init(),
+    }
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_filter() {
+        let (layer, filter) = LogFilter::new(EnvFilter::new("info"));
+        // Installed for this thread only, so other tests' logging is untouched
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        filter.set("warn,seamon_core::ais=trace").unwrap();
+        // Most specific first, as EnvFilter orders them
+        assert_eq!(filter.current().filter, "seamon_core::ais=trace,warn");
+        assert!(tracing::enabled!(target: "seamon_core::ais", tracing::Level::TRACE));
+        assert!(!tracing::enabled!(target: "seawatch::server", tracing::Level::INFO));
+
+        assert!(filter.set("seawatch=loud").is_err());
+        filter.reset().unwrap();
+        assert_eq!(filter.current().filter, "info");
+    }
+}
@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::geo::haversine_m;
+use crate::ports::Ports;
+use crate::ship::Ship;
+
+pub const DEFAULT_LOITER_SECS: u64 = 60 * 60;
+// A ship is holding position, or circling, while it stays within this
+// distance of where it was when it started
+const LOITER_RADIUS_M: f64 = 1852.0;
+// Ships silent for this long are forgotten
+const FORGET_SECS: u64 = 86_400;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum LoiterStatus {
+    Started,
+    Ended,
+}
+
+struct Hold {
+    lat: f64, // Where the ship was when it started holding
+    lng: f64,
+    since: u64,
+    last_seen: u64,
+    loitering: bool,
+}
+
+// Ships staying in one patch of open water without being anchored or moored
+pub struct Loitering {
+    min_secs: u64,
+    holds: Mutex<HashMap<u32, Hold>>,
+}
+
+impl Loitering {
+    pub fn new(min_secs: u64) -> Self {
+        Self { min_secs, holds: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn observe(&self, events: &EventLog, ports: &Ports, ship: &Ship) {
+        let mut holds = self.holds.lock().unwrap();
+        let now = ship.last_update;
+        let excused = ship.nav_status == 1 || ship.nav_status == 5 || ports.containing(ship.lat, ship.lng).is_some();
+        let moved_off = holds
+            .get(&ship.mmsi)
+            .is_none_or(|hold| excused || haversine_m(hold.lat, hold.lng, ship.lat, ship.lng) > LOITER_RADIUS_M);
+
+        if moved_off {
+            if let Some(hold) = holds.remove(&ship.mmsi)
+                && hold.loitering
+            {
+                events.push(
+                    now,
+                    ship.mmsi,
+                    EventKind::Loitering {
+                        status: LoiterStatus::Ended,
+                        lat: hold.lat,
+                        lng: hold.lng,
+                        duration_secs: hold.last_seen.saturating_sub(hold.since),
+                    },
+                );
+            }
+            if !excused {
+                holds.insert(ship.mmsi, Hold { lat: ship.lat, lng: ship.lng, since: now, last_seen: now, loitering: false });
+            }
+            return;
+        }
+
+        let hold = holds.get_mut(&ship.mmsi).unwrap();
+        hold.last_seen = hold.last_seen.max(now);
+        if !hold.loitering && hold.last_seen - hold.since >= self.min_secs {
+            hold.loitering = true;
+            events.push(
+                now,
+                ship.mmsi,
+                EventKind::Loitering {
+                    status: LoiterStatus::Started,
+                    lat: hold.lat,
+                    lng: hold.lng,
+                    duration_secs: hold.last_seen - hold.since,
+                },
+            );
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.holds.lock().unwrap().retain(|_, hold| now.saturating_sub(hold.last_seen) < FORGET_SECS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_circling_then_leaving() {
+        let (loitering, events, ports) = (Loitering::new(3600), EventLog::new(), Ports::builtin());
+        let mut ship = Ship::new(1, "");
+        ship.speed = 4.0;
+        // Circling about 600 m around a point for 90 minutes
+        for t in (0..=5400u64).step_by(300) {
+            let (sin, cos) = (t as f64 / 600.0).sin_cos();
+            (ship.lat, ship.lng, ship.last_update) = (40.0 + 0.005 * sin, -30.0 + 0.0065 * cos, t);
+            loitering.observe(&events, &ports, &ship);
+        }
+        // Then off on passage
+        (ship.lat, ship.last_update) = (40.5, 5700);
+        loitering.observe(&events, &ports, &ship);
+
+        // Anchored ships are excused
+        let mut anchored = Ship::new(2, "");
+        anchored.nav_status = 1;
+        for t in (0..=7200u64).step_by(600) {
+            anchored.last_update = t;
+            loitering.observe(&events, &ports, &anchored);
+        }
+
+        let logged: Vec<(u32, LoiterStatus, u64)> = events
+            .query(&EventFilter { kind: Some("loitering".into()), ..Default::default() })
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::Loitering { status, duration_secs, .. } => (event.mmsi, status, duration_secs),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(logged, vec![(1, LoiterStatus::Started, 3600), (1, LoiterStatus::Ended, 5400)]);
+    }
+}
@@ -1,206 +1,500 @@
 use anyhow::Result;
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, Json},
-    routing::get,
-    Router,
-};
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    env,
-    sync::{Arc, RwLock},
-    time::{SystemTime, UNIX_EPOCH},
-};
-use tokio::time::{interval, Duration};
-use tower_http::{cors::CorsLayer, services::ServeDir};
+use clap::Parser;
+use axum::Router;
+use std::{env, sync::Arc};
+use tokio::sync::watch;
+use tokio::time::Duration;
 use tracing::{error, info, warn, debug};
 use url::Url;
 
-mod ship;
-mod ais;
-
-use ais::{AisStream, AisMessage};
-use ship::{Ship, ShipCache, ShipState};
-
-type SharedShipCache = Arc<RwLock<ShipCache>>;
-
-#[derive(Clone)]
-struct AppState {
-    ships: SharedShipCache,
-}
+#[cfg(unix)]
+use seawatch::{reload, systemd};
+#[cfg(feature = "tls")]
+use seawatch::tls;
+#[cfg(feature = "ts")]
+use seawatch::typescript;
+#[cfg(feature = "mqtt")]
+use seawatch::{homeassistant, mqtt};
+#[cfg(feature = "kafka")]
+use seawatch::kafka;
+#[cfg(feature = "nats")]
+use seawatch::nats;
+use seawatch::{
+    alerts, assets, chat, check, config, diagnose, elastic, email, firehose, forward, listen, locode, logging, nmea, peer,
+    plugin, reports, shutdown, sinks, timeseries, webhooks, Seamon,
+};
+use seawatch::apikeys::ApiKeys;
+use seawatch::chat::{ChatConfig, ChatNotifier};
+use seawatch::email::Mailer;
+use seawatch::firehose::Firehose;
+use seawatch::forward::Forwarder;
+use seawatch::photos::Photos;
+use seawatch::monitor::Monitor;
+use seawatch::ports::Ports;
+use seawatch::searches::Searches;
+use seawatch::simulate::Simulation;
+use seawatch::tileproxy::TileProxy;
+use seawatch::webhooks::Webhooks;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-  
-    use tracing_subscriber::{EnvFilter, fmt, prelude::*};
-
-   
+    let cli = config::Cli::parse();
+    // `seawatch export-types [path]` writes the TypeScript declarations and exits
+    #[allow(unused_variables)]
+    if let Some(config::Command::ExportTypes { path }) = &cli.command {
+        #[cfg(feature = "ts")]
+        {
+            let path = path.as_deref().unwrap_or(typescript::DEFAULT_PATH);
+            std::fs::write(path, typescript::bundle())?;
+            println!("Wrote {}", path);
+            return Ok(());
+        }
+        #[cfg(not(feature = "ts"))]
+        return Err(anyhow::anyhow!("This build can't export types; build with --features ts"));
+    }
+    // `seawatch check-config` reports every problem it finds, not just the first
+    if let Some(config::Command::CheckConfig) = &cli.command {
+        let report = check::run(&cli, |var| env::var(var).ok());
+        print!("{}", report.render());
+        if report.errors() > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = config::Config::load(&cli)?;
+    // `--diagnose [secs]` only checks the upstream connection; parse warnings
+    // are counted rather than logged, as logging isn't set up yet
+    if let Some(secs) = cli.diagnose {
+        println!("Listening to {} for {}s...", config.upstream.url, secs);
+        let report = diagnose::run(&config.upstream, Duration::from_secs(secs)).await?;
+        print!("{}", report.render());
+        if report.frames == 0 {
+            return Err(anyhow::anyhow!("No messages received; check that the subscription covers an area with traffic"));
+        }
+        return Ok(());
+    }
+    let log_filter = logging::init(cli.log_format);
     let crate_name = env!("CARGO_PKG_NAME").replace('-', "_");
 
-    let env_filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new("info")) // Default to 'info' if RUST_LOG is unset/invalid
-        .add_directive(format!("{}={}", crate_name, "debug").parse().unwrap());
-
-    tracing_subscriber::registry()
-        .with(fmt::layer()) // Standard formatting layer
-        .with(env_filter)   // Apply the configured filter
-        .init();            // Initialize the subscriber
-
     // Test logs
     info!("Starting Rust Seawatch - crate: '{}'", crate_name);
     debug!("Debug logging enabled for {}", crate_name);
-    let ships = Arc::new(RwLock::new(ShipCache::new()));
-    let app_state = AppState {
-        ships: ships.clone(),
+    let index_kind = config.index_kind()?;
+    info!("Using {:?} spatial index", index_kind);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let monitor_config = &config.monitor;
+    let ports = match &monitor_config.ports_file {
+        Some(path) => Ports::from_file(&path.to_string_lossy())?,
+        None => Ports::builtin(),
     };
+    info!("Tracking port calls at {} ports", ports.all().len());
+    let locodes = match &monitor_config.locodes_file {
+        Some(path) => Some(locode::Locodes::from_file(&path.to_string_lossy(), &ports)?),
+        None => None,
+    };
+    let mut monitor = Monitor::new()
+        .with_collision_config(config.collisions())
+        .with_ports(ports)
+        .with_loiter_duration(monitor_config.loiter_min * 60);
+    if let Some(locodes) = locodes {
+        monitor = monitor.with_locodes(locodes);
+    }
+    info!("Resolving destinations against {} UN/LOCODEs", monitor.locodes.len());
+    if !config.regions.is_empty() {
+        info!("Set up {} regions from the configuration", config.regions.len());
+    }
+    let mut rules_from_file = std::collections::HashSet::new();
+    if let Some(path) = &monitor_config.alert_rules {
+        for rule in alerts::load_rules(&path.to_string_lossy())? {
+            rules_from_file.insert(rule.name.clone());
+            monitor.alerts.upsert(rule);
+        }
+        info!("Loaded {} alert rules from {}", monitor.alerts.rules().len(), path.display());
+    }
+    let forwarding = match &config.forwarding.targets_file {
+        Some(path) => Arc::new(Forwarder::from_file(&path.to_string_lossy())?),
+        None => Arc::new(Forwarder::default()),
+    };
+    let ports = monitor.port_calls.ports().all().to_vec();
+    let mut builder = Seamon::builder()
+        .config(config.clone())
+        .monitor(monitor)
+        .forwarding(forwarding.clone())
+        .log_filter(log_filter)
+        .shutdown(shutdown_rx.clone());
+    if let Some(path) = &config.server.api_keys {
+        let api_keys = ApiKeys::from_file(&path.to_string_lossy())?;
+        info!("Requiring one of {} API keys", api_keys.usage().len());
+        builder = builder.api_keys(api_keys);
+    } else {
+        info!("No API_KEYS, so the /api/admin endpoints are off");
+    }
+    if cli.offline {
+        builder = builder.offline();
+        if cli.simulate.is_none() && env::var("SOURCES").is_err() && config.peer.follow_url.is_none() {
+            return Err(anyhow::anyhow!("--offline needs somewhere local for ships to come from: SOURCES (e.g. nmea-udp:0.0.0.0:10110), peer.follow_url or --simulate"));
+        }
+        if !assets::has_vendored_maplibre(config.server.static_dir.as_deref()) && !config.server.headless {
+            warn!("MapLibre isn't in static/vendor/, so the map page still loads it from unpkg.com");
+        }
+    }
+    if cli.replay {
+        let replayable = env::var("SOURCES").is_ok_and(|specs| specs.split(',').any(plugin::replays));
+        if !replayable {
+            return Err(anyhow::anyhow!("--replay needs a recording in SOURCES to play, e.g. states:ships.ndjson or nmea-file:recording.nmea"));
+        }
+        if cli.simulate.is_some() || config.peer.follow_url.is_some() {
+            return Err(anyhow::anyhow!("--replay can't be combined with --simulate or peer.follow_url"));
+        }
+        builder = builder.replay();
+    }
+    let photo_config = &config.photos;
+    let photo_api_url = photo_config.api_url.as_ref().filter(|_| {
+        if cli.offline {
+            warn!("photos.api_url is set, but photos aren't fetched offline");
+        }
+        !cli.offline
+    });
+    if let Some(template) = photo_api_url {
+        let ttl = Duration::from_secs(photo_config.cache_hours * 60 * 60);
+        let photos = Photos::new(template, photo_config.api_key.clone(), &photo_config.pointer, ttl)?;
+        builder = builder.photos(photos);
+    }
+    if let Some(dir) = &config.tile_cache.dir {
+        let map = &config.map;
+        let max_age = Duration::from_secs(config.tile_cache.days * 86_400);
+        let mut proxy = TileProxy::new(&map.tile_url, map.seamark_url.as_deref(), dir, max_age)?;
+        if cli.offline {
+            info!("Serving the basemap tiles cached in {}", dir.display());
+            proxy = proxy.cache_only();
+        } else {
+            info!("Proxying basemap tiles, cached in {}", dir.display());
+        }
+        builder = builder.tile_proxy(proxy);
+    } else if cli.offline {
+        info!("No tile_cache.dir, so the map has no basemap offline");
+    }
+    if let Some(path) = &config.server.searches_file {
+        let searches = Searches::from_file(&path.to_string_lossy())?;
+        info!("Loaded {} saved searches from {}", searches.all().len(), path.display());
+        builder = builder.searches(searches);
+    }
+    let peer_token = config.peer.token.clone();
+    if let Some(token) = &peer_token {
+        builder = builder.peer_token(token.clone());
+    }
 
-    // Start AIS stream processing
-    tokio::spawn(ais_stream_task(ships.clone()));
-    
-    // Start cache cleanup task
-    tokio::spawn(cache_cleanup_task(ships.clone()));
-
-    // Setup web server
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/api/ships/:sw_lat/:sw_lng/:ne_lat/:ne_lng", get(get_ships_in_bbox))
-        .route("/api/ship/:mmsi", get(get_ship_info))
-        .nest_service("/static", ServeDir::new("static"))
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+    // Sources and sinks by name, including any from plugin libraries
+    #[allow(unused_mut)]
+    let mut registry = plugin::Registry::with_builtins();
+    #[cfg(feature = "dynamic-plugins")]
+    if let Ok(paths) = env::var("PLUGINS") {
+        for path in paths.split(',') {
+            // Safety: loading a plugin runs its code; PLUGINS is trusted configuration
+            unsafe { registry.load(path.trim())? };
+        }
+    }
+    #[cfg(not(feature = "dynamic-plugins"))]
+    if env::var("PLUGINS").is_ok() {
+        warn!("PLUGINS is set, but this build can't load plugins; build with --features dynamic-plugins");
+    }
+    if let Ok(specs) = env::var("SOURCES") {
+        for spec in specs.split(',') {
+            builder = builder.source(registry.source(spec)?);
+        }
+    }
+    if let Ok(specs) = env::var("SINKS") {
+        for spec in specs.split(',') {
+            builder = builder.sink(registry.sink(spec)?);
+        }
+    }
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
-    info!("Server running on http://127.0.0.1:8080");
-    
-    axum::serve(listener, app).await?;
-    Ok(())
-}
+    // Copy updates to the firehose endpoint, as raw frames or ship states
+    let mut normalized_firehose = None;
+    if let Ok(url) = env::var("FIREHOSE_URL") {
+        let format = env::var("FIREHOSE_FORMAT").map_or(Ok(firehose::Format::Normalized), |format| format.parse())?;
+        let firehose = Firehose::new(
+            Url::parse(&url)?,
+            env::var("FIREHOSE_SECRET").ok(),
+            env::var("FIREHOSE_GZIP").map_or(Ok(true), |gzip| gzip.parse::<bool>())?,
+            env::var("FIREHOSE_BATCH").map_or(Ok(firehose::DEFAULT_BATCH), |batch| batch.parse::<usize>())?,
+        )?;
+        let (tap, records) = firehose.channel();
+        match format {
+            firehose::Format::Raw => builder = builder.raw_tap(tap),
+            firehose::Format::Normalized => normalized_firehose = Some(tap),
+        }
+        tokio::spawn(firehose::firehose_task(firehose, records));
+    }
 
-async fn ais_stream_task(ships: SharedShipCache) {
-    loop {
-        if let Err(e) = run_ais_stream(ships.clone()).await {
-            error!("AIS stream error: {}", e);
-            tokio::time::sleep(Duration::from_secs(5)).await;
+    // Follow another instance, or simulate traffic, instead of connecting to aisstream.io
+    if let Some(vessels) = cli.simulate {
+        if config.peer.follow_url.is_some() {
+            return Err(anyhow::anyhow!("--simulate can't be combined with peer.follow_url"));
         }
+        builder = builder.simulate(Simulation::new(ports, vessels, cli.simulate_seed));
+    } else if let Some(url) = &config.peer.follow_url {
+        builder = builder.follow(Url::parse(url)?, peer_token.clone());
+    }
+    let mut seamon = builder.build()?;
+    let (ships, monitor) = (seamon.ships().clone(), seamon.monitor().clone());
+    if let Some(tap) = normalized_firehose {
+        tokio::spawn(firehose::normalized_task(tap, monitor.updates.subscribe()));
     }
-}
 
-async fn run_ais_stream(ships: SharedShipCache) -> Result<()> {
-    let api_key = env::var("AIS_STREAM_API_REAL")
-        .map_err(|_| anyhow::anyhow!("AIS_STREAM_API_KEY environment variable not set"))?;
-    
-    let url = Url::parse("wss://stream.aisstream.io/v0/stream")?;
-    let mut ais_stream = AisStream::connect(url, api_key).await?;
-    
-    info!("Connected to AIS stream");
-    
-    while let Some(message) = ais_stream.next_message().await? {
-        process_ais_message(message, ships.clone()).await;
-    }
-    
-    Ok(())
-}
+    if let Ok(urls) = env::var("WEBHOOK_URLS") {
+        let urls = urls
+            .split(',')
+            .map(|url| Url::parse(url.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        info!("Delivering events to {} webhooks", urls.len());
+        let webhooks = Webhooks::new(urls, env::var("WEBHOOK_SECRET").ok())?;
+        tokio::spawn(webhooks::webhook_task(monitor.events.subscribe(), Arc::new(webhooks)));
+    }
+    let mailer = match env::var("SMTP_URL") {
+        Ok(url) => {
+            let from = env::var("SMTP_FROM")
+                .map_err(|_| anyhow::anyhow!("SMTP_FROM must be set along with SMTP_URL"))?;
+            let max_per_hour = match env::var("SMTP_MAX_PER_HOUR") {
+                Ok(max) => max.parse::<usize>()?,
+                Err(_) => email::DEFAULT_MAX_PER_HOUR,
+            };
+            let mailer = Arc::new(Mailer::new(&url, &from, max_per_hour)?);
+            info!("Mailing alerts from {}", from);
+            tokio::spawn(email::email_task(monitor.events.subscribe(), mailer.clone(), monitor.clone(), ships.clone()));
+            Some(mailer)
+        }
+        Err(_) => None,
+    };
+    if !config.reports.is_empty() {
+        if mailer.is_none() && config.reports.values().any(|report| !report.email.is_empty()) {
+            return Err(anyhow::anyhow!("Mailing reports needs SMTP_URL"));
+        }
+        info!("Reporting on {} regions", config.reports.len());
+        tokio::spawn(reports::reports_task(config.reports.clone(), monitor.clone(), mailer, shutdown_rx.clone()));
+    }
+    let chat = ChatNotifier::new(ChatConfig {
+        telegram_token: env::var("TELEGRAM_BOT_TOKEN").ok(),
+        slack_webhook: env::var("SLACK_WEBHOOK_URL").ok(),
+        discord_webhook: env::var("DISCORD_WEBHOOK_URL").ok(),
+    })?;
+    tokio::spawn(chat::chat_task(monitor.events.subscribe(), Arc::new(chat), monitor.clone(), ships.clone()));
+    #[cfg(feature = "mqtt")]
+    if let Ok(url) = env::var("MQTT_URL") {
+        let qos = match env::var("MQTT_QOS") {
+            Ok(qos) => mqtt::parse_qos(&qos)?,
+            Err(_) => rumqttc::QoS::AtMostOnce,
+        };
+        let home_assistant = match env::var("MQTT_HA_VESSELS") {
+            Ok(vessels) => {
+                let vessels = vessels
+                    .split(',')
+                    .map(|mmsi| mmsi.trim().parse::<u32>())
+                    .collect::<Result<std::collections::HashSet<_>, _>>()?;
+                let prefix = env::var("MQTT_HA_PREFIX").unwrap_or_else(|_| homeassistant::DEFAULT_PREFIX.to_string());
+                Some(homeassistant::HomeAssistant::new(&prefix, vessels))
+            }
+            Err(_) => None,
+        };
+        let publisher = mqtt::MqttPublisher::start(mqtt::MqttConfig {
+            url,
+            ship_topic: env::var("MQTT_SHIP_TOPIC").unwrap_or_else(|_| mqtt::DEFAULT_SHIP_TOPIC.to_string()),
+            event_topic: env::var("MQTT_EVENT_TOPIC").unwrap_or_else(|_| mqtt::DEFAULT_EVENT_TOPIC.to_string()),
+            qos,
+            retain: env::var("MQTT_RETAIN").map_or(Ok(false), |retain| retain.parse::<bool>())?,
+            home_assistant,
+        })?;
+        tokio::spawn(mqtt::mqtt_task(sinks::Feed::new("MQTT publisher", &monitor), publisher));
+    }
+    #[cfg(not(feature = "mqtt"))]
+    if env::var("MQTT_URL").is_ok() {
+        warn!("MQTT_URL is set, but this build has no MQTT support; build with --features mqtt");
+    }
+    #[cfg(feature = "kafka")]
+    if let Ok(brokers) = env::var("KAFKA_BROKERS") {
+        let config = kafka::KafkaConfig {
+            brokers: brokers.split(',').map(|broker| broker.trim().to_string()).collect(),
+            topic: env::var("KAFKA_TOPIC").unwrap_or_else(|_| "seamon.ships".to_string()),
+            event_topic: env::var("KAFKA_EVENT_TOPIC").ok(),
+        };
+        tokio::spawn(kafka::kafka_task(sinks::Feed::new("Kafka producer", &monitor), config));
+    }
+    #[cfg(not(feature = "kafka"))]
+    if env::var("KAFKA_BROKERS").is_ok() {
+        warn!("KAFKA_BROKERS is set, but this build has no Kafka support; build with --features kafka");
+    }
+    #[cfg(feature = "nats")]
+    if let Ok(url) = env::var("NATS_URL") {
+        let config = nats::NatsConfig {
+            url,
+            ship_subject: env::var("NATS_SHIP_SUBJECT").unwrap_or_else(|_| nats::DEFAULT_SHIP_SUBJECT.to_string()),
+            event_subject: env::var("NATS_EVENT_SUBJECT").unwrap_or_else(|_| nats::DEFAULT_EVENT_SUBJECT.to_string()),
+            jetstream: env::var("NATS_JETSTREAM").map_or(Ok(false), |jetstream| jetstream.parse::<bool>())?,
+        };
+        nats::check_subject(&config.ship_subject)?;
+        nats::check_subject(&config.event_subject)?;
+        tokio::spawn(nats::nats_task(sinks::Feed::new("NATS publisher", &monitor), config));
+    }
+    #[cfg(not(feature = "nats"))]
+    if env::var("NATS_URL").is_ok() {
+        warn!("NATS_URL is set, but this build has no NATS support; build with --features nats");
+    }
+    if !forwarding.is_empty() {
+        tokio::spawn(forward::forward_task(forwarding, monitor.updates.subscribe()));
+    }
+    if let Ok(url) = env::var("INFLUX_URL") {
+        let bucket = env::var("INFLUX_BUCKET")
+            .map_err(|_| anyhow::anyhow!("INFLUX_BUCKET must be set along with INFLUX_URL"))?;
+        let org = env::var("INFLUX_ORG").unwrap_or_default();
+        let influx = timeseries::Influx::new(&url, &org, &bucket, env::var("INFLUX_TOKEN").ok())?;
+        info!("Writing positions to InfluxDB bucket {}", bucket);
+        let backend = timeseries::Backend::Influx(influx);
+        tokio::spawn(timeseries::timeseries_task(backend, monitor.updates.subscribe(), ships.clone(), monitor.clone()));
+    }
+    #[cfg(feature = "timescale")]
+    if let Ok(url) = env::var("TIMESCALE_URL") {
+        let backend = timeseries::Backend::Timescale(timeseries::Timescale::new(&url));
+        tokio::spawn(timeseries::timeseries_task(backend, monitor.updates.subscribe(), ships.clone(), monitor.clone()));
+    }
+    #[cfg(not(feature = "timescale"))]
+    if env::var("TIMESCALE_URL").is_ok() {
+        warn!("TIMESCALE_URL is set, but this build has no TimescaleDB support; build with --features timescale");
+    }
+    if let Ok(url) = env::var("ELASTICSEARCH_URL") {
+        let elastic = elastic::Elastic::new(elastic::ElasticConfig {
+            url,
+            api_key: env::var("ELASTICSEARCH_API_KEY").ok(),
+            prefix: env::var("ELASTICSEARCH_INDEX_PREFIX").unwrap_or_else(|_| elastic::DEFAULT_PREFIX.to_string()),
+            rollover: env::var("ELASTICSEARCH_ROLLOVER").map_or(Ok(elastic::Rollover::Daily), |rollover| rollover.parse())?,
+        })?;
+        tokio::spawn(elastic::elastic_task(sinks::Feed::new("Elasticsearch indexer", &monitor), elastic));
+    }
+    if let Some(url) = &config.peer.push_url {
+        let token = peer_token
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("peer.token must be set along with peer.push_url"))?;
+        let name = config.peer.name.clone();
+        tokio::spawn(peer::push_task(Url::parse(url)?, name, token, ships.clone(), monitor.clone(), shutdown_rx.clone()));
+    }
 
-async fn process_ais_message(message: AisMessage, ships: SharedShipCache) {
-    let mmsi = message.metadata.mmsi;
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        info!("Shutdown requested, draining connections");
+        #[cfg(unix)]
+        systemd::notify("STOPPING=1");
+        let _ = shutdown_tx.send(true);
+    });
 
-  
+    // Start AIS stream processing, or follow another instance instead;
+    // messages are buffered and applied in batches
+    let ingestion = seamon.start()?;
+    #[cfg(unix)]
+    tokio::spawn(reload::reload_task(cli.clone(), seamon.config().clone(), monitor.clone(), seamon.api_keys().clone(), rules_from_file));
+    #[cfg(not(unix))]
+    let _ = rules_from_file;
 
-    let mut cache = ships.write().unwrap();
-    
-    // Get or create ship
-    let ship = cache.ships.entry(mmsi).or_insert_with(|| {
-        Ship::new(mmsi, message.metadata.ship_name.clone())
-    });
-    
-    // Update basic info
-    ship.name = message.metadata.ship_name;
-    ship.lat = message.metadata.latitude;
-    ship.lng = message.metadata.longitude;
-    ship.last_update = timestamp;
+    // Start the Signal K stream, on /signalk/v1/stream and optionally over TCP
+    if let Ok(addr) = env::var("SIGNALK_TCP_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        info!("Serving Signal K deltas on tcp://{}", addr);
+        tokio::spawn(seamon.signalk().clone().serve_tcp(listener, ships.clone()));
+    }
 
+    // Start re-serving updates as NMEA for chartplotters
+    if let Ok(addr) = env::var("NMEA_TCP_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        info!("Serving AIVDM sentences on tcp://{}", addr);
+        tokio::spawn(nmea::serve(listener, ships.clone(), monitor.updates.subscribe()));
+    }
 
-    // Update type-specific data
-    match message.message_type.as_str() {
-        "PositionReport" => {
-            if let Some(pos_report) = message.message.position_report {
-                ship.heading = pos_report.true_heading;
-                ship.speed = pos_report.sog;
-                ship.nav_status = pos_report.navigational_status;
-            }
+    // Setup web server
+    let mut server = start_server(&config.server, seamon.app(), shutdown_rx.clone()).await?;
+    #[cfg(unix)]
+    {
+        systemd::notify("READY=1");
+        if let Some(period) = systemd::watchdog_interval() {
+            tokio::spawn(systemd::watchdog_task(period));
         }
-        "ShipStaticData" => {
-            if let Some(static_data) = message.message.ship_static_data {
-                ship.ship_type = static_data.ship_type;
-                ship.destination = static_data.destination;
-                ship.imo_number = static_data.imo_number;
-            }
+    }
+    tokio::select! {
+        result = &mut server => return Ok(result??),
+        _ = shutdown::requested(shutdown_rx) => {}
+    }
+
+    // Stop accepting connections and let in-flight requests finish, close the
+    // upstream socket, then apply whatever was still buffered
+    let shutdown_timeout = Duration::from_secs(seamon.config().borrow().server.shutdown_timeout_secs);
+    let drained = tokio::time::timeout(shutdown_timeout, async {
+        if let Ok(Err(e)) = server.await {
+            error!("Server error while shutting down: {}", e);
         }
-        _ => {}
+        let _ = ingestion.await;
+        seamon.drain();
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Shut down cleanly"),
+        Err(_) => warn!("Shutdown timed out after {:?}, exiting anyway", shutdown_timeout),
     }
-    
-  
+    Ok(())
 }
 
-async fn cache_cleanup_task(ships: SharedShipCache) {
-    let mut interval = interval(Duration::from_secs(300)); // Cleanup every 5 minutes
-    
-    loop {
-        interval.tick().await;
-        
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
-        let mut cache = ships.write().unwrap();
-        let mut to_remove = Vec::new();
-        
-        for (&mmsi, ship) in &cache.ships {
-            // Remove ships not seen for 24 hours
-            if current_time - ship.last_update > 86400 {
-                to_remove.push(mmsi);
+// Serves the app on the socket systemd passed, if any, else on a Unix socket
+// if one is configured, else on the TCP address; with HTTPS if a certificate is
+async fn start_server(
+    config: &config::ServerConfig,
+    app: Router,
+    shutdown_rx: watch::Receiver<bool>,
+) -> Result<tokio::task::JoinHandle<std::io::Result<()>>> {
+    #[cfg(unix)]
+    let activated = match systemd::listener()? {
+        Some(systemd::Listener::Unix(listener)) => {
+            info!("Server running on the Unix socket passed by systemd");
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+            return Ok(tokio::spawn(listen::serve_unix(listener, None, app, shutdown_rx)));
+        }
+        Some(systemd::Listener::Tcp(listener)) => Some(tokio::net::TcpListener::from_std(listener)?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let activated = None;
+
+    let listener = match activated {
+        Some(listener) => listener,
+        None => {
+            if let Some(path) = &config.unix_socket {
+                #[cfg(unix)]
+                {
+                    let listener = listen::bind_unix(path).map_err(|e| anyhow::anyhow!("Could not listen on {}: {}", path.display(), e))?;
+                    info!("Server running on unix:{}", path.display());
+                    return Ok(tokio::spawn(listen::serve_unix(listener, Some(path.clone()), app, shutdown_rx)));
+                }
+                #[cfg(not(unix))]
+                return Err(anyhow::anyhow!("server.unix_socket is set, but Unix sockets are only supported on Unix"));
             }
+            let addr = listen::resolve(&config.host, config.port).await?;
+            listen::bind(addr).map_err(|e| anyhow::anyhow!("Could not listen on {}: {}", addr, e))?
         }
-        
-        for mmsi in to_remove {
-            cache.ships.remove(&mmsi);
-         
+    };
+    let addr = listener.local_addr()?;
+    match (&config.tls_cert, &config.tls_key) {
+        #[cfg(feature = "tls")]
+        (Some(cert), Some(key)) => {
+            let files = tls::TlsFiles { cert: cert.clone(), key: key.clone() };
+            // Fail at startup rather than on the first connection
+            files.load().await?;
+            info!("Server running on https://{}", addr);
+            Ok(tokio::spawn(tls::serve(listener, app, files, shutdown_rx)))
+        }
+        #[cfg(not(feature = "tls"))]
+        (Some(_), Some(_)) => Err(anyhow::anyhow!("server.tls_cert is set, but this build has no TLS support; build with --features tls")),
+        _ => {
+            info!("Server running on http://{}", addr);
+            Ok(tokio::spawn(
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown::requested(shutdown_rx))
+                    .into_future(),
+            ))
         }
-        
-        info!("Cache cleanup completed, {} ships remaining", cache.ships.len());
     }
 }
-
-async fn index() -> Html<&'static str> {
-    Html(include_str!("../static/index.html"))
-}
-
-async fn get_ships_in_bbox(
-    Path((sw_lat, sw_lng, ne_lat, ne_lng)): Path<(f64, f64, f64, f64)>,
-    State(state): State<AppState>,
-) -> Result<Json<Vec<ShipState>>, StatusCode> {
-    let cache = state.ships.read().unwrap();
-    
-    // Use immutable version to avoid needing write lock
-    let ships = cache.get_ships_in_bbox_immutable(sw_lat, sw_lng, ne_lat, ne_lng);
-    
-    Ok(Json(ships))
-}
-async fn get_ship_info(
-    Path(mmsi): Path<u32>,
-    State(state): State<AppState>,
-) -> Result<Json<Ship>, StatusCode> {
-    let cache = state.ships.read().unwrap();
-    
-    match cache.ships.get(&mmsi) {
-        Some(ship) => Ok(Json(ship.clone())),
-        None => Err(StatusCode::NOT_FOUND),
-    }
-}
\ No newline at end of file
@@ -0,0 +1,91 @@
+use std::fmt::Write;
+
+use crate::forward::Forwarder;
+use crate::ingest::IngestQueue;
+use crate::memory::MemoryUsage;
+use crate::ship::ShipCache;
+
+// Prometheus text exposition of the cache's gauges
+pub fn render(ships: &ShipCache, ingest: &IngestQueue, memory: &MemoryUsage, forwarding: &Forwarder) -> String {
+    let mut out = String::new();
+
+    gauge(&mut out, "seawatch_ships", "Ships currently tracked", [("", ships.len())]);
+    gauge(
+        &mut out,
+        "seawatch_index_pending_changes",
+        "Index changes since the last rebuild",
+        [("", ships.pending_changes())],
+    );
+    counter(
+        &mut out,
+        "seawatch_ingest_shed_total",
+        "Messages shed because the ingestion queue was full",
+        ingest.shed_count(),
+    );
+    counter(
+        &mut out,
+        "seawatch_ingest_suppressed_total",
+        "Position reports skipped as unchanged from the stored state",
+        ingest.suppressed_count(),
+    );
+    gauge(
+        &mut out,
+        "seawatch_memory_bytes",
+        "Approximate memory used, by component",
+        memory.components(),
+    );
+    let targets = forwarding.status();
+    if !targets.is_empty() {
+        let _ = writeln!(out, "# HELP seawatch_udp_forwarded_packets_total Packets forwarded, by target");
+        let _ = writeln!(out, "# TYPE seawatch_udp_forwarded_packets_total counter");
+        for target in &targets {
+            let _ = writeln!(out, "seawatch_udp_forwarded_packets_total{{target=\"{}\"}} {}", target.name, target.forwarded_packets);
+        }
+        let _ = writeln!(out, "# HELP seawatch_udp_failed_packets_total Packets that could not be sent, by target");
+        let _ = writeln!(out, "# TYPE seawatch_udp_failed_packets_total counter");
+        for target in &targets {
+            let _ = writeln!(out, "seawatch_udp_failed_packets_total{{target=\"{}\"}} {}", target.name, target.failed_packets);
+        }
+    }
+
+    out
+}
+
+// A gauge with one sample per label value; an empty label value means no label
+fn gauge<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (&'a str, usize)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (component, value) in samples {
+        if component.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{component=\"{}\"}} {}", name, component, value);
+        }
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let ships = ShipCache::new();
+        let ingest = IngestQueue::new(10, Default::default());
+        let text = render(&ships, &ingest, &ships.memory_usage(), &Forwarder::default());
+        assert!(text.contains("# TYPE seawatch_ships gauge\nseawatch_ships 0\n"));
+        assert!(text.contains("seawatch_memory_bytes{component=\"index\"} "));
+        assert!(text.contains("# TYPE seawatch_ingest_shed_total counter\nseawatch_ingest_shed_total 0\n"));
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::alerts::{Action, AlertRules, Update};
+use crate::anchor::AnchorWatches;
+use crate::anomalies::Anomalies;
+use crate::area_stats::AreaStats;
+use crate::changes::Changes;
+use crate::collision::{CollisionConfig, CollisionWatch};
+use crate::dark::DarkShips;
+use crate::distance::Distances;
+use crate::emissions::Emissions;
+use crate::eta::EtaAccuracy;
+use crate::port_calls::PortCalls;
+use crate::ports::{NearestPort, Ports};
+use crate::events::{Event, EventKind, EventLog};
+use crate::geofence::Geofences;
+use crate::history::History;
+use crate::identity::Identities;
+use crate::ingest_stats::IngestStats;
+use crate::index::is_valid_position;
+use crate::kinematics::{self, Kinematics};
+use crate::locode::Locodes;
+use crate::loitering::{Loitering, DEFAULT_LOITER_SECS};
+use crate::predict::Tracks;
+use crate::quality::{DataQuality, Quality};
+use crate::rendezvous::Rendezvous;
+use crate::ship::{Ship, ShipCache};
+
+// Positions older than this are too stale to project into zones
+const MAX_LOOKAHEAD_REPORT_AGE: u64 = 300;
+// Ship updates buffered for sinks that fall behind before they start missing some
+const UPDATE_CHANNEL_CAPACITY: usize = 8192;
+
+// Everything that watches ship updates as they are applied, and the event
+// log it all reports into
+pub struct Monitor {
+    pub events: Arc<EventLog>,
+    pub geofences: Geofences,
+    pub alerts: AlertRules,
+    pub anchors: AnchorWatches,
+    pub dark: DarkShips,
+    pub collisions: CollisionWatch,
+    pub port_calls: PortCalls,
+    pub tracks: Tracks,
+    pub history: History,
+    pub distances: Distances,
+    pub emissions: Emissions,
+    pub area_stats: AreaStats,
+    pub anomalies: Anomalies,
+    pub rendezvous: Rendezvous,
+    pub loitering: Loitering,
+    pub locodes: Locodes,
+    pub eta: EtaAccuracy,
+    pub quality: Quality,
+    pub changes: Changes,
+    pub identities: Identities,
+    pub ingest: Arc<IngestStats>,
+    // Every applied update, for sinks that republish ship state; see `crate::sinks`
+    pub updates: broadcast::Sender<Arc<Ship>>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        let ports = Arc::new(Ports::builtin());
+        Self {
+            events: Arc::new(EventLog::new()),
+            geofences: Geofences::new(),
+            alerts: AlertRules::new(),
+            anchors: AnchorWatches::new(),
+            dark: DarkShips::new(),
+            collisions: CollisionWatch::new(CollisionConfig::default()),
+            locodes: Locodes::from_ports(&ports),
+            eta: EtaAccuracy::new(),
+            quality: Quality::new(),
+            changes: Changes::new(),
+            identities: Identities::new(),
+            ingest: Arc::new(IngestStats::new()),
+            port_calls: PortCalls::new(ports),
+            tracks: Tracks::new(),
+            history: History::new(),
+            distances: Distances::new(),
+            emissions: Emissions::new(),
+            area_stats: AreaStats::new(),
+            anomalies: Anomalies::new(),
+            rendezvous: Rendezvous::new(),
+            loitering: Loitering::new(DEFAULT_LOITER_SECS),
+            updates: broadcast::channel(UPDATE_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    // Also what destinations resolve against, unless given `with_locodes`
+    pub fn with_ports(mut self, ports: Ports) -> Self {
+        self.locodes = Locodes::from_ports(&ports);
+        self.port_calls = PortCalls::new(Arc::new(ports));
+        self
+    }
+
+    pub fn with_locodes(mut self, locodes: Locodes) -> Self {
+        self.locodes = locodes;
+        self
+    }
+
+    pub fn with_loiter_duration(mut self, secs: u64) -> Self {
+        self.loitering = Loitering::new(secs);
+        self
+    }
+
+    pub fn with_collision_config(mut self, config: CollisionConfig) -> Self {
+        self.collisions = CollisionWatch::new(config);
+        self
+    }
+
+    // Called with each ship right after an update is applied; `before` is
+    // None for a ship seen for the first time
+    pub fn observe(&self, before: Option<&Ship>, ship: &Ship) {
+        if is_valid_position(ship.lat, ship.lng) {
+            self.geofences.observe(&self.events, ship);
+            self.anchors.observe(&self.events, ship);
+            self.dark.observe(&self.events, ship);
+            if let Some(call) = self.port_calls.observe(&self.events, ship) {
+                self.eta.record(ship, &call);
+            }
+            self.tracks.observe(ship);
+            if let Some((from, to)) = self.history.observe(ship) {
+                self.distances.record(ship.mmsi, &from, &to);
+                self.emissions.record(ship, &from, &to);
+            }
+            self.quality.observe(ship);
+            self.anomalies.observe(&self.events, self.port_calls.ports(), ship);
+            self.loitering.observe(&self.events, self.port_calls.ports(), ship);
+        }
+        if let Some(before) = before {
+            self.changes.observe(before, ship);
+        }
+        if before.is_none_or(|before| before.imo_number != ship.imo_number || before.call_sign != ship.call_sign) {
+            self.identities.observe(&self.events, ship);
+        }
+        let update = Update { before, ship, now: ship.last_update };
+        self.alerts.evaluate(&self.events, &self.geofences, &update, false);
+        // Cloning every update is only worth it when something is listening
+        if self.updates.receiver_count() > 0 {
+            let _ = self.updates.send(Arc::new(ship.clone()));
+        }
+    }
+
+    // Context for alerts and ship details
+    pub fn nearest_port(&self, ship: &Ship) -> Option<NearestPort> {
+        if !is_valid_position(ship.lat, ship.lng) {
+            return None;
+        }
+        self.port_calls.ports().nearest(ship.lat, ship.lng)
+    }
+
+    // Speed and course over the last hour, from the history
+    pub fn kinematics(&self, mmsi: u32, now: u64) -> Option<Kinematics> {
+        Kinematics::of(&self.history.track(mmsi, now.saturating_sub(kinematics::WINDOW_SECS), now), now)
+    }
+
+    pub fn quality(&self, ship: &Ship, now: u64) -> DataQuality {
+        self.quality.assess(ship, self.anomalies.of(ship.mmsi), now)
+    }
+
+    // The notification actions for an event, with a label for messages. The
+    // rule or watch may be gone by the time a notifier asks.
+    pub fn actions_for(&self, event: &Event) -> Option<(String, Vec<Action>)> {
+        match &event.kind {
+            EventKind::Alert { rule } => self.alerts.rule(rule).map(|rule| (rule.name, rule.actions)),
+            EventKind::AnchorDrag { .. } => self.anchors.actions(event.mmsi).map(|actions| ("anchor watch".to_string(), actions)),
+            _ => None,
+        }
+    }
+
+    // Look for silences: ships gone dark, and rules that depend on time
+    // passing; look ahead along predicted tracks for zone entries; sample
+    // zone traffic; and find ships meeting at sea
+    pub fn sweep(&self, ships: &ShipCache, now: u64) {
+        self.dark.sweep(&self.events, now);
+        self.port_calls.purge(now);
+        self.tracks.purge(now);
+        self.history.purge(now);
+        self.distances.purge(now);
+        self.emissions.purge(now);
+        self.quality.purge(now);
+        self.changes.purge(now);
+        self.identities.purge(now);
+        self.anomalies.purge(now);
+        self.loitering.purge(now);
+        self.eta.purge(now);
+        self.area_stats.record(&self.geofences.occupants(), ships, now);
+        self.rendezvous.scan(ships, self.port_calls.ports(), &self.events, now);
+        if !self.geofences.zones().is_empty() {
+            for ship in ships.ships.iter() {
+                if now.saturating_sub(ship.last_update) <= MAX_LOOKAHEAD_REPORT_AGE && is_valid_position(ship.lat, ship.lng) {
+                    self.geofences.look_ahead(&self.events, ship.mmsi, &self.tracks.motion(&ship), now);
+                }
+            }
+        }
+        if !self.alerts.has_periodic() {
+            return;
+        }
+        for ship in ships.ships.iter() {
+            let update = Update { before: None, ship: &ship, now };
+            self.alerts.evaluate(&self.events, &self.geofences, &update, true);
+        }
+    }
+}
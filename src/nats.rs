@@ -0,0 +1,103 @@
+use anyhow::Result;
+use async_nats::jetstream;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use crate::sinks::{Feed, Update};
+
+pub const DEFAULT_SHIP_SUBJECT: &str = "seamon.ships.{mmsi}";
+pub const DEFAULT_EVENT_SUBJECT: &str = "seamon.events.{type}";
+// JetStream acknowledgements outstanding before publishing waits for them
+const MAX_PENDING_ACKS: usize = 256;
+
+pub struct NatsConfig {
+    pub url: String, // nats://host:4222, or several separated by commas
+    pub ship_subject: String,
+    pub event_subject: String,
+    pub jetstream: bool,
+}
+
+// Subjects are dot-separated tokens; wildcards are only for subscribers
+pub fn check_subject(template: &str) -> Result<()> {
+    if template.split('.').any(|token| token.is_empty() || token == "*" || token == ">")
+        || template.contains(char::is_whitespace)
+    {
+        return Err(anyhow::anyhow!("Not a NATS subject to publish to: {}", template));
+    }
+    Ok(())
+}
+
+enum Publisher {
+    Core(async_nats::Client),
+    JetStream(jetstream::Context, Arc<Semaphore>), // One permit per acknowledgement awaited
+}
+
+impl Publisher {
+    async fn publish(&mut self, subject: String, payload: Vec<u8>) -> Result<()> {
+        match self {
+            Publisher::Core(client) => client.publish(subject, payload.into()).await?,
+            Publisher::JetStream(context, pending) => {
+                let permit = pending.clone().acquire_owned().await?;
+                let ack = context.publish(subject, payload.into()).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = ack.await {
+                        warn!("JetStream did not store an update: {}", e);
+                    }
+                    drop(permit);
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Core NATS publishes are fire-and-forget. With JetStream, each publish is
+// acknowledged once stored, so a stream must cover the subjects. The client
+// reconnects on its own, buffering a little while the server is away.
+pub async fn nats_task(mut feed: Feed, config: NatsConfig) {
+    let client = match async_nats::ConnectOptions::new().retry_on_initial_connect().connect(config.url.as_str()).await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Could not connect to NATS at {}: {}", config.url, e);
+            return;
+        }
+    };
+    info!("Publishing to NATS at {}{}", config.url, if config.jetstream { " (JetStream)" } else { "" });
+    let mut publisher = if config.jetstream {
+        Publisher::JetStream(jetstream::new(client), Arc::new(Semaphore::new(MAX_PENDING_ACKS)))
+    } else {
+        Publisher::Core(client)
+    };
+
+    while let Some(update) = feed.next().await {
+        let payload = match update.to_json() {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Could not serialize update for NATS: {}", e);
+                continue;
+            }
+        };
+        let template = match update {
+            Update::Ship(_) => &config.ship_subject,
+            Update::Event(_) => &config.event_subject,
+        };
+        if let Err(e) = publisher.publish(update.topic(template), payload).await {
+            warn!("NATS publish failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_subject() {
+        assert!(check_subject(DEFAULT_SHIP_SUBJECT).is_ok());
+        assert!(check_subject(DEFAULT_EVENT_SUBJECT).is_ok());
+        assert!(check_subject("seamon.ships.>").is_err());
+        assert!(check_subject("seamon..ships").is_err());
+        assert!(check_subject("seamon ships").is_err());
+    }
+}
@@ -0,0 +1,642 @@
+use anyhow::Result;
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::ais::{AisMessage, Dimension, Eta, MessageData, Metadata, PositionReport, ShipStaticData};
+use crate::index::is_valid_position;
+use crate::intern::intern;
+use crate::ship::{Dimensions, Ship, ShipCache};
+
+// Sentences buffered for clients that fall behind before they start missing some
+const SENTENCE_BUFFER: usize = 4096;
+// Characters of armored payload per sentence, keeping sentences under 82 characters
+const MAX_PAYLOAD: usize = 60;
+// Static data is sent again this often, as a real transponder would
+const STATIC_INTERVAL_SECS: u64 = 6 * 60;
+// Multipart messages waiting for the rest of their parts, at most
+const MAX_PENDING: usize = 64;
+
+// AIS payloads are bit fields, packed six bits to a character
+#[derive(Default)]
+struct Bits {
+    bits: Vec<bool>,
+}
+
+impl Bits {
+    fn push(&mut self, value: u64, width: usize) {
+        for i in (0..width).rev() {
+            self.bits.push(value >> i & 1 == 1);
+        }
+    }
+
+    // Two's complement, in `width` bits
+    fn push_signed(&mut self, value: i64, width: usize) {
+        self.push(value as u64 & ((1 << width) - 1), width);
+    }
+
+    // AIS six-bit text, upper case, padded with '@'
+    fn push_text(&mut self, text: &str, chars: usize) {
+        let mut text = text.chars().map(|c| c.to_ascii_uppercase());
+        for _ in 0..chars {
+            let code = match text.next() {
+                Some(c @ '@'..='_') => c as u64 - 64,
+                Some(c @ ' '..='?') => c as u64,
+                Some(_) => ' ' as u64, // Nothing else can be encoded
+                None => 0,
+            };
+            self.push(code, 6);
+        }
+    }
+
+    // The armored payload and the number of fill bits
+    fn armor(mut self) -> (String, usize) {
+        let fill = (6 - self.bits.len() % 6) % 6;
+        self.bits.extend(std::iter::repeat_n(false, fill));
+        let payload = self
+            .bits
+            .chunks(6)
+            .map(|chunk| {
+                let value = chunk.iter().fold(0u8, |value, &bit| value << 1 | u8::from(bit));
+                (if value < 40 { value + 48 } else { value + 56 }) as char
+            })
+            .collect();
+        (payload, fill)
+    }
+}
+
+fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |sum, byte| sum ^ byte)
+}
+
+// `!AIVDM` sentences carrying a payload, split over several if it is long
+fn sentences(payload: &str, fill: usize, sequence: u8) -> Vec<String> {
+    let fragments: Vec<&str> = payload.as_bytes().chunks(MAX_PAYLOAD).map(|c| std::str::from_utf8(c).unwrap()).collect();
+    let count = fragments.len();
+    fragments
+        .iter()
+        .enumerate()
+        .map(|(i, fragment)| {
+            let sequence = if count > 1 { sequence.to_string() } else { String::new() };
+            let fill = if i + 1 == count { fill } else { 0 };
+            let body = format!("AIVDM,{},{},{},A,{},{}", count, i + 1, sequence, fragment, fill);
+            format!("!{}*{:02X}\r\n", body, checksum(&body))
+        })
+        .collect()
+}
+
+// Message 1, a Class A position report
+fn position_report(ship: &Ship) -> (String, usize) {
+    let mut bits = Bits::default();
+    bits.push(1, 6);
+    bits.push(0, 2); // Repeat indicator
+    bits.push(ship.mmsi as u64, 30);
+    bits.push(ship.nav_status.min(15) as u64, 4);
+    bits.push_signed(-128, 8); // Rate of turn not available
+    bits.push((ship.speed * 10.0).round().clamp(0.0, 1022.0) as u64, 10);
+    bits.push(ship.position_accuracy as u64, 1);
+    bits.push_signed((ship.lng * 600_000.0).round() as i64, 28);
+    bits.push_signed((ship.lat * 600_000.0).round() as i64, 27);
+    bits.push(if ship.cog < 360.0 { (ship.cog * 10.0).round() as u64 } else { 3600 }, 12);
+    bits.push(if ship.heading < 360 { ship.heading as u64 } else { 511 }, 9);
+    bits.push(ship.last_update % 60, 6);
+    bits.push(0, 2); // Manoeuvre indicator
+    bits.push(0, 3);
+    bits.push(0, 1); // RAIM
+    bits.push(0, 19); // Radio status
+    bits.armor()
+}
+
+// Message 5, static and voyage data
+fn static_data(ship: &Ship) -> (String, usize) {
+    let mut bits = Bits::default();
+    bits.push(5, 6);
+    bits.push(0, 2);
+    bits.push(ship.mmsi as u64, 30);
+    bits.push(0, 2); // AIS version
+    bits.push(ship.imo_number as u64, 30);
+    bits.push_text(&ship.call_sign, 7);
+    bits.push_text(&ship.name, 20);
+    bits.push(ship.ship_type.min(255) as u64, 8);
+    // Dimensions, capped at what the fields hold
+    let dimensions = ship.dimensions.map_or((0, 0, 0, 0), |d| (d.to_bow.min(511), d.to_stern.min(511), d.to_port.min(63), d.to_starboard.min(63)));
+    bits.push(dimensions.0 as u64, 9);
+    bits.push(dimensions.1 as u64, 9);
+    bits.push(dimensions.2 as u64, 6);
+    bits.push(dimensions.3 as u64, 6);
+    bits.push(0, 4); // Position fix type
+    let eta = ship.eta.and_then(|eta| Utc.timestamp_opt(eta as i64, 0).single());
+    match eta {
+        Some(eta) => {
+            bits.push(eta.month() as u64, 4);
+            bits.push(eta.day() as u64, 5);
+            bits.push(eta.hour() as u64, 5);
+            bits.push(eta.minute() as u64, 6);
+        }
+        None => {
+            bits.push(0, 4);
+            bits.push(0, 5);
+            bits.push(24, 5);
+            bits.push(60, 6);
+        }
+    }
+    bits.push(ship.draught.map_or(0, |draught| (draught * 10.0).round().clamp(0.0, 255.0) as u64), 8);
+    bits.push_text(&ship.destination, 20);
+    bits.push(1, 1); // Data terminal not ready
+    bits.push(0, 1);
+    bits.armor()
+}
+
+// What was last sent as static data, to resend on change
+type StaticKey = (Arc<str>, Arc<str>, Arc<str>, u32, u32, Option<u64>, Option<Dimensions>, Option<u64>);
+
+fn static_key(ship: &Ship) -> StaticKey {
+    // Draught as sent, in tenths of a metre
+    let draught = ship.draught.map(|draught| (draught * 10.0).round() as u64);
+    (ship.name.clone(), ship.destination.clone(), ship.call_sign.clone(), ship.ship_type, ship.imo_number, ship.eta, ship.dimensions, draught)
+}
+
+pub struct Encoder {
+    sequence: u8,
+    sent: HashMap<u32, (StaticKey, u64)>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self { sequence: 0, sent: HashMap::new() }
+    }
+
+    // Sentences for a ship update: its position, and its static data when that
+    // has changed or is due again
+    pub fn encode(&mut self, ship: &Ship, with_static: bool) -> Vec<String> {
+        let mut out = Vec::new();
+        if is_valid_position(ship.lat, ship.lng) {
+            let (payload, fill) = position_report(ship);
+            out.extend(sentences(&payload, fill, 0));
+        }
+        let key = static_key(ship);
+        let due = match self.sent.get(&ship.mmsi) {
+            Some((sent, at)) => *sent != key || ship.last_update.saturating_sub(*at) >= STATIC_INTERVAL_SECS,
+            None => true,
+        };
+        if with_static || due {
+            let (payload, fill) = static_data(ship);
+            out.extend(sentences(&payload, fill, self.sequence));
+            self.sequence = (self.sequence + 1) % 10;
+            self.sent.insert(ship.mmsi, (key, ship.last_update));
+        }
+        out
+    }
+}
+
+// Encode ship updates once, for every connected client
+pub async fn encoder_task(mut updates: broadcast::Receiver<Arc<Ship>>, sentences: broadcast::Sender<Arc<str>>) {
+    let mut encoder = Encoder::new();
+    loop {
+        match updates.recv().await {
+            Ok(ship) => {
+                if sentences.receiver_count() == 0 {
+                    continue;
+                }
+                for sentence in encoder.encode(&ship, false) {
+                    let _ = sentences.send(Arc::from(sentence));
+                }
+            }
+            Err(RecvError::Lagged(missed)) => warn!("NMEA encoder fell behind, {} updates not sent", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+async fn serve_client(mut socket: TcpStream, ships: Arc<ShipCache>, mut sentences: broadcast::Receiver<Arc<str>>) {
+    // Start with everything known, so a plotter fills in straight away
+    let mut snapshot = String::new();
+    let mut encoder = Encoder::new();
+    for ship in ships.get_full_ships_in_bbox(-90.0, -180.0, 90.0, 180.0) {
+        snapshot.extend(encoder.encode(&ship, true));
+    }
+    if socket.write_all(snapshot.as_bytes()).await.is_err() {
+        return;
+    }
+    loop {
+        match sentences.recv().await {
+            Ok(sentence) => {
+                if socket.write_all(sentence.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => debug!("NMEA client fell behind, {} sentences skipped", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+// Re-serve ship updates as AIVDM sentences, as a receiver on a TCP port would
+pub async fn serve(listener: TcpListener, ships: Arc<ShipCache>, updates: broadcast::Receiver<Arc<Ship>>) {
+    let (tx, _) = broadcast::channel(SENTENCE_BUFFER);
+    tokio::spawn(encoder_task(updates, tx.clone()));
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                info!("NMEA client connected from {}", peer);
+                let _ = socket.set_nodelay(true);
+                tokio::spawn(serve_client(socket, ships.clone(), tx.subscribe()));
+            }
+            Err(e) => warn!("NMEA listener failed to accept: {}", e),
+        }
+    }
+}
+
+// A received payload, unpacked. Fields past the end read as 0, as receivers
+// pass on messages cut short
+struct Payload {
+    bits: Vec<bool>,
+}
+
+impl Payload {
+    fn unarmor(payload: &str) -> Option<Self> {
+        let mut bits = Vec::with_capacity(payload.len() * 6);
+        for c in payload.bytes() {
+            let value = match c {
+                b'0'..=b'W' => c - 48,
+                b'`'..=b'w' => c - 56,
+                _ => return None,
+            };
+            bits.extend((0..6).rev().map(|i| value >> i & 1 == 1));
+        }
+        Some(Self { bits })
+    }
+
+    fn uint(&self, offset: usize, width: usize) -> u64 {
+        (offset..offset + width).fold(0, |value, i| value << 1 | u64::from(self.bits.get(i).copied().unwrap_or(false)))
+    }
+
+    fn int(&self, offset: usize, width: usize) -> i64 {
+        (self.uint(offset, width) << (64 - width)) as i64 >> (64 - width)
+    }
+
+    // Six-bit text, up to the first '@' and without trailing spaces
+    fn text(&self, offset: usize, chars: usize) -> Arc<str> {
+        let text: String = (0..chars)
+            .map(|i| self.uint(offset + 6 * i, 6) as u8)
+            .map(|c| if c < 32 { (c + 64) as char } else { c as char })
+            .take_while(|&c| c != '@')
+            .collect();
+        intern(text.trim_end())
+    }
+
+    // Degrees, None for "not available"
+    fn position(&self, offset: usize) -> Option<(f64, f64)> {
+        let lng = self.int(offset, 28) as f64 / 600_000.0;
+        let lat = self.int(offset + 28, 27) as f64 / 600_000.0;
+        (lat.abs() <= 90.0 && lng.abs() <= 180.0).then_some((lat, lng))
+    }
+
+    fn position_report(&self, sog: usize, cog: usize, nav_status: u32) -> PositionReport {
+        PositionReport {
+            cog: self.uint(cog, 12) as f64 / 10.0,
+            navigational_status: nav_status,
+            sog: self.uint(sog, 10) as f64 / 10.0,
+            true_heading: self.uint(cog + 12, 9) as u32,
+            position_accuracy: self.uint(sog + 10, 1) == 1,
+        }
+    }
+
+    fn dimension(&self, offset: usize) -> Dimension {
+        Dimension {
+            a: self.uint(offset, 9) as u32,
+            b: self.uint(offset + 9, 9) as u32,
+            c: self.uint(offset + 18, 6) as u32,
+            d: self.uint(offset + 24, 6) as u32,
+        }
+    }
+}
+
+// What a message says, before the name and position it leaves out are filled in
+struct Decoded {
+    mmsi: u32,
+    message_type: &'static str,
+    name: Option<Arc<str>>,
+    position: Option<(f64, f64)>,
+    message: MessageData,
+}
+
+// Messages 1 to 3 (class A positions), 5 (static and voyage data), 18 (class B
+// positions) and 24 (class B static data); None for the others
+fn decode_payload(payload: &Payload) -> Option<Decoded> {
+    let mmsi = payload.uint(8, 30) as u32;
+    let mut decoded = Decoded {
+        mmsi,
+        message_type: "",
+        name: None,
+        position: None,
+        message: MessageData { position_report: None, ship_static_data: None },
+    };
+    match payload.uint(0, 6) {
+        1..=3 => {
+            decoded.message_type = "PositionReport";
+            decoded.position = payload.position(61);
+            decoded.message.position_report = Some(payload.position_report(50, 116, payload.uint(38, 4) as u32));
+        }
+        18 => {
+            decoded.message_type = "StandardClassBPositionReport";
+            decoded.position = payload.position(57);
+            // Class B sets send no navigational status; 15 is "not defined"
+            decoded.message.position_report = Some(payload.position_report(46, 112, 15));
+        }
+        5 => {
+            decoded.message_type = "ShipStaticData";
+            decoded.name = Some(payload.text(112, 20));
+            decoded.message.ship_static_data = Some(ShipStaticData {
+                ship_type: payload.uint(232, 8) as u32,
+                destination: payload.text(302, 20),
+                imo_number: payload.uint(40, 30) as u32,
+                call_sign: payload.text(70, 7),
+                eta: Some(Eta {
+                    month: payload.uint(274, 4) as u32,
+                    day: payload.uint(278, 5) as u32,
+                    hour: payload.uint(283, 5) as u32,
+                    minute: payload.uint(288, 6) as u32,
+                }),
+                dimension: Some(payload.dimension(240)),
+                draught: Some(payload.uint(294, 8) as f64 / 10.0),
+            });
+        }
+        24 => {
+            decoded.message_type = "StaticDataReport";
+            match payload.uint(38, 2) {
+                0 => decoded.name = Some(payload.text(40, 20)),
+                1 => {
+                    decoded.message.ship_static_data = Some(ShipStaticData {
+                        ship_type: payload.uint(40, 8) as u32,
+                        destination: intern(""),
+                        imo_number: 0,
+                        call_sign: payload.text(90, 7),
+                        eta: None,
+                        dimension: Some(payload.dimension(132)),
+                        draught: None,
+                    });
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    }
+    Some(decoded)
+}
+
+// Turns `!AIVDM` and `!AIVDO` sentences from a receiver into messages as
+// aisstream.io would have sent them. Those always carry the ship's name and
+// position, which AIS messages mostly don't, so the decoder keeps the last of
+// each it saw per ship to fill them in.
+#[derive(Default)]
+pub struct Decoder {
+    pending: HashMap<(String, String), Vec<String>>, // Keyed by sequence id and channel
+    known: HashMap<u32, (Arc<str>, f64, f64)>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A line as received, optionally with an NMEA 4.10 tag block whose `c:`
+    // gives the time it was received; otherwise it was received `now`. None for
+    // other sentences, parts of a message still to come and message types
+    // that say nothing about ships; an error if the line is garbled.
+    pub fn decode(&mut self, line: &str, now: u64) -> Result<Option<(u64, AisMessage)>> {
+        let line = line.trim();
+        let (tags, sentence) = match line.strip_prefix('\\').and_then(|rest| rest.split_once('\\')) {
+            Some((tags, sentence)) => (Some(tags), sentence),
+            None => (None, line),
+        };
+        let Some(sentence) = sentence.strip_prefix('!') else {
+            return Ok(None); // GPS fixes and the like, from the same multiplexer
+        };
+        let (body, sum) = sentence.split_once('*').ok_or_else(|| anyhow::anyhow!("No checksum in {}", line))?;
+        if u8::from_str_radix(sum.get(..2).unwrap_or(sum), 16).ok() != Some(checksum(body)) {
+            return Err(anyhow::anyhow!("Bad checksum in {}", line));
+        }
+        let fields: Vec<&str> = body.split(',').collect();
+        if fields.len() < 7 || !(fields[0].ends_with("VDM") || fields[0].ends_with("VDO")) {
+            return Ok(None);
+        }
+        let (count, index) = match (fields[1].parse::<usize>(), fields[2].parse::<usize>()) {
+            (Ok(count), Ok(index)) if (1..=9).contains(&count) && (1..=count).contains(&index) => (count, index),
+            _ => return Err(anyhow::anyhow!("Bad fragment numbers in {}", line)),
+        };
+
+        let payload = if count == 1 {
+            fields[5].to_string()
+        } else {
+            let key = (fields[3].to_string(), fields[4].to_string());
+            if index == 1 {
+                if self.pending.len() >= MAX_PENDING {
+                    self.pending.clear();
+                }
+                self.pending.insert(key.clone(), Vec::with_capacity(count));
+            }
+            // A part out of order loses the whole message
+            match self.pending.get_mut(&key) {
+                Some(parts) if parts.len() + 1 == index => parts.push(fields[5].to_string()),
+                _ => {
+                    self.pending.remove(&key);
+                    return Ok(None);
+                }
+            }
+            if index < count {
+                return Ok(None);
+            }
+            self.pending.remove(&key).unwrap_or_default().concat()
+        };
+        let payload = Payload::unarmor(&payload).ok_or_else(|| anyhow::anyhow!("Bad payload in {}", line))?;
+        let Some(decoded) = decode_payload(&payload) else {
+            return Ok(None);
+        };
+
+        let timestamp = tags.and_then(received_at).unwrap_or(now);
+        let known = self.known.entry(decoded.mmsi).or_insert_with(|| (intern(""), 0.0, 0.0));
+        if let Some(name) = decoded.name {
+            known.0 = name;
+        }
+        if let Some((lat, lng)) = decoded.position {
+            (known.1, known.2) = (lat, lng);
+        }
+        let time_utc = Utc.timestamp_opt(timestamp as i64, 0).single().map(|time| time.format("%Y-%m-%d %H:%M:%S%.9f +0000 UTC").to_string());
+        let message = AisMessage {
+            message_type: decoded.message_type.to_string(),
+            metadata: Metadata {
+                mmsi: decoded.mmsi,
+                ship_name: known.0.clone(),
+                latitude: known.1,
+                longitude: known.2,
+                time_utc: time_utc.unwrap_or_default(),
+            },
+            message: decoded.message,
+        };
+        Ok(Some((timestamp, message)))
+    }
+}
+
+// The `c:` of a tag block, in seconds or, from some receivers, milliseconds
+fn received_at(tags: &str) -> Option<u64> {
+    let tags = tags.split_once('*').map_or(tags, |(tags, _)| tags);
+    let time = tags.split(',').find_map(|tag| tag.strip_prefix("c:"))?.parse::<u64>().ok()?;
+    Some(if time > 100_000_000_000 { time / 1000 } else { time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read `width` bits at `offset` from an armored payload
+    fn field(payload: &str, offset: usize, width: usize) -> u64 {
+        let bits: Vec<u64> = payload
+            .bytes()
+            .flat_map(|c| {
+                let value = if c - 48 > 40 { c - 56 } else { c - 48 };
+                (0..6).rev().map(move |i| (value >> i & 1) as u64)
+            })
+            .collect();
+        bits[offset..offset + width].iter().fold(0, |value, bit| value << 1 | bit)
+    }
+
+    fn signed(value: u64, width: usize) -> i64 {
+        (value << (64 - width)) as i64 >> (64 - width)
+    }
+
+    #[test]
+    fn test_position_report() {
+        // A real report, to check the field layout against
+        let real = "177KQJ5000G?tO`K>RA1wUbN0TKH";
+        assert_eq!(field(real, 8, 30), 477553000);
+        assert_eq!(checksum("AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0"), 0x5C);
+
+        let mut ship = Ship::new(477553000, "");
+        (ship.lat, ship.lng, ship.speed, ship.cog, ship.heading, ship.nav_status) = (47.582833, -122.345832, 10.3, 51.0, 181, 5);
+        let (payload, fill) = position_report(&ship);
+        assert_eq!((payload.len(), fill), (28, 0));
+        assert_eq!(field(&payload, 0, 6), 1);
+        assert_eq!(field(&payload, 8, 30), 477553000);
+        assert_eq!(field(&payload, 38, 4), 5);
+        assert_eq!(field(&payload, 50, 10), 103);
+        // Within rounding of the real one
+        assert!((signed(field(&payload, 61, 28), 28) - signed(field(real, 61, 28), 28)).abs() <= 1);
+        assert!((signed(field(&payload, 89, 27), 27) - signed(field(real, 89, 27), 27)).abs() <= 1);
+        assert_eq!(field(&payload, 116, 12), 510);
+        assert_eq!(field(&payload, 128, 9), 181);
+    }
+
+    #[test]
+    fn test_static_data_is_split() {
+        let mut ship = Ship::new(244660000, "Alida");
+        ship.destination = Arc::from("NLRTM");
+        ship.dimensions = Dimensions::from_ais(Dimension { a: 150, b: 30, c: 12, d: 80 });
+        let mut encoder = Encoder::new();
+        let sent = encoder.encode(&ship, false);
+        assert_eq!(sent.len(), 2); // No position yet, static data in two parts
+        assert!(sent[0].starts_with("!AIVDM,2,1,0,A,") && sent[1].starts_with("!AIVDM,2,2,0,A,"));
+        assert!(sent.iter().all(|sentence| sentence.len() <= 82));
+        assert!(sent[1].contains(",2*"));
+
+        let payload: String = sent.iter().map(|sentence| sentence.split(',').nth(5).unwrap()).collect();
+        let text = |offset: usize, chars: usize| -> String {
+            (0..chars)
+                .map(|i| field(&payload, offset + 6 * i, 6) as u8)
+                .map(|c| if c < 32 { (c + 64) as char } else { c as char })
+                .collect()
+        };
+        assert_eq!(text(112, 20), "ALIDA@@@@@@@@@@@@@@@");
+        assert_eq!(text(302, 20), "NLRTM@@@@@@@@@@@@@@@");
+        assert_eq!([field(&payload, 240, 9), field(&payload, 249, 9), field(&payload, 258, 6), field(&payload, 264, 6)], [150, 30, 12, 63]);
+
+        // Unchanged static data isn't sent again until it is due
+        (ship.lat, ship.lng) = (51.9, 4.1);
+        assert_eq!(encoder.encode(&ship, false).len(), 1);
+        ship.last_update = STATIC_INTERVAL_SECS;
+        assert_eq!(encoder.encode(&ship, false).len(), 3);
+    }
+
+    #[test]
+    fn test_decoder() {
+        let mut decoder = Decoder::new();
+        let (timestamp, message) = decoder.decode("!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C", 1_700_000_000).unwrap().unwrap();
+        assert_eq!((timestamp, message.message_type.as_str(), message.metadata.mmsi), (1_700_000_000, "PositionReport", 477553000));
+        assert!((message.metadata.latitude - 47.582833).abs() < 1e-5 && (message.metadata.longitude + 122.345832).abs() < 1e-5);
+        let report = message.message.position_report.unwrap();
+        assert_eq!((report.navigational_status, report.true_heading, report.cog), (5, 181, 51.0));
+        assert!(decoder.decode("!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5D", 0).is_err());
+        assert!(decoder.decode("$GPGLL,4916.45,N,12311.12,W,225444,A*31", 0).unwrap().is_none());
+
+        // What the encoder sends comes back, with the time from a tag block
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.speed, ship.cog, ship.heading) = (51.9, 4.1, 12.3, 271.5, 270);
+        (ship.ship_type, ship.imo_number, ship.call_sign, ship.destination) = (70, 9_123_456, intern("PDAB"), intern("NLRTM"));
+        ship.dimensions = Dimensions::from_ais(Dimension { a: 150, b: 30, c: 12, d: 10 });
+        ship.draught = Some(7.5);
+        let mut decoded = Ship::new(244660000, "");
+        for sentence in Encoder::new().encode(&ship, true) {
+            if let Some((timestamp, message)) = decoder.decode(&format!("\\s:station,c:1700000060000*5A\\{}", sentence), 0).unwrap() {
+                decoded.apply_message(message, timestamp);
+            }
+        }
+        assert_eq!((decoded.name.as_ref(), decoded.destination.as_ref(), decoded.call_sign.as_ref()), ("ALIDA", "NLRTM", "PDAB"));
+        assert_eq!((decoded.ship_type, decoded.imo_number, decoded.heading, decoded.last_update), (70, 9_123_456, 270, 1_700_000_060));
+        assert_eq!((decoded.speed, decoded.cog, decoded.dimensions, decoded.draught), (12.3, 271.5, ship.dimensions, Some(7.5)));
+        assert!((decoded.lat - 51.9).abs() < 1e-5 && (decoded.lng - 4.1).abs() < 1e-5);
+
+        // A class B position, then both parts of its static data
+        let mut position = Bits::default();
+        position.push(18, 6);
+        position.push(0, 2);
+        position.push(235012345, 30);
+        position.push(0, 8);
+        position.push(62, 10);
+        position.push(0, 1);
+        position.push_signed((-1.3f64 * 600_000.0).round() as i64, 28);
+        position.push_signed((50.8f64 * 600_000.0).round() as i64, 27);
+        position.push(1800, 12);
+        position.push(511, 9);
+        let mut part_a = Bits::default();
+        part_a.push(24, 6);
+        part_a.push(0, 2);
+        part_a.push(235012345, 30);
+        part_a.push(0, 2);
+        part_a.push_text("SEA BREEZE", 20);
+        let mut part_b = Bits::default();
+        part_b.push(24, 6);
+        part_b.push(0, 2);
+        part_b.push(235012345, 30);
+        part_b.push(1, 2);
+        part_b.push(37, 8);
+        part_b.push(0, 42); // Vendor id
+        part_b.push_text("MABC1", 7);
+        for dimension in [8, 4] {
+            part_b.push(dimension, 9);
+        }
+        for dimension in [2, 2] {
+            part_b.push(dimension, 6);
+        }
+        let mut small = Ship::new(235012345, "");
+        for bits in [position, part_a, part_b] {
+            let (payload, fill) = bits.armor();
+            let sentence = &sentences(&payload, fill, 0)[0];
+            let (timestamp, message) = decoder.decode(sentence, 1_700_000_000).unwrap().unwrap();
+            small.apply_message(message, timestamp);
+        }
+        assert_eq!((small.class, small.speed, small.cog, small.heading), (Some(crate::ais::AisClass::B), 6.2, 180.0, 511));
+        assert_eq!((small.name.as_ref(), small.call_sign.as_ref(), small.ship_type), ("SEA BREEZE", "MABC1", 37));
+        assert_eq!(small.dimensions.map(|dimensions| dimensions.length), Some(12));
+        assert!((small.lat - 50.8).abs() < 1e-5 && (small.lng + 1.3).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,359 @@
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
+use tokio_tungstenite::{connect_async, tungstenite};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::ingest;
+use crate::monitor::Monitor;
+use crate::ship::{Ship, ShipCache};
+use crate::shutdown;
+
+// Bumped whenever a change would confuse an older peer
+pub const PROTOCOL_VERSION: u32 = 1;
+// States applied at once when a peer sends faster than we apply them
+const MAX_BATCH: usize = 500;
+const INITIAL_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+// One JSON text frame each. The connecting side opens with `hello`. When it
+// pushes, it then sends every ship it knows (the backfill) followed by each
+// update as it is applied; when it follows, the other side answers with its
+// own `hello` and does the same.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeerMessage {
+    Hello { version: u32, name: String, token: Option<String> },
+    Ship { ship: Ship },
+}
+
+fn check_hello(message: &str, token: &str) -> Result<String> {
+    match serde_json::from_str::<PeerMessage>(message)? {
+        PeerMessage::Hello { version, .. } if version != PROTOCOL_VERSION => {
+            Err(anyhow::anyhow!("protocol version {} is not {}", version, PROTOCOL_VERSION))
+        }
+        PeerMessage::Hello { token: Some(given), name, .. } if given == token => Ok(name),
+        PeerMessage::Hello { .. } => Err(anyhow::anyhow!("wrong token")),
+        _ => Err(anyhow::anyhow!("expected hello")),
+    }
+}
+
+fn ship_state(message: &str) -> Option<Ship> {
+    match serde_json::from_str::<PeerMessage>(message) {
+        Ok(PeerMessage::Ship { ship }) => Some(ship),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("Ignoring peer message: {}", e);
+            None
+        }
+    }
+}
+
+// The receiving end: a peer pushing its ships to us over /api/peer
+pub async fn receive_session(mut socket: WebSocket, token: String, ships: Arc<ShipCache>, monitor: Arc<Monitor>) {
+    let peer = match socket.recv().await {
+        Some(Ok(Message::Text(hello))) => match check_hello(&hello, &token) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("Rejected peer: {}", e);
+                let _ = socket.send(Message::Close(None)).await;
+                return;
+            }
+        },
+        _ => return,
+    };
+    info!("Peer '{}' connected", peer);
+
+    let mut received = 0u64;
+    while let Some(Ok(message)) = socket.recv().await {
+        // Take whatever else has already arrived, to apply as one batch
+        let mut batch = Vec::new();
+        let mut next = Some(message);
+        while let Some(message) = next.take() {
+            match message {
+                Message::Text(text) => batch.extend(ship_state(&text)),
+                Message::Close(_) => break,
+                _ => {}
+            }
+            if batch.len() < MAX_BATCH {
+                next = socket.recv().now_or_never().flatten().and_then(Result::ok);
+            }
+        }
+        received += batch.len() as u64;
+        monitor.ingest.record_states(&format!("peer:{}", peer), batch.len());
+        ingest::apply_states(&ships, batch, &monitor);
+    }
+    info!("Peer '{}' disconnected after sending {} updates", peer, received);
+}
+
+fn ship_json(ship: &Ship) -> String {
+    serde_json::json!({"type": "ship", "ship": ship}).to_string()
+}
+
+fn ship_frame(ship: &Ship) -> tungstenite::Message {
+    tungstenite::Message::Text(ship_json(ship))
+}
+
+// The serving end for followers: answer their hello, then backfill and stream
+pub async fn feed_session(mut socket: WebSocket, token: String, ships: Arc<ShipCache>, monitor: Arc<Monitor>) {
+    let follower = match socket.recv().await {
+        Some(Ok(Message::Text(hello))) => match check_hello(&hello, &token) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("Rejected follower: {}", e);
+                let _ = socket.send(Message::Close(None)).await;
+                return;
+            }
+        },
+        _ => return,
+    };
+    info!("Follower '{}' connected", follower);
+
+    let mut updates = monitor.updates.subscribe();
+    let hello = PeerMessage::Hello { version: PROTOCOL_VERSION, name: "seawatch".to_string(), token: None };
+    let backfill = ships.get_full_ships_in_bbox(-90.0, -180.0, 90.0, 180.0);
+    let sent = async {
+        socket.feed(Message::Text(serde_json::to_string(&hello)?)).await?;
+        for ship in &backfill {
+            socket.feed(Message::Text(ship_json(ship))).await?;
+        }
+        socket.flush().await?;
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(ship) => socket.send(Message::Text(ship_json(&ship))).await?,
+                    // The follower reconnects and is backfilled again
+                    Err(RecvError::Lagged(missed)) => return Err(anyhow::anyhow!("fell behind by {} updates", missed)),
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    };
+    let result: Result<()> = sent.await;
+    match result {
+        Ok(()) => info!("Follower '{}' disconnected", follower),
+        Err(e) => warn!("Dropped follower '{}': {}", follower, e),
+    }
+}
+
+async fn push(
+    url: &Url,
+    hello: &PeerMessage,
+    ships: &ShipCache,
+    updates: &mut broadcast::Receiver<Arc<Ship>>,
+) -> Result<()> {
+    let (mut socket, _) = connect_async(url.clone()).await?;
+    info!("Pushing updates to peer {}", url);
+    socket.send(tungstenite::Message::Text(serde_json::to_string(hello)?)).await?;
+
+    // Updates are already being buffered, so nothing between the backfill and
+    // the live updates is missed; anything sent twice the peer skips
+    let backfill = ships.get_full_ships_in_bbox(-90.0, -180.0, 90.0, 180.0);
+    for ship in &backfill {
+        socket.feed(ship_frame(ship)).await?;
+    }
+    socket.flush().await?;
+    debug!("Sent {} ships to peer as backfill", backfill.len());
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(ship) => socket.send(ship_frame(&ship)).await?,
+                // Catch up the way a new connection would
+                Err(RecvError::Lagged(missed)) => return Err(anyhow::anyhow!("fell behind by {} updates", missed)),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            message = socket.next() => match message {
+                Some(Ok(tungstenite::Message::Close(_))) | None => return Err(anyhow::anyhow!("peer closed the connection")),
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn follow(url: &Url, hello: &PeerMessage, ships: &ShipCache, monitor: &Monitor) -> Result<()> {
+    let (mut socket, _) = connect_async(url.clone()).await?;
+    socket.send(tungstenite::Message::Text(serde_json::to_string(hello)?)).await?;
+    match socket.next().await {
+        Some(Ok(tungstenite::Message::Text(reply))) => match serde_json::from_str::<PeerMessage>(&reply)? {
+            PeerMessage::Hello { version, .. } if version == PROTOCOL_VERSION => {}
+            PeerMessage::Hello { version, .. } => {
+                return Err(anyhow::anyhow!("protocol version {} is not {}", version, PROTOCOL_VERSION));
+            }
+            _ => return Err(anyhow::anyhow!("expected hello")),
+        },
+        Some(Ok(tungstenite::Message::Close(_))) | None => return Err(anyhow::anyhow!("refused, check PEER_TOKEN")),
+        Some(Ok(_)) => return Err(anyhow::anyhow!("expected hello")),
+        Some(Err(e)) => return Err(e.into()),
+    }
+    info!("Following {}", url);
+    monitor.ingest.connected("follow");
+
+    while let Some(message) = socket.next().await {
+        let mut batch = Vec::new();
+        let mut next = Some(message?);
+        while let Some(message) = next.take() {
+            match message {
+                tungstenite::Message::Text(text) => batch.extend(ship_state(&text)),
+                tungstenite::Message::Close(_) => break,
+                _ => {}
+            }
+            if batch.len() < MAX_BATCH {
+                next = socket.next().now_or_never().flatten().and_then(Result::ok);
+            }
+        }
+        monitor.ingest.record_states("follow", batch.len());
+        ingest::apply_states(ships, batch, monitor);
+    }
+    Err(anyhow::anyhow!("connection closed"))
+}
+
+// Take another instance's feed as our upstream instead of aisstream.io,
+// reconnecting with backoff
+pub async fn follow_task(
+    url: Url,
+    token: Option<String>,
+    ships: Arc<ShipCache>,
+    monitor: Arc<Monitor>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let hello = PeerMessage::Hello { version: PROTOCOL_VERSION, name: "follower".to_string(), token };
+    let mut retry = INITIAL_RETRY;
+    while !*shutdown.borrow() {
+        let started = std::time::Instant::now();
+        tokio::select! {
+            result = follow(&url, &hello, &ships, &monitor) => {
+                if let Err(e) = result {
+                    warn!("Following {} failed: {}", url, e);
+                    monitor.ingest.disconnected("follow", e.to_string());
+                }
+            }
+            _ = shutdown::requested(shutdown.clone()) => return,
+        }
+        if started.elapsed() > MAX_RETRY {
+            retry = INITIAL_RETRY;
+        }
+        tokio::select! {
+            _ = sleep(retry) => {}
+            _ = shutdown::requested(shutdown.clone()) => return,
+        }
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+// The sending end: stream our updates to another instance, reconnecting with
+// backoff, and backfilling every ship each time
+pub async fn push_task(
+    url: Url,
+    name: String,
+    token: String,
+    ships: Arc<ShipCache>,
+    monitor: Arc<Monitor>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let hello = PeerMessage::Hello { version: PROTOCOL_VERSION, name, token: Some(token) };
+    let mut retry = INITIAL_RETRY;
+    loop {
+        let mut updates = monitor.updates.subscribe();
+        let started = std::time::Instant::now();
+        tokio::select! {
+            result = push(&url, &hello, &ships, &mut updates) => match result {
+                Ok(()) => return,
+                Err(e) => warn!("Peer {} connection lost: {}", url, e),
+            },
+            _ = shutdown::requested(shutdown.clone()) => return,
+        }
+        if started.elapsed() > MAX_RETRY {
+            retry = INITIAL_RETRY;
+        }
+        tokio::select! {
+            _ = sleep(retry) => {}
+            _ = shutdown::requested(shutdown.clone()) => return,
+        }
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Seamon;
+
+    #[test]
+    fn test_handshake() {
+        let hello = |version, token: Option<&str>| {
+            serde_json::to_string(&PeerMessage::Hello { version, name: "edge".into(), token: token.map(String::from) }).unwrap()
+        };
+        assert_eq!(check_hello(&hello(PROTOCOL_VERSION, Some("secret")), "secret").unwrap(), "edge");
+        assert!(check_hello(&hello(PROTOCOL_VERSION, Some("guess")), "secret").is_err());
+        assert!(check_hello(&hello(PROTOCOL_VERSION, None), "secret").is_err());
+        assert!(check_hello(&hello(PROTOCOL_VERSION + 1, Some("secret")), "secret").is_err());
+
+        let frame = match ship_frame(&Ship::new(244660000, "ALIDA")) {
+            tungstenite::Message::Text(text) => text,
+            _ => unreachable!(),
+        };
+        assert!(check_hello(&frame, "secret").is_err());
+        assert_eq!(ship_state(&frame).unwrap().mmsi, 244660000);
+    }
+
+    #[tokio::test]
+    async fn test_follow_feed() {
+        let leader = Seamon::builder().without_upstream().peer_token("secret".to_string()).build().unwrap();
+        let ship = |mmsi, lat, last_update| {
+            let mut ship = Ship::new(mmsi, "ALIDA");
+            (ship.lat, ship.lng, ship.last_update) = (lat, 4.1, last_update);
+            ship
+        };
+        leader.ships().insert_ship(244660000, ship(244660000, 51.9, 1_700_000_000));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}/api/peer/feed", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(axum::serve(listener, leader.router()).into_future());
+
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let task = tokio::spawn(follow_task(url, Some("secret".to_string()), ships.clone(), monitor.clone(), shutdown_rx));
+        let lat = |mmsi| {
+            let ships = ships.clone();
+            async move {
+                for _ in 0..100 {
+                    if let Some(ship) = ships.ships.get(&mmsi) {
+                        return Some(ship.lat);
+                    }
+                    sleep(Duration::from_millis(50)).await;
+                }
+                None
+            }
+        };
+        // Backfilled on connecting, then sent what changes
+        assert_eq!(lat(244660000).await, Some(51.9));
+        ingest::apply_states(leader.ships(), vec![ship(235012345, 50.1, 1_700_000_060)], leader.monitor());
+        assert_eq!(lat(235012345).await, Some(50.1));
+
+        shutdown.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_stops_while_backing_off() {
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        // Nothing listens on the discard port, so it is soon waiting to retry
+        let url = Url::parse("ws://127.0.0.1:9/api/peer").unwrap();
+        let task = tokio::spawn(push_task(url, "edge".to_string(), "secret".to_string(), ships, monitor, shutdown_rx));
+        sleep(Duration::from_millis(100)).await;
+        shutdown.send(true).unwrap();
+        tokio::time::timeout(INITIAL_RETRY / 2, task).await.unwrap().unwrap();
+    }
+}
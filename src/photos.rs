@@ -0,0 +1,178 @@
+use anyhow::Result;
+use axum::body::Bytes;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Enough for a detail-panel picture; anything bigger isn't one
+const MAX_PHOTO_BYTES: usize = 2 * 1024 * 1024;
+const MAX_ENTRIES: usize = 500;
+// Ships without a photo are asked about again after this long
+const MISS_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub const DEFAULT_CACHE_HOURS: u64 = 24;
+pub const DEFAULT_POINTER: &str = "/url";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Photo {
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+struct Entry {
+    imo: u32, // A ship that starts broadcasting its IMO number is looked up again
+    photo: Option<Photo>,
+    fetched: Instant,
+}
+
+// Ship photos from a photo API, fetched by the server so neither the API's
+// key nor its CORS policy reach the browser. The URL template gets {mmsi},
+// {imo} and {key} filled in; without {key}, the key goes in an
+// `Authorization: Bearer` header instead. The API may answer with the image
+// itself, or with JSON holding the image's URL at `pointer` (a JSON
+// pointer, e.g. "/photos/0/url").
+pub struct Photos {
+    template: String,
+    api_key: Option<String>,
+    pointer: String,
+    ttl: Duration,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<u32, Entry>>,
+}
+
+impl Photos {
+    pub fn new(template: &str, api_key: Option<String>, pointer: &str, ttl: Duration) -> Result<Self> {
+        // Catch a bad template at startup rather than on the first lookup
+        Url::parse(&fill(template, 244660000, 9074729, api_key.as_deref()))?;
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            return Err(anyhow::anyhow!("The photo URL pointer must start with /, not {}", pointer));
+        }
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self {
+            template: template.to_string(),
+            api_key,
+            pointer: pointer.to_string(),
+            ttl,
+            client,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // None when the API has no photo; an error when it couldn't be asked
+    pub async fn get(&self, mmsi: u32, imo: u32) -> Result<Option<Photo>> {
+        if let Some(entry) = self.cache.lock().unwrap().get(&mmsi) {
+            let ttl = if entry.photo.is_some() { self.ttl } else { MISS_TTL };
+            if entry.imo == imo && entry.fetched.elapsed() < ttl {
+                return Ok(entry.photo.clone());
+            }
+        }
+        // Templates that need the IMO number have nothing to go on without one
+        let photo = if imo == 0 && self.template.contains("{imo}") { None } else { self.fetch(mmsi, imo).await? };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_ENTRIES && !cache.contains_key(&mmsi) {
+            let oldest = cache.iter().min_by_key(|(_, entry)| entry.fetched).map(|(mmsi, _)| *mmsi);
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(mmsi, Entry { imo, photo: photo.clone(), fetched: Instant::now() });
+        Ok(photo)
+    }
+
+    async fn fetch(&self, mmsi: u32, imo: u32) -> Result<Option<Photo>> {
+        let url = fill(&self.template, mmsi, imo, self.api_key.as_deref());
+        let mut request = self.client.get(&url);
+        if let Some(key) = &self.api_key
+            && !self.template.contains("{key}")
+        {
+            request = request.bearer_auth(key);
+        }
+        let Some((content_type, body)) = fetch(request).await? else {
+            return Ok(None);
+        };
+        if content_type.starts_with("image/") {
+            return Ok(Some(Photo { content_type, bytes: body }));
+        }
+
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        let Some(image_url) = json.pointer(&self.pointer).and_then(|url| url.as_str()) else {
+            debug!(mmsi, "No photo URL at {} in the photo API's answer", self.pointer);
+            return Ok(None);
+        };
+        match fetch(self.client.get(image_url)).await? {
+            Some((content_type, bytes)) if content_type.starts_with("image/") => Ok(Some(Photo { content_type, bytes })),
+            Some((content_type, _)) => Err(anyhow::anyhow!("{} is {}, not an image", image_url, content_type)),
+            None => Ok(None),
+        }
+    }
+}
+
+// The content type and body; None on 404
+async fn fetch(request: reqwest::RequestBuilder) -> Result<Option<(String, Bytes)>> {
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    if response.content_length().is_some_and(|length| length as usize > MAX_PHOTO_BYTES) {
+        return Err(anyhow::anyhow!("Photo larger than {} bytes", MAX_PHOTO_BYTES));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let body = response.bytes().await?;
+    if body.len() > MAX_PHOTO_BYTES {
+        return Err(anyhow::anyhow!("Photo larger than {} bytes", MAX_PHOTO_BYTES));
+    }
+    Ok(Some((content_type, body)))
+}
+
+fn fill(template: &str, mmsi: u32, imo: u32, api_key: Option<&str>) -> String {
+    template
+        .replace("{mmsi}", &mmsi.to_string())
+        .replace("{imo}", &imo.to_string())
+        .replace("{key}", api_key.unwrap_or(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::Router;
+
+    #[tokio::test]
+    async fn test_photo_lookup() {
+        // A photo API answering with the image's URL, and the image
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let image_url = format!("{}/image.jpg", base);
+        let app = Router::new()
+            .route("/ships/:imo", get(move |axum::extract::Path(imo): axum::extract::Path<u32>| async move {
+                match imo {
+                    9074729 => Ok(axum::Json(serde_json::json!({"photos": [{"url": image_url}]}))),
+                    _ => Err(axum::http::StatusCode::NOT_FOUND),
+                }
+            }))
+            .route("/image.jpg", get(|| async { ([(axum::http::header::CONTENT_TYPE, "image/jpeg")], "JPEG") }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let photos = Photos::new(&format!("{}/ships/{{imo}}", base), Some("secret".into()), "/photos/0/url", Duration::from_secs(60)).unwrap();
+        assert!(photos.get(244660000, 0).await.unwrap().is_none());
+        assert!(photos.get(244660000, 9000000).await.unwrap().is_none());
+        let jpeg = Photo { content_type: "image/jpeg".to_string(), bytes: Bytes::from_static(b"JPEG") };
+        assert_eq!(photos.get(244660000, 9074729).await.unwrap(), Some(jpeg.clone()));
+        assert_eq!(photos.cache.lock().unwrap().len(), 1);
+
+        // Or the image itself
+        let direct = Photos::new(&format!("{}/image.jpg?mmsi={{mmsi}}", base), None, "", Duration::from_secs(60)).unwrap();
+        assert_eq!(direct.get(244660000, 0).await.unwrap(), Some(jpeg));
+        assert!(Photos::new("not a url {mmsi}", None, "", Duration::from_secs(60)).is_err());
+    }
+}
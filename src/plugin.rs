@@ -0,0 +1,308 @@
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::ais::AisMessage;
+use crate::ingest;
+use crate::monitor::Monitor;
+use crate::receiver::NmeaSource;
+use crate::replay::{self, Replay};
+use crate::ship::{Ship, ShipCache};
+use crate::shutdown;
+use crate::sinks::{Feed, Update};
+
+// Bumped whenever these traits or the registry change; dynamic plugins built
+// against another version are refused
+#[cfg(feature = "dynamic-plugins")]
+pub const PLUGIN_API_VERSION: u32 = 1;
+// States a source hands over per call at most, to keep index lock holds short
+pub(crate) const MAX_BATCH: usize = 500;
+
+// What a source can do with the ships it produces
+pub struct SourceContext {
+    name: String,
+    ships: Arc<ShipCache>,
+    monitor: Arc<Monitor>,
+    shutdown: watch::Receiver<bool>,
+    replay: Option<Arc<Replay>>,
+}
+
+impl SourceContext {
+    // `name` is what /api/stats/ingest counts the source's states under
+    pub fn new(name: &str, ships: Arc<ShipCache>, monitor: Arc<Monitor>, shutdown: watch::Receiver<bool>) -> Self {
+        Self { name: name.to_string(), ships, monitor, shutdown, replay: None }
+    }
+
+    // In replay mode, sources reading a recording play it against this clock
+    pub fn with_replay(mut self, replay: Arc<Replay>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn replay(&self) -> Option<&Arc<Replay>> {
+        self.replay.as_ref()
+    }
+
+    // Apply whole ship states, exactly as a peer's are; a state no newer (by
+    // `last_update`) than the one we have is skipped
+    pub fn apply(&self, states: Vec<Ship>) {
+        self.monitor.ingest.record_states(&self.name, states.len());
+        ingest::apply_states(&self.ships, states, &self.monitor);
+    }
+
+    // Apply AIS messages received at the given times, as ones from
+    // aisstream.io are
+    pub fn apply_messages(&self, batch: Vec<(u64, AisMessage)>) {
+        for (_, message) in &batch {
+            self.monitor.ingest.record_message(&self.name, &message.message_type);
+        }
+        ingest::apply_batch(&self.ships, batch, &self.monitor);
+    }
+
+    // Something the source received but couldn't make sense of
+    pub fn record_failure(&self) {
+        self.monitor.ingest.record_failure();
+    }
+
+    // For sources with a connection, shown in /api/stats/ingest
+    pub fn connected(&self) {
+        self.monitor.ingest.connected(&self.name);
+    }
+
+    pub fn disconnected(&self, error: String) {
+        self.monitor.ingest.disconnected(&self.name, error);
+    }
+
+    // Resolves once the server is shutting down
+    pub fn shutdown(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        shutdown::requested(self.shutdown.clone())
+    }
+}
+
+// A provider of ship data besides aisstream.io. `run` returns when the
+// source is done or the server shuts down; errors are logged, not retried.
+pub trait AisSource: Send {
+    fn name(&self) -> &str;
+    fn run(self: Box<Self>, context: SourceContext) -> BoxFuture<'static, Result<()>>;
+}
+
+// An output for ship updates and events, fed one update at a time. A sink
+// that falls behind skips updates, see `Feed::next`.
+pub trait AisSink: Send {
+    fn name(&self) -> &str;
+    fn send<'a>(&'a mut self, update: &'a Update) -> BoxFuture<'a, Result<()>>;
+}
+
+// Factories get whatever followed the name in the spec, see `Registry::source`
+pub type SourceFactory = fn(Option<&str>) -> Result<Box<dyn AisSource>>;
+pub type SinkFactory = fn(Option<&str>) -> Result<Box<dyn AisSink>>;
+
+// Sources and sinks by name, built-in or registered by plugins
+#[derive(Default)]
+pub struct Registry {
+    sources: HashMap<String, SourceFactory>,
+    sinks: HashMap<String, SinkFactory>,
+    #[cfg(feature = "dynamic-plugins")]
+    libraries: Vec<libloading::Library>, // Kept loaded for as long as their factories may run
+}
+
+// "name" or "name:argument", e.g. "states:/var/lib/seawatch/replay.ndjson"
+fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.trim().split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (spec.trim(), None),
+    }
+}
+
+// Whether a source reads a recording, which replay mode plays at its own pace
+pub fn replays(spec: &str) -> bool {
+    matches!(split_spec(spec), ("states" | "nmea-file", Some(_)))
+}
+
+impl Registry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry.register_source("states", StatesSource::create);
+        registry.register_source("nmea-udp", NmeaSource::udp);
+        registry.register_source("nmea-tcp", NmeaSource::tcp);
+        registry.register_source("nmea-file", NmeaSource::file);
+        registry.register_sink("stdout", StdoutSink::create);
+        registry
+    }
+
+    pub fn register_source(&mut self, name: &str, factory: SourceFactory) {
+        self.sources.insert(name.to_string(), factory);
+    }
+
+    pub fn register_sink(&mut self, name: &str, factory: SinkFactory) {
+        self.sinks.insert(name.to_string(), factory);
+    }
+
+    pub fn source(&self, spec: &str) -> Result<Box<dyn AisSource>> {
+        let (name, argument) = split_spec(spec);
+        let factory = self.sources.get(name).ok_or_else(|| anyhow::anyhow!("No source named '{}'", name))?;
+        factory(argument)
+    }
+
+    pub fn sink(&self, spec: &str) -> Result<Box<dyn AisSink>> {
+        let (name, argument) = split_spec(spec);
+        let factory = self.sinks.get(name).ok_or_else(|| anyhow::anyhow!("No sink named '{}'", name))?;
+        factory(argument)
+    }
+
+    /// Load a shared library that exports `SEAWATCH_PLUGIN_API: u32` and
+    /// `seawatch_register(&mut Registry)`. Rust has no stable ABI, so it must
+    /// be built with the same compiler and seawatch version as this binary.
+    ///
+    /// # Safety
+    ///
+    /// The library's initializers and `seawatch_register` run in this
+    /// process, and must match the signatures above.
+    #[cfg(feature = "dynamic-plugins")]
+    pub unsafe fn load(&mut self, path: &str) -> Result<()> {
+        let library = unsafe { libloading::Library::new(path)? };
+        let version = unsafe { **library.get::<*const u32>(b"SEAWATCH_PLUGIN_API")? };
+        if version != PLUGIN_API_VERSION {
+            return Err(anyhow::anyhow!("{} was built for plugin API {}, not {}", path, version, PLUGIN_API_VERSION));
+        }
+        let register = unsafe { library.get::<fn(&mut Registry)>(b"seawatch_register")? };
+        register(self);
+        self.libraries.push(library);
+        info!("Loaded plugin {}", path);
+        Ok(())
+    }
+}
+
+pub async fn source_task(source: Box<dyn AisSource>, context: SourceContext) {
+    let name = source.name().to_string();
+    info!("Starting source '{}'", name);
+    match source.run(context).await {
+        Ok(()) => info!("Source '{}' finished", name),
+        Err(e) => warn!("Source '{}' failed: {}", name, e),
+    }
+}
+
+pub async fn sink_task(mut feed: Feed, mut sink: Box<dyn AisSink>) {
+    while let Some(update) = feed.next().await {
+        if let Err(e) = sink.send(&update).await {
+            warn!("Sink '{}' failed: {}", sink.name(), e);
+        }
+    }
+}
+
+// Ship states as newline-delimited JSON from a file, or stdin for "-", such
+// as a normalized firehose capture
+pub struct StatesSource {
+    path: String,
+}
+
+impl StatesSource {
+    fn create(argument: Option<&str>) -> Result<Box<dyn AisSource>> {
+        let path = argument.ok_or_else(|| anyhow::anyhow!("The states source needs a path, e.g. states:ships.ndjson"))?;
+        Ok(Box::new(Self { path: path.to_string() }))
+    }
+}
+
+impl AisSource for StatesSource {
+    fn name(&self) -> &str {
+        "states"
+    }
+
+    fn run(self: Box<Self>, context: SourceContext) -> BoxFuture<'static, Result<()>> {
+        async move {
+            let reader: Box<dyn tokio::io::AsyncRead + Send + Unpin> = match self.path.as_str() {
+                "-" => Box::new(tokio::io::stdin()),
+                path => Box::new(tokio::fs::File::open(path).await?),
+            };
+            let mut lines = BufReader::new(reader).lines();
+            if let Some(replay) = context.replay().cloned() {
+                // By when each state was last updated
+                let mut states = Vec::new();
+                while let Some(line) = lines.next_line().await? {
+                    if !line.trim().is_empty() {
+                        let ship = serde_json::from_str::<Ship>(&line)?;
+                        states.push((ship.last_update, ship));
+                    }
+                }
+                let apply = |context: &SourceContext, batch: Vec<(u64, Ship)>| context.apply(batch.into_iter().map(|(_, ship)| ship).collect());
+                return replay::play(&context, &replay, states, apply).await;
+            }
+            let mut batch = Vec::new();
+            loop {
+                let line = tokio::select! {
+                    line = lines.next_line() => line?,
+                    _ = context.shutdown() => None,
+                };
+                match line {
+                    Some(line) if line.trim().is_empty() => continue,
+                    Some(line) => batch.push(serde_json::from_str::<Ship>(&line)?),
+                    None => break,
+                }
+                if batch.len() >= MAX_BATCH {
+                    context.apply(std::mem::take(&mut batch));
+                }
+            }
+            context.apply(batch);
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+// Every update as a line of JSON on stdout
+pub struct StdoutSink {
+    stdout: tokio::io::Stdout,
+}
+
+impl StdoutSink {
+    fn create(_: Option<&str>) -> Result<Box<dyn AisSink>> {
+        Ok(Box::new(Self { stdout: tokio::io::stdout() }))
+    }
+}
+
+impl AisSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    fn send<'a>(&'a mut self, update: &'a Update) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let mut line = update.to_json()?;
+            line.push(b'\n');
+            self.stdout.write_all(&line).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_states_source() {
+        let path = std::env::temp_dir().join(format!("seawatch-states-{}.ndjson", std::process::id()));
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.last_update) = (51.9, 4.1, 1_700_000_000);
+        std::fs::write(&path, format!("{}\n\n", serde_json::to_string(&ship).unwrap())).unwrap();
+
+        let registry = Registry::with_builtins();
+        assert!(registry.source("nope").is_err());
+        assert!(registry.source("states").is_err());
+        let source = registry.source(&format!("states:{}", path.display())).unwrap();
+
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let name = source.name().to_string();
+        source.run(SourceContext::new(&name, ships.clone(), monitor.clone(), shutdown_rx)).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(ships.len(), 1);
+        assert_eq!(monitor.ingest.report().windows[0].by_source[&name], 1);
+    }
+}
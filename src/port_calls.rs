@@ -0,0 +1,130 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::events::{EventKind, EventLog};
+use crate::ports::Ports;
+use crate::ship::Ship;
+
+// A ship has arrived once it is inside a port area at or below this speed
+const ARRIVAL_SPEED: f64 = 1.0;
+// Port calls kept per ship, and how long a ship's history outlives its last call
+const MAX_CALLS_PER_SHIP: usize = 50;
+const RETENTION_SECS: u64 = 7 * 86_400;
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct PortCall {
+    pub port: Arc<str>,
+    pub locode: Arc<str>,
+    pub arrived: u64,
+    pub departed: Option<u64>, // None while the ship is still in port
+}
+
+// Arrivals and departures per ship, newest call last
+pub struct PortCalls {
+    ports: Arc<Ports>,
+    calls: Mutex<HashMap<u32, VecDeque<PortCall>>>,
+}
+
+impl PortCalls {
+    pub fn new(ports: Arc<Ports>) -> Self {
+        Self { ports, calls: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn ports(&self) -> &Ports {
+        &self.ports
+    }
+
+    pub fn calls(&self, mmsi: u32) -> Vec<PortCall> {
+        self.calls.lock().unwrap().get(&mmsi).map_or_else(Vec::new, |calls| calls.iter().cloned().collect())
+    }
+
+    // Returns the new call when the ship has just arrived
+    pub fn observe(&self, events: &EventLog, ship: &Ship) -> Option<PortCall> {
+        let port = self.ports.containing(ship.lat, ship.lng);
+        let mut calls = self.calls.lock().unwrap();
+        let in_port = calls
+            .get_mut(&ship.mmsi)
+            .and_then(|calls| calls.back_mut())
+            .filter(|call| call.departed.is_none());
+
+        match (in_port, port) {
+            // Still in the port it arrived at
+            (Some(call), Some(port)) if call.locode == port.locode => {}
+            (Some(call), _) => {
+                call.departed = Some(ship.last_update);
+                let duration_secs = ship.last_update.saturating_sub(call.arrived);
+                events.push(
+                    ship.last_update,
+                    ship.mmsi,
+                    EventKind::PortDeparture { port: call.port.clone(), locode: call.locode.clone(), duration_secs },
+                );
+            }
+            (None, Some(port)) if ship.speed <= ARRIVAL_SPEED => {
+                let history = calls.entry(ship.mmsi).or_default();
+                if history.len() >= MAX_CALLS_PER_SHIP {
+                    history.pop_front();
+                }
+                let call = PortCall {
+                    port: port.name.clone(),
+                    locode: port.locode.clone(),
+                    arrived: ship.last_update,
+                    departed: None,
+                };
+                history.push_back(call.clone());
+                events.push(
+                    ship.last_update,
+                    ship.mmsi,
+                    EventKind::PortArrival { port: port.name.clone(), locode: port.locode.clone() },
+                );
+                return Some(call);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // Drop the history of ships that left their last port long ago
+    pub fn purge(&self, now: u64) {
+        let cutoff = now.saturating_sub(RETENTION_SECS);
+        self.calls.lock().unwrap().retain(|_, calls| {
+            calls.back().is_some_and(|call| call.departed.is_none_or(|departed| departed >= cutoff))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_arrival_and_departure() {
+        let (calls, events) = (PortCalls::new(Arc::new(Ports::builtin())), EventLog::new());
+        let mut ship = Ship::new(1, "PRIDE OF KENT");
+        // Sails through Dover without stopping, then berths, then leaves
+        for (t, lat, speed) in [(0, 51.0, 15.0), (10, 51.12, 12.0), (20, 51.121, 0.2), (30, 51.122, 0.0), (40, 51.12, 8.0), (50, 51.0, 18.0)] {
+            (ship.lat, ship.lng, ship.speed, ship.last_update) = (lat, 1.33, speed, t);
+            calls.observe(&events, &ship);
+        }
+
+        let dover: Arc<str> = Arc::from("Dover");
+        let locode: Arc<str> = Arc::from("GBDVR");
+        assert_eq!(
+            calls.calls(1),
+            vec![PortCall { port: dover.clone(), locode: locode.clone(), arrived: 20, departed: Some(50) }]
+        );
+        let logged: Vec<EventKind> = events.query(&EventFilter::default()).iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            logged,
+            vec![
+                EventKind::PortArrival { port: dover.clone(), locode: locode.clone() },
+                EventKind::PortDeparture { port: dover, locode, duration_secs: 30 },
+            ]
+        );
+
+        calls.purge(50 + RETENTION_SECS + 1);
+        assert!(calls.calls(1).is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geo::{bearing_deg, haversine_m, EARTH_RADIUS_M};
+
+// Approximate areas of major container and ferry ports; monitor.ports_file replaces it
+const BUILTIN_PORTS: &str = include_str!("../data/ports.json");
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Port {
+    pub name: Arc<str>,
+    pub locode: Arc<str>,
+    pub lat: f64,
+    pub lng: f64,
+    pub radius_m: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct NearestPort {
+    pub name: Arc<str>,
+    pub locode: Arc<str>,
+    pub distance_m: f64,
+    pub bearing: f64, // Degrees from the ship to the port
+}
+
+impl fmt::Display for NearestPort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}), {:.1} nm bearing {:03.0}", self.name, self.locode, self.distance_m / 1852.0, self.bearing)
+    }
+}
+
+// Ports, bucketed into one degree cells by the area they cover
+pub struct Ports {
+    ports: Vec<Port>,
+    grid: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn grid_cell(lat: f64, lng: f64) -> (i32, i32) {
+    (lat.floor() as i32, lng.floor() as i32)
+}
+
+impl Ports {
+    pub fn new(ports: Vec<Port>) -> Self {
+        let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (i, port) in ports.iter().enumerate() {
+            let d_lat = (port.radius_m / EARTH_RADIUS_M).to_degrees();
+            let d_lng = d_lat / port.lat.to_radians().cos().max(1e-6);
+            let (min_lat, min_lng) = grid_cell(port.lat - d_lat, port.lng - d_lng);
+            let (max_lat, max_lng) = grid_cell(port.lat + d_lat, port.lng + d_lng);
+            for lat in min_lat..=max_lat {
+                for lng in min_lng..=max_lng {
+                    grid.entry((lat, lng)).or_default().push(i);
+                }
+            }
+        }
+        Self { ports, grid }
+    }
+
+    pub fn builtin() -> Self {
+        Self::new(serde_json::from_str(BUILTIN_PORTS).expect("built-in port list is valid"))
+    }
+
+    // A JSON array of ports, in the format of data/ports.json
+    pub fn from_file(path: &str) -> Result<Self> {
+        let ports: Vec<Port> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if let Some(port) = ports.iter().find(|port| port.radius_m.is_nan() || port.radius_m <= 0.0) {
+            return Err(anyhow::anyhow!("Port {} needs a positive radius_m", port.name));
+        }
+        Ok(Self::new(ports))
+    }
+
+    pub fn all(&self) -> &[Port] {
+        &self.ports
+    }
+
+    // The closest port to a position, however far
+    pub fn nearest(&self, lat: f64, lng: f64) -> Option<NearestPort> {
+        self.ports
+            .iter()
+            .map(|port| (port, haversine_m(lat, lng, port.lat, port.lng)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(port, distance_m)| NearestPort {
+                name: port.name.clone(),
+                locode: port.locode.clone(),
+                distance_m,
+                bearing: bearing_deg(lat, lng, port.lat, port.lng),
+            })
+    }
+
+    // The port whose area contains the position, the nearest if areas overlap
+    pub fn containing(&self, lat: f64, lng: f64) -> Option<&Port> {
+        self.grid
+            .get(&grid_cell(lat, lng))?
+            .iter()
+            .map(|&i| (&self.ports[i], haversine_m(lat, lng, self.ports[i].lat, self.ports[i].lng)))
+            .filter(|(port, distance)| *distance <= port.radius_m)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(port, _)| port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_containing() {
+        let ports = Ports::builtin();
+        assert!(ports.all().len() > 40);
+        assert_eq!(ports.containing(51.12, 1.34).map(|port| &*port.locode), Some("GBDVR"));
+        // Between Los Angeles and Long Beach, nearer Long Beach
+        assert_eq!(ports.containing(33.745, -118.22).map(|port| &*port.locode), Some("USLGB"));
+        assert!(ports.containing(50.0, -20.0).is_none());
+    }
+
+    #[test]
+    fn test_nearest() {
+        let ports = Ports::builtin();
+        // Mid-Channel, west of Dover
+        let nearest = ports.nearest(51.0, 1.0).unwrap();
+        assert_eq!(&*nearest.locode, "GBDVR");
+        assert!((nearest.bearing - 60.0).abs() < 5.0, "{}", nearest.bearing);
+        assert!(nearest.to_string().starts_with("Dover (GBDVR), "), "{}", nearest);
+        assert!(Ports::new(vec![]).nearest(0.0, 0.0).is_none());
+    }
+}
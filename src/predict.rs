@@ -0,0 +1,225 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::geo::EARTH_RADIUS_M;
+use crate::ship::Ship;
+
+// Recent fixes kept per ship to estimate its rate of turn
+const TRACK_WINDOW_SECS: u64 = 15 * 60;
+const MAX_FIXES: usize = 20;
+// Turns are only estimated over at least this much track...
+const MIN_TURN_SAMPLE_SECS: u64 = 60;
+// ...capped at a hard-over rate, and only kept up until the ship has turned
+// this far: few vessels steam in circles for an hour
+const MAX_TURN_RATE: f64 = 3.0; // Degrees per second
+const MAX_TOTAL_TURN: f64 = 180.0; // Degrees
+// Slower than this, a ship is predicted to stay where it is
+const MIN_SPEED: f64 = 0.5;
+const KNOTS_TO_MS: f64 = 1852.0 / 3600.0;
+
+#[derive(Clone, Copy, Debug)]
+struct Fix {
+    t: u64,
+    cog: f64,
+}
+
+// Where a ship is heading from its last fix: constant speed, and a constant
+// rate of turn until MAX_TOTAL_TURN, then straight on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Motion {
+    pub t0: u64,
+    pub lat: f64,
+    pub lng: f64,
+    pub speed_ms: f64,
+    pub heading: f64,   // Degrees clockwise from north
+    pub turn_rate: f64, // Degrees per second, positive to starboard
+}
+
+impl Motion {
+    // Straight ahead at the ship's current course and speed
+    pub fn of(ship: &Ship) -> Self {
+        let moving = ship.speed >= MIN_SPEED && ship.cog < 360.0;
+        Self {
+            t0: ship.last_update,
+            lat: ship.lat,
+            lng: ship.lng,
+            speed_ms: if moving { ship.speed * KNOTS_TO_MS } else { 0.0 },
+            heading: if moving { ship.cog } else { 0.0 },
+            turn_rate: 0.0,
+        }
+    }
+
+    pub fn is_turning(&self) -> bool {
+        self.speed_ms > 0.0 && self.turn_rate.abs() > 1e-3
+    }
+
+    pub fn position_at(&self, t: u64) -> (f64, f64) {
+        let dt = t.saturating_sub(self.t0) as f64;
+        if self.speed_ms == 0.0 || dt == 0.0 {
+            return (self.lat, self.lng);
+        }
+
+        let turn_secs = if self.turn_rate.abs() > 1e-6 { dt.min(MAX_TOTAL_TURN / self.turn_rate.abs()) } else { 0.0 };
+        let (mut east, mut north) = (0.0, 0.0);
+        let h0 = self.heading.to_radians();
+        let mut heading = h0;
+        if turn_secs > 0.0 {
+            // Along the arc
+            let w = self.turn_rate.to_radians();
+            heading = h0 + w * turn_secs;
+            east += self.speed_ms / w * (h0.cos() - heading.cos());
+            north += self.speed_ms / w * (heading.sin() - h0.sin());
+        }
+        // Then straight on
+        let straight = (dt - turn_secs) * self.speed_ms;
+        east += straight * heading.sin();
+        north += straight * heading.cos();
+
+        let metres_per_degree = EARTH_RADIUS_M.to_radians();
+        let lat = self.lat + north / metres_per_degree;
+        let lng = self.lng + east / (metres_per_degree * self.lat.to_radians().cos().max(1e-6));
+        (lat.clamp(-90.0, 90.0), (lng + 540.0).rem_euclid(360.0) - 180.0)
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct PredictedPoint {
+    pub timestamp: u64,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Prediction {
+    pub mmsi: u32,
+    pub from: u64, // Time of the last report the prediction starts from
+    pub turn_rate: f64,
+    pub points: Vec<PredictedPoint>,
+}
+
+// Recent courses per ship
+#[derive(Default)]
+pub struct Tracks {
+    fixes: Mutex<HashMap<u32, VecDeque<Fix>>>,
+}
+
+// Signed difference between two courses, in (-180, 180]
+pub fn course_change(from: f64, to: f64) -> f64 {
+    let d = (to - from).rem_euclid(360.0);
+    if d > 180.0 { d - 360.0 } else { d }
+}
+
+impl Tracks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, ship: &Ship) {
+        if ship.cog >= 360.0 || ship.speed < MIN_SPEED {
+            self.fixes.lock().unwrap().remove(&ship.mmsi);
+            return;
+        }
+        let mut tracks = self.fixes.lock().unwrap();
+        let fixes = tracks.entry(ship.mmsi).or_default();
+        if fixes.back().is_some_and(|last| last.t >= ship.last_update) {
+            return;
+        }
+        fixes.push_back(Fix { t: ship.last_update, cog: ship.cog });
+        while fixes.len() > MAX_FIXES
+            || fixes.front().is_some_and(|first| ship.last_update.saturating_sub(first.t) > TRACK_WINDOW_SECS)
+        {
+            fixes.pop_front();
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.fixes
+            .lock()
+            .unwrap()
+            .retain(|_, fixes| fixes.back().is_some_and(|last| now.saturating_sub(last.t) <= TRACK_WINDOW_SECS));
+    }
+
+    // Average rate of turn over the recent track, degrees per second
+    fn turn_rate(&self, mmsi: u32) -> f64 {
+        let tracks = self.fixes.lock().unwrap();
+        let Some(fixes) = tracks.get(&mmsi) else {
+            return 0.0;
+        };
+        let (Some(first), Some(last)) = (fixes.front(), fixes.back()) else {
+            return 0.0;
+        };
+        let dt = last.t - first.t;
+        if dt < MIN_TURN_SAMPLE_SECS {
+            return 0.0;
+        }
+        // Summed fix to fix so a turn through north isn't mistaken for a full circle
+        let turned: f64 = fixes.iter().zip(fixes.iter().skip(1)).map(|(a, b)| course_change(a.cog, b.cog)).sum();
+        (turned / dt as f64).clamp(-MAX_TURN_RATE, MAX_TURN_RATE)
+    }
+
+    pub fn motion(&self, ship: &Ship) -> Motion {
+        let mut motion = Motion::of(ship);
+        if motion.speed_ms > 0.0 {
+            motion.turn_rate = self.turn_rate(ship.mmsi);
+        }
+        motion
+    }
+
+    // Positions every `step_secs` for `horizon_secs` after the last report
+    pub fn predict(&self, ship: &Ship, horizon_secs: u64, step_secs: u64) -> Prediction {
+        let motion = self.motion(ship);
+        let points = (1..=horizon_secs / step_secs.max(1))
+            .map(|i| {
+                let timestamp = motion.t0 + i * step_secs;
+                let (lat, lng) = motion.position_at(timestamp);
+                PredictedPoint { timestamp, lat, lng }
+            })
+            .collect();
+        Prediction { mmsi: ship.mmsi, from: motion.t0, turn_rate: motion.turn_rate, points }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::haversine_m;
+
+    #[test]
+    fn test_straight_and_turning_predictions() {
+        let tracks = Tracks::new();
+        let mut ship = Ship::new(1, "");
+        (ship.lat, ship.lng, ship.speed, ship.cog, ship.last_update) = (50.0, -4.0, 10.0, 90.0, 1000);
+        tracks.observe(&ship);
+
+        // Due east at 10 kn: 10 nm in an hour
+        let prediction = tracks.predict(&ship, 3600, 600);
+        assert_eq!(prediction.points.len(), 6);
+        let last = prediction.points.last().unwrap();
+        assert!((haversine_m(50.0, -4.0, last.lat, last.lng) - 18_520.0).abs() < 50.0);
+        assert!((last.lat - 50.0).abs() < 1e-9);
+
+        // Turning to starboard through north at 0.1 deg/s
+        let tracks = Tracks::new();
+        for (t, cog) in [(0, 350.0), (60, 356.0), (120, 2.0)] {
+            (ship.lat, ship.lng, ship.cog, ship.last_update) = (50.0, -4.0, cog, t);
+            tracks.observe(&ship);
+        }
+        let motion = tracks.motion(&ship);
+        assert!((motion.turn_rate - 0.1).abs() < 1e-9, "{}", motion.turn_rate);
+        assert!(motion.is_turning());
+        // After 900s it has turned 90 degrees: a quarter circle of radius v/w
+        let radius = motion.speed_ms / 0.1f64.to_radians();
+        let (lat, lng) = motion.position_at(120 + 900);
+        let chord = 2.0 * radius * (45.0f64).to_radians().sin();
+        assert!((haversine_m(50.0, -4.0, lat, lng) - chord).abs() < 20.0);
+
+        // The turn stops after 180 degrees
+        let far = motion.position_at(120 + 3600);
+        let later = motion.position_at(120 + 3660);
+        let step = haversine_m(far.0, far.1, later.0, later.1);
+        assert!((step - motion.speed_ms * 60.0).abs() < 1.0);
+        assert!(later.0 < far.0, "heading south after turning 180");
+    }
+}
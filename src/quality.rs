@@ -0,0 +1,145 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::ais::AisClass;
+use crate::anomalies::AnomalyKind;
+use crate::ship::Ship;
+
+// Report times kept per ship for judging how often it reports
+const MAX_REPORTS: usize = 10;
+const REPORT_WINDOW_SECS: u64 = 30 * 60;
+// How often a ship should report: class A underway every 10 seconds or
+// less, class B every 30, and either every 3 minutes at anchor or moored
+const UNDERWAY_A_SECS: f64 = 10.0;
+const UNDERWAY_B_SECS: f64 = 30.0;
+const STATIONARY_SECS: f64 = 180.0;
+const STATIONARY_SPEED: f64 = 3.0;
+// What each part counts towards the score
+const FREQUENCY_WEIGHT: f64 = 0.35;
+const STATIC_WEIGHT: f64 = 0.25;
+const ANOMALY_WEIGHT: f64 = 0.25;
+const ACCURACY_WEIGHT: f64 = 0.15;
+
+// How far a ship's data can be trusted, 0 to 1, with the parts that went
+// into it; for /api/ship/:mmsi and the `min_quality` filter
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct DataQuality {
+    pub score: f64,
+    // Expected over actual time between recent reports, counting the
+    // silence since the last one; 0.5 until there are two to go on
+    pub frequency: f64,
+    // 1 for a DGPS-grade fix, 0.6 otherwise
+    pub position_accuracy: f64,
+    // The share of name, type and dimensions it has sent
+    pub static_data: f64,
+    // Less 0.4 for each kind of anomaly listed against it
+    pub anomaly: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub anomalies: Vec<AnomalyKind>,
+}
+
+// Recent report times per ship
+#[derive(Default)]
+pub struct Quality {
+    reports: Mutex<HashMap<u32, VecDeque<u64>>>,
+}
+
+impl Quality {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, ship: &Ship) {
+        let mut reports = self.reports.lock().unwrap();
+        let times = reports.entry(ship.mmsi).or_default();
+        if times.back().is_some_and(|&last| last >= ship.last_update) {
+            return;
+        }
+        times.push_back(ship.last_update);
+        while times.len() > MAX_REPORTS || times.front().is_some_and(|&first| ship.last_update.saturating_sub(first) > REPORT_WINDOW_SECS) {
+            times.pop_front();
+        }
+    }
+
+    pub fn purge(&self, now: u64) {
+        self.reports
+            .lock()
+            .unwrap()
+            .retain(|_, times| times.back().is_some_and(|&last| now.saturating_sub(last) <= REPORT_WINDOW_SECS));
+    }
+
+    pub fn assess(&self, ship: &Ship, anomalies: Vec<AnomalyKind>, now: u64) -> DataQuality {
+        let frequency = self.frequency(ship, now);
+        let position_accuracy = if ship.position_accuracy { 1.0 } else { 0.6 };
+        let present = [!ship.name.trim().is_empty(), ship.ship_type != 0, ship.dimensions.is_some()];
+        let static_data = present.iter().filter(|&&present| present).count() as f64 / present.len() as f64;
+        let anomaly = (1.0 - 0.4 * anomalies.len() as f64).max(0.0);
+        let score = FREQUENCY_WEIGHT * frequency + STATIC_WEIGHT * static_data + ANOMALY_WEIGHT * anomaly + ACCURACY_WEIGHT * position_accuracy;
+        DataQuality {
+            score: round(score),
+            frequency: round(frequency),
+            position_accuracy,
+            static_data: round(static_data),
+            anomaly: round(anomaly),
+            anomalies,
+        }
+    }
+
+    fn frequency(&self, ship: &Ship, now: u64) -> f64 {
+        let reports = self.reports.lock().unwrap();
+        let Some(times) = reports.get(&ship.mmsi).filter(|times| times.len() >= 2) else {
+            return 0.5;
+        };
+        let (first, last) = (times[0], times[times.len() - 1]);
+        let mean = (last - first) as f64 / (times.len() - 1) as f64;
+        let interval = mean.max(now.saturating_sub(last) as f64);
+        let expected = if matches!(ship.nav_status, 1 | 5) || ship.speed < STATIONARY_SPEED {
+            STATIONARY_SECS
+        } else if ship.class == Some(AisClass::B) {
+            UNDERWAY_B_SECS
+        } else {
+            UNDERWAY_A_SECS
+        };
+        (expected / interval.max(1.0)).min(1.0)
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::Dimensions;
+
+    #[test]
+    fn test_quality_score() {
+        let quality = Quality::new();
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.speed, ship.ship_type, ship.class, ship.position_accuracy) = (12.0, 70, Some(AisClass::A), true);
+        ship.dimensions = Some(Dimensions { length: 120, beam: 18, to_bow: 100, to_stern: 20, to_port: 9, to_starboard: 9 });
+        for t in (1_700_000_000..1_700_000_100).step_by(10) {
+            ship.last_update = t;
+            quality.observe(&ship);
+        }
+        let good = quality.assess(&ship, Vec::new(), ship.last_update);
+        assert_eq!((good.score, good.frequency), (1.0, 1.0));
+
+        // An unnamed ship reporting every minute while underway, flagged for speed
+        let mut poor = Ship::new(244660001, "");
+        poor.speed = 12.0;
+        for t in (1_700_000_000..1_700_000_300).step_by(60) {
+            poor.last_update = t;
+            quality.observe(&poor);
+        }
+        let assessed = quality.assess(&poor, vec![AnomalyKind::ImplausibleSpeed], poor.last_update);
+        assert_eq!((assessed.frequency, assessed.static_data, assessed.anomaly), (0.17, 0.0, 0.6));
+        assert!(assessed.score < 0.4);
+
+        // Going quiet drags the frequency down
+        assert_eq!(quality.assess(&ship, Vec::new(), ship.last_update + 100).frequency, 0.1);
+    }
+}
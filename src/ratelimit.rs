@@ -0,0 +1,105 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Allows at most `max` sends per key within any `window`
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self { max, window, sent: HashMap::new() }
+    }
+
+    // Records a send and returns true if `key` is under its limit
+    pub fn allow(&mut self, key: &str, now: Instant) -> bool {
+        if !self.check(key, now) {
+            return false;
+        }
+        self.record(key, now);
+        true
+    }
+
+    // Whether `key` is under its limit, without recording a send; for when
+    // other limits have a say too
+    pub fn check(&mut self, key: &str, now: Instant) -> bool {
+        let window = self.window;
+        // Forget keys that have been quiet for a whole window
+        self.sent.retain(|_, sent| sent.back().is_some_and(|&last| now.duration_since(last) < window));
+
+        let Some(sent) = self.sent.get_mut(key) else {
+            return self.max > 0;
+        };
+        while sent.front().is_some_and(|&first| now.duration_since(first) >= window) {
+            sent.pop_front();
+        }
+        sent.len() < self.max
+    }
+
+    pub fn record(&mut self, key: &str, now: Instant) {
+        self.sent.entry(key.to_string()).or_default().push_back(now);
+    }
+}
+
+// Allows at most `max` sends in each `window` from the first send after the
+// last one ended. Keeps a count rather than every send, for long windows
+// with high limits, at the cost of allowing up to twice `max` across the
+// boundary of two windows.
+pub struct FixedWindow {
+    max: usize,
+    window: Duration,
+    started: Option<Instant>,
+    count: usize,
+}
+
+impl FixedWindow {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self { max, window, started: None, count: 0 }
+    }
+
+    // Whether a send is under the limit, without recording it
+    pub fn check(&mut self, now: Instant) -> bool {
+        if self.started.is_some_and(|started| now.duration_since(started) >= self.window) {
+            (self.started, self.count) = (None, 0);
+        }
+        self.count < self.max
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.started.get_or_insert(now);
+        self.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(limiter.allow("a", start));
+        assert!(limiter.allow("a", start + Duration::from_secs(10)));
+        assert!(!limiter.allow("a", start + Duration::from_secs(20)));
+        assert!(limiter.allow("b", start + Duration::from_secs(20)));
+        // The first send has left the window
+        assert!(limiter.allow("a", start + Duration::from_secs(61)));
+        assert!(!limiter.allow("a", start + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn test_fixed_window() {
+        let mut limiter = FixedWindow::new(2, Duration::from_secs(86_400));
+        let start = Instant::now();
+        // Checking alone uses nothing up
+        assert!(limiter.check(start) && limiter.check(start));
+        limiter.record(start);
+        limiter.record(start + Duration::from_secs(3600));
+        assert!(!limiter.check(start + Duration::from_secs(7200)));
+        // The window runs from the first send
+        assert!(limiter.check(start + Duration::from_secs(86_400)));
+    }
+}
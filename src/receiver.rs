@@ -0,0 +1,270 @@
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::ais::AisMessage;
+use crate::ingest::BATCH_INTERVAL;
+use crate::nmea::Decoder;
+use crate::plugin::{AisSource, SourceContext, MAX_BATCH};
+use crate::replay;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+enum Input {
+    Udp(SocketAddr), // To listen on
+    Tcp(String),     // host:port to connect to
+    File(String),    // Or a serial device, or "-" for stdin
+}
+
+// AIVDM sentences from an AIS receiver on the local network or plugged into
+// this machine, so ships come in without aisstream.io or the internet:
+// `nmea-udp:0.0.0.0:10110` listens for datagrams, `nmea-tcp:host:port`
+// connects to a receiver's TCP server and reconnects when it drops, and
+// `nmea-file:/dev/ttyUSB0` reads a serial device (line speed set beforehand,
+// e.g. with stty) or a recorded file.
+pub struct NmeaSource {
+    name: &'static str,
+    input: Input,
+}
+
+impl NmeaSource {
+    pub fn udp(argument: Option<&str>) -> Result<Box<dyn AisSource>> {
+        let addr = argument.ok_or_else(|| anyhow::anyhow!("The nmea-udp source needs an address to listen on, e.g. nmea-udp:0.0.0.0:10110"))?;
+        let addr = addr.parse().map_err(|e| anyhow::anyhow!("{}: {}", addr, e))?;
+        Ok(Box::new(Self { name: "nmea-udp", input: Input::Udp(addr) }))
+    }
+
+    pub fn tcp(argument: Option<&str>) -> Result<Box<dyn AisSource>> {
+        let addr = argument.ok_or_else(|| anyhow::anyhow!("The nmea-tcp source needs a receiver to connect to, e.g. nmea-tcp:192.168.1.20:10110"))?;
+        if !addr.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) {
+            return Err(anyhow::anyhow!("{} is not a host:port", addr));
+        }
+        Ok(Box::new(Self { name: "nmea-tcp", input: Input::Tcp(addr.to_string()) }))
+    }
+
+    pub fn file(argument: Option<&str>) -> Result<Box<dyn AisSource>> {
+        let path = argument.filter(|path| !path.is_empty());
+        let path = path.ok_or_else(|| anyhow::anyhow!("The nmea-file source needs a path, e.g. nmea-file:/dev/ttyUSB0"))?;
+        Ok(Box::new(Self { name: "nmea-file", input: Input::File(path.to_string()) }))
+    }
+}
+
+impl AisSource for NmeaSource {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn run(self: Box<Self>, context: SourceContext) -> BoxFuture<'static, Result<()>> {
+        async move {
+            let mut batch = Batch { context: &context, decoder: Decoder::new(), messages: Vec::new() };
+            match self.input {
+                Input::Udp(addr) => listen(addr, &mut batch).await,
+                Input::Tcp(addr) => connect(&addr, &mut batch).await,
+                Input::File(path) => {
+                    let reader: Box<dyn tokio::io::AsyncRead + Send + Unpin> = match path.as_str() {
+                        "-" => Box::new(tokio::io::stdin()),
+                        path => Box::new(tokio::fs::File::open(path).await?),
+                    };
+                    match context.replay().cloned() {
+                        Some(replay) => replay::play(&context, &replay, read_recording(reader, &context).await?, SourceContext::apply_messages).await,
+                        None => read_lines(BufReader::new(reader), &mut batch).await.map(|_| ()),
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+// Decoded messages, applied a batch at a time like the upstream's
+struct Batch<'a> {
+    context: &'a SourceContext,
+    decoder: Decoder,
+    messages: Vec<(u64, AisMessage)>,
+}
+
+impl Batch<'_> {
+    fn line(&mut self, line: &str) {
+        match self.decoder.decode(line, now()) {
+            Ok(Some(message)) => self.messages.push(message),
+            Ok(None) => {}
+            Err(e) => {
+                debug!("{}", e);
+                self.context.record_failure();
+            }
+        }
+        if self.messages.len() >= MAX_BATCH {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.context.apply_messages(std::mem::take(&mut self.messages));
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Until the end of the input, or true if the server is shutting down first
+async fn read_lines(mut reader: impl AsyncBufRead + Unpin, batch: &mut Batch<'_>) -> Result<bool> {
+    let mut flush = interval(BATCH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let shutdown = batch.context.shutdown();
+    tokio::pin!(shutdown);
+    // Bytes rather than lines, as serial lines pick up the odd invalid byte
+    let mut line = Vec::new();
+    let stopped = loop {
+        tokio::select! {
+            read = reader.read_until(b'\n', &mut line) => {
+                if read? == 0 {
+                    break false;
+                }
+                batch.line(&String::from_utf8_lossy(&line));
+                line.clear();
+            }
+            _ = flush.tick() => batch.flush(),
+            _ = &mut shutdown => break true,
+        }
+    };
+    batch.flush();
+    Ok(stopped)
+}
+
+// A whole recording for replay, timed by its tag blocks; lines without one
+// are taken to be received with the line before
+async fn read_recording(reader: impl tokio::io::AsyncRead + Unpin, context: &SourceContext) -> Result<Vec<(u64, AisMessage)>> {
+    let mut reader = BufReader::new(reader);
+    let (mut decoder, mut messages, mut line) = (Decoder::new(), Vec::new(), Vec::new());
+    let mut last = 0;
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        match decoder.decode(&String::from_utf8_lossy(&line), last) {
+            Ok(Some((timestamp, message))) => {
+                last = timestamp;
+                messages.push((timestamp, message));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                debug!("{}", e);
+                context.record_failure();
+            }
+        }
+        line.clear();
+    }
+    // Untimed lines before the first timed one go with it
+    let first = messages.iter().map(|&(timestamp, _)| timestamp).find(|&timestamp| timestamp > 0);
+    let first = first.ok_or_else(|| anyhow::anyhow!("No tag block times (\\c:...\\) in the recording to replay it by"))?;
+    for (timestamp, _) in messages.iter_mut().take_while(|(timestamp, _)| *timestamp == 0) {
+        *timestamp = first;
+    }
+    Ok(messages)
+}
+
+async fn listen(addr: SocketAddr, batch: &mut Batch<'_>) -> Result<()> {
+    let socket = UdpSocket::bind(addr).await.map_err(|e| anyhow::anyhow!("Could not listen on {}: {}", addr, e))?;
+    info!("Listening for AIVDM sentences on udp://{}", addr);
+    let mut flush = interval(BATCH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let shutdown = batch.context.shutdown();
+    tokio::pin!(shutdown);
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        tokio::select! {
+            received = socket.recv(&mut buffer) => {
+                // One sentence a datagram usually, but some receivers send several
+                for line in String::from_utf8_lossy(&buffer[..received?]).lines() {
+                    batch.line(line);
+                }
+            }
+            _ = flush.tick() => batch.flush(),
+            _ = &mut shutdown => break,
+        }
+    }
+    batch.flush();
+    Ok(())
+}
+
+async fn connect(addr: &str, batch: &mut Batch<'_>) -> Result<()> {
+    loop {
+        let connected = tokio::select! {
+            connected = TcpStream::connect(addr) => connected,
+            _ = batch.context.shutdown() => return Ok(()),
+        };
+        let error = match connected {
+            Ok(stream) => {
+                info!("Connected to the AIS receiver at {}", addr);
+                batch.context.connected();
+                match read_lines(BufReader::new(stream), batch).await {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => "connection closed".to_string(),
+                    Err(e) => e.to_string(),
+                }
+            }
+            Err(e) => e.to_string(),
+        };
+        warn!("AIS receiver at {}: {}; reconnecting in {:?}", addr, error, RECONNECT_DELAY);
+        batch.context.disconnected(error);
+        tokio::select! {
+            _ = sleep(RECONNECT_DELAY) => {}
+            _ = batch.context.shutdown() => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Monitor;
+    use crate::plugin::Registry;
+    use crate::ship::ShipCache;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn test_file_source() {
+        let path = std::env::temp_dir().join(format!("seawatch-nmea-{}.txt", std::process::id()));
+        let sentences = [
+            "!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C",
+            "!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*00",
+            "$GPGLL,4916.45,N,12311.12,W,225444,A*31",
+        ];
+        std::fs::write(&path, sentences.join("\r\n")).unwrap();
+
+        let registry = Registry::with_builtins();
+        assert!(registry.source("nmea-udp:nowhere").is_err());
+        assert!(registry.source("nmea-tcp:10110").is_err());
+        assert!(registry.source("nmea-tcp:receiver.local:10110").is_ok());
+        let source = registry.source(&format!("nmea-file:{}", path.display())).unwrap();
+
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        source.run(SourceContext::new("nmea-file", ships.clone(), monitor.clone(), shutdown_rx)).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        let ship = ships.ships.get(&477553000).map(|ship| ship.clone()).unwrap();
+        assert_eq!((ship.nav_status, ship.heading), (5, 181));
+        let report = monitor.ingest.report();
+        assert_eq!((report.windows[0].by_source["nmea-file"], report.windows[0].parse_failures), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_read_recording() {
+        let sentence = "!AIVDM,1,1,,B,177KQJ5000G?tO`K>RA1wUbN0TKH,0*5C";
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let context = SourceContext::new("nmea-file", ships, monitor, shutdown_rx);
+
+        let recording = format!("{0}\n\\c:1700000060*00\\{0}\n{0}\n", sentence);
+        let messages = read_recording(recording.as_bytes(), &context).await.unwrap();
+        let times: Vec<u64> = messages.iter().map(|&(timestamp, _)| timestamp).collect();
+        assert_eq!(times, vec![1_700_000_060; 3]);
+        assert!(read_recording(format!("{}\n", sentence).as_bytes(), &context).await.is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::alerts::{self, AlertRules, Rule};
+use crate::apikeys::ApiKeys;
+use crate::config::{Cli, Config};
+use crate::geofence::{Geofences, Zone, ZoneSpec};
+use crate::monitor::Monitor;
+use crate::systemd;
+
+// Settings that only take effect on a restart: where the server listens, and
+// what is built once at startup (the routes, the ship index and ingestion
+// queue, the monitor's thresholds and data files, forwarding and peering,
+// the photo and tile caches, report schedules). Everything else is
+// applied here or followed by the server as it is reloaded.
+fn deferred(old: &Config, new: &Config) -> Vec<&'static str> {
+    let (server, ingest) = ((&old.server, &new.server), (&old.ingest, &new.ingest));
+    let changes = [
+        ("server.host", server.0.host != server.1.host),
+        ("server.port", server.0.port != server.1.port),
+        ("server.unix_socket", server.0.unix_socket != server.1.unix_socket),
+        ("server.tls_cert", server.0.tls_cert != server.1.tls_cert),
+        ("server.tls_key", server.0.tls_key != server.1.tls_key),
+        ("server.base_path", server.0.base_path != server.1.base_path),
+        ("server.headless", server.0.headless != server.1.headless),
+        ("server.static_dir", server.0.static_dir != server.1.static_dir),
+        ("server.searches_file", server.0.searches_file != server.1.searches_file),
+        ("ingest.spatial_index", ingest.0.spatial_index != ingest.1.spatial_index),
+        ("ingest.geohash_precision", ingest.0.geohash_precision != ingest.1.geohash_precision),
+        ("ingest.queue_size", ingest.0.queue_size != ingest.1.queue_size),
+        ("ingest.parse_workers", ingest.0.parse_workers != ingest.1.parse_workers),
+        ("ingest.memory_budget_mb", ingest.0.memory_budget_mb != ingest.1.memory_budget_mb),
+        ("photos", old.photos != new.photos),
+        ("tile_cache", old.tile_cache != new.tile_cache),
+        ("reports", old.reports != new.reports),
+        ("monitor.cpa_range_nm", old.monitor.cpa_range_nm != new.monitor.cpa_range_nm),
+        ("monitor.cpa_alert_nm", old.monitor.cpa_alert_nm != new.monitor.cpa_alert_nm),
+        ("monitor.tcpa_alert_min", old.monitor.tcpa_alert_min != new.monitor.tcpa_alert_min),
+        ("monitor.loiter_min", old.monitor.loiter_min != new.monitor.loiter_min),
+        ("monitor.ports_file", old.monitor.ports_file != new.monitor.ports_file),
+        ("monitor.locodes_file", old.monitor.locodes_file != new.monitor.locodes_file),
+        ("forwarding", old.forwarding != new.forwarding),
+        ("peer", old.peer != new.peer),
+    ];
+    changes.into_iter().filter(|(_, changed)| *changed).map(|(key, _)| key).collect()
+}
+
+// Whether the zone called `name` is (still) the one `old` set up, if any,
+// rather than one created or replaced through the API since
+fn from_config(geofences: &Geofences, name: &str, old: Option<&ZoneSpec>) -> bool {
+    match (geofences.zone(name), old) {
+        (None, _) => true,
+        (Some(zone), Some(region)) => zone.shape == region.shape && zone.max_speed_kn == region.max_speed_kn,
+        (Some(_), None) => false,
+    }
+}
+
+// Brings the zones from [regions] up to date, as `apply_rules` does the
+// rules: changed ones are replaced, so their ships get entry and exit events
+// for the new shape, and ones gone from the configuration are removed.
+// Zones created or replaced through the API are never touched.
+fn apply_regions(geofences: &Geofences, old: &BTreeMap<String, ZoneSpec>, new: &BTreeMap<String, ZoneSpec>) {
+    let changed = old.keys().chain(new.keys()).filter(|name| old.get(*name) != new.get(*name));
+    for name in changed.collect::<HashSet<_>>() {
+        if !from_config(geofences, name, old.get(name)) {
+            warn!("Region '{}' was set through the API, so it is left as it is", name);
+            continue;
+        }
+        match new.get(name) {
+            Some(region) => geofences.upsert(Zone::new(name, region.shape.clone()).with_max_speed(region.max_speed_kn)),
+            None => {
+                geofences.remove(name);
+            }
+        }
+    }
+}
+
+// The keys in server.api_keys, read again, in place of the running ones;
+// none if it was taken out
+fn reload_api_keys(api_keys: &ApiKeys, config: &Config) {
+    let Some(path) = &config.server.api_keys else {
+        if !api_keys.is_empty() {
+            warn!("server.api_keys was taken out, so the API is open and /api/admin is off");
+            api_keys.replace(ApiKeys::default());
+        }
+        return;
+    };
+    match ApiKeys::from_file(&path.to_string_lossy()) {
+        Ok(keys) => {
+            info!("Reloaded {} API keys from {}", keys.usage().len(), path.display());
+            api_keys.replace(keys);
+        }
+        Err(e) => warn!("API keys not reloaded from {}: {}", path.display(), e),
+    }
+}
+
+// Brings the rules from the file up to date: new and changed ones are
+// upserted (unchanged ones are left alone, so they don't fire again for
+// ships they already matched) and ones gone from the file are removed.
+// Rules added through the API are never touched. Returns the file's rule names.
+fn apply_rules(alerts: &AlertRules, from_file: &HashSet<String>, rules: Vec<Rule>) -> HashSet<String> {
+    let names: HashSet<String> = rules.iter().map(|rule| rule.name.clone()).collect();
+    for name in from_file.difference(&names) {
+        alerts.remove(name);
+    }
+    for rule in rules {
+        if alerts.rule(&rule.name).as_ref() != Some(&rule) {
+            alerts.upsert(rule);
+        }
+    }
+    names
+}
+
+// On SIGHUP, reads the configuration again (file, environment and --set, as
+// at startup), the API keys and the monitor.alert_rules file. Anything invalid
+// is reported and the running settings are kept. `from_file` names the rules
+// loaded at startup.
+pub async fn reload_task(
+    cli: Cli,
+    config: watch::Sender<Arc<Config>>,
+    monitor: Arc<Monitor>,
+    api_keys: Arc<ApiKeys>,
+    mut from_file: HashSet<String>,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Could not listen for SIGHUP, configuration reload is off: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading configuration");
+        systemd::notify("RELOADING=1");
+        match Config::load(&cli) {
+            Ok(new) => {
+                let deferred = deferred(&config.borrow(), &new);
+                if !deferred.is_empty() {
+                    warn!("Changes to {} take effect after a restart", deferred.join(", "));
+                }
+                apply_regions(&monitor.geofences, &config.borrow().regions, &new.regions);
+                reload_api_keys(&api_keys, &new);
+                config.send_replace(Arc::new(new));
+            }
+            Err(e) => warn!("Configuration not reloaded: {}", e),
+        }
+        // The file as now configured; its rules all go if it was taken out
+        let rules_file = config.borrow().monitor.alert_rules.clone();
+        match &rules_file {
+            Some(path) => match alerts::load_rules(&path.to_string_lossy()) {
+                Ok(rules) => {
+                    from_file = apply_rules(&monitor.alerts, &from_file, rules);
+                    info!("Reloaded {} alert rules from {}", from_file.len(), path.display());
+                }
+                Err(e) => warn!("Alert rules not reloaded from {}: {}", path.display(), e),
+            },
+            None => from_file = apply_rules(&monitor.alerts, &from_file, Vec::new()),
+        }
+        systemd::notify("READY=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Condition;
+
+    #[test]
+    fn test_deferred_and_rules() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.set("retention.ship_ttl_secs", "60").unwrap();
+        new.set("server.shutdown_timeout_secs", "30").unwrap();
+        assert!(deferred(&old, &new).is_empty());
+        new.set("intervals.sweep_secs", "5").unwrap();
+        new.set("ingest.shed_policy", "class-a").unwrap();
+        new.set("upstream.url", "wss://ais.example/stream").unwrap();
+        new.set("server.cors_origins", "https://harbour.example").unwrap();
+        new.set("server.api_keys", "keys.json").unwrap();
+        assert!(deferred(&old, &new).is_empty());
+        new.set("server.port", "9090").unwrap();
+        new.set("ingest.queue_size", "10").unwrap();
+        assert_eq!(deferred(&old, &new), vec!["server.port", "ingest.queue_size"]);
+
+        // Regions from the configuration follow it; zones from the API stay
+        let region = |radius_m| toml::from_str::<ZoneSpec>(&format!("type = \"circle\"\nlat = 51.9\nlng = 4.1\nradius_m = {}", radius_m)).unwrap();
+        let geofences = Geofences::default();
+        geofences.upsert(Zone::new("from api", region(500.0).shape));
+        let regions = BTreeMap::from([("Maasvlakte".to_string(), region(1000.0)), ("Botlek".to_string(), region(2000.0))]);
+        apply_regions(&geofences, &BTreeMap::new(), &regions);
+        let reloaded = BTreeMap::from([("Maasvlakte".to_string(), region(1500.0))]);
+        apply_regions(&geofences, &regions, &reloaded);
+        let names: Vec<_> = geofences.zones().into_iter().map(|zone| zone.name.to_string()).collect();
+        assert_eq!(names, vec!["from api", "Maasvlakte"]);
+        assert_eq!(geofences.zone("Maasvlakte").unwrap().shape, region(1500.0).shape);
+        // Nor once the API has replaced a region, or taken its name first
+        geofences.upsert(Zone::new("Maasvlakte", region(100.0).shape));
+        let renamed = BTreeMap::from([("from api".to_string(), region(3000.0))]);
+        apply_regions(&geofences, &reloaded, &renamed);
+        assert_eq!(geofences.zone("Maasvlakte").unwrap().shape, region(100.0).shape);
+        assert_eq!(geofences.zone("from api").unwrap().shape, region(500.0).shape);
+
+        let rule = |name: &str, knots| Rule { name: name.into(), conditions: vec![Condition::SpeedAbove { knots }], actions: vec![] };
+        let alerts = AlertRules::new();
+        alerts.upsert(rule("from api", 5.0));
+        let from_file = apply_rules(&alerts, &HashSet::new(), vec![rule("fast", 20.0), rule("slow", 1.0)]);
+        let from_file = apply_rules(&alerts, &from_file, vec![rule("fast", 25.0)]);
+        assert_eq!(from_file, HashSet::from(["fast".to_string()]));
+        let names: Vec<_> = alerts.rules().into_iter().map(|rule| rule.name).collect();
+        assert_eq!(names, vec!["from api", "fast"]);
+        assert_eq!(alerts.rule("fast"), Some(rule("fast", 25.0)));
+    }
+}
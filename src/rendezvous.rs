@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::events::{EventKind, EventLog};
+use crate::geo::{haversine_m, EARTH_RADIUS_M};
+use crate::ports::Ports;
+use crate::ship::{Ship, ShipCache};
+
+// Two ships are meeting while both are slower than this, no further apart
+// than MAX_DISTANCE_M and outside any port area...
+const MAX_SPEED: f64 = 2.0;
+const MAX_DISTANCE_M: f64 = 500.0;
+// ...and it becomes a rendezvous once that has lasted MIN_DURATION_SECS.
+// A pair missing from a scan or two is given GRACE_SECS to reappear.
+const MIN_DURATION_SECS: u64 = 2 * 3600;
+const GRACE_SECS: u64 = 10 * 60;
+// Positions older than this don't place a ship anywhere
+const MAX_REPORT_AGE: u64 = 10 * 60;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum MeetingStatus {
+    Started,
+    Ended,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Meeting {
+    pub mmsi: u32,
+    pub other: u32,
+    pub lat: f64, // Midway between the two at the last scan
+    pub lng: f64,
+    pub since: u64,
+    pub last_seen: u64,
+    pub reported: bool, // Lasted long enough to count as a rendezvous
+}
+
+// Pairs of slow ships side by side in open water, a possible transshipment
+#[derive(Default)]
+pub struct Rendezvous {
+    meetings: Mutex<HashMap<(u32, u32), Meeting>>,
+}
+
+impl Rendezvous {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Meetings that have become rendezvous, longest first
+    pub fn current(&self) -> Vec<Meeting> {
+        let mut meetings: Vec<Meeting> =
+            self.meetings.lock().unwrap().values().filter(|meeting| meeting.reported).cloned().collect();
+        meetings.sort_by_key(|meeting| meeting.since);
+        meetings
+    }
+
+    pub fn scan(&self, ships: &ShipCache, ports: &Ports, events: &EventLog, now: u64) {
+        let candidate = |ship: &Ship| {
+            ship.speed < MAX_SPEED
+                && now.saturating_sub(ship.last_update) <= MAX_REPORT_AGE
+                && ports.containing(ship.lat, ship.lng).is_none()
+        };
+        let slow: Vec<Ship> = ships.ships.iter().filter(|ship| candidate(ship)).map(|ship| ship.clone()).collect();
+
+        let mut close = Vec::new();
+        let d_lat = (MAX_DISTANCE_M / EARTH_RADIUS_M).to_degrees();
+        for a in &slow {
+            let d_lng = d_lat / a.lat.to_radians().cos().max(1e-6);
+            for b in ships.get_full_ships_in_bbox(a.lat - d_lat, a.lng - d_lng, a.lat + d_lat, a.lng + d_lng) {
+                if b.mmsi > a.mmsi && candidate(&b) && haversine_m(a.lat, a.lng, b.lat, b.lng) <= MAX_DISTANCE_M {
+                    close.push(((a.mmsi, b.mmsi), (a.lat + b.lat) / 2.0, (a.lng + b.lng) / 2.0));
+                }
+            }
+        }
+
+        let mut meetings = self.meetings.lock().unwrap();
+        for ((mmsi, other), lat, lng) in close {
+            let meeting = meetings
+                .entry((mmsi, other))
+                .or_insert(Meeting { mmsi, other, lat, lng, since: now, last_seen: now, reported: false });
+            (meeting.lat, meeting.lng, meeting.last_seen) = (lat, lng, now);
+            if !meeting.reported && now.saturating_sub(meeting.since) >= MIN_DURATION_SECS {
+                meeting.reported = true;
+                events.push(
+                    now,
+                    mmsi,
+                    EventKind::Rendezvous { other, status: MeetingStatus::Started, lat, lng, duration_secs: now.saturating_sub(meeting.since) },
+                );
+            }
+        }
+        meetings.retain(|_, meeting| {
+            if now.saturating_sub(meeting.last_seen) <= GRACE_SECS {
+                return true;
+            }
+            if meeting.reported {
+                events.push(
+                    now,
+                    meeting.mmsi,
+                    EventKind::Rendezvous {
+                        other: meeting.other,
+                        status: MeetingStatus::Ended,
+                        lat: meeting.lat,
+                        lng: meeting.lng,
+                        duration_secs: meeting.last_seen - meeting.since,
+                    },
+                );
+            }
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventFilter;
+
+    #[test]
+    fn test_rendezvous() {
+        let (rendezvous, events, ports) = (Rendezvous::new(), EventLog::new(), Ports::builtin());
+        let ships = ShipCache::new();
+        let place = |mmsi: u32, lat: f64, lng: f64, t: u64| {
+            let mut ship = Ship::new(mmsi, "");
+            (ship.lat, ship.lng, ship.speed, ship.last_update) = (lat, lng, 0.5, t);
+            ships.insert_ship(mmsi, ship);
+        };
+
+        // Two ships alongside in mid-Atlantic, and two moored together in Dover
+        let mut t = 0;
+        while t <= MIN_DURATION_SECS {
+            place(1, 40.0, -30.0, t);
+            place(2, 40.001, -30.0, t);
+            place(3, 51.12, 1.34, t);
+            place(4, 51.1201, 1.34, t);
+            rendezvous.scan(&ships, &ports, &events, t);
+            t += 600;
+        }
+        let current = rendezvous.current();
+        assert_eq!(current.len(), 1);
+        assert_eq!((current[0].mmsi, current[0].other, current[0].since), (1, 2, 0));
+
+        // They part
+        place(2, 40.1, -30.0, t);
+        rendezvous.scan(&ships, &ports, &events, t);
+        assert_eq!(rendezvous.current().len(), 1, "within the grace period");
+        rendezvous.scan(&ships, &ports, &events, t + GRACE_SECS + 600);
+
+        let filter = EventFilter { kind: Some("rendezvous".into()), ..Default::default() };
+        let logged: Vec<(MeetingStatus, u64)> = events
+            .query(&filter)
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::Rendezvous { status, duration_secs, .. } => (status, duration_secs),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(logged, vec![(MeetingStatus::Started, 7200), (MeetingStatus::Ended, 7200)]);
+        assert!(rendezvous.current().is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::plugin::{SourceContext, MAX_BATCH};
+
+pub const MAX_SPEED: f64 = 1000.0;
+// Sources look at the clock at least this often while playing, so a speed
+// change takes effect without waiting out the gap to the next record
+const MAX_WAIT: Duration = Duration::from_secs(1);
+
+// Where playback is, in the recording's time
+struct Playback {
+    position: f64, // Unix time at `since`
+    since: Instant,
+    playing: bool,
+    speed: f64,
+    range: Option<(u64, u64)>, // First and last record of every source
+    seeked: bool,
+}
+
+impl Playback {
+    fn now(&self) -> f64 {
+        let position = match self.playing {
+            true => self.position + self.since.elapsed().as_secs_f64() * self.speed,
+            false => self.position,
+        };
+        self.range.map_or(position, |(_, end)| position.min(end as f64))
+    }
+
+    // Re-anchors the clock, so what changes next starts from here
+    fn settle(&mut self) {
+        (self.position, self.since) = (self.now(), Instant::now());
+    }
+}
+
+// What /api/replay returns
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ReplayStatus {
+    pub time: u64,
+    pub playing: bool,
+    pub speed: f64,
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+// The clock of replay mode, which `states:` and `nmea-file:` sources read
+// their recording against instead of straight through: it can be paused,
+// moved to any time in the recording and run faster or slower. Starts
+// playing at normal speed from the start of the recording.
+pub struct Replay {
+    playback: Mutex<Playback>,
+    // Bumped on every seek, so the sources wake up and rewind if need be
+    seeks: watch::Sender<u64>,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        let playback = Playback { position: 0.0, since: Instant::now(), playing: true, speed: 1.0, range: None, seeked: false };
+        Self { playback: Mutex::new(playback), seeks: watch::channel(0).0 }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.playback.lock().unwrap().now() as u64
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        let playback = self.playback.lock().unwrap();
+        ReplayStatus {
+            time: playback.now() as u64,
+            playing: playback.playing,
+            speed: playback.speed,
+            start: playback.range.map(|(start, _)| start),
+            end: playback.range.map(|(_, end)| end),
+        }
+    }
+
+    pub fn play(&self) {
+        let mut playback = self.playback.lock().unwrap();
+        playback.settle();
+        playback.playing = true;
+        self.seeks.send_modify(|_| {}); // Paused sources are waiting for this
+    }
+
+    pub fn pause(&self) {
+        let mut playback = self.playback.lock().unwrap();
+        playback.settle();
+        playback.playing = false;
+    }
+
+    pub fn set_speed(&self, speed: f64) -> Result<()> {
+        if !(speed > 0.0 && speed <= MAX_SPEED) {
+            return Err(anyhow::anyhow!("The speed must be above 0 and at most {}", MAX_SPEED));
+        }
+        let mut playback = self.playback.lock().unwrap();
+        playback.settle();
+        playback.speed = speed;
+        Ok(())
+    }
+
+    // Moves to `time`, within the recording. Going back calls `reset` first,
+    // to forget what was applied since; the sources then replay from the start.
+    pub fn seek(&self, time: u64, reset: impl FnOnce()) {
+        let mut playback = self.playback.lock().unwrap();
+        let time = match playback.range {
+            Some((start, end)) => time.clamp(start, end),
+            None => time,
+        };
+        if (time as f64) < playback.now() {
+            reset();
+        }
+        (playback.position, playback.since, playback.seeked) = (time as f64, Instant::now(), true);
+        drop(playback);
+        self.seeks.send_modify(|seeks| *seeks += 1);
+    }
+
+    // A source's recording runs from `start` to `end`; until a seek, playback
+    // starts at the earliest
+    fn extend(&self, start: u64, end: u64) {
+        let mut playback = self.playback.lock().unwrap();
+        let range = playback.range.map_or((start, end), |(first, last)| (first.min(start), last.max(end)));
+        playback.range = Some(range);
+        if !playback.seeked {
+            (playback.position, playback.since) = (range.0 as f64, Instant::now());
+        }
+    }
+
+    // How long until the clock reaches `time`, or None while paused
+    fn until(&self, time: u64) -> Option<Duration> {
+        let playback = self.playback.lock().unwrap();
+        let ahead = time as f64 - playback.now();
+        playback.playing.then(|| Duration::from_secs_f64((ahead / playback.speed).max(0.0)))
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.seeks.subscribe()
+    }
+}
+
+// What the server takes the time to be: the wall clock, or the replay's
+#[derive(Clone, Default)]
+pub struct Clock(Option<Arc<Replay>>);
+
+impl Clock {
+    pub fn replay(replay: Arc<Replay>) -> Self {
+        Self(Some(replay))
+    }
+
+    pub fn now(&self) -> u64 {
+        match &self.0 {
+            Some(replay) => replay.now(),
+            None => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }
+    }
+}
+
+// Middleware telling clients the replay clock's time in X-Replay-Time, as
+// the Unix time their response reflects
+pub async fn header(State(replay): State<Arc<Replay>>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let time = HeaderValue::from(replay.now());
+    response.headers_mut().insert("x-replay-time", time);
+    response
+}
+
+// Applies `records`, sorted by time, as the replay clock reaches them; after
+// the last it waits for a seek back, until the server shuts down
+pub async fn play<T: Clone>(context: &SourceContext, replay: &Replay, mut records: Vec<(u64, T)>, apply: impl Fn(&SourceContext, Vec<(u64, T)>)) -> Result<()> {
+    records.sort_by_key(|(time, _)| *time);
+    let (Some(&(start, _)), Some(&(end, _))) = (records.first(), records.last()) else {
+        return Ok(());
+    };
+    replay.extend(start, end);
+    let mut seeks = replay.subscribe();
+    let shutdown = context.shutdown();
+    tokio::pin!(shutdown);
+    let (mut next, mut applied) = (0, 0);
+    loop {
+        let now = replay.now();
+        if now < applied {
+            next = 0; // Seeked back, and the ships were reset
+        }
+        while next < records.len() && records[next].0 <= now {
+            let until = records[next..].iter().take(MAX_BATCH).take_while(|(time, _)| *time <= now).count();
+            apply(context, records[next..next + until].to_vec());
+            next += until;
+        }
+        applied = now;
+
+        let wait = records.get(next).and_then(|&(time, _)| replay.until(time));
+        tokio::select! {
+            _ = sleep(wait.unwrap_or(MAX_WAIT).min(MAX_WAIT)), if wait.is_some() => {}
+            changed = seeks.changed() => changed?,
+            _ = &mut shutdown => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Monitor;
+    use crate::ship::{Ship, ShipCache};
+
+    fn ship(lat: f64, last_update: u64) -> Ship {
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.last_update) = (lat, 4.1, last_update);
+        ship
+    }
+
+    #[tokio::test]
+    async fn test_replay_controls() {
+        let (ships, monitor) = (Arc::new(ShipCache::new()), Arc::new(Monitor::new()));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let replay = Arc::new(Replay::new());
+        let context = SourceContext::new("states", ships.clone(), monitor.clone(), shutdown_rx).with_replay(replay.clone());
+        let records: Vec<(u64, Ship)> = (0..4).rev().map(|i| (1_700_000_000 + i * 60, ship(51.0 + i as f64, 1_700_000_000 + i * 60))).collect();
+        let task = tokio::spawn({
+            let replay = replay.clone();
+            async move { play(&context, &replay, records, |context, batch| context.apply(batch.into_iter().map(|(_, ship)| ship).collect())).await }
+        });
+        let lat = || ships.ships.get(&244660000).map(|ship| ship.lat);
+        let settle = || sleep(Duration::from_millis(50));
+
+        // Plays from the first record in time, and stays there while paused
+        settle().await;
+        replay.pause();
+        assert_eq!((replay.status().start, replay.status().end), (Some(1_700_000_000), Some(1_700_000_180)));
+        assert_eq!((lat(), replay.status().playing), (Some(51.0), false));
+
+        // Seeking forward applies what was skipped, back starts over
+        replay.seek(1_700_000_130, || {});
+        settle().await;
+        assert_eq!((lat(), replay.now()), (Some(53.0), 1_700_000_130));
+        replay.seek(1_700_000_000, || {
+            ships.remove_stale(u64::MAX);
+        });
+        settle().await;
+        assert_eq!(lat(), Some(51.0));
+
+        // Fast, and no further than the end
+        assert!(replay.set_speed(0.0).is_err());
+        replay.set_speed(MAX_SPEED).unwrap();
+        replay.play();
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!((lat(), replay.now()), (Some(54.0), 1_700_000_180));
+
+        shutdown_tx.send(true).unwrap();
+        task.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::Result;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::distance::DAY_SECS;
+use crate::email::Mailer;
+use crate::events::{Event, EventKind};
+use crate::monitor::Monitor;
+use crate::shutdown;
+
+// How often the ships inside each reported region are counted
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+// Hours listed as the busiest, at most
+const BUSIEST_HOURS: usize = 3;
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    #[default]
+    Daily,
+    Weekly, // From Monday
+}
+
+impl Period {
+    // Start of the period `now` falls in, at 00:00 UTC
+    pub fn start(self, now: u64) -> u64 {
+        let day = now / DAY_SECS;
+        match self {
+            Self::Daily => day * DAY_SECS,
+            // 1970-01-01 was a Thursday
+            Self::Weekly => (day - (day + 3) % 7) * DAY_SECS,
+        }
+    }
+
+    fn secs(self) -> u64 {
+        match self {
+            Self::Daily => DAY_SECS,
+            Self::Weekly => 7 * DAY_SECS,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+// A region's summary report, as set under [reports.<region>]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    pub period: Period,
+    pub formats: Vec<Format>,
+    pub dir: Option<PathBuf>, // Written here, as <region>-<period>-<date>.<format>
+    pub email: Vec<String>, // And mailed to these, as attachments; needs SMTP_URL
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self { period: Period::Daily, formats: vec![Format::Json], dir: None, email: Vec::new() }
+    }
+}
+
+impl ReportConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.formats.is_empty() {
+            return Err(anyhow::anyhow!("formats can't be empty"));
+        }
+        if self.dir.is_none() && self.email.is_empty() {
+            return Err(anyhow::anyhow!("needs a dir to write to or an email to send to"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+pub struct HourSummary {
+    pub hour: u32, // Of the day, UTC
+    pub ships: usize, // Distinct ships inside during that hour, on any day of the period
+    pub arrivals: usize,
+    pub alerts: usize,
+}
+
+// One region's traffic over a day or a week
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub region: String,
+    pub period: Period,
+    pub from: u64, // Later than the period's start if the server started during it
+    pub until: u64,
+    pub unique_ships: usize,
+    pub arrivals: usize, // Port arrivals by ships inside the region
+    pub arrivals_by_port: BTreeMap<String, usize>,
+    pub alerts: usize, // Alert rules matching ships inside the region
+    pub alerts_by_rule: BTreeMap<String, usize>,
+    pub busiest_hours: Vec<u32>, // Most ships first
+    pub hours: Vec<HourSummary>,
+}
+
+struct Tally {
+    start: u64, // Of the period
+    from: u64,
+    ships: HashSet<u32>,
+    hours: Vec<HashSet<u32>>,
+    arrivals: [usize; 24],
+    alerts: [usize; 24],
+    ports: BTreeMap<String, usize>,
+    rules: BTreeMap<String, usize>,
+}
+
+fn hour(timestamp: u64) -> usize {
+    (timestamp % DAY_SECS / 3600) as usize
+}
+
+impl Tally {
+    fn new(start: u64, from: u64) -> Self {
+        Self {
+            start,
+            from,
+            ships: HashSet::new(),
+            hours: vec![HashSet::new(); 24],
+            arrivals: [0; 24],
+            alerts: [0; 24],
+            ports: BTreeMap::new(),
+            rules: BTreeMap::new(),
+        }
+    }
+
+    fn ship(&mut self, mmsi: u32, timestamp: u64) {
+        self.ships.insert(mmsi);
+        self.hours[hour(timestamp)].insert(mmsi);
+    }
+
+    fn summary(&self, region: &str, period: Period) -> Summary {
+        let hours: Vec<HourSummary> = (0..24)
+            .map(|hour| HourSummary { hour: hour as u32, ships: self.hours[hour].len(), arrivals: self.arrivals[hour], alerts: self.alerts[hour] })
+            .collect();
+        let mut busiest: Vec<&HourSummary> = hours.iter().filter(|hour| hour.ships > 0).collect();
+        busiest.sort_by(|a, b| b.ships.cmp(&a.ships).then(a.hour.cmp(&b.hour)));
+        Summary {
+            region: region.to_string(),
+            period,
+            from: self.from,
+            until: self.start + period.secs(),
+            unique_ships: self.ships.len(),
+            arrivals: self.arrivals.iter().sum(),
+            arrivals_by_port: self.ports.clone(),
+            alerts: self.alerts.iter().sum(),
+            alerts_by_rule: self.rules.clone(),
+            busiest_hours: busiest.iter().take(BUSIEST_HOURS).map(|hour| hour.hour).collect(),
+            hours,
+        }
+    }
+}
+
+// The regions being reported on, each tallied over its current period from
+// samples of who is inside and from the events of ships inside
+pub struct Reports {
+    regions: Vec<(String, ReportConfig, Tally)>,
+}
+
+impl Reports {
+    pub fn new(configs: &BTreeMap<String, ReportConfig>, now: u64) -> Self {
+        let regions = configs.iter().map(|(region, config)| (region.clone(), config.clone(), Tally::new(config.period.start(now), now))).collect();
+        Self { regions }
+    }
+
+    // `occupants` as from `Geofences::occupants`
+    pub fn sample(&mut self, occupants: &HashMap<Arc<str>, Vec<u32>>, now: u64) {
+        for (region, _, tally) in &mut self.regions {
+            for &mmsi in occupants.get(region.as_str()).into_iter().flatten() {
+                tally.ship(mmsi, now);
+            }
+        }
+    }
+
+    // `inside` says whether the event's ship is in a region
+    pub fn event(&mut self, event: &Event, inside: impl Fn(&str) -> bool) {
+        for (region, _, tally) in &mut self.regions {
+            if event.timestamp < tally.start {
+                continue;
+            }
+            let hour = hour(event.timestamp);
+            match &event.kind {
+                // Ships passing through between samples still count
+                EventKind::ZoneEnter { zone } if &**zone == region.as_str() => tally.ship(event.mmsi, event.timestamp),
+                EventKind::PortArrival { port, .. } if inside(region) => {
+                    tally.arrivals[hour] += 1;
+                    *tally.ports.entry(port.to_string()).or_default() += 1;
+                }
+                EventKind::Alert { rule } if inside(region) => {
+                    tally.alerts[hour] += 1;
+                    *tally.rules.entry(rule.to_string()).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Summaries of the periods over by `now`, with where they go; the
+    // regions start on their next period
+    pub fn due(&mut self, now: u64) -> Vec<(Summary, ReportConfig)> {
+        let mut due = Vec::new();
+        for (region, config, tally) in &mut self.regions {
+            let start = config.period.start(now);
+            if start > tally.start {
+                due.push((tally.summary(region, config.period), config.clone()));
+                *tally = Tally::new(start, start);
+            }
+        }
+        due
+    }
+}
+
+pub fn render(summary: &Summary, format: Format) -> Result<String> {
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(summary)?),
+        Format::Csv => {
+            let mut csv = String::from("region,period,from,until,hour,ships,arrivals,alerts\n");
+            let prefix = format!("{},{},{},{}", csv_field(&summary.region), summary.period.name(), time(summary.from), time(summary.until));
+            for hour in &summary.hours {
+                csv.push_str(&format!("{},{:02},{},{},{}\n", prefix, hour.hour, hour.ships, hour.arrivals, hour.alerts));
+            }
+            csv.push_str(&format!("{},all,{},{},{}\n", prefix, summary.unique_ships, summary.arrivals, summary.alerts));
+            Ok(csv)
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn time(timestamp: u64) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0).map_or_else(|| timestamp.to_string(), |time| time.to_rfc3339())
+}
+
+// e.g. port-of-la-daily-2024-05-01.csv, dated by the period's start
+fn file_name(summary: &Summary, format: Format) -> String {
+    let region: String = summary.region.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' }).collect();
+    let date = DateTime::from_timestamp(summary.from as i64, 0).map_or_else(|| summary.from.to_string(), |time| time.format("%Y-%m-%d").to_string());
+    format!("{}-{}-{}.{}", region, summary.period.name(), date, format.extension())
+}
+
+async fn deliver(summary: &Summary, config: &ReportConfig, mailer: Option<&Mailer>) -> Result<()> {
+    let mut files = Vec::new();
+    for &format in &config.formats {
+        files.push((file_name(summary, format), format, render(summary, format)?));
+    }
+    if let Some(dir) = &config.dir {
+        tokio::fs::create_dir_all(dir).await?;
+        for (name, _, content) in &files {
+            tokio::fs::write(Path::new(dir).join(name), content).await?;
+        }
+        info!("Wrote the {} report for {} to {}", summary.period.name(), summary.region, dir.display());
+    }
+    if let Some(mailer) = mailer.filter(|_| !config.email.is_empty()) {
+        let subject = format!("[seawatch] {} {} report", summary.region, summary.period.name());
+        let body = format!(
+            "{} from {} to {}: {} ships, {} port arrivals, {} alerts.\n",
+            summary.region,
+            time(summary.from),
+            time(summary.until),
+            summary.unique_ships,
+            summary.arrivals,
+            summary.alerts
+        );
+        let attachments: Vec<(String, &str, String)> = files
+            .into_iter()
+            .map(|(name, format, content)| (name, if format == Format::Json { "application/json" } else { "text/csv" }, content))
+            .collect();
+        for to in &config.email {
+            mailer.send_report(to, &subject, &body, &attachments).await?;
+        }
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Tallies the regions' traffic as it happens and sends each report once its
+// period is over
+pub async fn reports_task(configs: BTreeMap<String, ReportConfig>, monitor: Arc<Monitor>, mailer: Option<Arc<Mailer>>, shutdown: watch::Receiver<bool>) {
+    let mut reports = Reports::new(&configs, now());
+    let mut events = monitor.events.subscribe();
+    let mut samples = interval(SAMPLE_INTERVAL);
+    samples.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = samples.tick() => {}
+            event = events.recv() => {
+                match event {
+                    Ok(event) => reports.event(&event, |region| monitor.geofences.is_inside(event.mmsi, region)),
+                    Err(RecvError::Lagged(missed)) => warn!("Reports fell behind, {} events not counted", missed),
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            }
+            _ = shutdown::requested(shutdown.clone()) => return,
+        }
+        let now = now();
+        for (summary, config) in reports.due(now) {
+            if let Err(e) = deliver(&summary, &config, mailer.as_deref()).await {
+                warn!("Could not send the {} report for {}: {}", summary.period.name(), summary.region, e);
+            }
+        }
+        reports.sample(&monitor.geofences.occupants(), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Priority;
+
+    #[test]
+    fn test_daily_report() {
+        let midnight = 1_699_920_000; // Tuesday 2023-11-14
+        assert_eq!(Period::Weekly.start(midnight + 3600), midnight - DAY_SECS);
+        let configs = BTreeMap::from([("Port of LA".to_string(), ReportConfig { formats: vec![Format::Json, Format::Csv], ..Default::default() })]);
+        assert!(configs["Port of LA"].validate().is_err());
+        let mut reports = Reports::new(&configs, midnight + 600);
+
+        let zone: Arc<str> = Arc::from("Port of LA");
+        let occupants = HashMap::from([(zone.clone(), vec![1, 2])]);
+        reports.sample(&occupants, midnight + 9 * 3600);
+        reports.sample(&occupants, midnight + 9 * 3600 + 60);
+        reports.sample(&HashMap::from([(zone.clone(), vec![2])]), midnight + 14 * 3600);
+        let event = |mmsi: u32, hour: u64, kind: EventKind| Event { id: 0, timestamp: midnight + hour * 3600, mmsi, priority: Priority::Normal, kind };
+        reports.event(&event(3, 9, EventKind::ZoneEnter { zone: zone.clone() }), |_| true);
+        reports.event(&event(2, 14, EventKind::PortArrival { port: "Los Angeles".into(), locode: "USLAX".into() }), |_| true);
+        reports.event(&event(4, 14, EventKind::Alert { rule: "tankers".into() }), |_| false);
+        assert!(reports.due(midnight + DAY_SECS - 1).is_empty());
+
+        let due = reports.due(midnight + DAY_SECS + 30);
+        assert_eq!(due.len(), 1);
+        let summary = &due[0].0;
+        assert_eq!((summary.from, summary.until), (midnight + 600, midnight + DAY_SECS));
+        assert_eq!((summary.unique_ships, summary.arrivals, summary.alerts), (3, 1, 0));
+        assert_eq!(summary.arrivals_by_port["Los Angeles"], 1);
+        assert_eq!(summary.busiest_hours, vec![9, 14]);
+        assert_eq!((summary.hours[9].ships, summary.hours[14].arrivals), (3, 1));
+
+        let csv = render(summary, Format::Csv).unwrap();
+        assert_eq!(csv.lines().count(), 26);
+        assert!(csv.contains("\nPort of LA,daily,2023-11-14T00:10:00+00:00,2023-11-15T00:00:00+00:00,09,3,0,0\n"), "{}", csv);
+        assert!(csv.ends_with(",all,3,1,0\n"));
+        assert_eq!(file_name(summary, Format::Csv), "port-of-la-daily-2023-11-14.csv");
+        assert_eq!(csv_field("Dover, \"east\""), "\"Dover, \"\"east\"\"\"");
+        // The next day starts from nothing
+        assert_eq!(reports.due(midnight + 2 * DAY_SECS)[0].0.unique_ships, 0);
+    }
+}
@@ -0,0 +1,95 @@
+use schemars::schema_for;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::ais::Subscription;
+use crate::alerts::Rule;
+use crate::apikeys::KeyUsage;
+use crate::anchor::AnchorWatch;
+use crate::anomalies::Anomaly;
+use crate::area_stats::AreaHistory;
+use crate::changes::Change;
+use crate::collision::Risk;
+use crate::config::MapConfig;
+use crate::distance::{DistanceReport, ShipDistance};
+use crate::emissions::{EmissionsReport, ShipEmissions};
+use crate::eta::{EtaSummary, ShipEtaStats};
+use crate::events::{Event, EventKind};
+use crate::forward::TargetStatus;
+use crate::geofence::Zone;
+use crate::history::Timelapse;
+use crate::identity::Conflict;
+use crate::ingest_stats::IngestReport;
+use crate::logging::LogLevel;
+use crate::port_calls::PortCall;
+use crate::ports::{NearestPort, Port};
+use crate::predict::Prediction;
+use crate::rendezvous::Meeting;
+use crate::replay::ReplayStatus;
+use crate::reports::Summary;
+use crate::searches::SavedSearch;
+use crate::ship::{Ship, ShipState};
+use crate::server::{Occupant, ShipDetail, Stats};
+use crate::upstream::UpstreamStatus;
+use crate::voyages::Voyage;
+
+// JSON Schemas for every response and event type, by type name, built once
+pub fn schemas() -> &'static BTreeMap<&'static str, serde_json::Value> {
+    static SCHEMAS: OnceLock<BTreeMap<&'static str, serde_json::Value>> = OnceLock::new();
+    SCHEMAS.get_or_init(|| {
+        BTreeMap::from([
+            ("AnchorWatch", schema_for!(AnchorWatch).to_value()),
+            ("Anomaly", schema_for!(Anomaly).to_value()),
+            ("AreaHistory", schema_for!(AreaHistory).to_value()),
+            ("Change", schema_for!(Change).to_value()),
+            ("Conflict", schema_for!(Conflict).to_value()),
+            ("DistanceReport", schema_for!(DistanceReport).to_value()),
+            ("EmissionsReport", schema_for!(EmissionsReport).to_value()),
+            ("EtaSummary", schema_for!(EtaSummary).to_value()),
+            ("Event", schema_for!(Event).to_value()),
+            ("EventKind", schema_for!(EventKind).to_value()),
+            ("IngestReport", schema_for!(IngestReport).to_value()),
+            ("KeyUsage", schema_for!(KeyUsage).to_value()),
+            ("LogLevel", schema_for!(LogLevel).to_value()),
+            ("Meeting", schema_for!(Meeting).to_value()),
+            ("MapConfig", schema_for!(MapConfig).to_value()),
+            ("NearestPort", schema_for!(NearestPort).to_value()),
+            ("Occupant", schema_for!(Occupant).to_value()),
+            ("Port", schema_for!(Port).to_value()),
+            ("PortCall", schema_for!(PortCall).to_value()),
+            ("Prediction", schema_for!(Prediction).to_value()),
+            ("ReplayStatus", schema_for!(ReplayStatus).to_value()),
+            ("Risk", schema_for!(Risk).to_value()),
+            ("Rule", schema_for!(Rule).to_value()),
+            ("SavedSearch", schema_for!(SavedSearch).to_value()),
+            ("Ship", schema_for!(Ship).to_value()),
+            ("ShipDetail", schema_for!(ShipDetail).to_value()),
+            ("ShipDistance", schema_for!(ShipDistance).to_value()),
+            ("ShipEmissions", schema_for!(ShipEmissions).to_value()),
+            ("ShipEtaStats", schema_for!(ShipEtaStats).to_value()),
+            ("ShipState", schema_for!(ShipState).to_value()),
+            ("Stats", schema_for!(Stats).to_value()),
+            ("Subscription", schema_for!(Subscription).to_value()),
+            ("Summary", schema_for!(Summary).to_value()),
+            ("TargetStatus", schema_for!(TargetStatus).to_value()),
+            ("Timelapse", schema_for!(Timelapse).to_value()),
+            ("UpstreamStatus", schema_for!(UpstreamStatus).to_value()),
+            ("Voyage", schema_for!(Voyage).to_value()),
+            ("Zone", schema_for!(Zone).to_value()),
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemas() {
+        let schemas = schemas();
+        assert!(schemas.values().all(|schema| schema["$schema"].is_string()));
+        let event = schemas["Event"].to_string();
+        assert!(event.contains("zone_enter") && event.contains("priority"));
+        assert_eq!(schemas["Ship"]["properties"]["mmsi"]["type"], "integer");
+    }
+}
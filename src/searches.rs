@@ -0,0 +1,185 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::ais::AisClass;
+use crate::ship::Ship;
+
+// What a search leaves in; every filter set must match
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filters {
+    // AIS type codes, inclusive, e.g. 70 and 79 for cargo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ship_type: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ship_type: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_speed_kn: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_speed_kn: Option<f64>,
+    // Any of these navigational statuses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nav_status: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<AisClass>,
+    // Part of the name or destination, ignoring case
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    // See `crate::quality`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_quality: Option<f64>,
+}
+
+impl Filters {
+    // `quality` is only asked for when `min_quality` is set
+    pub fn matches(&self, ship: &Ship, quality: impl Fn(&Ship) -> f64) -> bool {
+        let contains = |text: &str, part: &Option<String>| part.as_ref().is_none_or(|part| text.to_lowercase().contains(&part.to_lowercase()));
+        self.min_ship_type.is_none_or(|min| ship.ship_type >= min)
+            && self.max_ship_type.is_none_or(|max| ship.ship_type <= max)
+            && self.min_speed_kn.is_none_or(|min| ship.speed >= min)
+            && self.max_speed_kn.is_none_or(|max| ship.speed <= max)
+            && (self.nav_status.is_empty() || self.nav_status.contains(&ship.nav_status))
+            && self.class.is_none_or(|class| ship.class == Some(class))
+            && contains(&ship.name, &self.name)
+            && contains(&ship.destination, &self.destination)
+            && self.min_quality.is_none_or(|min| quality(ship) >= min)
+    }
+}
+
+// A search as PUT to /api/searches/:name: where to look and what to keep.
+// With neither `bbox` nor `region` it covers every ship
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Search {
+    // [south, west, north, east]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<[f64; 4]>,
+    // A named region or zone; see `crate::geofence`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub filters: Filters,
+}
+
+impl Search {
+    pub fn validate(&self) -> Result<()> {
+        if self.bbox.is_some() && self.region.is_some() {
+            return Err(anyhow::anyhow!("Give a bbox or a region, not both"));
+        }
+        if let Some([south, west, north, east]) = self.bbox
+            && !(south <= north && west <= east && (-90.0..=90.0).contains(&south) && (-90.0..=90.0).contains(&north))
+        {
+            return Err(anyhow::anyhow!("bbox must be [south, west, north, east]"));
+        }
+        let filters = &self.filters;
+        let numbers = [filters.min_speed_kn, filters.max_speed_kn, filters.min_quality];
+        if numbers.iter().flatten().any(|value| !value.is_finite()) {
+            return Err(anyhow::anyhow!("Speeds and min_quality must be numbers"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct SavedSearch {
+    pub name: String,
+    #[serde(flatten)]
+    pub search: Search,
+    pub updated: u64,
+}
+
+// Saved searches by name, written back to their file, if any, on every change
+#[derive(Default)]
+pub struct Searches {
+    searches: RwLock<BTreeMap<String, SavedSearch>>,
+    path: Option<PathBuf>,
+}
+
+impl Searches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts empty if the file doesn't exist yet
+    pub fn from_file(path: &str) -> Result<Self> {
+        let saved: Vec<SavedSearch> = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(anyhow::anyhow!("Could not read {}: {}", path, e)),
+        };
+        let searches = saved.into_iter().map(|search| (search.name.clone(), search)).collect();
+        Ok(Self { searches: RwLock::new(searches), path: Some(PathBuf::from(path)) })
+    }
+
+    pub fn all(&self) -> Vec<SavedSearch> {
+        self.searches.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<SavedSearch> {
+        self.searches.read().unwrap().get(name).cloned()
+    }
+
+    // Add a search, or replace the one with the same name
+    pub fn upsert(&self, saved: SavedSearch) -> Result<()> {
+        let mut searches = self.searches.write().unwrap();
+        searches.insert(saved.name.clone(), saved);
+        self.save(&searches)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut searches = self.searches.write().unwrap();
+        if searches.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save(&searches).map(|_| true)
+    }
+
+    // Written whole to a temporary file first, so a crash mid-write can't lose the rest
+    fn save(&self, searches: &BTreeMap<String, SavedSearch>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved: Vec<&SavedSearch> = searches.values().collect();
+        let partial = path.with_extension("tmp");
+        std::fs::write(&partial, serde_json::to_vec_pretty(&saved)?)?;
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::intern;
+
+    #[test]
+    fn test_saved_searches() {
+        let search: Search = serde_json::from_str(
+            r#"{"region": "Bosphorus", "filters": {"min_ship_type": 80, "max_ship_type": 89, "min_speed_kn": 5, "destination": "ist"}}"#,
+        )
+        .unwrap();
+        search.validate().unwrap();
+        let mut ship = Ship::new(271000001, "KARADENIZ");
+        (ship.ship_type, ship.speed, ship.destination) = (84, 9.5, intern("TRIST"));
+        assert!(search.filters.matches(&ship, |_| 0.0));
+        ship.speed = 2.0;
+        assert!(!search.filters.matches(&ship, |_| 0.0));
+        assert!(serde_json::from_str::<Search>(r#"{"bbox": [41.0, 28.9, 41.3, 29.2], "region": "Bosphorus"}"#).unwrap().validate().is_err());
+        assert!(serde_json::from_str::<Search>(r#"{"filters": {"speed": 5}}"#).is_err());
+
+        let path = std::env::temp_dir().join(format!("seawatch-searches-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let searches = Searches::from_file(path).unwrap();
+        searches.upsert(SavedSearch { name: "tankers".into(), search: search.clone(), updated: 1_700_000_000 }).unwrap();
+        assert_eq!(Searches::from_file(path).unwrap().get("tankers").unwrap().search, search);
+        assert!(searches.remove("tankers").unwrap());
+        assert!(Searches::from_file(path).unwrap().all().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
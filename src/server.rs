@@ -0,0 +1,1942 @@
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::{get, post, put, MethodRouter},
+    Extension, Router,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, interval_at, Duration, Instant, Interval};
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    services::ServeDir,
+};
+use tracing::{error, info, warn, debug};
+use url::Url;
+
+use crate::ais::{AisStream, Subscription, SubscriptionUpdate};
+use crate::alerts::{Action, Rule};
+use crate::anchor::AnchorWatch;
+use crate::anomalies::Anomaly;
+use crate::apikeys::{self, ApiKeys, Area, KeyUsage};
+use crate::area_stats::AreaHistory;
+use crate::changes::Change;
+use crate::identity::Conflict;
+use crate::collision::Risk;
+use crate::distance::{self, DistanceReport, ShipDistance};
+use crate::emissions::{EmissionsReport, ShipEmissions};
+use crate::eta::{EtaSummary, ShipEtaStats};
+use crate::events::{Event, EventFilter};
+use crate::filter::{Filter, Scored};
+use crate::forward::{Forwarder, TargetStatus};
+use crate::geofence::{Zone, ZoneSpec};
+use crate::history::Timelapse;
+use crate::ingest::{IngestQueue, ParsePool};
+use crate::ingest_stats::IngestReport;
+use crate::live::LiveTick;
+use crate::logging::{LogFilter, LogLevel};
+use crate::kinematics::Kinematics;
+use crate::memory::MemoryUsage;
+use crate::monitor::Monitor;
+use crate::photos::Photos;
+use crate::tileproxy::{Layer, TileProxy};
+use crate::port_calls::PortCall;
+use crate::ports::{NearestPort, Port};
+use crate::predict::Prediction;
+use crate::quality::DataQuality;
+use crate::replay::{Clock, Replay, ReplayStatus};
+use crate::rendezvous::Meeting;
+use crate::searches::{SavedSearch, Search, Searches};
+use crate::simulate::{simulate_task, Simulation};
+use crate::ship::{Ship, ShipCache, ShipState};
+use crate::tiles::Tile;
+use crate::upstream::{UpstreamStatus, UpstreamTracker};
+use crate::config::{self, Config, MapConfig};
+use crate::firehose::Tap;
+use crate::plugin::{self, AisSink, AisSource};
+use crate::voyages::{self, Voyage};
+use crate::{access, alerts, area_stats, assets, export, history, index, ingest, intern, live, metrics, peer, schema, shutdown, signalk, sinks, units};
+
+type SharedShipCache = Arc<ShipCache>;
+
+#[derive(Clone)]
+struct AppState {
+    ships: SharedShipCache,
+    upstream: Arc<watch::Sender<Subscription>>,
+    upstream_status: Arc<UpstreamTracker>,
+    live: broadcast::Sender<Arc<LiveTick>>,
+    ingest: Arc<IngestQueue>,
+    memory_budget: Option<usize>, // Bytes
+    shutdown: watch::Receiver<bool>,
+    monitor: Arc<Monitor>,
+    forwarding: Arc<Forwarder>,
+    peer_token: Option<String>, // Accepting pushes from peers on /api/peer
+    signalk: Arc<signalk::SignalK>,
+    base_path: Arc<str>, // For URLs handed out to clients
+    http: Arc<access::HttpMetrics>,
+    api_keys: Arc<ApiKeys>,
+    log_filter: Option<LogFilter>, // When the process's logging was set up by `logging::init`
+    photos: Option<Arc<Photos>>,
+    searches: Arc<Searches>,
+    tile_proxy: Option<Arc<TileProxy>>,
+    offline: bool, // Nothing in /api/config may point at the internet
+    config: watch::Receiver<Arc<Config>>, // As last loaded or reloaded
+    replay: Option<Arc<Replay>>,
+    clock: Clock,
+}
+
+impl AppState {
+    // Replaying, the replay clock's time
+    fn now(&self) -> u64 {
+        self.clock.now()
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct Stats {
+    ships: usize,
+    pending_index_changes: usize,
+    shed_messages: u64,
+    suppressed_messages: u64, // Unchanged reports skipped; see `ingest::Unchanged`
+    memory: MemoryUsage,
+    memory_budget: Option<usize>,
+}
+
+// /api/ship/:mmsi: the ship plus derived fields
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct ShipDetail {
+    #[serde(flatten)]
+    ship: Ship,
+    nearest_port: Option<NearestPort>,
+    quality: DataQuality,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kinematics: Option<Kinematics>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    identity_conflicts: Vec<Conflict>,
+}
+
+// /api/export/history.ndjson: one ship or all of them, between Unix times
+#[derive(Deserialize)]
+struct HistoryParams {
+    mmsi: Option<u32>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+// /api/history/timelapse: Unix times, by default the last hour, and a step
+// like "5m"
+#[derive(Deserialize)]
+struct TimelapseParams {
+    from: Option<u64>,
+    to: Option<u64>,
+    step: Option<String>,
+}
+
+// /api/stats/distance and /api/stats/emissions: the last `days` UTC days,
+// today included, and the top `limit` ships
+#[derive(Deserialize)]
+struct DailyStatsParams {
+    days: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct LiveParams {
+    #[serde(default)]
+    format: live::Format,
+}
+
+// /api/replay/seek and /api/replay/speed
+#[derive(Deserialize)]
+struct SeekRequest {
+    time: u64,
+}
+
+#[derive(Deserialize)]
+struct SpeedRequest {
+    speed: f64,
+}
+
+// Ship list filters
+#[derive(Deserialize)]
+struct ShipFilter {
+    // Leave out ships whose data quality score is below this; see `crate::quality`
+    min_quality: Option<f64>,
+    // Only ships matching this expression, e.g. `type:cargo AND speed>12`; see `crate::filter`
+    filter: Option<Filter>,
+}
+
+// A ship inside a zone, for /api/zones/:name/occupancy
+#[derive(Serialize, JsonSchema)]
+pub(crate) struct Occupant {
+    mmsi: u32,
+    name: Arc<str>,
+    entered: u64,
+    dwell_secs: u64,
+}
+
+// Anchor watch request; the anchor defaults to the ship's last position
+#[derive(Deserialize)]
+struct AnchorWatchRequest {
+    radius_m: f64,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    #[serde(default)]
+    actions: Vec<Action>,
+}
+
+// How ships arrive, besides any sources
+enum Ingestion {
+    Upstream,
+    Follow(Url, Option<String>),
+    Simulate(Simulation),
+    None,
+}
+
+// Assembles seawatch from parts: the configuration, and optionally a ship
+// cache or monitor of one's own, extra sources and sinks, and where ships
+// come from. `build` and `start` need a Tokio runtime.
+pub struct SeamonBuilder {
+    config: Config,
+    ships: Option<Arc<ShipCache>>,
+    monitor: Option<Monitor>,
+    sources: Vec<Box<dyn AisSource>>,
+    sinks: Vec<Box<dyn AisSink>>,
+    ingestion: Ingestion,
+    raw_tap: Option<Tap>,
+    forwarding: Arc<Forwarder>,
+    peer_token: Option<String>,
+    api_keys: ApiKeys,
+    log_filter: Option<LogFilter>,
+    photos: Option<Photos>,
+    searches: Searches,
+    tile_proxy: Option<TileProxy>,
+    offline: bool,
+    replay: bool,
+    shutdown: Option<watch::Receiver<bool>>,
+}
+
+impl SeamonBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    // A cache to keep ships in, e.g. one shared with the host application
+    pub fn ships(mut self, ships: Arc<ShipCache>) -> Self {
+        self.ships = Some(ships);
+        self
+    }
+
+    // Zones, alert rules, ports and the rest, set up beforehand
+    pub fn monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    pub fn source(mut self, source: Box<dyn AisSource>) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn sink(mut self, sink: Box<dyn AisSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    // Take ships from another instance's /api/peer/feed instead of aisstream.io
+    pub fn follow(mut self, url: Url, token: Option<String>) -> Self {
+        self.ingestion = Ingestion::Follow(url, token);
+        self
+    }
+
+    // Synthetic vessels in place of aisstream.io, through the same parsing and batching
+    pub fn simulate(mut self, simulation: Simulation) -> Self {
+        self.ingestion = Ingestion::Simulate(simulation);
+        self
+    }
+
+    // Don't connect to aisstream.io; ships come from the sources only
+    pub fn without_upstream(mut self) -> Self {
+        self.ingestion = Ingestion::None;
+        self
+    }
+
+    // Gets a copy of every frame from aisstream.io, as received
+    pub fn raw_tap(mut self, tap: Tap) -> Self {
+        self.raw_tap = Some(tap);
+        self
+    }
+
+    pub fn forwarding(mut self, forwarding: Arc<Forwarder>) -> Self {
+        self.forwarding = forwarding;
+        self
+    }
+
+    // Accept pushes and followers on /api/peer with this token
+    pub fn peer_token(mut self, token: String) -> Self {
+        self.peer_token = Some(token);
+        self
+    }
+
+    // Require one of these keys on the API; without any, it is open but for
+    // /api/admin, which is off
+    pub fn api_keys(mut self, api_keys: ApiKeys) -> Self {
+        self.api_keys = api_keys;
+        self
+    }
+
+    // Lets /api/admin/log change the log filter at runtime
+    pub fn log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    // Serve ship photos on /api/ship/:mmsi/photo
+    pub fn photos(mut self, photos: Photos) -> Self {
+        self.photos = Some(photos);
+        self
+    }
+
+    // Serve basemap tiles from /tiles/{base,seamark}/:z/:x/:y.png, and point
+    // /api/config at them
+    pub fn tile_proxy(mut self, proxy: TileProxy) -> Self {
+        self.tile_proxy = Some(proxy);
+        self
+    }
+
+    // Nothing that needs the internet: no aisstream.io unless following or
+    // simulating instead, and no public tile servers in /api/config, so
+    // without a tile proxy the map has no basemap
+    pub fn offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    // Play the `states:` and `nmea-file:` sources' recordings against a clock
+    // /api/replay controls, instead of reading them straight through, and
+    // take the time to be the replay's everywhere; no aisstream.io
+    pub fn replay(mut self) -> Self {
+        self.replay = true;
+        self
+    }
+
+    // Saved searches to start with, e.g. `Searches::from_file`; empty otherwise
+    pub fn searches(mut self, searches: Searches) -> Self {
+        self.searches = searches;
+        self
+    }
+
+    // Winds everything down once `true` is sent; without it, runs until dropped
+    pub fn shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn build(self) -> Result<Seamon> {
+        let config = self.config;
+        let ships = match self.ships {
+            Some(ships) => ships,
+            None => Arc::new(ShipCache::with_index(config.index_kind()?).with_geohash_precision(config.ingest.geohash_precision)),
+        };
+        if self.peer_token.as_deref().is_some_and(|token| token.trim().is_empty()) {
+            return Err(anyhow::anyhow!("The peer token can't be empty"));
+        }
+        let monitor = Arc::new(self.monitor.unwrap_or_default());
+        // [regions] are zones like any other, by name
+        for (name, region) in &config.regions {
+            monitor.geofences.upsert(Zone::new(name, region.shape.clone()).with_max_speed(region.max_speed_kn));
+        }
+        let mut queue = IngestQueue::new(config.ingest.queue_size, config.shed_policy()?);
+        if let Some(unchanged) = config.unchanged() {
+            queue = queue.with_unchanged(unchanged);
+        }
+        let queue = Arc::new(queue);
+        let (upstream_tx, upstream_rx) = watch::channel(Subscription::default());
+        let (live_tx, _) = broadcast::channel(live::TICK_BUFFER);
+        // A sender that is never used, kept so the receiver doesn't see it go
+        let (never, shutdown) = match self.shutdown {
+            Some(shutdown) => (None, shutdown),
+            None => {
+                let (tx, rx) = watch::channel(false);
+                (Some(tx), rx)
+            }
+        };
+        let config_tx = watch::channel(Arc::new(config.clone())).0;
+        let replay = self.replay.then(|| Arc::new(Replay::new()));
+        let state = AppState {
+            ships,
+            upstream: Arc::new(upstream_tx),
+            upstream_status: Arc::new(UpstreamTracker::new()),
+            live: live_tx,
+            ingest: queue,
+            memory_budget: config.ingest.memory_budget_mb.map(|mb| mb * 1024 * 1024),
+            shutdown,
+            signalk: signalk::SignalK::start(monitor.updates.subscribe()),
+            monitor,
+            forwarding: self.forwarding,
+            peer_token: self.peer_token,
+            base_path: Arc::from(config.base_path()),
+            http: Arc::new(access::HttpMetrics::new(config.server.access_log)),
+            api_keys: Arc::new(self.api_keys),
+            log_filter: self.log_filter,
+            photos: self.photos.map(Arc::new),
+            searches: Arc::new(self.searches),
+            tile_proxy: self.tile_proxy.map(Arc::new),
+            offline: self.offline,
+            config: config_tx.subscribe(),
+            clock: replay.clone().map(Clock::replay).unwrap_or_default(),
+            replay,
+        };
+        let ingestion = match self.ingestion {
+            Ingestion::Upstream if self.offline || self.replay => Ingestion::None,
+            ingestion => ingestion,
+        };
+        Ok(Seamon {
+            config: config_tx,
+            state,
+            pending: Some(Pending {
+                upstream: upstream_rx,
+                sources: self.sources,
+                sinks: self.sinks,
+                ingestion,
+                raw_tap: self.raw_tap,
+            }),
+            _never: never,
+        })
+    }
+}
+
+// What `start` hands over to the tasks it spawns
+struct Pending {
+    upstream: watch::Receiver<Subscription>,
+    sources: Vec<Box<dyn AisSource>>,
+    sinks: Vec<Box<dyn AisSink>>,
+    ingestion: Ingestion,
+    raw_tap: Option<Tap>,
+}
+
+// The ship cache, monitor and API of a seawatch instance. `router` gives the
+// endpoints to serve or mount; `start` gets ships flowing.
+pub struct Seamon {
+    config: watch::Sender<Arc<Config>>,
+    state: AppState,
+    pending: Option<Pending>,
+    _never: Option<watch::Sender<bool>>,
+}
+
+impl Seamon {
+    pub fn builder() -> SeamonBuilder {
+        SeamonBuilder {
+            config: Config::default(),
+            ships: None,
+            monitor: None,
+            sources: Vec::new(),
+            sinks: Vec::new(),
+            ingestion: Ingestion::Upstream,
+            raw_tap: None,
+            forwarding: Arc::default(),
+            peer_token: None,
+            api_keys: ApiKeys::default(),
+            log_filter: None,
+            photos: None,
+            searches: Searches::default(),
+            tile_proxy: None,
+            offline: false,
+            replay: false,
+            shutdown: None,
+        }
+    }
+
+    pub fn ships(&self) -> &Arc<ShipCache> {
+        &self.state.ships
+    }
+
+    pub fn monitor(&self) -> &Arc<Monitor> {
+        &self.state.monitor
+    }
+
+    pub fn signalk(&self) -> &Arc<signalk::SignalK> {
+        &self.state.signalk
+    }
+
+    // Send a new configuration to apply it while running; see
+    // `reload::reload_task` for what needs a restart instead
+    pub fn config(&self) -> &watch::Sender<Arc<Config>> {
+        &self.config
+    }
+
+    // Swap the keys with `ApiKeys::replace` to change them while running
+    pub fn api_keys(&self) -> &Arc<ApiKeys> {
+        &self.state.api_keys
+    }
+
+    // Spawns ingestion, the sources and sinks and the periodic upkeep of the
+    // cache and monitor. Returns the ingestion task, which ends on shutdown
+    // once the frames received so far are parsed; only the first call starts anything.
+    pub fn start(&mut self) -> Result<JoinHandle<()>> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(tokio::spawn(async {}));
+        };
+        let config = self.config.borrow().clone();
+        let AppState { ships, monitor, ingest: queue, shutdown, clock, .. } = &self.state;
+
+        let ingestion = match pending.ingestion {
+            Ingestion::Follow(url, token) => {
+                if pending.raw_tap.is_some() {
+                    warn!("FIREHOSE_FORMAT=raw has no raw frames to send while following another instance");
+                }
+                tokio::spawn(peer::follow_task(url, token, ships.clone(), monitor.clone(), shutdown.clone()))
+            }
+            Ingestion::Upstream => {
+                let mut parsers = ParsePool::new(config.parse_workers(), queue.clone(), monitor.ingest.clone(), "upstream")?;
+                if let Some(tap) = pending.raw_tap {
+                    parsers.tap(tap);
+                }
+                tokio::spawn(ais_stream_task(parsers, self.config.subscribe(), pending.upstream, self.state.upstream_status.clone(), shutdown.clone()))
+            }
+            Ingestion::Simulate(simulation) => {
+                let mut parsers = ParsePool::new(config.parse_workers(), queue.clone(), monitor.ingest.clone(), "simulator")?;
+                if let Some(tap) = pending.raw_tap {
+                    parsers.tap(tap);
+                }
+                tokio::spawn(simulate_task(parsers, simulation, shutdown.clone()))
+            }
+            Ingestion::None => tokio::spawn(async {}),
+        };
+        for source in pending.sources {
+            let mut context = plugin::SourceContext::new(source.name(), ships.clone(), monitor.clone(), shutdown.clone());
+            if let Some(replay) = &self.state.replay {
+                context = context.with_replay(replay.clone());
+            }
+            tokio::spawn(plugin::source_task(source, context));
+        }
+        for sink in pending.sinks {
+            let feed = sinks::Feed::new(format!("Sink '{}'", sink.name()), monitor);
+            tokio::spawn(plugin::sink_task(feed, sink));
+        }
+        tokio::spawn(ingest::batch_writer_task(ships.clone(), queue.clone(), monitor.clone()));
+        tokio::spawn(settings_task(queue.clone(), self.state.http.clone(), self.config.subscribe()));
+
+        // Start the sweep for ships and alert rules that have gone quiet
+        tokio::spawn(monitor_sweep_task(ships.clone(), monitor.clone(), clock.clone(), self.config.subscribe()));
+
+        // Start collision risk monitoring
+        tokio::spawn(collision_scan_task(ships.clone(), monitor.clone(), clock.clone(), self.config.subscribe()));
+
+        // Start cache cleanup task
+        tokio::spawn(cache_cleanup_task(ships.clone(), self.config.subscribe(), clock.clone()));
+
+        // Start spatial index rebuild task
+        tokio::spawn(index_rebuild_task(ships.clone(), self.config.subscribe()));
+
+        // Start live feed publisher
+        tokio::spawn(live::publisher_task(ships.clone(), self.state.live.clone()));
+
+        // Start memory budget watcher
+        if let Some(budget) = self.state.memory_budget {
+            tokio::spawn(memory_watch_task(ships.clone(), budget, self.config.subscribe()));
+        }
+        Ok(ingestion)
+    }
+
+    // Applies whatever is still buffered, for after the ingestion task has ended
+    pub fn drain(&self) {
+        ingest::apply_batch(&self.state.ships, self.state.ingest.drain(), &self.state.monitor);
+    }
+
+    // The API and, unless headless, the UI, relative to the configured base
+    // path; to be served as is or nested in another router
+    pub fn router(&self) -> Router {
+        let config = self.config.borrow();
+        let mut app = Router::new()
+        .route("/api/ships/:sw_lat/:sw_lng/:ne_lat/:ne_lng", get(get_ships_in_bbox))
+        .route("/api/ships/region/:name", get(get_ships_in_region))
+        .route("/api/export/ships.ndjson", get(export_ships))
+        .route("/api/export/history.ndjson", get(export_history))
+        .route("/api/history/timelapse/:sw_lat/:sw_lng/:ne_lat/:ne_lng", get(get_timelapse))
+        .route("/api/searches", get(get_searches))
+        .route("/api/searches/:name", get(get_search).put(put_search).delete(delete_search))
+        .route("/api/searches/:name/ships", get(get_search_results))
+        .route("/api/tiles/:z/:x/:y", get(get_ships_in_tile))
+        .route("/api/ship/:mmsi", get(get_ship_info))
+        .route("/api/ship/:mmsi/port-calls", get(get_port_calls))
+        .route("/api/ship/:mmsi/changes", get(get_ship_changes))
+        .route("/api/ship/:mmsi/voyages", get(get_voyages))
+        .route("/api/ship/:mmsi/nearest-port", get(get_nearest_port))
+        .route("/api/ship/:mmsi/prediction", get(get_prediction))
+        .route("/api/ship/:mmsi/photo", get(get_ship_photo))
+        .route("/api/ports", get(get_ports))
+        .route("/api/destinations/:locode", get(get_ships_by_destination))
+        .route("/api/live", get(live_feed))
+        .route("/api/peer", get(peer_session))
+        .route("/api/peer/feed", get(peer_feed))
+        .route("/api/zones", get(get_zones))
+        .route("/api/zones/:name/occupancy", get(get_zone_occupancy))
+        .route("/api/stats/area/:name", get(get_area_stats))
+        .route("/api/stats/distance", get(get_distance_stats))
+        .route("/api/stats/distance/:mmsi", get(get_ship_distance))
+        .route("/api/stats/emissions", get(get_emissions_stats))
+        .route("/api/stats/emissions/:mmsi", get(get_ship_emissions))
+        .route("/api/stats/eta", get(get_eta_accuracy))
+        .route("/api/stats/ingest", get(get_ingest_stats))
+        .route("/api/stats/eta/:mmsi", get(get_ship_eta_accuracy))
+        .route("/api/events", get(get_events))
+        .route("/api/events/stream", get(stream_events))
+        .route("/api/collisions", get(get_collision_risks))
+        .route("/api/anomalies", get(get_anomalies))
+        .route("/api/identity-conflicts", get(get_identity_conflicts))
+        .route("/api/rendezvous", get(get_rendezvous))
+        .route("/api/anchors", get(get_anchor_watches))
+        .route("/api/anchors/:mmsi", put(put_anchor_watch).delete(delete_anchor_watch))
+        .route("/api/replay", get(get_replay))
+        .route("/api/replay/play", post(play_replay))
+        .route("/api/replay/pause", post(pause_replay))
+        .route("/api/replay/seek", post(seek_replay))
+        .route("/api/replay/speed", post(set_replay_speed))
+        .route("/signalk", get(get_signalk))
+        .route("/signalk/v1/stream", get(signalk_stream))
+        .route("/api/config", get(get_map_config))
+        .route("/tiles/:layer/:z/:x/:file", get(get_base_tile))
+        .route("/api/schema", get(get_schemas))
+        .route("/api/schema/:name", get(get_schema))
+        .route("/metrics", get(get_metrics));
+        // Headless, only the API is served, for a frontend of one's own
+        if !config.server.headless {
+            app = app.route("/", self.index());
+            app = match &config.server.static_dir {
+                // Files on disk in place of the embedded ones, e.g. a customised UI
+                Some(dir) => app.nest_service("/static", ServeDir::new(dir)),
+                None => app.route("/static/*path", get(static_asset)),
+            };
+        }
+        if let Some(replay) = &self.state.replay {
+            app = app.layer(axum::middleware::from_fn_with_state(replay.clone(), crate::replay::header));
+        }
+        // The web pages in server.cors_origins may read the API, as it is
+        // when the request comes, but none may change the server through it
+        let admin = Router::new()
+        .route("/api/admin/upstream", post(update_upstream))
+        .route("/api/admin/upstream/status", get(get_upstream_status))
+        .route("/api/admin/zones/:name", put(put_zone).delete(delete_zone))
+        .route("/api/admin/alerts", get(get_alert_rules))
+        .route("/api/admin/alerts/:name", put(put_alert_rule).delete(delete_alert_rule))
+        .route("/api/admin/stats", get(get_stats))
+        .route("/api/admin/keys", get(get_api_keys))
+        .route("/api/admin/log", get(get_log_level).put(put_log_level).delete(reset_log_level))
+        .route("/api/admin/forwarding", get(get_forwarding))
+        .route("/api/admin/forwarding/:name", put(put_forwarding));
+        let origins = self.state.config.clone();
+        let cors = AllowOrigin::predicate(move |origin, _| {
+            let allowed = &origins.borrow().server.cors_origins;
+            allowed.iter().any(|allowed| allowed == "*" || allowed.as_bytes() == origin.as_bytes())
+        });
+        app = app.layer(CorsLayer::permissive().allow_origin(cors)).merge(admin);
+        let keys = (self.state.api_keys.clone(), self.state.base_path.clone());
+        app.route_layer(axum::middleware::from_fn(units::convert))
+            .route_layer(axum::middleware::from_fn_with_state(keys, apikeys::authorize))
+            .route_layer(axum::middleware::from_fn_with_state(self.state.http.clone(), access::track))
+            .with_state(self.state.clone())
+    }
+
+    // The router as served on its own: `router`, moved under the base path
+    // for a proxy that forwards /seamon/... as is
+    pub fn app(&self) -> Router {
+        let config = self.config.borrow().clone();
+        let base = config.base_path();
+        if base.is_empty() {
+            return self.router();
+        }
+        let app = Router::new().nest(base, self.router());
+        // The nested "/" only matches without the trailing slash
+        match config.server.headless {
+            true => app,
+            false => app.route(&format!("{}/", base), self.index()),
+        }
+    }
+
+    // The UI page, told the base path; for mounting at paths of one's own
+    pub fn index<S: Clone + Send + Sync + 'static>(&self) -> MethodRouter<S> {
+        let config = self.config.borrow();
+        let (base, dir) = (config.base_path().to_string(), config.server.static_dir.clone());
+        get(move |headers: HeaderMap| async move { assets::index(&base, dir.as_deref(), &headers) })
+    }
+}
+
+// Connects again with the new settings when [upstream] is reloaded
+async fn ais_stream_task(
+    parsers: ParsePool,
+    mut reloads: watch::Receiver<Arc<Config>>,
+    mut upstream: watch::Receiver<Subscription>,
+    status: Arc<UpstreamTracker>,
+    shutdown: watch::Receiver<bool>,
+) {
+    while !*shutdown.borrow() {
+        let config = reloads.borrow_and_update().upstream.clone();
+        status.connecting(&config.url);
+        if let Err(e) = run_ais_stream(&parsers, &config, &mut reloads, &mut upstream, &status, shutdown.clone()).await {
+            error!(url = %config.url, "AIS stream error: {}", e);
+            parsers.stats().disconnected("upstream", e.to_string());
+            status.failed(e.to_string());
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(config.reconnect_secs)) => {}
+                _ = shutdown::requested(shutdown.clone()) => {}
+            }
+        }
+    }
+
+    status.disconnected();
+    // Let the parse workers finish the frames already handed to them
+    let _ = tokio::task::spawn_blocking(move || parsers.finish()).await;
+}
+
+async fn run_ais_stream(
+    parsers: &ParsePool,
+    config: &config::UpstreamConfig,
+    reloads: &mut watch::Receiver<Arc<Config>>,
+    upstream: &mut watch::Receiver<Subscription>,
+    status: &UpstreamTracker,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let api_key = config
+        .api_key
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No aisstream.io API key; set AIS_STREAM_API_KEY or upstream.api_key"))?;
+    
+    let url = Url::parse(&config.url)?;
+    let subscription = upstream.borrow_and_update().clone();
+    let mut ais_stream = AisStream::connect(url, api_key, &subscription).await?;
+    
+    info!(url = %config.url, "Connected to AIS stream");
+    parsers.stats().connected("upstream");
+    status.connected(&subscription);
+
+    loop {
+        tokio::select! {
+            frame = ais_stream.next_frame() => match frame? {
+                Some(frame) => {
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+                    status.message(timestamp);
+                    parsers.submit(timestamp, frame);
+                }
+                None => {
+                    parsers.stats().disconnected("upstream", "Closed by the server".to_string());
+                    status.failed("Closed by the server".to_string());
+                    return Ok(());
+                }
+            },
+            _ = shutdown::requested(shutdown.clone()) => {
+                info!("Closing AIS stream");
+                ais_stream.close().await;
+                return Ok(());
+            }
+            changed = upstream.changed() => {
+                changed?;
+                // Tear down and let the caller reconnect with the new subscription
+                info!("Upstream subscription changed, reconnecting");
+                ais_stream.close().await;
+                return Ok(());
+            }
+            Ok(()) = reloads.changed() => {
+                if reloads.borrow().upstream != *config {
+                    info!("Upstream configuration reloaded, reconnecting");
+                    ais_stream.close().await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// An interval whose period is a setting, following reloads of it; a new
+// period starts from the reload
+struct Ticker {
+    config: watch::Receiver<Arc<Config>>,
+    secs: fn(&Config) -> u64,
+    period: u64,
+    interval: Interval,
+}
+
+impl Ticker {
+    fn new(mut config: watch::Receiver<Arc<Config>>, secs: fn(&Config) -> u64) -> Self {
+        let period = secs(&config.borrow_and_update());
+        Self { config, secs, period, interval: interval(Duration::from_secs(period)) }
+    }
+
+    async fn tick(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.interval.tick() => return,
+                Ok(()) = self.config.changed() => {
+                    let period = (self.secs)(&self.config.borrow_and_update());
+                    if period != self.period {
+                        self.period = period;
+                        self.interval = interval_at(Instant::now() + Duration::from_secs(period), Duration::from_secs(period));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Applies reloads of the shed policy, the unchanged-report thresholds and
+// the access log
+async fn settings_task(queue: Arc<IngestQueue>, http: Arc<access::HttpMetrics>, mut config: watch::Receiver<Arc<Config>>) {
+    while config.changed().await.is_ok() {
+        let config = config.borrow_and_update().clone();
+        // Loaded configurations have a valid policy
+        if let Ok(policy) = config.shed_policy() {
+            queue.reconfigure(policy, config.unchanged());
+        }
+        http.set_access_log(config.server.access_log);
+    }
+}
+
+// Follows reloads of the retention settings
+async fn cache_cleanup_task(ships: SharedShipCache, config: watch::Receiver<Arc<config::Config>>, clock: Clock) {
+    let mut ticker = Ticker::new(config.clone(), |config| config.retention.cleanup_interval_secs);
+    
+    loop {
+        ticker.tick().await;
+        let ttl = config.borrow().retention.ship_ttl_secs;
+        
+        let current_time = clock.now();
+        
+        // Remove ships not seen for a while (a day by default)
+        ships.remove_stale(current_time.saturating_sub(ttl));
+        // Names and destinations only the removed ships were using
+        intern::purge_unused();
+        
+        info!("Cache cleanup completed, {} ships remaining", ships.len());
+    }
+}
+
+async fn index_rebuild_task(ships: SharedShipCache, config: watch::Receiver<Arc<Config>>) {
+    let mut ticker = Ticker::new(config, |config| config.intervals.index_check_secs);
+
+    loop {
+        ticker.tick().await;
+
+        // The index is updated in place; rebuilds only restore its balance, one
+        // drifted cell at a time
+        for cell in ships.cells_needing_rebuild() {
+            // Building the tree is CPU-bound, keep it off the async workers
+            let ships = ships.clone();
+            let started = std::time::Instant::now();
+            if let Err(e) = tokio::task::spawn_blocking(move || ships.rebuild_cell(cell)).await {
+                error!("Index rebuild failed: {}", e);
+            }
+            debug!("Rebuilt spatial index cell {} in {:?}", cell, started.elapsed());
+        }
+    }
+}
+
+async fn memory_watch_task(ships: SharedShipCache, budget: usize, config: watch::Receiver<Arc<Config>>) {
+    let mut ticker = Ticker::new(config, |config| config.intervals.memory_check_secs);
+
+    loop {
+        ticker.tick().await;
+
+        let ships = ships.clone();
+        let Ok(usage) = tokio::task::spawn_blocking(move || ships.memory_usage()).await else {
+            continue;
+        };
+        if usage.total > budget {
+            warn!(
+                "Memory use ~{} MB is over the {} MB budget: {:?}",
+                usage.total / (1024 * 1024),
+                budget / (1024 * 1024),
+                usage
+            );
+        }
+    }
+}
+
+async fn monitor_sweep_task(ships: SharedShipCache, monitor: Arc<Monitor>, clock: Clock, config: watch::Receiver<Arc<Config>>) {
+    let mut ticker = Ticker::new(config, |config| config.intervals.sweep_secs);
+
+    loop {
+        ticker.tick().await;
+
+        let now = clock.now();
+        let (ships, monitor) = (ships.clone(), monitor.clone());
+        let _ = tokio::task::spawn_blocking(move || monitor.sweep(&ships, now)).await;
+    }
+}
+
+async fn collision_scan_task(ships: SharedShipCache, monitor: Arc<Monitor>, clock: Clock, config: watch::Receiver<Arc<Config>>) {
+    let mut ticker = Ticker::new(config, |config| config.intervals.collision_scan_secs);
+
+    loop {
+        ticker.tick().await;
+
+        let now = clock.now();
+        let (ships, monitor) = (ships.clone(), monitor.clone());
+        let _ = tokio::task::spawn_blocking(move || monitor.collisions.scan(&ships, &monitor.tracks, &monitor.events, now)).await;
+    }
+}
+
+async fn static_asset(Path(path): Path<String>, headers: HeaderMap) -> Response {
+    assets::serve(&path, &headers)
+}
+
+async fn get_ships_in_bbox(
+    Path((sw_lat, sw_lng, ne_lat, ne_lng)): Path<(f64, f64, f64, f64)>,
+    Query(filter): Query<ShipFilter>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    // An API key limited to an area only sees the part of the bbox inside it
+    let bbox = match area {
+        Some(Extension(area)) => area.clip(sw_lat, sw_lng, ne_lat, ne_lng),
+        None => Some((sw_lat, sw_lng, ne_lat, ne_lng)),
+    };
+    // The index is kept current on every update, so this never waits on a rebuild
+    let body = match (bbox, filter.min_quality, &filter.filter) {
+        (Some((sw_lat, sw_lng, ne_lat, ne_lng)), None, None) => state.ships.get_ships_in_bbox_cached(sw_lat, sw_lng, ne_lat, ne_lng),
+        // Scores change with time as well as updates, so filtered lists aren't cached
+        (Some((sw_lat, sw_lng, ne_lat, ne_lng)), min_quality, expression) => {
+            let now = state.now();
+            let context = Scored { monitor: &state.monitor, now };
+            let states: Vec<ShipState> = state
+                .ships
+                .get_full_ships_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng)
+                .iter()
+                .filter(|ship| min_quality.is_none_or(|min_quality| state.monitor.quality(ship, now).score >= min_quality))
+                .filter(|ship| expression.as_ref().is_none_or(|expression| expression.matches(ship, &context)))
+                .map(Ship::to_state)
+                .collect();
+            Bytes::from(serde_json::to_vec(&states).unwrap_or_default())
+        }
+        (None, _, _) => Bytes::from_static(b"[]"),
+    };
+    
+    ([(header::CONTENT_TYPE, "application/json")], body)
+}
+
+// Ships inside a named zone, whether set up under [regions] or through the API
+async fn get_ships_in_region(
+    Path(name): Path<String>,
+    Query(filter): Query<ShipFilter>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ShipState>>, StatusCode> {
+    let mut search = Search { region: Some(name), ..Default::default() };
+    search.filters.min_quality = filter.min_quality;
+    run_search(&state, &search, filter.filter.as_ref(), area.map(|Extension(area)| area)).map(Json)
+}
+
+// The ships a search finds, and `expression` if given, within the caller's
+// area if its key has one; 404 for a region that doesn't exist (any more)
+fn run_search(state: &AppState, search: &Search, expression: Option<&Filter>, area: Option<Area>) -> Result<Vec<ShipState>, StatusCode> {
+    let zone = match &search.region {
+        Some(name) => Some(state.monitor.geofences.zone(name).ok_or(StatusCode::NOT_FOUND)?),
+        None => None,
+    };
+    let (sw_lat, sw_lng, ne_lat, ne_lng) = match (&zone, search.bbox) {
+        (Some(zone), _) => zone.bounds(),
+        (None, Some([south, west, north, east])) => (south, west, north, east),
+        (None, None) => (-90.0, -180.0, 90.0, 180.0),
+    };
+    let bbox = match area {
+        Some(area) => area.clip(sw_lat, sw_lng, ne_lat, ne_lng),
+        None => Some((sw_lat, sw_lng, ne_lat, ne_lng)),
+    };
+    let Some((sw_lat, sw_lng, ne_lat, ne_lng)) = bbox else {
+        return Ok(Vec::new());
+    };
+    let now = state.now();
+    let context = Scored { monitor: &state.monitor, now };
+    Ok(state
+        .ships
+        .get_full_ships_in_bbox(sw_lat, sw_lng, ne_lat, ne_lng)
+        .iter()
+        .filter(|ship| zone.as_ref().is_none_or(|zone| zone.contains(ship.lat, ship.lng)))
+        .filter(|ship| search.filters.matches(ship, |ship| state.monitor.quality(ship, now).score))
+        .filter(|ship| expression.is_none_or(|expression| expression.matches(ship, &context)))
+        .map(Ship::to_state)
+        .collect())
+}
+
+// Newline-delimited JSON, streamed a chunk at a time
+async fn export_ships(State(state): State<AppState>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], export::ships(state.ships.clone()))
+}
+
+async fn export_history(Query(params): Query<HistoryParams>, State(state): State<AppState>) -> impl IntoResponse {
+    let body = export::history(state.monitor.clone(), params.mmsi, params.since.unwrap_or(0), params.until.unwrap_or(u64::MAX));
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], body)
+}
+
+async fn get_timelapse(
+    Path((sw_lat, sw_lng, ne_lat, ne_lng)): Path<(f64, f64, f64, f64)>,
+    Query(params): Query<TimelapseParams>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> Result<Json<Timelapse>, StatusCode> {
+    let now = state.now();
+    let step = match params.step.as_deref().map(area_stats::parse_window) {
+        None => 300,
+        Some(Ok(step)) => step,
+        Some(Err(_)) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let to = params.to.unwrap_or(now);
+    let from = params.from.unwrap_or(to.saturating_sub(3600));
+    if from > to || (to - from) / step >= history::MAX_FRAMES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let bbox = match area {
+        Some(Extension(area)) => area.clip(sw_lat, sw_lng, ne_lat, ne_lng),
+        None => Some((sw_lat, sw_lng, ne_lat, ne_lng)),
+    };
+    // Outside the caller's area every frame is empty
+    let bbox = bbox.map_or([0.0, 0.0, -1.0, -1.0], |(sw_lat, sw_lng, ne_lat, ne_lng)| [sw_lat, sw_lng, ne_lat, ne_lng]);
+    // Interpolating every frame is CPU-bound, keep it off the async workers
+    let tracks = state.monitor.history.window(bbox, from, to);
+    let timelapse = tokio::task::spawn_blocking(move || history::timelapse(&tracks, bbox, from, to, step));
+    Ok(Json(timelapse.await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?))
+}
+
+async fn get_searches(State(state): State<AppState>) -> Json<Vec<SavedSearch>> {
+    Json(state.searches.all())
+}
+
+async fn get_search(Path(name): Path<String>, State(state): State<AppState>) -> Result<Json<SavedSearch>, StatusCode> {
+    state.searches.get(&name).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn put_search(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(search): Json<Search>,
+) -> Result<Json<SavedSearch>, StatusCode> {
+    if let Err(e) = search.validate() {
+        warn!("Rejected search '{}': {}", name, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let updated = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let saved = SavedSearch { name, search, updated };
+    if let Err(e) = state.searches.upsert(saved.clone()) {
+        warn!("Could not save search '{}': {}", saved.name, e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    Ok(Json(saved))
+}
+
+async fn delete_search(Path(name): Path<String>, State(state): State<AppState>) -> StatusCode {
+    match state.searches.remove(&name) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            warn!("Could not save searches after removing '{}': {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_search_results(
+    Path(name): Path<String>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ShipState>>, StatusCode> {
+    let saved = state.searches.get(&name).ok_or(StatusCode::NOT_FOUND)?;
+    run_search(&state, &saved.search, None, area.map(|Extension(area)| area)).map(Json)
+}
+
+async fn get_ships_in_tile(
+    Path((z, x, y)): Path<(u8, u32, u32)>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let tile = Tile::new(z, x, y).ok_or(StatusCode::BAD_REQUEST)?;
+    // Cached tiles are whole, so for an area that doesn't cover the tile, only its part is served
+    let (sw_lat, sw_lng, ne_lat, ne_lng) = tile.bounds();
+    if let Some(Extension(area)) = area
+        && !area.covers(sw_lat, sw_lng, ne_lat, ne_lng)
+    {
+        let body = match area.clip(sw_lat, sw_lng, ne_lat, ne_lng) {
+            Some((sw_lat, sw_lng, ne_lat, ne_lng)) => Bytes::from(state.ships.get_ships_in_bbox_json(sw_lat, sw_lng, ne_lat, ne_lng)),
+            None => Bytes::from_static(b"[]"),
+        };
+        return Ok(([(header::CONTENT_TYPE, "application/json")], body));
+    }
+
+    let body = state.ships.tiles.get_or_compute(tile, || {
+        let (sw_lat, sw_lng, ne_lat, ne_lng) = tile.bounds();
+        state.ships.get_ships_in_bbox_json(sw_lat, sw_lng, ne_lat, ne_lng).into()
+    });
+
+    Ok(([(header::CONTENT_TYPE, "application/json")], body))
+}
+
+async fn get_ship_info(
+    Path(mmsi): Path<u32>,
+    area: Option<Extension<Area>>,
+    State(state): State<AppState>,
+) -> Result<Json<ShipDetail>, StatusCode> {
+    match state.ships.ships.get(&mmsi) {
+        // Ships outside a key's area are as good as unknown to it
+        Some(ship) if area.is_some_and(|Extension(area)| !area.contains(ship.lat, ship.lng)) => Err(StatusCode::NOT_FOUND),
+        Some(ship) => {
+            let now = state.now();
+            Ok(Json(ShipDetail {
+                nearest_port: state.monitor.nearest_port(&ship),
+                quality: state.monitor.quality(&ship, now),
+                kinematics: state.monitor.kinematics(ship.mmsi, now),
+                identity_conflicts: state.monitor.identities.of(ship.mmsi),
+                ship: ship.clone(),
+            }))
+        }
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_nearest_port(
+    Path(mmsi): Path<u32>,
+    State(state): State<AppState>,
+) -> Result<Json<NearestPort>, StatusCode> {
+    let ship = state.ships.ships.get(&mmsi).map(|ship| ship.clone()).ok_or(StatusCode::NOT_FOUND)?;
+    state.monitor.nearest_port(&ship).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_ship_photo(Path(mmsi): Path<u32>, State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let photos = state.photos.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let imo = state.ships.ships.get(&mmsi).map(|ship| ship.imo_number).ok_or(StatusCode::NOT_FOUND)?;
+    match photos.get(mmsi, imo).await {
+        Ok(Some(photo)) => Ok((
+            [(header::CONTENT_TYPE, photo.content_type), (header::CACHE_CONTROL, "public, max-age=86400".to_string())],
+            photo.bytes,
+        )
+            .into_response()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!(mmsi, "Photo lookup failed: {}", e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+async fn live_feed(ws: WebSocketUpgrade, Query(params): Query<LiveParams>, State(state): State<AppState>) -> impl IntoResponse {
+    let ticks = state.live.subscribe();
+    ws.on_upgrade(move |socket| live::client_session(socket, state.ships, state.monitor, params.format, ticks, state.shutdown))
+}
+
+async fn get_signalk(headers: HeaderMap, State(state): State<AppState>) -> Json<serde_json::Value> {
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or("localhost");
+    Json(signalk::endpoints(&format!("{}{}", host, state.base_path)))
+}
+
+async fn signalk_stream(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| async move { state.signalk.stream(socket, state.ships).await })
+}
+
+async fn peer_session(ws: WebSocketUpgrade, State(state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    let token = state.peer_token.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(ws.on_upgrade(move |socket| peer::receive_session(socket, token, state.ships, state.monitor)))
+}
+
+async fn peer_feed(ws: WebSocketUpgrade, State(state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    let token = state.peer_token.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(ws.on_upgrade(move |socket| peer::feed_session(socket, token, state.ships, state.monitor)))
+}
+
+async fn update_upstream(
+    State(state): State<AppState>,
+    Json(update): Json<SubscriptionUpdate>,
+) -> Result<Json<Subscription>, StatusCode> {
+    // Merged in place, so concurrent updates each apply on top of the other
+    let mut result = Err(StatusCode::BAD_REQUEST);
+    state.upstream.send_if_modified(|current| {
+        let mut subscription = current.clone();
+        subscription.apply(update);
+        if let Err(e) = subscription.validate() {
+            warn!("Rejected upstream subscription update: {}", e);
+            return false;
+        }
+        info!("Updating upstream subscription: {:?}", subscription);
+        *current = subscription.clone();
+        result = Ok(Json(subscription));
+        true
+    });
+    result
+}
+
+async fn get_upstream_status(State(state): State<AppState>) -> Json<UpstreamStatus> {
+    Json(state.upstream_status.status())
+}
+
+async fn get_stats(State(state): State<AppState>) -> Json<Stats> {
+    Json(Stats {
+        ships: state.ships.len(),
+        pending_index_changes: state.ships.pending_changes(),
+        shed_messages: state.ingest.shed_count(),
+        suppressed_messages: state.ingest.suppressed_count(),
+        memory: state.ships.memory_usage(),
+        memory_budget: state.memory_budget,
+    })
+}
+
+async fn get_api_keys(State(state): State<AppState>) -> Json<Vec<KeyUsage>> {
+    Json(state.api_keys.usage())
+}
+
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = metrics::render(&state.ships, &state.ingest, &state.ships.memory_usage(), &state.forwarding);
+    state.http.render(&mut body);
+    state.api_keys.render(&mut body);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+async fn get_zones(State(state): State<AppState>) -> Json<Vec<Zone>> {
+    Json(state.monitor.geofences.zones())
+}
+
+async fn get_zone_occupancy(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Occupant>>, StatusCode> {
+    let occupancy = state.monitor.geofences.occupancy(&name).ok_or(StatusCode::NOT_FOUND)?;
+    let now = state.now();
+    let occupants = occupancy
+        .into_iter()
+        .map(|(mmsi, entered)| Occupant {
+            mmsi,
+            name: state.ships.ships.get(&mmsi).map_or_else(|| Arc::from(""), |ship| ship.name.clone()),
+            entered,
+            dwell_secs: now.saturating_sub(entered),
+        })
+        .collect();
+    Ok(Json(occupants))
+}
+
+async fn get_distance_stats(
+    Query(params): Query<DailyStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<DistanceReport>, StatusCode> {
+    let days = stats_days(params.days)?;
+    let now = state.now();
+    Ok(Json(state.monitor.distances.report(days, params.limit.unwrap_or(20), now)))
+}
+
+async fn get_ship_distance(
+    Path(mmsi): Path<u32>,
+    Query(params): Query<DailyStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<ShipDistance>, StatusCode> {
+    let days = stats_days(params.days)?;
+    let now = state.now();
+    state.monitor.distances.ship(mmsi, days, now).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_emissions_stats(
+    Query(params): Query<DailyStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<EmissionsReport>, StatusCode> {
+    let days = stats_days(params.days)?;
+    let now = state.now();
+    Ok(Json(state.monitor.emissions.report(days, params.limit.unwrap_or(20), now)))
+}
+
+async fn get_ship_emissions(
+    Path(mmsi): Path<u32>,
+    Query(params): Query<DailyStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<ShipEmissions>, StatusCode> {
+    let days = stats_days(params.days)?;
+    let now = state.now();
+    state.monitor.emissions.ship(mmsi, days, now).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+fn stats_days(days: Option<u64>) -> Result<u64, StatusCode> {
+    match days.unwrap_or(7) {
+        days @ 1..=distance::DAYS_KEPT => Ok(days),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_eta_accuracy(State(state): State<AppState>) -> Json<EtaSummary> {
+    Json(state.monitor.eta.summary())
+}
+
+async fn get_ship_eta_accuracy(
+    Path(mmsi): Path<u32>,
+    State(state): State<AppState>,
+) -> Result<Json<ShipEtaStats>, StatusCode> {
+    state.monitor.eta.ship(mmsi).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+struct AreaStatsParams {
+    window: Option<String>,
+}
+
+async fn get_area_stats(
+    Path(name): Path<String>,
+    Query(params): Query<AreaStatsParams>,
+    State(state): State<AppState>,
+) -> Result<Json<AreaHistory>, StatusCode> {
+    let window = match params.window.as_deref().map(area_stats::parse_window) {
+        None => 86_400,
+        Some(Ok(window)) => window,
+        Some(Err(_)) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let now = state.now();
+    state.monitor.area_stats.history(&name, window, now).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_ingest_stats(State(state): State<AppState>) -> Json<IngestReport> {
+    Json(state.monitor.ingest.report())
+}
+
+async fn put_zone(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(spec): Json<ZoneSpec>,
+) -> Result<Json<Zone>, StatusCode> {
+    if let Err(e) = spec.validate() {
+        warn!("Rejected zone '{}': {}", name, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let zone = Zone::new(&name, spec.shape).with_max_speed(spec.max_speed_kn);
+    info!("Updating zone '{}'", name);
+    state.monitor.geofences.upsert(zone.clone());
+    Ok(Json(zone))
+}
+
+async fn delete_zone(Path(name): Path<String>, State(state): State<AppState>) -> StatusCode {
+    if state.monitor.geofences.remove(&name) {
+        info!("Removed zone '{}'", name);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn get_events(
+    Query(filter): Query<EventFilter>,
+    State(state): State<AppState>,
+) -> Json<Vec<Arc<Event>>> {
+    Json(state.monitor.events.query(&filter))
+}
+
+// Server-sent events; reconnecting clients resume after the Last-Event-ID
+// they send, as long as it is still in the log
+async fn stream_events(
+    Query(mut filter): Query<EventFilter>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<SseEvent, axum::Error>>> {
+    if let Some(id) = headers.get("last-event-id").and_then(|id| id.to_str().ok()?.parse().ok()) {
+        filter.since = Some(id);
+    }
+    let events = state
+        .monitor
+        .events
+        .clone()
+        .stream(filter)
+        .take_until(shutdown::requested(state.shutdown.clone()))
+        .map(|event| SseEvent::default().id(event.id.to_string()).event(event.kind.name()).json_data(&*event));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+struct ForwardingUpdate {
+    enabled: bool,
+}
+
+async fn get_log_level(State(state): State<AppState>) -> Result<Json<LogLevel>, StatusCode> {
+    let log_filter = state.log_filter.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(log_filter.current()))
+}
+
+async fn put_log_level(State(state): State<AppState>, Json(level): Json<LogLevel>) -> Result<Json<LogLevel>, StatusCode> {
+    let log_filter = state.log_filter.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    if let Err(e) = log_filter.set(&level.filter) {
+        warn!("Rejected log filter: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    info!(filter = %level.filter, "Changed the log filter");
+    Ok(Json(log_filter.current()))
+}
+
+// Back to the filter from startup
+async fn reset_log_level(State(state): State<AppState>) -> Result<Json<LogLevel>, StatusCode> {
+    let log_filter = state.log_filter.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    log_filter.reset().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    info!("Reset the log filter");
+    Ok(Json(log_filter.current()))
+}
+
+// Everything under /api/replay is there in replay mode only
+async fn get_replay(State(state): State<AppState>) -> Result<Json<ReplayStatus>, StatusCode> {
+    let replay = state.replay.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(replay.status()))
+}
+
+async fn play_replay(State(state): State<AppState>) -> Result<Json<ReplayStatus>, StatusCode> {
+    let replay = state.replay.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    replay.play();
+    Ok(Json(replay.status()))
+}
+
+async fn pause_replay(State(state): State<AppState>) -> Result<Json<ReplayStatus>, StatusCode> {
+    let replay = state.replay.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    replay.pause();
+    Ok(Json(replay.status()))
+}
+
+// Going back forgets the ships and tracks, which the sources then rebuild
+// from the start of the recording; events and statistics are kept
+async fn seek_replay(State(state): State<AppState>, Json(seek): Json<SeekRequest>) -> Result<Json<ReplayStatus>, StatusCode> {
+    let replay = state.replay.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    replay.seek(seek.time, || {
+        state.ships.remove_stale(u64::MAX);
+        state.monitor.history.purge(u64::MAX);
+    });
+    info!(time = seek.time, "Replay seeked");
+    Ok(Json(replay.status()))
+}
+
+async fn set_replay_speed(State(state): State<AppState>, Json(speed): Json<SpeedRequest>) -> Result<Json<ReplayStatus>, StatusCode> {
+    let replay = state.replay.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    if let Err(e) = replay.set_speed(speed.speed) {
+        warn!("Rejected replay speed: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(replay.status()))
+}
+
+async fn get_forwarding(State(state): State<AppState>) -> Json<Vec<TargetStatus>> {
+    Json(state.forwarding.status())
+}
+
+async fn put_forwarding(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(update): Json<ForwardingUpdate>,
+) -> StatusCode {
+    if state.forwarding.set_enabled(&name, update.enabled) {
+        info!("{} UDP forwarding to '{}'", if update.enabled { "Enabled" } else { "Disabled" }, name);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Picks up a reloaded [map] section straight away, though proxied tiles
+// keep coming from the tile servers configured at startup
+async fn get_map_config(State(state): State<AppState>) -> Json<MapConfig> {
+    let mut map = state.config.borrow().map.clone();
+    if let Some(proxy) = &state.tile_proxy {
+        let proxied = |layer: Layer| format!("{}/tiles/{}/{{z}}/{{x}}/{{y}}.png", state.base_path, layer.name());
+        map.tile_url = proxied(Layer::Base);
+        map.seamark_url = proxy.has(Layer::Seamark).then(|| proxied(Layer::Seamark));
+    } else if state.offline {
+        map.tile_url = String::new();
+        map.seamark_url = None;
+    }
+    Json(map)
+}
+
+async fn get_base_tile(
+    Path((layer, z, x, file)): Path<(String, u8, u32, String)>,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let proxy = state.tile_proxy.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let layer = Layer::parse(&layer).ok_or(StatusCode::NOT_FOUND)?;
+    let y = file.strip_suffix(".png").and_then(|y| y.parse().ok()).ok_or(StatusCode::NOT_FOUND)?;
+    match proxy.get(layer, z, x, y).await {
+        Ok(Some(bytes)) => Ok(([(header::CONTENT_TYPE, "image/png"), (header::CACHE_CONTROL, "public, max-age=86400")], bytes).into_response()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            warn!(layer = layer.name(), z, x, y, "Tile fetch failed: {}", e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+async fn get_schemas() -> Json<Vec<&'static str>> {
+    Json(schema::schemas().keys().copied().collect())
+}
+
+async fn get_schema(Path(name): Path<String>) -> Result<Json<serde_json::Value>, StatusCode> {
+    schema::schemas().get(name.as_str()).cloned().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_alert_rules(State(state): State<AppState>) -> Json<Vec<Rule>> {
+    Json(state.monitor.alerts.rules())
+}
+
+async fn put_alert_rule(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(mut rule): Json<Rule>,
+) -> Result<Json<Rule>, StatusCode> {
+    rule.name = name;
+    if let Err(e) = rule.validate() {
+        warn!("Rejected alert rule '{}': {}", rule.name, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    info!("Updating alert rule '{}'", rule.name);
+    state.monitor.alerts.upsert(rule.clone());
+    Ok(Json(rule))
+}
+
+async fn delete_alert_rule(Path(name): Path<String>, State(state): State<AppState>) -> StatusCode {
+    if state.monitor.alerts.remove(&name) {
+        info!("Removed alert rule '{}'", name);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn get_anchor_watches(State(state): State<AppState>) -> Json<HashMap<u32, AnchorWatch>> {
+    Json(state.monitor.anchors.watches())
+}
+
+async fn put_anchor_watch(
+    Path(mmsi): Path<u32>,
+    State(state): State<AppState>,
+    Json(request): Json<AnchorWatchRequest>,
+) -> Result<Json<AnchorWatch>, StatusCode> {
+    let (lat, lng) = match (request.lat, request.lng) {
+        (Some(lat), Some(lng)) => (lat, lng),
+        _ => {
+            let ship = state.ships.ships.get(&mmsi).ok_or(StatusCode::NOT_FOUND)?;
+            (ship.lat, ship.lng)
+        }
+    };
+    if !index::is_valid_position(lat, lng) || !request.radius_m.is_finite() || request.radius_m <= 0.0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Err(e) = alerts::validate_actions(&request.actions) {
+        warn!("Rejected anchor watch for {}: {}", mmsi, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let watch = AnchorWatch { lat, lng, radius_m: request.radius_m, actions: request.actions, dragging: false };
+    info!("Watching {} at anchor within {}m of {}, {}", mmsi, watch.radius_m, lat, lng);
+    state.monitor.anchors.set(mmsi, watch.clone());
+    Ok(Json(watch))
+}
+
+async fn delete_anchor_watch(Path(mmsi): Path<u32>, State(state): State<AppState>) -> StatusCode {
+    if state.monitor.anchors.remove(mmsi) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn get_collision_risks(State(state): State<AppState>) -> Json<Vec<Risk>> {
+    Json(state.monitor.collisions.risks())
+}
+
+#[derive(Deserialize)]
+struct AnomalyParams {
+    limit: Option<usize>,
+}
+
+async fn get_anomalies(Query(params): Query<AnomalyParams>, State(state): State<AppState>) -> Json<Vec<Anomaly>> {
+    Json(state.monitor.anomalies.ranked(params.limit.unwrap_or(100)))
+}
+
+async fn get_identity_conflicts(State(state): State<AppState>) -> Json<Vec<Conflict>> {
+    Json(state.monitor.identities.all())
+}
+
+async fn get_rendezvous(State(state): State<AppState>) -> Json<Vec<Meeting>> {
+    Json(state.monitor.rendezvous.current())
+}
+
+// Ships whose destination resolved to the given UN/LOCODE
+async fn get_ships_by_destination(Path(locode): Path<String>, State(state): State<AppState>) -> Json<Vec<ShipState>> {
+    let locode = locode.to_uppercase();
+    let ships = state
+        .ships
+        .ships
+        .iter()
+        .filter(|ship| ship.destination_locode.as_deref() == Some(locode.as_str()))
+        .map(|ship| ship.to_state())
+        .collect();
+    Json(ships)
+}
+
+async fn get_ports(State(state): State<AppState>) -> Json<Vec<Port>> {
+    Json(state.monitor.port_calls.ports().all().to_vec())
+}
+
+#[derive(Deserialize)]
+struct PredictionParams {
+    // How far ahead, capped at an hour; default half an hour
+    minutes: Option<u64>,
+}
+
+async fn get_prediction(
+    Path(mmsi): Path<u32>,
+    Query(params): Query<PredictionParams>,
+    State(state): State<AppState>,
+) -> Result<Json<Prediction>, StatusCode> {
+    let ship = state.ships.ships.get(&mmsi).map(|ship| ship.clone()).ok_or(StatusCode::NOT_FOUND)?;
+    let minutes = params.minutes.unwrap_or(30).clamp(1, 60);
+    // One point a minute
+    Ok(Json(state.monitor.tracks.predict(&ship, minutes * 60, 60)))
+}
+
+async fn get_port_calls(Path(mmsi): Path<u32>, State(state): State<AppState>) -> Json<Vec<PortCall>> {
+    Json(state.monitor.port_calls.calls(mmsi))
+}
+
+async fn get_voyages(Path(mmsi): Path<u32>, State(state): State<AppState>) -> Json<Vec<Voyage>> {
+    let now = state.now();
+    let track = state.monitor.history.track(mmsi, 0, now);
+    Json(voyages::voyages(&state.monitor.port_calls.calls(mmsi), &track, now))
+}
+
+async fn get_ship_changes(Path(mmsi): Path<u32>, State(state): State<AppState>) -> Json<Vec<Change>> {
+    Json(state.monitor.changes.of(mmsi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_builder_router() {
+        let ships = Arc::new(ShipCache::new());
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.last_update) = (51.9, 4.1, 1);
+        ships.insert_ship(ship.mmsi, ship);
+        let seamon = Seamon::builder().ships(ships.clone()).without_upstream().build().unwrap();
+        assert!(Arc::ptr_eq(seamon.ships(), &ships));
+
+        // Mounted in an application of one's own, next to its routes
+        let app = Router::new().route("/health", get(|| async { "ok" })).nest("/ais", seamon.router());
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        assert_eq!(app.clone().oneshot(get("/health")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(app.clone().oneshot(get("/ais/api/ship/244660000")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(app.clone().oneshot(get("/ais/api/ship/235012345")).await.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(app.oneshot(get("/api/ship/244660000")).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_empty_peer_token() {
+        assert!(Seamon::builder().without_upstream().peer_token(String::new()).build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        let mut config = Config::default();
+        config.set("server.base_path", "/seamon/").unwrap();
+        let keys = ApiKeys::new(serde_json::from_str(r#"[{"name": "ops", "key": "k"}]"#).unwrap()).unwrap();
+        let app = Seamon::builder().config(config).without_upstream().api_keys(keys).build().unwrap().app();
+        let status = |uri: &str, key: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap());
+            async move { response.await.unwrap().status() }
+        };
+        // The UI with and without the slash; its settings and assets need no key
+        assert_eq!(status("/seamon", None).await, StatusCode::OK);
+        assert_eq!(status("/seamon/", None).await, StatusCode::OK);
+        assert_eq!(status("/seamon/api/config", None).await, StatusCode::OK);
+        assert_eq!(status("/seamon/static/map-config.js", None).await, StatusCode::OK);
+        // Route templates are matched without the base path
+        assert_eq!(status("/seamon/api/events", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status("/seamon/api/events", Some("k")).await, StatusCode::OK);
+        assert_eq!(status("/api/events", Some("k")).await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/", None).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_headless() {
+        let mut config = Config::default();
+        config.set("server.headless", "true").unwrap();
+        let status = |app: Router, uri: &'static str| async move { app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap().status() };
+        let app = Seamon::builder().config(config.clone()).without_upstream().build().unwrap().app();
+        assert_eq!(status(app.clone(), "/").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(app.clone(), "/static/map-config.js").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(app, "/api/zones").await, StatusCode::OK);
+        // Nor the UI at the base path
+        config.set("server.base_path", "/seamon").unwrap();
+        let app = Seamon::builder().config(config).without_upstream().build().unwrap().app();
+        assert_eq!(status(app.clone(), "/seamon").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(app.clone(), "/seamon/").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(app, "/seamon/api/zones").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_regions() {
+        let config: Config = toml::from_str("[regions.Maasvlakte]\ntype = \"circle\"\nlat = 51.96\nlng = 4.0\nradius_m = 5000\nmax_speed_kn = 6\n").unwrap();
+        let seamon = Seamon::builder().config(config).without_upstream().build().unwrap();
+        for (mmsi, lat) in [(244660000, 51.95), (244670000, 52.5)] {
+            let mut ship = Ship::new(mmsi, "ALIDA");
+            (ship.lat, ship.lng, ship.last_update) = (lat, 4.0, 1_700_000_000);
+            seamon.ships().insert_ship(mmsi, ship);
+        }
+        let app = seamon.router();
+        let get = |uri: &str| {
+            let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap());
+            async move {
+                let response = response.await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default())
+            }
+        };
+        let (status, ships) = get("/api/ships/region/Maasvlakte").await;
+        assert_eq!(status, StatusCode::OK);
+        let mmsis: Vec<_> = ships.as_array().unwrap().iter().map(|ship| ship["mmsi"].as_u64().unwrap()).collect();
+        assert_eq!(mmsis, vec![244660000]);
+        assert_eq!(get("/api/ships/region/Botlek").await.0, StatusCode::NOT_FOUND);
+        // Listed with the zones, speed limit and all
+        let zone = seamon.monitor().geofences.zone("Maasvlakte").unwrap();
+        assert_eq!(zone.max_speed_kn, Some(6.0));
+        let (_, zones) = get("/api/zones").await;
+        assert_eq!(zones[0]["name"], "Maasvlakte");
+    }
+
+    #[tokio::test]
+    async fn test_map_config() {
+        let map_config = |seamon: Seamon| async move {
+            let response = seamon.app().oneshot(Request::get(format!("{}/api/config", seamon.state.base_path)).body(Body::empty()).unwrap()).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+        let mut config = Config::default();
+        config.set("map.zoom", "9").unwrap();
+        config.set("map.features.photos", "false").unwrap();
+        config.set("upstream.api_key", "secret").unwrap();
+        let map = map_config(Seamon::builder().config(config.clone()).without_upstream().build().unwrap()).await;
+        assert_eq!((map["zoom"].as_f64(), map["tile_url"].as_str()), (Some(9.0), Some(Config::default().map.tile_url.as_str())));
+        assert_eq!(map["features"]["photos"], false);
+        // The map settings and nothing else of the configuration
+        assert!(!map.to_string().contains("secret"));
+        assert!(map.get("upstream").is_none());
+
+        // Behind the tile proxy, the tiles come from under the base path
+        let dir = std::env::temp_dir().join(format!("seawatch-map-config-{}", std::process::id()));
+        let proxy = TileProxy::new("http://127.0.0.1:9/{z}/{x}/{y}.png", None, &dir, Duration::from_secs(3600)).unwrap();
+        config.set("server.base_path", "/seamon").unwrap();
+        let map = map_config(Seamon::builder().config(config).without_upstream().tile_proxy(proxy).build().unwrap()).await;
+        assert_eq!(map["tile_url"], "/seamon/tiles/base/{z}/{x}/{y}.png");
+        assert!(map["seamark_url"].is_null());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_offline() {
+        // Nothing connects to aisstream.io, and the UI gets no public tile servers
+        let seamon = Seamon::builder().offline().build().unwrap();
+        assert!(matches!(seamon.pending.as_ref().unwrap().ingestion, Ingestion::None));
+        let response = seamon.router().oneshot(Request::get("/api/config").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let map: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((map["tile_url"].as_str(), map["seamark_url"].as_str()), (Some(""), None));
+        // A local source still runs
+        let seamon = Seamon::builder().offline().simulate(Simulation::new(Vec::new(), 0, 1)).build().unwrap();
+        assert!(matches!(seamon.pending.as_ref().unwrap().ingestion, Ingestion::Simulate(_)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains() {
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let seamon = Seamon::builder().without_upstream().shutdown(shutdown_rx.clone()).build().unwrap();
+        // Ingestion as `start` runs it, without the batch writer, so only the drain applies anything
+        let parsers = ParsePool::new(2, seamon.state.ingest.clone(), seamon.monitor().ingest.clone(), "simulator").unwrap();
+        let simulation = Simulation::new(crate::ports::Ports::builtin().all().to_vec(), 20, 7);
+        let ingestion = tokio::spawn(simulate_task(parsers, simulation, shutdown_rx));
+        // Every vessel reports on the first tick
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), ingestion).await.unwrap().unwrap();
+        assert_eq!(seamon.ships().len(), 0);
+        seamon.drain();
+        assert_eq!(seamon.ships().len(), 20);
+        assert!(seamon.state.ingest.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes() {
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::ORIGIN, "https://example.com")
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .header("x-api-key", "k")
+                .body(Body::empty())
+                .unwrap()
+        };
+        // Without keys, nobody may use them
+        let open = Seamon::builder().without_upstream().build().unwrap().router();
+        assert_eq!(open.clone().oneshot(request("GET", "/api/admin/stats")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(open.oneshot(request("GET", "/api/stats/ingest")).await.unwrap().status(), StatusCode::OK);
+
+        // With an admin key, and never for another origin
+        let keys = ApiKeys::new(serde_json::from_str(r#"[{"name": "ops", "key": "k", "admin": true}, {"name": "app", "key": "a"}]"#).unwrap()).unwrap();
+        let app = Seamon::builder().without_upstream().api_keys(keys).build().unwrap().router();
+        assert_eq!(app.clone().oneshot(request("GET", "/api/admin/stats")).await.unwrap().status(), StatusCode::OK);
+        let mut tenant = request("GET", "/api/admin/stats");
+        tenant.headers_mut().insert("x-api-key", "a".parse().unwrap());
+        assert_eq!(app.clone().oneshot(tenant).await.unwrap().status(), StatusCode::FORBIDDEN);
+        let preflight = app.clone().oneshot(request("OPTIONS", "/api/admin/upstream")).await.unwrap();
+        assert!(!preflight.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        let preflight = app.oneshot(request("OPTIONS", "/api/ships/51/3/52/5")).await.unwrap();
+        assert!(preflight.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_update_upstream() {
+        let keys = ApiKeys::new(serde_json::from_str(r#"[{"name": "ops", "key": "k", "admin": true}, {"name": "app", "key": "a"}]"#).unwrap()).unwrap();
+        let seamon = Seamon::builder().without_upstream().api_keys(keys).build().unwrap();
+        let post = |key: &str| {
+            Request::post("/api/admin/upstream")
+                .header("x-api-key", key)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"mmsi": [244660000]}"#))
+                .unwrap()
+        };
+        // Any key may read the API, only an admin key repoint the feed
+        assert_eq!(seamon.router().oneshot(post("a")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert!(seamon.state.upstream.borrow().mmsi.is_empty());
+        assert_eq!(seamon.router().oneshot(post("k")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(seamon.state.upstream.borrow().mmsi, vec![244660000]);
+    }
+
+    #[tokio::test]
+    async fn test_cors_reload() {
+        let seamon = Seamon::builder().without_upstream().build().unwrap();
+        let app = seamon.router();
+        let allowed = |origin: &'static str| {
+            let preflight = Request::builder()
+                .method("OPTIONS")
+                .uri("/api/ships/51/3/52/5")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(preflight).await.unwrap().headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).cloned() }
+        };
+        assert_eq!(allowed("https://example.com").await.unwrap(), "https://example.com");
+
+        let mut config = Config::default();
+        config.set("server.cors_origins", "https://harbour.example, https://ops.example").unwrap();
+        seamon.config().send_replace(Arc::new(config));
+        assert_eq!(allowed("https://example.com").await, None);
+        assert_eq!(allowed("https://ops.example").await.unwrap(), "https://ops.example");
+    }
+
+    #[tokio::test]
+    async fn test_timelapse_area_key() {
+        let keys = ApiKeys::new(serde_json::from_str(r#"[{"name": "harbour", "key": "h", "bbox": [51.0, 3.0, 52.0, 5.0]}]"#).unwrap()).unwrap();
+        let seamon = Seamon::builder().without_upstream().api_keys(keys).build().unwrap();
+        for (mmsi, lat) in [(244660000, 51.9), (244670000, 52.5)] {
+            let mut ship = Ship::new(mmsi, "ALIDA");
+            (ship.lat, ship.lng, ship.last_update) = (lat, 4.1, 1_700_000_000);
+            seamon.monitor().history.observe(&ship);
+        }
+        let get = |uri: &str| Request::get(uri).header("x-api-key", "h").body(Body::empty()).unwrap();
+        let frames = |response: Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let timelapse: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            timelapse["frames"].as_array().unwrap().iter().map(|frame| frame["ships"].as_array().unwrap().len()).collect::<Vec<_>>()
+        };
+
+        // Only the ship inside the key's area, in both frames
+        let app = seamon.router();
+        let response = app.clone().oneshot(get("/api/history/timelapse/50/3/53/5?from=1700000000&to=1700000060&step=1m")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(frames(response).await, vec![1, 1]);
+        let response = app.clone().oneshot(get("/api/history/timelapse/60/3/61/5?from=1700000000&to=1700000060&step=1m")).await.unwrap();
+        assert_eq!(frames(response).await, vec![0, 0]);
+        let response = app.oneshot(get("/api/history/timelapse/50/3/53/5?from=1700000000&to=1700100000&step=1m")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_replay_routes() {
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        let post = |uri: &str, body: &str| Request::post(uri).header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())).unwrap();
+        let live = Seamon::builder().without_upstream().build().unwrap().router();
+        assert_eq!(live.oneshot(get("/api/replay")).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        let app = Seamon::builder().replay().build().unwrap().router();
+        let response = app.clone().oneshot(post("/api/replay/pause", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(post("/api/replay/seek", r#"{"time": 1700000000}"#)).await.unwrap();
+        assert_eq!(response.headers()["x-replay-time"], "1700000000");
+        assert_eq!(app.clone().oneshot(post("/api/replay/speed", r#"{"speed": -1}"#)).await.unwrap().status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(post("/api/replay/speed", r#"{"speed": 8}"#)).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((status["time"].as_u64(), status["playing"].as_bool(), status["speed"].as_f64()), (Some(1_700_000_000), Some(false), Some(8.0)));
+    }
+
+    #[tokio::test]
+    async fn test_stream_reconnects() {
+        use crate::ingest::ShedPolicy;
+        use seamon_core::mock::{position_report, End, MockAisStream, Script};
+
+        // Three frames a connection, the first taken by the handshake, then a close
+        let frames = (0..3).map(|i| position_report(244660000 + i, "ALIDA", 51.9, 4.1)).collect();
+        let mock = MockAisStream::start(Script { frames, then: End::Close, ..Default::default() }).await.unwrap();
+        let queue = Arc::new(IngestQueue::new(100, ShedPolicy::default()));
+        let stats = Arc::new(crate::ingest_stats::IngestStats::new());
+        let parsers = ParsePool::new(1, queue.clone(), stats.clone(), "upstream").unwrap();
+        let upstream = config::UpstreamConfig { url: mock.url().to_string(), api_key: Some("test-key".to_string()), reconnect_secs: 0 };
+        let (_config, config) = watch::channel(Arc::new(Config { upstream, ..Config::default() }));
+        let (_upstream, upstream_rx) = watch::channel(Subscription::default());
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let status = Arc::new(UpstreamTracker::new());
+        let task = tokio::spawn(ais_stream_task(parsers, config, upstream_rx, status.clone(), shutdown_rx));
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while mock.connections() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        shutdown.send(true).unwrap();
+        task.await.unwrap();
+
+        // At least two whole connections' worth
+        let messages = queue.drain();
+        assert!(messages.len() >= 4);
+        assert_eq!(messages[0].1.metadata.mmsi, 244660001);
+        // Each close shows up in the connection history
+        let report = stats.report();
+        assert!(report.connections.len() >= 4);
+        assert!(report.windows[0].disconnects >= 2);
+        let status = status.status();
+        assert_eq!(status.state, crate::upstream::ConnectionState::Disconnected);
+        assert!(status.last_error.is_some());
+    }
+}
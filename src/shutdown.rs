@@ -0,0 +1,37 @@
+use tokio::sync::watch;
+use tokio::time::Duration;
+
+// How long in-flight requests, live clients and ingestion get to wind down
+// once shutdown starts, before the process exits regardless; the default for
+// `server.shutdown_timeout_secs`
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Resolves on SIGINT (Ctrl-C) or SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Resolves once shutdown has been requested on the channel (or its sender is gone)
+pub async fn requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|&requested| requested).await;
+}
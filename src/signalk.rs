@@ -0,0 +1,223 @@
+use axum::extract::ws::{Message, WebSocket};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::index::is_valid_position;
+use crate::ship::{Ship, ShipCache};
+
+// Deltas buffered for clients that fall behind before they skip some
+const DELTA_BUFFER: usize = 4096;
+const KNOTS_TO_MS: f64 = 1852.0 / 3600.0;
+
+// Signal K names for AIS navigational statuses 0-8, as in its `ais-status` enum
+const NAV_STATES: [&str; 9] = [
+    "motoring",
+    "anchored",
+    "not-under-command",
+    "restricted-manouverability",
+    "constrained-by-draft",
+    "moored",
+    "aground",
+    "fishing",
+    "sailing",
+];
+
+fn timestamp(secs: u64) -> String {
+    DateTime::<Utc>::from_timestamp(secs as i64, 0).unwrap_or_default().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// A Signal K delta with everything known about a ship, in SI units
+pub fn delta(ship: &Ship) -> Value {
+    let mut values = vec![json!({"path": "", "value": {"mmsi": ship.mmsi.to_string()}})];
+    if !ship.name.trim().is_empty() {
+        values.push(json!({"path": "", "value": {"name": ship.name.trim()}}));
+    }
+    if is_valid_position(ship.lat, ship.lng) {
+        values.push(json!({"path": "navigation.position", "value": {"latitude": ship.lat, "longitude": ship.lng}}));
+        values.push(json!({"path": "navigation.speedOverGround", "value": ship.speed * KNOTS_TO_MS}));
+    }
+    if ship.cog < 360.0 {
+        values.push(json!({"path": "navigation.courseOverGroundTrue", "value": ship.cog.to_radians()}));
+    }
+    if ship.heading < 360 {
+        values.push(json!({"path": "navigation.headingTrue", "value": (ship.heading as f64).to_radians()}));
+    }
+    if let Some(state) = NAV_STATES.get(ship.nav_status as usize) {
+        values.push(json!({"path": "navigation.state", "value": state}));
+    }
+    if ship.ship_type != 0 {
+        values.push(json!({"path": "design.aisShipType", "value": {"id": ship.ship_type}}));
+    }
+    if let Some(class) = ship.class {
+        values.push(json!({"path": "sensors.ais.class", "value": class}));
+    }
+    if let Some(dimensions) = ship.dimensions {
+        values.push(json!({"path": "design.length", "value": {"overall": dimensions.length}}));
+        values.push(json!({"path": "design.beam", "value": dimensions.beam}));
+    }
+    if !ship.call_sign.trim().is_empty() {
+        values.push(json!({"path": "communication.callsignVhf", "value": ship.call_sign.trim()}));
+    }
+    if let Some(draught) = ship.draught {
+        values.push(json!({"path": "design.draft", "value": {"maximum": draught}}));
+    }
+    if !ship.destination.trim().is_empty() {
+        values.push(json!({"path": "navigation.destination.commonName", "value": ship.destination.trim()}));
+    }
+    json!({
+        "context": format!("vessels.urn:mrn:imo:mmsi:{}", ship.mmsi),
+        "updates": [{
+            "source": {"label": "seawatch", "type": "AIS"},
+            "timestamp": timestamp(ship.last_update),
+            "values": values,
+        }],
+    })
+}
+
+// The first message on a stream. There is no self vessel, only AIS targets.
+fn hello() -> String {
+    json!({
+        "name": "seawatch",
+        "version": env!("CARGO_PKG_VERSION"),
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "roots": ["vessels"],
+    })
+    .to_string()
+}
+
+// Server discovery, as served at /signalk
+pub fn endpoints(host: &str) -> Value {
+    json!({
+        "endpoints": {
+            "v1": {
+                "version": "1.0.0",
+                "signalk-ws": format!("ws://{}/signalk/v1/stream", host),
+            }
+        },
+        "server": {"id": "seawatch", "version": env!("CARGO_PKG_VERSION")},
+    })
+}
+
+fn snapshot(ships: &ShipCache) -> Vec<String> {
+    ships.get_full_ships_in_bbox(-90.0, -180.0, 90.0, 180.0).iter().map(|ship| delta(ship).to_string()).collect()
+}
+
+// Serialized once for however many clients there are
+pub struct SignalK {
+    deltas: broadcast::Sender<Arc<str>>,
+}
+
+impl SignalK {
+    pub fn start(updates: broadcast::Receiver<Arc<Ship>>) -> Arc<Self> {
+        let (deltas, _) = broadcast::channel(DELTA_BUFFER);
+        tokio::spawn(delta_task(updates, deltas.clone()));
+        Arc::new(Self { deltas })
+    }
+
+    // A WebSocket client on /signalk/v1/stream: hello, every ship, then updates
+    pub async fn stream(&self, mut socket: WebSocket, ships: Arc<ShipCache>) {
+        let mut deltas = self.deltas.subscribe();
+        let _ = socket.send(Message::Text(hello())).await;
+        for delta in snapshot(&ships) {
+            if socket.send(Message::Text(delta)).await.is_err() {
+                return;
+            }
+        }
+        loop {
+            tokio::select! {
+                delta = deltas.recv() => match delta {
+                    Ok(delta) => {
+                        if socket.send(Message::Text(delta.to_string())).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => debug!("Signal K client fell behind, {} deltas skipped", missed),
+                    Err(RecvError::Closed) => return,
+                },
+                // Subscription requests are ignored; every client gets everything
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+
+    // The same stream as newline-delimited JSON on a TCP port
+    pub async fn serve_tcp(self: Arc<Self>, listener: TcpListener, ships: Arc<ShipCache>) {
+        loop {
+            match listener.accept().await {
+                Ok((socket, peer)) => {
+                    info!("Signal K client connected from {}", peer);
+                    let _ = socket.set_nodelay(true);
+                    tokio::spawn(tcp_client(socket, ships.clone(), self.deltas.subscribe()));
+                }
+                Err(e) => warn!("Signal K listener failed to accept: {}", e),
+            }
+        }
+    }
+}
+
+async fn tcp_client(mut socket: TcpStream, ships: Arc<ShipCache>, mut deltas: broadcast::Receiver<Arc<str>>) {
+    let mut backlog = hello();
+    backlog.push('\n');
+    for delta in snapshot(&ships) {
+        backlog.push_str(&delta);
+        backlog.push('\n');
+    }
+    if socket.write_all(backlog.as_bytes()).await.is_err() {
+        return;
+    }
+    loop {
+        match deltas.recv().await {
+            Ok(delta) => {
+                if socket.write_all(format!("{}\n", delta).as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => debug!("Signal K client fell behind, {} deltas skipped", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+async fn delta_task(mut updates: broadcast::Receiver<Arc<Ship>>, deltas: broadcast::Sender<Arc<str>>) {
+    loop {
+        match updates.recv().await {
+            Ok(ship) => {
+                if deltas.receiver_count() > 0 {
+                    let _ = deltas.send(Arc::from(delta(&ship).to_string()));
+                }
+            }
+            Err(RecvError::Lagged(missed)) => warn!("Signal K output fell behind, {} updates skipped", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta() {
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.speed, ship.cog, ship.heading, ship.last_update) = (51.9, 4.1, 10.0, 180.0, 511, 1_700_000_000);
+        let delta = delta(&ship);
+        assert_eq!(delta["context"], "vessels.urn:mrn:imo:mmsi:244660000");
+        assert_eq!(delta["updates"][0]["timestamp"], "2023-11-14T22:13:20Z");
+
+        let values = delta["updates"][0]["values"].as_array().unwrap();
+        let value = |path: &str| values.iter().find(|value| value["path"] == path).map(|value| value["value"].clone());
+        assert_eq!(value("navigation.position").unwrap()["latitude"], 51.9);
+        assert!((value("navigation.speedOverGround").unwrap().as_f64().unwrap() - 5.144).abs() < 0.001);
+        assert!((value("navigation.courseOverGroundTrue").unwrap().as_f64().unwrap() - std::f64::consts::PI).abs() < 1e-9);
+        assert_eq!(value("navigation.headingTrue"), None);
+        assert_eq!(value("navigation.state").unwrap(), "motoring");
+    }
+}
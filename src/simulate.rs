@@ -0,0 +1,286 @@
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::{interval, Duration};
+use tracing::info;
+
+use crate::ais::{AisMessage, Dimension, Eta, MessageData, Metadata, PositionReport, ShipStaticData};
+use crate::geo::{bearing_deg, haversine_m};
+use crate::ingest::ParsePool;
+use crate::intern::intern;
+use crate::ports::Port;
+use crate::shutdown;
+
+pub const DEFAULT_VESSELS: usize = 200;
+pub const DEFAULT_SEED: u64 = 1;
+const TICK: Duration = Duration::from_secs(1);
+// Report intervals in ticks, about what Class A transponders use
+const UNDERWAY_REPORT_TICKS: u64 = 10;
+const MOORED_REPORT_TICKS: u64 = 180;
+const STATIC_REPORT_TICKS: u64 = 360;
+// Legs go to one of the nearest ports, so voyages stay regional
+const NEAREST_PORTS: usize = 5;
+const KNOTS_TO_MS: f64 = 1852.0 / 3600.0;
+
+// AIS ship type, the speed range in knots vessels of it cruise at, and
+// their range of lengths in metres
+const KINDS: [(u32, f64, f64, f64, f64); 6] = [
+    (70, 12.0, 18.0, 100.0, 300.0),
+    (80, 10.0, 15.0, 100.0, 330.0),
+    (60, 16.0, 24.0, 80.0, 300.0),
+    (30, 5.0, 9.0, 15.0, 60.0),
+    (52, 6.0, 11.0, 20.0, 40.0),
+    (37, 4.0, 8.0, 8.0, 25.0),
+];
+// Flag states by MID, for MMSIs that look the part
+const MIDS: [u32; 10] = [211, 219, 235, 244, 257, 265, 311, 477, 538, 636];
+const NAME_FIRST: [&str; 12] = ["NORDIC", "OCEAN", "ATLANTIC", "BALTIC", "CAPE", "SILVER", "NORTHERN", "EVER", "MAERSK", "STELLA", "HARBOUR", "BLUE"];
+const NAME_SECOND: [&str; 12] = ["STAR", "SPIRIT", "PIONEER", "WAVE", "HORIZON", "TRADER", "EXPRESS", "QUEEN", "FALCON", "GRACE", "VOYAGER", "BREEZE"];
+
+// SplitMix64: small, fast and the same everywhere, which is the point here
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn between(&mut self, low: f64, high: f64) -> f64 {
+        low + (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * (high - low)
+    }
+}
+
+struct Vessel {
+    mmsi: u32,
+    name: Arc<str>,
+    imo: u32,
+    ship_type: u32,
+    dimension: Dimension,
+    knots: f64,
+    lat: f64,
+    lng: f64,
+    to: usize, // Index into the ports
+    moored_ticks: u64, // Left in port before the next leg
+}
+
+// A fleet sailing between ports; the same ports, size and seed always give
+// the same vessels and voyages
+pub struct Simulation {
+    rng: Rng,
+    ports: Vec<Port>,
+    vessels: Vec<Vessel>,
+    tick: u64,
+}
+
+// With a valid check digit: the last digit is the weighted sum of the other six, mod 10
+fn imo_number(rng: &mut Rng) -> u32 {
+    let base = 900_000 + rng.below(99_999) as u32;
+    let digits = base.to_string().into_bytes();
+    let check: u32 = digits.iter().zip((2..=7).rev()).map(|(digit, weight)| (digit - b'0') as u32 * weight).sum();
+    base * 10 + check % 10
+}
+
+impl Simulation {
+    pub fn new(ports: Vec<Port>, vessels: usize, seed: u64) -> Self {
+        let mut simulation = Self { rng: Rng(seed), ports, vessels: Vec::with_capacity(vessels), tick: 0 };
+        if simulation.ports.len() < 2 {
+            return simulation;
+        }
+        let mut mmsis = std::collections::HashSet::new();
+        while simulation.vessels.len() < vessels {
+            let rng = &mut simulation.rng;
+            let mmsi = MIDS[rng.below(MIDS.len())] * 1_000_000 + 100_000 + rng.below(900_000) as u32;
+            if !mmsis.insert(mmsi) {
+                continue;
+            }
+            let (ship_type, slowest, fastest, shortest, longest) = KINDS[rng.below(KINDS.len())];
+            let name = format!("{} {}", NAME_FIRST[rng.below(NAME_FIRST.len())], NAME_SECOND[rng.below(NAME_SECOND.len())]);
+            let knots = rng.between(slowest, fastest);
+            // About six and a half times as long as wide, with the antenna on the bridge
+            let length = rng.between(shortest, longest);
+            let beam = (length / 6.5).round() as u32;
+            let to_bow = (length * rng.between(0.6, 0.9)).round() as u32;
+            let dimension = Dimension { a: to_bow, b: length.round() as u32 - to_bow, c: beam / 2, d: beam - beam / 2 };
+            let imo = if ship_type == 37 || ship_type == 30 { 0 } else { imo_number(rng) };
+            // Somewhere along its first leg, so the map fills straight away
+            let from = rng.below(simulation.ports.len());
+            let to = simulation.next_port(from);
+            let progress = simulation.rng.between(0.0, 1.0);
+            let (from, to_port) = (&simulation.ports[from], &simulation.ports[to]);
+            let (lat, lng) = (from.lat + (to_port.lat - from.lat) * progress, from.lng + (to_port.lng - from.lng) * progress);
+            simulation.vessels.push(Vessel { mmsi, name: Arc::from(name), imo, ship_type, dimension, knots, lat, lng, to, moored_ticks: 0 });
+        }
+        simulation
+    }
+
+    // One of the ports nearest `from`, other than itself
+    fn next_port(&mut self, from: usize) -> usize {
+        let origin = &self.ports[from];
+        let mut nearest: Vec<(f64, usize)> = self
+            .ports
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != from)
+            .map(|(index, port)| (haversine_m(origin.lat, origin.lng, port.lat, port.lng), index))
+            .collect();
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearest.truncate(NEAREST_PORTS);
+        nearest[self.rng.below(nearest.len())].1
+    }
+
+    // Moves every vessel on by `secs` and returns the reports due at `now`
+    pub fn step(&mut self, secs: f64, now: u64) -> Vec<AisMessage> {
+        let tick = self.tick;
+        self.tick += 1;
+        let mut messages = Vec::new();
+        for index in 0..self.vessels.len() {
+            let (cog, sog, status) = self.advance(index, secs);
+            let vessel = &self.vessels[index];
+            // Spread over the ticks, rather than everyone at once
+            let phase = tick + vessel.mmsi as u64;
+            let report_ticks = if status == 5 { MOORED_REPORT_TICKS } else { UNDERWAY_REPORT_TICKS };
+            if tick == 0 || phase.is_multiple_of(report_ticks) {
+                let report = PositionReport { cog, navigational_status: status, sog, true_heading: cog.round() as u32 % 360, position_accuracy: false };
+                messages.push(self.message(vessel, now, "PositionReport", MessageData { position_report: Some(report), ship_static_data: None }));
+            }
+            if tick == 0 || phase.is_multiple_of(STATIC_REPORT_TICKS) {
+                let port = &self.ports[vessel.to];
+                let remaining_secs = haversine_m(vessel.lat, vessel.lng, port.lat, port.lng) / (vessel.knots * KNOTS_TO_MS);
+                let eta = Utc.timestamp_opt((now as f64 + remaining_secs) as i64, 0).single().map(|eta| Eta {
+                    month: eta.month(),
+                    day: eta.day(),
+                    hour: eta.hour(),
+                    minute: eta.minute(),
+                });
+                let data = ShipStaticData {
+                    ship_type: vessel.ship_type,
+                    destination: port.locode.clone(),
+                    imo_number: vessel.imo,
+                    call_sign: intern(&format!("SIM{}", vessel.mmsi % 10_000)),
+                    eta,
+                    dimension: Some(vessel.dimension),
+                    // Roughly what a laden hull of that length draws
+                    draught: Some(((vessel.dimension.a + vessel.dimension.b) as f64 / 20.0 * 10.0).round() / 10.0),
+                };
+                messages.push(self.message(vessel, now, "ShipStaticData", MessageData { position_report: None, ship_static_data: Some(data) }));
+            }
+        }
+        messages
+    }
+
+    // Course, speed and navigational status after moving one vessel on
+    fn advance(&mut self, index: usize, secs: f64) -> (f64, f64, u32) {
+        if self.vessels[index].moored_ticks > 0 {
+            self.vessels[index].moored_ticks -= 1;
+            if self.vessels[index].moored_ticks == 0 {
+                let to = self.vessels[index].to;
+                self.vessels[index].to = self.next_port(to);
+            }
+            return (360.0, 0.0, 5);
+        }
+        let sog = (self.vessels[index].knots + self.rng.between(-0.3, 0.3)).max(0.1);
+        let vessel = &mut self.vessels[index];
+        let port = &self.ports[vessel.to];
+        let cog = bearing_deg(vessel.lat, vessel.lng, port.lat, port.lng);
+        let remaining = haversine_m(vessel.lat, vessel.lng, port.lat, port.lng);
+        let step = sog * KNOTS_TO_MS * secs;
+        if remaining <= step {
+            // Alongside within the port, not all on the same spot
+            let (lat, lng) = (port.lat, port.lng);
+            vessel.lat = lat + self.rng.between(-0.003, 0.003);
+            vessel.lng = lng + self.rng.between(-0.003, 0.003);
+            // Between half an hour and four hours in port
+            vessel.moored_ticks = self.rng.between(1800.0, 14400.0) as u64;
+            return (360.0, 0.0, 5);
+        }
+        let fraction = step / remaining;
+        vessel.lat += (port.lat - vessel.lat) * fraction;
+        vessel.lng += (port.lng - vessel.lng) * fraction;
+        let status = if vessel.ship_type == 30 { 7 } else { 0 }; // Fishing vessels are engaged in fishing
+        (cog, (sog * 10.0).round() / 10.0, status)
+    }
+
+    fn message(&self, vessel: &Vessel, now: u64, message_type: &str, message: MessageData) -> AisMessage {
+        let time_utc = Utc.timestamp_opt(now as i64, 0).single().map(|time| time.format("%Y-%m-%d %H:%M:%S%.9f +0000 UTC").to_string());
+        AisMessage {
+            message_type: message_type.to_string(),
+            metadata: Metadata {
+                mmsi: vessel.mmsi,
+                ship_name: vessel.name.clone(),
+                latitude: vessel.lat,
+                longitude: vessel.lng,
+                time_utc: time_utc.unwrap_or_default(),
+            },
+            message,
+        }
+    }
+}
+
+// Feeds the simulation to the parse workers as aisstream.io frames, in real
+// time, so everything from parsing on runs as it would with the live stream
+pub async fn simulate_task(parsers: ParsePool, mut simulation: Simulation, shutdown: watch::Receiver<bool>) {
+    info!("Simulating {} vessels between {} ports", simulation.vessels.len(), simulation.ports.len());
+    let mut ticks = interval(TICK);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = shutdown::requested(shutdown.clone()) => break,
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        for message in simulation.step(TICK.as_secs_f64(), now) {
+            if let Ok(frame) = serde_json::to_vec(&message) {
+                parsers.submit(now, frame);
+            }
+        }
+    }
+
+    // Let the parse workers finish the frames already handed to them
+    let _ = tokio::task::spawn_blocking(move || parsers.finish()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::Ports;
+    use crate::ship::Dimensions;
+
+    #[test]
+    fn test_simulation() {
+        let ports = Ports::builtin().all().to_vec();
+        let first = |seed| Simulation::new(ports.clone(), 50, seed).step(1.0, 1_750_000_000);
+        let messages = first(7);
+        // Everyone reports position and static data on the first tick
+        assert_eq!(messages.len(), 100);
+        assert_eq!(serde_json::to_string(&messages).unwrap(), serde_json::to_string(&first(7)).unwrap());
+        assert_ne!(serde_json::to_string(&messages).unwrap(), serde_json::to_string(&first(8)).unwrap());
+
+        let mut simulation = Simulation::new(ports.clone(), 50, 7);
+        let start: Vec<(f64, f64)> = simulation.vessels.iter().map(|vessel| (vessel.lat, vessel.lng)).collect();
+        for tick in 0..600 {
+            for message in simulation.step(1.0, 1_750_000_000 + tick) {
+                if let Some(report) = message.message.position_report {
+                    assert!(report.sog <= 24.5 && report.cog <= 360.0, "{:?}", report);
+                }
+                if let Some(data) = message.message.ship_static_data {
+                    assert!(ports.iter().any(|port| port.locode == data.destination));
+                    assert!(data.imo_number == 0 || data.imo_number >= 9_000_000);
+                    assert!(data.dimension.and_then(Dimensions::from_ais).is_some_and(|d| (8..=330).contains(&d.length)));
+                }
+            }
+        }
+        // Ten minutes at 4 to 24 knots, or moored in port
+        for (vessel, (lat, lng)) in simulation.vessels.iter().zip(start) {
+            let moved = haversine_m(lat, lng, vessel.lat, vessel.lng);
+            assert!(moved < 600.0 * 24.5 * KNOTS_TO_MS + 700.0, "{} moved {}m", vessel.mmsi, moved);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::events::Event;
+use crate::monitor::Monitor;
+use crate::ship::Ship;
+
+// Something a sink republishes: the latest state of a ship, or an event
+pub enum Update {
+    Ship(Arc<Ship>),
+    Event(Arc<Event>),
+}
+
+// Ship updates and events merged into one feed for a sink
+pub struct Feed {
+    name: String,
+    ships: broadcast::Receiver<Arc<Ship>>,
+    events: broadcast::Receiver<Arc<Event>>,
+}
+
+impl Feed {
+    pub fn new(name: impl Into<String>, monitor: &Monitor) -> Self {
+        Self { name: name.into(), ships: monitor.updates.subscribe(), events: monitor.events.subscribe() }
+    }
+
+    // The next update, or None once the monitor is gone. A sink that can't
+    // keep up skips what it missed rather than falling further behind.
+    pub async fn next(&mut self) -> Option<Update> {
+        loop {
+            let received = tokio::select! {
+                ship = self.ships.recv() => ship.map(Update::Ship),
+                event = self.events.recv() => event.map(Update::Event),
+            };
+            match received {
+                Ok(update) => return Some(update),
+                Err(RecvError::Lagged(missed)) => warn!("{} fell behind, {} updates not published", self.name, missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Update {
+    // Where a sink puts this update: `{mmsi}` in the template is the ship's
+    // MMSI, and `{type}` the event type (or "ship")
+    pub fn topic(&self, template: &str) -> String {
+        template.replace("{mmsi}", &self.mmsi().to_string()).replace("{type}", self.kind())
+    }
+
+    pub fn mmsi(&self) -> u32 {
+        match self {
+            Update::Ship(ship) => ship.mmsi,
+            Update::Event(event) => event.mmsi,
+        }
+    }
+
+    // The event type, or "ship"
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Update::Ship(_) => "ship",
+            Update::Event(event) => event.kind.name(),
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Update::Ship(ship) => ship.last_update,
+            Update::Event(event) => event.timestamp,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        match self {
+            Update::Ship(ship) => serde_json::to_vec(ship),
+            Update::Event(event) => serde_json::to_vec(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventKind, EventLog};
+
+    #[test]
+    fn test_topic() {
+        let ship = Update::Ship(Arc::new(Ship::new(244660000, "")));
+        assert_eq!(ship.topic("seamon/ships/{mmsi}"), "seamon/ships/244660000");
+
+        let event = Update::Event(EventLog::new().push(0, 1, EventKind::ZoneEnter { zone: Arc::from("harbour") }));
+        assert_eq!(event.topic("seamon/events/{type}/{mmsi}"), "seamon/events/zone_enter/1");
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::Result;
+use socket2::{Socket, Type};
+use std::env;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use tokio::time::{interval, Duration};
+use tracing::{debug, warn};
+
+// Where systemd puts the first socket it passes (SD_LISTEN_FDS_START)
+const LISTEN_FDS_START: RawFd = 3;
+
+pub enum Listener {
+    Tcp(std::net::TcpListener),
+    Unix(std::os::unix::net::UnixListener),
+}
+
+// LISTEN_PID guards against variables inherited from a parent that was
+// socket activated itself
+fn passed_sockets(listen_pid: Option<&str>, listen_fds: Option<&str>) -> usize {
+    if listen_pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+        return 0;
+    }
+    listen_fds.and_then(|count| count.parse().ok()).unwrap_or(0)
+}
+
+// The socket systemd bound for us through a .socket unit, if it started this
+// process that way; it takes the place of the configured listen address
+pub fn listener() -> Result<Option<Listener>> {
+    let count = passed_sockets(env::var("LISTEN_PID").ok().as_deref(), env::var("LISTEN_FDS").ok().as_deref());
+    if count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("systemd passed {} sockets; only the first is used", count);
+    }
+    // Safety: systemd hands over fds from 3 on, and nothing else in the
+    // process owns fd 3
+    let socket = unsafe { Socket::from_raw_fd(LISTEN_FDS_START) };
+    if socket.r#type()? != Type::STREAM {
+        return Err(anyhow::anyhow!("The socket passed by systemd is not a stream socket; use ListenStream="));
+    }
+    socket.set_nonblocking(true)?;
+    Ok(Some(match socket.local_addr()?.as_socket() {
+        Some(_) => Listener::Tcp(socket.into()),
+        None => Listener::Unix(socket.into()),
+    }))
+}
+
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    // A leading @ is a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        return socket.send_to_addr(state.as_bytes(), &addr).map(drop);
+    }
+    socket.send_to(state.as_bytes(), path).map(drop)
+}
+
+// Tells systemd about a state change (READY=1, STOPPING=1, WATCHDOG=1) when
+// it runs us as a Type=notify service; otherwise does nothing
+pub fn notify(state: &str) {
+    if let Ok(path) = env::var("NOTIFY_SOCKET")
+        && let Err(e) = send(&path, state)
+    {
+        debug!("Could not notify systemd of {}: {}", state, e);
+    }
+}
+
+// Half the watchdog timeout, if the unit sets WatchdogSec=
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+// Pings stop if the runtime wedges, and systemd then restarts the service
+pub async fn watchdog_task(period: Duration) {
+    let mut ping = interval(period);
+    loop {
+        ping.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passed_sockets_and_notify() {
+        let pid = std::process::id().to_string();
+        assert_eq!(passed_sockets(Some(&pid), Some("1")), 1);
+        assert_eq!(passed_sockets(Some("1"), Some("1")), 0);
+        assert_eq!(passed_sockets(None, Some("2")), 0);
+        assert_eq!(passed_sockets(Some(&pid), None), 0);
+
+        let path = env::temp_dir().join(format!("seawatch-notify-{}.sock", pid));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
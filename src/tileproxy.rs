@@ -0,0 +1,184 @@
+use anyhow::Result;
+use axum::body::Bytes;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_TILE_BYTES: usize = 1024 * 1024;
+// The OpenStreetMap tile policy asks clients to keep connections few
+const MAX_FETCHES: usize = 2;
+const MAX_ZOOM: u8 = 19;
+
+pub const DEFAULT_CACHE_DAYS: u64 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Base,
+    Seamark,
+}
+
+impl Layer {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "base" => Some(Self::Base),
+            "seamark" => Some(Self::Seamark),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Base => "base",
+            Self::Seamark => "seamark",
+        }
+    }
+}
+
+// Basemap tiles fetched from the configured tile servers on behalf of the
+// browsers and kept on disk under `dir/<layer>/<z>/<x>/<y>.png`, so every
+// browser in a deployment costs the tile server a tile once. Tiles older
+// than `max_age` are fetched again, and served stale if that fails.
+pub struct TileProxy {
+    base: String,
+    seamark: Option<String>,
+    dir: PathBuf,
+    max_age: Duration,
+    client: reqwest::Client,
+    fetches: Semaphore,
+    cache_only: bool,
+}
+
+impl TileProxy {
+    pub fn new(base: &str, seamark: Option<&str>, dir: &Path, max_age: Duration) -> Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| anyhow::anyhow!("Could not create {}: {}", dir.display(), e))?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!("seawatch/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self {
+            base: base.to_string(),
+            seamark: seamark.map(str::to_string),
+            dir: dir.to_path_buf(),
+            max_age,
+            client,
+            fetches: Semaphore::new(MAX_FETCHES),
+            cache_only: false,
+        })
+    }
+
+    // Serve what is on disk, however old, and never ask the tile servers
+    pub fn cache_only(mut self) -> Self {
+        self.cache_only = true;
+        self
+    }
+
+    pub fn has(&self, layer: Layer) -> bool {
+        layer == Layer::Base || self.seamark.is_some()
+    }
+
+    // None for a tile that doesn't exist; an error when it isn't cached and
+    // couldn't be fetched
+    pub async fn get(&self, layer: Layer, z: u8, x: u32, y: u32) -> Result<Option<Bytes>> {
+        let template = match layer {
+            Layer::Base => Some(&self.base),
+            Layer::Seamark => self.seamark.as_ref(),
+        };
+        let Some(template) = template.filter(|_| z <= MAX_ZOOM && x < 1 << z && y < 1 << z) else {
+            return Ok(None);
+        };
+        let path = self.path(layer, z, x, y);
+        let cached = read(&path).await;
+        if self.cache_only {
+            return Ok(cached.map(|(bytes, _)| bytes));
+        }
+        if let Some((bytes, modified)) = &cached
+            && modified.elapsed().is_ok_and(|age| age < self.max_age)
+        {
+            return Ok(Some(bytes.clone()));
+        }
+        match self.fetch(template, z, x, y).await {
+            Ok(Some(bytes)) => {
+                if let Err(e) = write(&path, &bytes).await {
+                    debug!("Could not cache tile {}: {}", path.display(), e);
+                }
+                Ok(Some(bytes))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => match cached {
+                Some((bytes, _)) => {
+                    debug!("Serving stale tile {}: {}", path.display(), e);
+                    Ok(Some(bytes))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn path(&self, layer: Layer, z: u8, x: u32, y: u32) -> PathBuf {
+        self.dir.join(layer.name()).join(z.to_string()).join(x.to_string()).join(format!("{}.png", y))
+    }
+
+    async fn fetch(&self, template: &str, z: u8, x: u32, y: u32) -> Result<Option<Bytes>> {
+        let url = template.replace("{z}", &z.to_string()).replace("{x}", &x.to_string()).replace("{y}", &y.to_string());
+        let _permit = self.fetches.acquire().await?;
+        let response = self.client.get(&url).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        if response.content_length().is_some_and(|length| length as usize > MAX_TILE_BYTES) {
+            return Err(anyhow::anyhow!("{} is too big for a tile", url));
+        }
+        let bytes = response.bytes().await?;
+        if bytes.len() > MAX_TILE_BYTES {
+            return Err(anyhow::anyhow!("{} is too big for a tile", url));
+        }
+        Ok(Some(bytes))
+    }
+}
+
+async fn read(path: &Path) -> Option<(Bytes, SystemTime)> {
+    let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+    let bytes = tokio::fs::read(path).await.ok()?;
+    Some((Bytes::from(bytes), modified))
+}
+
+// Through a temporary file, so a tile is never read half-written
+async fn write(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let partial = path.with_extension("tmp");
+    tokio::fs::write(&partial, bytes).await?;
+    tokio::fs::rename(&partial, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cached_tiles() {
+        let dir = std::env::temp_dir().join(format!("seawatch-tiles-{}", std::process::id()));
+        // Nothing listens on the discard port, so only cached tiles can be served
+        let proxy = TileProxy::new("http://127.0.0.1:9/{z}/{x}/{y}.png", None, &dir, Duration::from_secs(3600)).unwrap();
+        write(&proxy.path(Layer::Base, 3, 4, 2), b"tile").await.unwrap();
+        assert_eq!(proxy.get(Layer::Base, 3, 4, 2).await.unwrap().as_deref(), Some(&b"tile"[..]));
+        assert!(proxy.get(Layer::Base, 3, 4, 3).await.is_err());
+        // Off the map, or a layer that isn't configured
+        assert!(proxy.get(Layer::Base, 3, 8, 0).await.unwrap().is_none());
+        assert!(proxy.get(Layer::Seamark, 3, 4, 2).await.unwrap().is_none());
+        assert!(!proxy.has(Layer::Seamark));
+
+        // Stale, with the tile server down, still beats nothing
+        let stale = TileProxy::new("http://127.0.0.1:9/{z}/{x}/{y}.png", None, &dir, Duration::ZERO).unwrap();
+        assert!(stale.get(Layer::Base, 3, 4, 2).await.unwrap().is_some());
+        // Offline, a missing tile is just missing
+        let offline = TileProxy::new("http://127.0.0.1:9/{z}/{x}/{y}.png", None, &dir, Duration::ZERO).unwrap().cache_only();
+        assert!(offline.get(Layer::Base, 3, 4, 2).await.unwrap().is_some());
+        assert!(offline.get(Layer::Base, 3, 4, 3).await.unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
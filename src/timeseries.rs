@@ -0,0 +1,272 @@
+use anyhow::Result;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::warn;
+use url::Url;
+
+use crate::index::is_valid_position;
+use crate::monitor::Monitor;
+use crate::ship::{Ship, ShipCache};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// Zone occupancy and the ship count are sampled this often
+const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+// Points kept while the database is unreachable; the oldest go first
+const MAX_PENDING: usize = 200_000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum Point {
+    Position(Arc<Ship>),
+    Occupancy { zone: Arc<str>, ships: usize, time: u64 },
+    Tracked { ships: usize, time: u64 },
+}
+
+// Tag values can't contain unescaped commas, equals signs or spaces
+fn escape_tag(value: &str) -> String {
+    value.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+// InfluxDB line protocol, with second precision
+fn line(point: &Point) -> String {
+    let mut line = String::new();
+    match point {
+        Point::Position(ship) => {
+            let _ = write!(
+                line,
+                "ship_position,mmsi={},ship_type={} lat={},lng={},speed={},nav_status={}i",
+                ship.mmsi, ship.ship_type, ship.lat, ship.lng, ship.speed, ship.nav_status
+            );
+            if ship.cog < 360.0 {
+                let _ = write!(line, ",cog={}", ship.cog);
+            }
+            if ship.heading < 360 {
+                let _ = write!(line, ",heading={}i", ship.heading);
+            }
+            let _ = write!(line, " {}", ship.last_update);
+        }
+        Point::Occupancy { zone, ships, time } => {
+            let _ = write!(line, "zone_occupancy,zone={} ships={}i {}", escape_tag(zone), ships, time);
+        }
+        Point::Tracked { ships, time } => {
+            let _ = write!(line, "seawatch ships={}i {}", ships, time);
+        }
+    }
+    line
+}
+
+pub struct Influx {
+    client: reqwest::Client,
+    write_url: Url,
+    token: Option<String>,
+}
+
+impl Influx {
+    // The v2 write API, which InfluxDB 1.8+ also serves (with `bucket` as
+    // "database/retention-policy")
+    pub fn new(url: &str, org: &str, bucket: &str, token: Option<String>) -> Result<Self> {
+        let mut write_url = Url::parse(url)?.join("api/v2/write")?;
+        write_url.query_pairs_mut().append_pair("org", org).append_pair("bucket", bucket).append_pair("precision", "s");
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { client, write_url, token })
+    }
+
+    async fn write(&self, points: &[Point]) -> Result<()> {
+        let body = points.iter().map(line).collect::<Vec<_>>().join("\n");
+        let mut request = self.client.post(self.write_url.clone()).body(body);
+        if let Some(token) = &self.token {
+            request = request.header(reqwest::header::AUTHORIZATION, format!("Token {}", token));
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("InfluxDB returned {}: {}", response.status(), response.text().await.unwrap_or_default()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "timescale")]
+pub struct Timescale {
+    url: String,
+    client: Option<tokio_postgres::Client>,
+}
+
+#[cfg(feature = "timescale")]
+impl Timescale {
+    pub fn new(url: &str) -> Self {
+        Self { url: url.to_string(), client: None }
+    }
+
+    async fn connect(&self) -> Result<tokio_postgres::Client> {
+        let (client, connection) = tokio_postgres::connect(&self.url, tokio_postgres::NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("TimescaleDB connection lost: {}", e);
+            }
+        });
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS ship_positions (
+                    time TIMESTAMPTZ NOT NULL, mmsi INTEGER NOT NULL, ship_type INTEGER NOT NULL,
+                    lat DOUBLE PRECISION NOT NULL, lng DOUBLE PRECISION NOT NULL, speed DOUBLE PRECISION NOT NULL,
+                    cog DOUBLE PRECISION, heading INTEGER, nav_status INTEGER NOT NULL);
+                CREATE TABLE IF NOT EXISTS zone_occupancy (time TIMESTAMPTZ NOT NULL, zone TEXT NOT NULL, ships INTEGER NOT NULL);
+                CREATE TABLE IF NOT EXISTS seawatch_ships (time TIMESTAMPTZ NOT NULL, ships INTEGER NOT NULL);",
+            )
+            .await?;
+        for table in ["ship_positions", "zone_occupancy", "seawatch_ships"] {
+            let hypertable = format!("SELECT create_hypertable('{}', 'time', if_not_exists => TRUE)", table);
+            if let Err(e) = client.batch_execute(&hypertable).await {
+                warn!("Keeping {} as a plain table, is the timescaledb extension installed? {}", table, e);
+                break;
+            }
+        }
+        tracing::info!("Connected to TimescaleDB");
+        Ok(client)
+    }
+
+    async fn write(&mut self, points: &[Point]) -> Result<()> {
+        if self.client.as_ref().is_none_or(|client| client.is_closed()) {
+            self.client = Some(self.connect().await?);
+        }
+        let client = self.client.as_ref().unwrap();
+
+        let (mut time, mut mmsi, mut ship_type, mut lat, mut lng, mut speed, mut cog, mut heading, mut nav_status) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let (mut zone_time, mut zone, mut zone_ships) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut tracked_time, mut tracked) = (Vec::new(), Vec::new());
+        for point in points {
+            match point {
+                Point::Position(ship) => {
+                    time.push(ship.last_update as f64);
+                    mmsi.push(ship.mmsi as i32);
+                    ship_type.push(ship.ship_type as i32);
+                    lat.push(ship.lat);
+                    lng.push(ship.lng);
+                    speed.push(ship.speed);
+                    cog.push((ship.cog < 360.0).then_some(ship.cog));
+                    heading.push((ship.heading < 360).then_some(ship.heading as i32));
+                    nav_status.push(ship.nav_status as i32);
+                }
+                Point::Occupancy { zone: name, ships, time } => {
+                    zone_time.push(*time as f64);
+                    zone.push(name.to_string());
+                    zone_ships.push(*ships as i32);
+                }
+                Point::Tracked { ships, time } => {
+                    tracked_time.push(*time as f64);
+                    tracked.push(*ships as i32);
+                }
+            }
+        }
+        client
+            .execute(
+                "INSERT INTO ship_positions
+                 SELECT to_timestamp(t), m, st, la, ln, sp, c, h, ns
+                 FROM UNNEST($1::float8[], $2::int4[], $3::int4[], $4::float8[], $5::float8[], $6::float8[], $7::float8[], $8::int4[], $9::int4[])
+                 AS u(t, m, st, la, ln, sp, c, h, ns)",
+                &[&time, &mmsi, &ship_type, &lat, &lng, &speed, &cog, &heading, &nav_status],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO zone_occupancy SELECT to_timestamp(t), z, s FROM UNNEST($1::float8[], $2::text[], $3::int4[]) AS u(t, z, s)",
+                &[&zone_time, &zone, &zone_ships],
+            )
+            .await?;
+        client
+            .execute(
+                "INSERT INTO seawatch_ships SELECT to_timestamp(t), s FROM UNNEST($1::float8[], $2::int4[]) AS u(t, s)",
+                &[&tracked_time, &tracked],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+pub enum Backend {
+    Influx(Influx),
+    #[cfg(feature = "timescale")]
+    Timescale(Timescale),
+}
+
+impl Backend {
+    async fn write(&mut self, points: &[Point]) -> Result<()> {
+        match self {
+            Backend::Influx(influx) => influx.write(points).await,
+            #[cfg(feature = "timescale")]
+            Backend::Timescale(timescale) => timescale.write(points).await,
+        }
+    }
+}
+
+// Batch positions, plus zone occupancy and the ship count once a minute,
+// keeping what couldn't be written for the next attempt
+pub async fn timeseries_task(
+    mut backend: Backend,
+    mut updates: broadcast::Receiver<Arc<Ship>>,
+    ships: Arc<ShipCache>,
+    monitor: Arc<Monitor>,
+) {
+    let mut flush = interval(FLUSH_INTERVAL);
+    let mut metrics = interval(METRICS_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    metrics.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut pending: Vec<Point> = Vec::new();
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(ship) => {
+                    if is_valid_position(ship.lat, ship.lng) {
+                        pending.push(Point::Position(ship));
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => warn!("Time-series writer fell behind, {} updates not written", missed),
+                Err(RecvError::Closed) => return,
+            },
+            _ = metrics.tick() => {
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+                for (zone, inside) in monitor.geofences.occupants() {
+                    pending.push(Point::Occupancy { zone, ships: inside.len(), time });
+                }
+                pending.push(Point::Tracked { ships: ships.len(), time });
+            }
+            _ = flush.tick() => {
+                if pending.is_empty() {
+                    continue;
+                }
+                match backend.write(&pending).await {
+                    Ok(()) => pending.clear(),
+                    Err(e) => {
+                        warn!("Time-series write of {} points failed: {}", pending.len(), e);
+                        if pending.len() > MAX_PENDING {
+                            pending.drain(..pending.len() - MAX_PENDING);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_protocol() {
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.speed, ship.heading, ship.ship_type, ship.last_update) = (51.9, 4.1, 11.2, 511, 70, 1_700_000_000);
+        assert_eq!(
+            line(&Point::Position(Arc::new(ship))),
+            "ship_position,mmsi=244660000,ship_type=70 lat=51.9,lng=4.1,speed=11.2,nav_status=0i 1700000000"
+        );
+        assert_eq!(
+            line(&Point::Occupancy { zone: Arc::from("Harbour mouth, north"), ships: 3, time: 60 }),
+            "zone_occupancy,zone=Harbour\\ mouth\\,\\ north ships=3i 60"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::shutdown;
+
+// How often the certificate and key are checked for a renewal (by certbot,
+// say), which is then picked up without a restart
+const RELOAD_CHECK: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf, // PEM, the certificate followed by any intermediates
+    pub key: PathBuf,  // PEM, PKCS#8, PKCS#1 or SEC1
+}
+
+impl TlsFiles {
+    pub async fn load(&self) -> Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert, &self.key)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not load TLS certificate {} and key {}: {}", self.cert.display(), self.key.display(), e))
+    }
+
+    // The later of the two modification times
+    fn modified(&self) -> Option<SystemTime> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        modified(&self.cert).max(modified(&self.key))
+    }
+}
+
+// HTTPS on an already bound listener, stopping (and finishing in-flight
+// requests) once shutdown is requested, as the plain HTTP server does
+pub async fn serve(listener: TcpListener, app: Router, files: TlsFiles, shutdown_rx: watch::Receiver<bool>) -> std::io::Result<()> {
+    let config = files.load().await.map_err(std::io::Error::other)?;
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown::requested(shutdown_rx).await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    tokio::spawn(reload_task(config.clone(), files));
+    axum_server::from_tcp_rustls(listener.into_std()?, config).handle(handle).serve(app.into_make_service()).await
+}
+
+async fn reload_task(config: RustlsConfig, files: TlsFiles) {
+    let mut loaded = files.modified();
+    let mut check = interval(RELOAD_CHECK);
+    check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    check.tick().await;
+    loop {
+        check.tick().await;
+        let modified = files.modified();
+        if modified == loaded {
+            continue;
+        }
+        // Keep serving the old certificate if the new one is half written
+        match config.reload_from_pem_file(&files.cert, &files.key).await {
+            Ok(()) => {
+                info!("Reloaded TLS certificate {}", files.cert.display());
+                loaded = modified;
+            }
+            Err(e) => warn!("Could not reload TLS certificate {}: {}", files.cert.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_errors() {
+        let files = TlsFiles { cert: PathBuf::from("missing.pem"), key: PathBuf::from("missing.key") };
+        assert_eq!(files.modified(), None);
+        let error = files.load().await.unwrap_err().to_string();
+        assert!(error.contains("missing.pem"), "{}", error);
+    }
+}
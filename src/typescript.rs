@@ -0,0 +1,56 @@
+use ts_rs::TS;
+
+use crate::ais::AisClass;
+use crate::anomalies::AnomalyKind;
+use crate::dark::DarkStatus;
+use crate::events::{Event, EventKind, Priority};
+use crate::geofence::SpeedingStatus;
+use crate::identity::ConflictKind;
+use crate::loitering::LoiterStatus;
+use crate::rendezvous::MeetingStatus;
+use crate::ship::{Dimensions, Ship, ShipState};
+
+pub const DEFAULT_PATH: &str = "static/seawatch.d.ts";
+
+// Every API type as one declaration file, for the frontend and JS clients.
+// ts-rs declares 64-bit integers as bigint, but serde_json writes them as
+// plain numbers, which is what JSON.parse hands back.
+pub fn bundle() -> String {
+    let declarations = [
+        Ship::decl(),
+        ShipState::decl(),
+        Dimensions::decl(),
+        AisClass::decl(),
+        Event::decl(),
+        EventKind::decl(),
+        Priority::decl(),
+        AnomalyKind::decl(),
+        ConflictKind::decl(),
+        DarkStatus::decl(),
+        SpeedingStatus::decl(),
+        LoiterStatus::decl(),
+        MeetingStatus::decl(),
+    ];
+    let mut bundle = String::from("// Generated by `seawatch export-types`; do not edit\n");
+    for declaration in declarations {
+        bundle.push_str("\nexport ");
+        bundle.push_str(&declaration.replace("bigint", "number"));
+        bundle.push('\n');
+    }
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle() {
+        let bundle = bundle();
+        assert!(bundle.contains("export type Ship = {"));
+        assert!(bundle.contains("last_update: number"));
+        assert!(!bundle.contains("bigint"));
+        // Kept in step with the structs by running `seawatch export-types`
+        assert_eq!(std::fs::read_to_string(DEFAULT_PATH).unwrap(), bundle);
+    }
+}
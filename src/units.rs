@@ -0,0 +1,155 @@
+use axum::body::{to_bytes, Body, HttpBody};
+use axum::extract::{Query, Request};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+const KMH_PER_KNOT: f64 = 1.852;
+const MPH_PER_KNOT: f64 = 1.150779;
+const METRES_PER_NM: f64 = 1852.0;
+const SPEED_UNAVAILABLE: f64 = 102.2;
+// Responses known to be bigger than this are passed through unconverted
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeedUnit {
+    #[default]
+    Knots,
+    Kmh,
+    Mph,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceUnit {
+    #[default]
+    M,
+    Nm,
+    Km,
+}
+
+// `?speed_unit=` and `?distance_unit=`, on any JSON response
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Units {
+    pub speed_unit: SpeedUnit,
+    pub distance_unit: DistanceUnit,
+}
+
+impl Units {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Speeds are `speed`, `sog` and anything ending in `_kn`, which gets the
+    // new unit's suffix; distances end in `_m` and are renamed the same way.
+    // Lists and objects under those names have their numbers converted
+    pub fn convert(&self, value: &mut Value) {
+        match value {
+            Value::Array(values) => values.iter_mut().for_each(|value| self.convert(value)),
+            Value::Object(fields) => {
+                let converted: Map<String, Value> = std::mem::take(fields)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        self.convert(&mut value);
+                        self.convert_field(key, value)
+                    })
+                    .collect();
+                *fields = converted;
+            }
+            _ => {}
+        }
+    }
+
+    fn convert_field(&self, key: String, value: Value) -> (String, Value) {
+        let speed = match self.speed_unit {
+            SpeedUnit::Knots => None,
+            SpeedUnit::Kmh => Some((KMH_PER_KNOT, "_kmh")),
+            SpeedUnit::Mph => Some((MPH_PER_KNOT, "_mph")),
+        };
+        let distance = match self.distance_unit {
+            DistanceUnit::M => None,
+            DistanceUnit::Nm => Some((1.0 / METRES_PER_NM, "_nm")),
+            DistanceUnit::Km => Some((0.001, "_km")),
+        };
+        let (factor, key, is_speed) = match (speed, distance) {
+            (Some((factor, _)), _) if key == "speed" || key == "sog" => (factor, key, true),
+            (Some((factor, suffix)), _) if key.ends_with("_kn") => (factor, format!("{}{}", key.trim_end_matches("_kn"), suffix), true),
+            (_, Some((factor, suffix))) if key.ends_with("_m") => (factor, format!("{}{}", key.trim_end_matches("_m"), suffix), false),
+            _ => return (key, value),
+        };
+        (key, scale(value, factor, is_speed))
+    }
+}
+
+// A number, or the numbers in a list or object of them, e.g. a min/max/avg
+fn scale(value: Value, factor: f64, is_speed: bool) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(values.into_iter().map(|value| scale(value, factor, is_speed)).collect()),
+        Value::Object(fields) => Value::Object(fields.into_iter().map(|(key, value)| (key, scale(value, factor, is_speed))).collect()),
+        value => match value.as_f64() {
+            // AIS's 102.3 kn for "not available" stays recognisable
+            Some(speed) if is_speed && speed >= SPEED_UNAVAILABLE => value,
+            Some(number) => round(number * factor),
+            None => value,
+        },
+    }
+}
+
+fn round(value: f64) -> Value {
+    Value::from((value * 1000.0).round() / 1000.0)
+}
+
+// Rewrites JSON responses into the units the query string asks for, so
+// simple clients needn't convert themselves; streams pass through untouched
+pub async fn convert(request: Request, next: Next) -> Response {
+    let units = match Query::<Units>::try_from_uri(request.uri()) {
+        Ok(Query(units)) if !units.is_default() => units,
+        Ok(_) => return next.run(request).await,
+        Err(_) => return (StatusCode::BAD_REQUEST, "speed_unit is knots, kmh or mph; distance_unit is m, nm or km").into_response(),
+    };
+    let response = next.run(request).await;
+    let is_json = response.headers().get(header::CONTENT_TYPE).is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json || response.body().size_hint().lower() > MAX_BODY_BYTES as u64 {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    units.convert(&mut value);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(serde_json::to_vec(&value).unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_units() {
+        let units = Units { speed_unit: SpeedUnit::Kmh, distance_unit: DistanceUnit::Nm };
+        let mut value = serde_json::json!([
+            {"mmsi": 244660000, "speed": 10.0, "heading": 90, "lat": 51.9},
+            {"speed": 102.3, "nearest_port": {"distance_m": 3704.0, "bearing": 45.0}},
+            {"kind": {"type": "speed_limit", "peak_kn": 20.0}, "max_speed_kn": null},
+            {"speed_kn": {"min": 5.0, "max": 10.0}, "speeds_kn": [5.0, null]},
+        ]);
+        units.convert(&mut value);
+        assert_eq!(value, serde_json::json!([
+            {"mmsi": 244660000, "speed": 18.52, "heading": 90, "lat": 51.9},
+            {"speed": 102.3, "nearest_port": {"distance_nm": 2.0, "bearing": 45.0}},
+            {"kind": {"type": "speed_limit", "peak_kmh": 37.04}, "max_speed_kmh": null},
+            {"speed_kmh": {"min": 9.26, "max": 18.52}, "speeds_kmh": [9.26, null]},
+        ]));
+
+        let uri: axum::http::Uri = "/api/collisions?speed_unit=furlongs".parse().unwrap();
+        assert!(Query::<Units>::try_from_uri(&uri).is_err());
+    }
+}
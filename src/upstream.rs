@@ -0,0 +1,126 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ais::Subscription;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    // Not reading from aisstream.io: simulating, following or not started
+    #[default]
+    Idle,
+    Connecting,
+    Connected,
+    // Waiting to reconnect, or stopped by shutdown
+    Disconnected,
+}
+
+// The aisstream.io connection, as /api/admin/upstream/status shows it
+#[derive(Serialize, JsonSchema, Clone, Debug, Default)]
+pub struct UpstreamStatus {
+    pub state: ConnectionState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connected_since: Option<u64>,
+    // Of the last frame received, on this connection or an earlier one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_message: Option<u64>,
+    pub messages: u64, // On the current connection
+    // What the current connection subscribed to; a change posted to
+    // /api/admin/upstream shows up here once the reconnect goes through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<Subscription>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<u64>,
+}
+
+// Kept up to date by the stream task. Frames only touch the atomics, so the
+// read loop never waits on a status request
+#[derive(Default)]
+pub struct UpstreamTracker {
+    status: Mutex<UpstreamStatus>,
+    last_message: AtomicU64,
+    messages: AtomicU64,
+}
+
+impl UpstreamTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connecting(&self, url: &str) {
+        let mut status = self.status.lock().unwrap();
+        (status.state, status.url, status.connected_since) = (ConnectionState::Connecting, Some(url.to_string()), None);
+    }
+
+    pub fn connected(&self, subscription: &Subscription) {
+        self.messages.store(0, Ordering::Relaxed);
+        let mut status = self.status.lock().unwrap();
+        (status.state, status.connected_since, status.subscription) = (ConnectionState::Connected, Some(now()), Some(subscription.clone()));
+    }
+
+    pub fn message(&self, timestamp: u64) {
+        self.last_message.store(timestamp, Ordering::Relaxed);
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self, error: String) {
+        let mut status = self.status.lock().unwrap();
+        (status.last_error, status.last_error_at) = (Some(error), Some(now()));
+        self.disconnect(&mut status);
+    }
+
+    pub fn disconnected(&self) {
+        self.disconnect(&mut self.status.lock().unwrap());
+    }
+
+    fn disconnect(&self, status: &mut UpstreamStatus) {
+        (status.state, status.connected_since) = (ConnectionState::Disconnected, None);
+    }
+
+    pub fn status(&self) -> UpstreamStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        status.last_message = Some(self.last_message.load(Ordering::Relaxed)).filter(|&timestamp| timestamp > 0);
+        if status.state == ConnectionState::Connected {
+            status.messages = self.messages.load(Ordering::Relaxed);
+        }
+        status
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_status() {
+        let tracker = UpstreamTracker::new();
+        assert_eq!(tracker.status().state, ConnectionState::Idle);
+
+        tracker.connecting("wss://stream.aisstream.io/v0/stream");
+        tracker.connected(&Subscription::default());
+        tracker.message(1_700_000_000);
+        tracker.message(1_700_000_001);
+        let status = tracker.status();
+        assert_eq!((status.state, status.messages, status.last_message), (ConnectionState::Connected, 2, Some(1_700_000_001)));
+        assert!(status.connected_since.is_some());
+
+        // The last message outlives the connection; the count doesn't
+        tracker.failed("Connection reset without closing handshake".to_string());
+        let status = tracker.status();
+        assert_eq!((status.state, status.messages, status.last_message), (ConnectionState::Disconnected, 0, Some(1_700_000_001)));
+        assert_eq!(status.last_error.as_deref(), Some("Connection reset without closing handshake"));
+        assert!(status.connected_since.is_none());
+        assert_eq!(status.subscription.unwrap().bounding_boxes, Subscription::default().bounding_boxes);
+    }
+}
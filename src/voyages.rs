@@ -0,0 +1,153 @@
+use serde::Serialize;
+use schemars::JsonSchema;
+use std::sync::Arc;
+
+use crate::geo::haversine_m;
+use crate::history::TrackPoint;
+use crate::port_calls::PortCall;
+
+// A stop at sea: at or below this speed for at least this long
+const STOP_SPEED: f64 = 1.0;
+const LONG_STOP_SECS: u64 = 2 * 3600;
+
+// One leg between port calls or long stops, for /api/ship/:mmsi/voyages
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct Voyage {
+    // The port it left and the one it reached; None for a stop at sea, or
+    // where the leg began before the history does
+    pub from_port: Option<Arc<str>>,
+    pub from_locode: Option<Arc<str>>,
+    pub to_port: Option<Arc<str>>,
+    pub to_locode: Option<Arc<str>>,
+    pub started: u64,
+    pub ended: Option<u64>, // None while still under way
+    pub duration_secs: u64,
+    // Along the positions kept in the history, so only the last day of an older leg
+    pub distance_m: f64,
+}
+
+// Where a ship stayed put, from `start` until `end` (None if it still is)
+struct Stop {
+    start: u64,
+    end: Option<u64>,
+    port: Option<(Arc<str>, Arc<str>)>,
+}
+
+// A ship's voyages, oldest first, from its port calls and track
+pub fn voyages(calls: &[PortCall], track: &[TrackPoint], now: u64) -> Vec<Voyage> {
+    let mut stops: Vec<Stop> = calls
+        .iter()
+        .map(|call| Stop { start: call.arrived, end: call.departed, port: Some((call.port.clone(), call.locode.clone())) })
+        .collect();
+    stops.extend(stops_at_sea(track));
+    stops.sort_by_key(|stop| stop.start);
+
+    // Stops that overlap are one, named after the port if either was in one
+    let mut merged: Vec<Stop> = Vec::new();
+    for stop in stops {
+        match merged.last_mut() {
+            Some(last) if last.end.is_none_or(|end| stop.start <= end) => {
+                last.end = last.end.zip(stop.end).map(|(a, b)| a.max(b));
+                if last.port.is_none() {
+                    last.port = stop.port;
+                }
+            }
+            _ => merged.push(stop),
+        }
+    }
+
+    let mut voyages = Vec::new();
+    let port = |stop: Option<&Stop>| stop.and_then(|stop| stop.port.clone()).unzip();
+    // Under way before the first stop, as far back as the track goes
+    let first_moving = track.iter().find(|point| point.speed > STOP_SPEED).map(|point| point.timestamp);
+    if let Some(started) = first_moving.filter(|&t| merged.first().is_none_or(|stop| t < stop.start)) {
+        let ended = merged.first().map(|stop| stop.start);
+        voyages.push(voyage((None, None), port(merged.first()), started, ended, track, now));
+    }
+    for (i, stop) in merged.iter().enumerate() {
+        let Some(started) = stop.end else {
+            break;
+        };
+        let next = merged.get(i + 1);
+        voyages.push(voyage(port(Some(stop)), port(next), started, next.map(|next| next.start), track, now));
+    }
+    voyages
+}
+
+type Port = (Option<Arc<str>>, Option<Arc<str>>);
+
+fn voyage(from: Port, to: Port, started: u64, ended: Option<u64>, track: &[TrackPoint], now: u64) -> Voyage {
+    let (from_port, from_locode) = from;
+    let (to_port, to_locode) = to;
+    let until = ended.unwrap_or(now);
+    let points: Vec<&TrackPoint> = track.iter().filter(|point| point.timestamp >= started && point.timestamp <= until).collect();
+    let distance_m = points.windows(2).map(|pair| haversine_m(pair[0].lat, pair[0].lng, pair[1].lat, pair[1].lng)).sum::<f64>();
+    Voyage {
+        from_port,
+        from_locode,
+        to_port,
+        to_locode,
+        started,
+        ended,
+        duration_secs: until.saturating_sub(started),
+        distance_m: distance_m.round(),
+    }
+}
+
+// Runs of slow points lasting at least LONG_STOP_SECS. One still going on
+// at the end of the track is open-ended
+fn stops_at_sea(track: &[TrackPoint]) -> Vec<Stop> {
+    let mut stops = Vec::new();
+    let mut run: Option<(u64, u64)> = None;
+    for point in track {
+        if point.speed <= STOP_SPEED {
+            let (start, _) = run.unwrap_or((point.timestamp, point.timestamp));
+            run = Some((start, point.timestamp));
+            continue;
+        }
+        if let Some((start, end)) = run.take()
+            && end - start >= LONG_STOP_SECS
+        {
+            stops.push(Stop { start, end: Some(point.timestamp), port: None });
+        }
+    }
+    if let Some((start, end)) = run
+        && end - start >= LONG_STOP_SECS
+    {
+        stops.push(Stop { start, end: None, port: None });
+    }
+    stops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voyages() {
+        let t0 = 1_700_000_000;
+        let point = |minute: u64, lat: f64, speed: f64| TrackPoint { timestamp: t0 + minute * 60, lat, lng: 4.0, speed, cog: 0.0 };
+        // Three hours out of Rotterdam, three drifting at sea, then on into Felixstowe
+        let mut track: Vec<TrackPoint> = (0..=180).map(|m| point(m, 52.0 + m as f64 / 600.0, 12.0)).collect();
+        track.extend((181..=360).map(|m| point(m, 52.3, 0.2)));
+        track.extend((361..=480).map(|m| point(m, 52.3 + (m - 360) as f64 / 600.0, 12.0)));
+        let calls = [
+            PortCall { port: "Rotterdam".into(), locode: "NLRTM".into(), arrived: t0 - 86_400, departed: Some(t0) },
+            PortCall { port: "Felixstowe".into(), locode: "GBFXT".into(), arrived: t0 + 480 * 60, departed: None },
+        ];
+
+        let voyages = voyages(&calls, &track, t0 + 500 * 60);
+        assert_eq!(voyages.len(), 2);
+        assert_eq!((voyages[0].from_locode.as_deref(), voyages[0].to_locode.as_deref()), (Some("NLRTM"), None));
+        assert_eq!((voyages[0].started, voyages[0].ended), (t0, Some(t0 + 181 * 60)));
+        assert_eq!((voyages[1].from_port.as_deref(), voyages[1].to_port.as_deref()), (None, Some("Felixstowe")));
+        assert_eq!(voyages[1].duration_secs, (480 - 361) * 60);
+        // 0.3 degrees of latitude, then just under 0.2
+        assert!((voyages[0].distance_m - 33_360.0).abs() < 200.0);
+        assert!((voyages[1].distance_m - 22_050.0).abs() < 200.0);
+
+        // Still drifting: no voyage after the stop yet
+        let voyages = super::voyages(&calls[..1], &track[..331], t0 + 330 * 60);
+        assert_eq!(voyages.len(), 1);
+    }
+}
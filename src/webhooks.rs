@@ -0,0 +1,128 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep, Duration};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::events::Event;
+
+// Deliveries are retried with exponential backoff before being given up on
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const SIGNATURE_HEADER: &str = "X-Seawatch-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Seawatch-Timestamp";
+
+// Every event is POSTed as JSON to each URL. With a secret, requests carry
+// `X-Seawatch-Signature: sha256=<hex>`, an HMAC-SHA256 of "<timestamp>.<body>"
+// where the timestamp is the `X-Seawatch-Timestamp` header.
+pub struct Webhooks {
+    urls: Vec<Url>,
+    secret: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<Url>, secret: Option<String>) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { urls, secret, client })
+    }
+
+    async fn deliver(&self, url: &Url, event: &Event) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Could not serialize event {}: {}", event.id, e);
+                return;
+            }
+        };
+
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let mut request = self
+                .client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(TIMESTAMP_HEADER, timestamp)
+                .body(body.clone());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, sign(secret, timestamp, &body));
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Delivered event {} to {}", event.id, url);
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    // The receiver rejected the event itself; sending it again won't help
+                    if status.is_client_error()
+                        && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                        && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        warn!("Webhook {} rejected event {}: {}", url, event.id, status);
+                        return;
+                    }
+                    debug!("Webhook {} returned {} (attempt {})", url, status, attempt);
+                }
+                Err(e) => debug!("Webhook {} failed: {} (attempt {})", url, e, attempt),
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        warn!("Giving up on delivering event {} to {} after {} attempts", event.id, url, MAX_ATTEMPTS);
+    }
+}
+
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    let mut signature = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{:02x}", byte);
+    }
+    signature
+}
+
+// Forward events to the webhooks as they are logged; each delivery runs on
+// its own so a slow receiver doesn't hold up the others
+pub async fn webhook_task(mut events: broadcast::Receiver<Arc<Event>>, webhooks: Arc<Webhooks>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                for index in 0..webhooks.urls.len() {
+                    let (webhooks, event) = (webhooks.clone(), event.clone());
+                    tokio::spawn(async move { webhooks.deliver(&webhooks.urls[index], &event).await });
+                }
+            }
+            Err(RecvError::Lagged(missed)) => warn!("Webhooks fell behind, {} events not delivered", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            sign("secret", 1_700_000_000, br#"{"id":1}"#),
+            "sha256=3dd1b9aef568d75f6790a84bd2e5dfa1f44409eef3cbdbd3f10b837376100c11"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+use crate::ais::AisClass;
+use crate::intern::intern;
+use crate::ship::{Dimensions, ShipState};
+
+// The binary live feed, for /api/live?format=binary. Every frame is
+//
+//   kind: u8 (1 snapshot, 2 delta), tick: u64, then records to the end:
+//   mmsi: u32, fields: u16, then each field whose bit is set, in bit order
+//
+// all little-endian. A snapshot replaces everything the client has; a delta
+// carries, per ship, only the fields that changed since it was last sent,
+// and REMOVED for ships to drop. A delta record for a ship the client doesn't
+// have yet carries every field.
+pub const SNAPSHOT: u8 = 1;
+pub const DELTA: u8 = 2;
+
+pub const LAT: u16 = 1 << 0; // i32, 1e-7 degrees
+pub const LNG: u16 = 1 << 1; // i32, 1e-7 degrees
+pub const HEADING: u16 = 1 << 2; // u16, degrees; 511 when unknown
+pub const SPEED: u16 = 1 << 3; // u16, tenths of a knot
+pub const SHIP_TYPE: u16 = 1 << 4; // u8
+pub const NAME: u16 = 1 << 5; // u8 length, then UTF-8
+pub const DIMENSIONS: u16 = 1 << 6; // u16 length, beam, to_bow, to_stern, to_port, to_starboard; all 0 when unknown
+pub const CLASS: u16 = 1 << 7; // u8: 0 unknown, 1 A, 2 B
+pub const LAST_UPDATE: u16 = 1 << 8; // u32, Unix time
+pub const REMOVED: u16 = 1 << 15; // No fields follow
+const ALL: u16 = LAT | LNG | HEADING | SPEED | SHIP_TYPE | NAME | DIMENSIONS | CLASS | LAST_UPDATE;
+
+const HEADER_LEN: usize = 9;
+
+// A ship's fields as they go on the wire; equal ones need not be sent again
+#[derive(Clone, PartialEq)]
+struct Encoded {
+    lat: i32,
+    lng: i32,
+    heading: u16,
+    speed: u16,
+    ship_type: u8,
+    name: Box<[u8]>,
+    dimensions: [u16; 6],
+    class: u8,
+    last_update: u32,
+}
+
+impl Encoded {
+    fn of(state: &ShipState) -> Self {
+        let mut name = state.name.as_bytes();
+        if name.len() > u8::MAX as usize {
+            let mut end = u8::MAX as usize;
+            while !state.name.is_char_boundary(end) {
+                end -= 1;
+            }
+            name = &name[..end];
+        }
+        let dimensions = state.dimensions.map_or([0; 6], |d| {
+            [d.length, d.beam, d.to_bow, d.to_stern, d.to_port, d.to_starboard].map(|value| value.min(u16::MAX as u32) as u16)
+        });
+        Self {
+            lat: (state.lat * 1e7).round() as i32,
+            lng: (state.lng * 1e7).round() as i32,
+            heading: state.heading.min(u16::MAX as u32) as u16,
+            speed: (state.speed * 10.0).round().clamp(0.0, u16::MAX as f64) as u16,
+            ship_type: state.ship_type.min(u8::MAX as u32) as u8,
+            name: name.into(),
+            dimensions,
+            class: match state.class {
+                None => 0,
+                Some(AisClass::A) => 1,
+                Some(AisClass::B) => 2,
+            },
+            last_update: state.last_update.min(u32::MAX as u64) as u32,
+        }
+    }
+
+    fn changed(&self, before: &Self) -> u16 {
+        [
+            (LAT, self.lat != before.lat),
+            (LNG, self.lng != before.lng),
+            (HEADING, self.heading != before.heading),
+            (SPEED, self.speed != before.speed),
+            (SHIP_TYPE, self.ship_type != before.ship_type),
+            (NAME, self.name != before.name),
+            (DIMENSIONS, self.dimensions != before.dimensions),
+            (CLASS, self.class != before.class),
+            (LAST_UPDATE, self.last_update != before.last_update),
+        ]
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .fold(0, |fields, (bit, _)| fields | bit)
+    }
+
+    fn write(&self, mmsi: u32, fields: u16, frame: &mut Vec<u8>) {
+        frame.extend_from_slice(&mmsi.to_le_bytes());
+        frame.extend_from_slice(&fields.to_le_bytes());
+        if fields & LAT != 0 {
+            frame.extend_from_slice(&self.lat.to_le_bytes());
+        }
+        if fields & LNG != 0 {
+            frame.extend_from_slice(&self.lng.to_le_bytes());
+        }
+        if fields & HEADING != 0 {
+            frame.extend_from_slice(&self.heading.to_le_bytes());
+        }
+        if fields & SPEED != 0 {
+            frame.extend_from_slice(&self.speed.to_le_bytes());
+        }
+        if fields & SHIP_TYPE != 0 {
+            frame.push(self.ship_type);
+        }
+        if fields & NAME != 0 {
+            frame.push(self.name.len() as u8);
+            frame.extend_from_slice(&self.name);
+        }
+        if fields & DIMENSIONS != 0 {
+            for value in self.dimensions {
+                frame.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        if fields & CLASS != 0 {
+            frame.push(self.class);
+        }
+        if fields & LAST_UPDATE != 0 {
+            frame.extend_from_slice(&self.last_update.to_le_bytes());
+        }
+    }
+}
+
+fn header(kind: u8, tick: u64) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(256);
+    frame.push(kind);
+    frame.extend_from_slice(&tick.to_le_bytes());
+    frame
+}
+
+// What one binary client has been sent, to work out its deltas
+#[derive(Default)]
+pub struct Encoder {
+    sent: HashMap<u32, Encoded>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot<'a>(&mut self, tick: u64, states: impl IntoIterator<Item = &'a ShipState>) -> Vec<u8> {
+        self.sent.clear();
+        let mut frame = header(SNAPSHOT, tick);
+        for state in states {
+            let encoded = Encoded::of(state);
+            encoded.write(state.mmsi, ALL, &mut frame);
+            self.sent.insert(state.mmsi, encoded);
+        }
+        frame
+    }
+
+    // None when nothing the client has changed
+    pub fn delta<'a>(&mut self, tick: u64, updated: impl IntoIterator<Item = &'a ShipState>, removed: impl IntoIterator<Item = u32>) -> Option<Vec<u8>> {
+        let mut frame = header(DELTA, tick);
+        let mut kept = HashSet::new();
+        for state in updated {
+            let encoded = Encoded::of(state);
+            let fields = match self.sent.get(&state.mmsi) {
+                Some(before) => encoded.changed(before),
+                None => ALL,
+            };
+            kept.insert(state.mmsi);
+            if fields != 0 {
+                encoded.write(state.mmsi, fields, &mut frame);
+                self.sent.insert(state.mmsi, encoded);
+            }
+        }
+        // A ship that moved between two of the client's regions is in both lists
+        for mmsi in removed {
+            if !kept.contains(&mmsi) && self.sent.remove(&mmsi).is_some() {
+                frame.extend_from_slice(&mmsi.to_le_bytes());
+                frame.extend_from_slice(&REMOVED.to_le_bytes());
+            }
+        }
+        (frame.len() > HEADER_LEN).then_some(frame)
+    }
+}
+
+// A frame as a client would read it, applied to the ships it has
+pub fn apply(frame: &[u8], ships: &mut HashMap<u32, ShipState>) -> Result<u64> {
+    let mut reader = Reader { frame, at: 0 };
+    let kind = reader.u8()?;
+    let tick = u64::from_le_bytes(reader.take(8)?.try_into()?);
+    match kind {
+        SNAPSHOT => ships.clear(),
+        DELTA => {}
+        _ => return Err(anyhow::anyhow!("Unknown frame kind {}", kind)),
+    }
+    while reader.at < frame.len() {
+        let mmsi = reader.u32()?;
+        let fields = reader.u16()?;
+        if fields & REMOVED != 0 {
+            ships.remove(&mmsi);
+            continue;
+        }
+        let ship = ships.entry(mmsi).or_insert_with(|| ShipState {
+            mmsi,
+            name: intern(""),
+            lat: 0.0,
+            lng: 0.0,
+            heading: 511,
+            speed: 0.0,
+            ship_type: 0,
+            dimensions: None,
+            class: None,
+            last_update: 0,
+        });
+        if fields & LAT != 0 {
+            ship.lat = reader.u32()? as i32 as f64 / 1e7;
+        }
+        if fields & LNG != 0 {
+            ship.lng = reader.u32()? as i32 as f64 / 1e7;
+        }
+        if fields & HEADING != 0 {
+            ship.heading = reader.u16()? as u32;
+        }
+        if fields & SPEED != 0 {
+            ship.speed = reader.u16()? as f64 / 10.0;
+        }
+        if fields & SHIP_TYPE != 0 {
+            ship.ship_type = reader.u8()? as u32;
+        }
+        if fields & NAME != 0 {
+            let len = reader.u8()? as usize;
+            ship.name = intern(std::str::from_utf8(reader.take(len)?)?);
+        }
+        if fields & DIMENSIONS != 0 {
+            let mut values = [0; 6];
+            for value in &mut values {
+                *value = reader.u16()? as u32;
+            }
+            let [length, beam, to_bow, to_stern, to_port, to_starboard] = values;
+            ship.dimensions = (values != [0; 6]).then_some(Dimensions { length, beam, to_bow, to_stern, to_port, to_starboard });
+        }
+        if fields & CLASS != 0 {
+            ship.class = match reader.u8()? {
+                1 => Some(AisClass::A),
+                2 => Some(AisClass::B),
+                _ => None,
+            };
+        }
+        if fields & LAST_UPDATE != 0 {
+            ship.last_update = reader.u32()? as u64;
+        }
+    }
+    Ok(tick)
+}
+
+struct Reader<'a> {
+    frame: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.frame.get(self.at..self.at + len).ok_or_else(|| anyhow::anyhow!("Frame ends early at byte {}", self.at))?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ship::Ship;
+
+    #[test]
+    fn test_deltas() {
+        let mut ship = Ship::new(244660000, "ALIDA");
+        (ship.lat, ship.lng, ship.speed, ship.heading, ship.ship_type, ship.last_update) = (51.9, 4.1, 12.3, 90, 70, 1_700_000_000);
+        ship.dimensions = Some(Dimensions { length: 200, beam: 30, to_bow: 150, to_stern: 50, to_port: 15, to_starboard: 15 });
+        let other = Ship::new(211000000, "KLEIN").to_state();
+        let mut encoder = Encoder::new();
+        let mut client = HashMap::new();
+
+        let snapshot = encoder.snapshot(1, [&ship.to_state(), &other]);
+        assert_eq!(apply(&snapshot, &mut client).unwrap(), 1);
+        assert_eq!(client[&244660000].name.as_ref(), "ALIDA");
+        assert!((client[&244660000].lat - 51.9).abs() < 1e-6);
+        assert_eq!(client[&244660000].dimensions, ship.dimensions);
+
+        // A position report: position and time, in a fraction of the JSON
+        (ship.lat, ship.lng, ship.last_update) = (51.91, 4.11, 1_700_000_010);
+        let state = ship.to_state();
+        let delta = encoder.delta(2, [&state], []).unwrap();
+        assert_eq!(delta.len(), HEADER_LEN + 6 + 4 + 4 + 4);
+        assert!((delta.len() - HEADER_LEN) * 10 < serde_json::to_vec(&state).unwrap().len());
+        apply(&delta, &mut client).unwrap();
+        assert!((client[&244660000].lng - 4.11).abs() < 1e-6);
+        assert_eq!((client[&244660000].speed, client[&244660000].last_update), (12.3, 1_700_000_010));
+
+        // Nothing new, nothing sent; unknown ships aren't removed
+        assert!(encoder.delta(3, [&state], [1]).is_none());
+        let removed = encoder.delta(4, [], [211000000]).unwrap();
+        apply(&removed, &mut client).unwrap();
+        assert_eq!(client.len(), 1);
+        assert!(apply(&removed[..removed.len() - 1], &mut client).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+// Generated by `seawatch export-types`; do not edit
+
+export type Ship = { mmsi: number, name: string, lat: number, lng: number, heading: number, speed: number, cog: number, nav_status: number, ship_type: number, destination: string, destination_locode: string | null, eta: number | null, imo_number: number, call_sign: string, dimensions: Dimensions | null, draught: number | null, class: AisClass | null, position_accuracy: boolean, last_update: number, };
+
+export type ShipState = { mmsi: number, name: string, lat: number, lng: number, heading: number, speed: number, ship_type: number, dimensions?: Dimensions, class?: AisClass, last_update: number, };
+
+export type Dimensions = { length: number, beam: number, to_bow: number, to_stern: number, to_port: number, to_starboard: number, };
+
+export type AisClass = "A" | "B";
+
+export type Event = { id: number, timestamp: number, mmsi: number, priority: Priority, } & ({ "type": "zone_enter", zone: string, } | { "type": "zone_exit", zone: string, } | { "type": "zone_approach", zone: string, eta_secs: number, } | { "type": "alert", rule: string, } | { "type": "anchor_drag", lat: number, lng: number, distance_m: number, radius_m: number, } | { "type": "dark", status: DarkStatus, lat: number, lng: number, silent_secs: number, } | { "type": "collision_risk", other: number, cpa_m: number, tcpa_secs: number, } | { "type": "port_arrival", port: string, locode: string, } | { "type": "port_departure", port: string, locode: string, duration_secs: number, } | { "type": "speed_limit", zone: string, status: SpeedingStatus, limit_kn: number, peak_kn: number, duration_secs: number, } | { "type": "anomaly", anomaly: AnomalyKind, score: number, } | { "type": "rendezvous", other: number, status: MeetingStatus, lat: number, lng: number, duration_secs: number, } | { "type": "loitering", status: LoiterStatus, lat: number, lng: number, duration_secs: number, } | { "type": "identity_conflict", conflict: ConflictKind, values: Array<string>, others: Array<number>, });
+
+export type EventKind = { "type": "zone_enter", zone: string, } | { "type": "zone_exit", zone: string, } | { "type": "zone_approach", zone: string, eta_secs: number, } | { "type": "alert", rule: string, } | { "type": "anchor_drag", lat: number, lng: number, distance_m: number, radius_m: number, } | { "type": "dark", status: DarkStatus, lat: number, lng: number, silent_secs: number, } | { "type": "collision_risk", other: number, cpa_m: number, tcpa_secs: number, } | { "type": "port_arrival", port: string, locode: string, } | { "type": "port_departure", port: string, locode: string, duration_secs: number, } | { "type": "speed_limit", zone: string, status: SpeedingStatus, limit_kn: number, peak_kn: number, duration_secs: number, } | { "type": "anomaly", anomaly: AnomalyKind, score: number, } | { "type": "rendezvous", other: number, status: MeetingStatus, lat: number, lng: number, duration_secs: number, } | { "type": "loitering", status: LoiterStatus, lat: number, lng: number, duration_secs: number, } | { "type": "identity_conflict", conflict: ConflictKind, values: Array<string>, others: Array<number>, };
+
+export type Priority = "normal" | "high";
+
+export type AnomalyKind = "implausible_speed" | "course_reversal" | "drifting_underway";
+
+export type ConflictKind = "imo_number" | "call_sign" | "shared_imo";
+
+export type DarkStatus = "went_dark" | "reappeared";
+
+export type SpeedingStatus = "started" | "ended";
+
+export type LoiterStatus = "started" | "ended";
+
+export type MeetingStatus = "started" | "ended";